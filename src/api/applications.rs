@@ -1,131 +1,168 @@
+//! API handlers for application management.
+
 use axum::{
     extract::{Path, State},
     http::StatusCode,
-    response::IntoResponse,
     Json,
 };
-use chrono::Utc;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use std::sync::Arc;
 
+use crate::docker::applications;
+use crate::models::application::{CreateApplicationRequest, UpdateApplicationRequest};
 use crate::models::Application;
-use crate::proxy::AppState;
+use crate::proxy::balancer::RouteHealth;
+use crate::proxy::{routing, AppState, Route};
 
-#[derive(Debug, Deserialize)]
-pub struct CreateApplicationRequest {
-    pub name: String,
-    pub domain: String,
-    pub container_id: Option<String>,
-    pub container_port: u16,
+/// An application together with the health of its backends.
+#[derive(Debug, Serialize)]
+pub struct ApplicationResponse {
+    #[serde(flatten)]
+    pub application: Application,
+    pub health: RouteHealth,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct UpdateApplicationRequest {
-    pub name: Option<String>,
-    pub domain: Option<String>,
-    pub container_id: Option<String>,
-    pub container_port: Option<u16>,
-    pub enabled: Option<bool>,
+/// Attach the current backend health to an application.
+async fn with_health(state: &AppState, application: Application) -> ApplicationResponse {
+    let route = Route::for_application(application.clone());
+    let health = match route.as_ref().and_then(|route| route.pool().map(|pool| (route, pool))) {
+        Some((route, pool)) => state
+            .balancers
+            .pool(&state.docker, &route.id, pool.clone())
+            .await
+            .health(),
+        None => {
+            state.balancers.remove(&application.id);
+            RouteHealth::unknown()
+        }
+    };
+
+    ApplicationResponse { application, health }
 }
 
+/// List all applications.
 pub async fn list_applications(
-    State(_state): State<Arc<AppState>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // For now, return a simplified response
-    let applications = vec![
-        Application {
-            id: "1".to_string(),
-            name: "Example App".to_string(),
-            domain: "example.com".to_string(),
-            container_id: Some("abc123".to_string()),
-            container_port: 8080,
-            enabled: true,
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<ApplicationResponse>>, StatusCode> {
+    match applications::list_applications(&state.db).await {
+        Ok(list) => {
+            let mut responses = Vec::with_capacity(list.len());
+            for application in list {
+                responses.push(with_health(&state, application).await);
+            }
+            Ok(Json(responses))
+        }
+        Err(e) => {
+            tracing::error!("Failed to list applications: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
-    ];
-    
-    Ok(Json(applications))
+    }
 }
 
+/// Get an application by ID.
 pub async fn get_application(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // For now, return a simplified response
-    let application = Application {
-        id: "1".to_string(),
-        name: "Example App".to_string(),
-        domain: "example.com".to_string(),
-        container_id: Some("abc123".to_string()),
-        container_port: 8080,
-        enabled: true,
-        created_at: Utc::now(),
-        updated_at: Utc::now(),
-    };
-    
-    Ok(Json(application))
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ApplicationResponse>, StatusCode> {
+    match applications::get_application(&state.db, &id).await {
+        Ok(Some(application)) => Ok(Json(with_health(&state, application).await)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to get application {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
+/// Create a new application.
 pub async fn create_application(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     Json(req): Json<CreateApplicationRequest>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // For now, return a simplified response
-    let application = Application {
-        id: uuid::Uuid::new_v4().to_string(),
-        name: req.name,
-        domain: req.domain,
-        container_id: req.container_id,
-        container_port: req.container_port as i64, // Convert u16 to i64
-        enabled: true,
-        created_at: Utc::now(),
-        updated_at: Utc::now(),
-    };
-    
-    Ok((StatusCode::CREATED, Json(application)))
+) -> Result<(StatusCode, Json<ApplicationResponse>), StatusCode> {
+    if req.container_id.is_none() && req.target_label.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match applications::create_application(&state.db, req).await {
+        Ok(application) => {
+            routing::reload(&state).await;
+            Ok((StatusCode::CREATED, Json(with_health(&state, application).await)))
+        }
+        Err(e) => {
+            tracing::error!("Failed to create application: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
+/// Update an application.
 pub async fn update_application(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
-    Json(_req): Json<UpdateApplicationRequest>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // For now, return a simplified response
-    let application = Application {
-        id: "1".to_string(),
-        name: "Updated App".to_string(),
-        domain: "example.com".to_string(),
-        container_id: Some("abc123".to_string()),
-        container_port: 8080,
-        enabled: true,
-        created_at: Utc::now(),
-        updated_at: Utc::now(),
-    };
-    
-    Ok(Json(application))
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<UpdateApplicationRequest>,
+) -> Result<Json<ApplicationResponse>, StatusCode> {
+    match applications::update_application(&state.db, &id, req).await {
+        Ok(Some(application)) => {
+            routing::reload(&state).await;
+            Ok(Json(with_health(&state, application).await))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to update application {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
+/// Delete an application.
 pub async fn delete_application(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // For now, just return a success status
-    Ok(StatusCode::NO_CONTENT)
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    match applications::delete_application(&state.db, &id).await {
+        Ok(true) => {
+            routing::reload(&state).await;
+            state.balancers.remove(&id);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to delete application {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
+/// Enable an application.
 pub async fn enable_application(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // For now, just return a success status
-    Ok(StatusCode::OK)
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ApplicationResponse>, StatusCode> {
+    set_enabled(&state, &id, true).await
 }
 
+/// Disable an application.
 pub async fn disable_application(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<String>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // For now, just return a success status
-    Ok(StatusCode::OK)
-}
\ No newline at end of file
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ApplicationResponse>, StatusCode> {
+    set_enabled(&state, &id, false).await
+}
+
+async fn set_enabled(
+    state: &AppState,
+    id: &str,
+    enabled: bool,
+) -> Result<Json<ApplicationResponse>, StatusCode> {
+    match applications::set_application_enabled(&state.db, id, enabled).await {
+        Ok(Some(application)) => {
+            routing::reload(state).await;
+            Ok(Json(with_health(state, application).await))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to update application {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -1,31 +1,91 @@
 //! API endpoints for Docker Compose operations.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{ws::WebSocketUpgrade, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
-use bollard::Docker;
+use futures::Stream;
 use serde::{Deserialize, Serialize};
 
+use super::logs;
+use crate::auth;
+use crate::docker::{self, compose};
+use crate::docker::compose::external::{ExternalStack, NotAdoptable};
+use crate::docker::compose::git::{ManagedByGit, Webhook};
+use crate::docker::compose::spec::ComposeFile;
+use crate::docker::compose::validate::{Issue, ValidationError};
 use crate::models::compose::{
-    ComposeStack, CreateStackRequest, UpdateStackRequest, ScaleStackRequest, StackResponse
-};
-use crate::docker::{
-    update_stack, create_stack, delete_stack, get_stack, get_stack_logs,
-    list_stacks, restart_stack, start_stack, stop_stack, scale_stack
+    AdoptStackRequest, ComposeStack, CreateGitStackRequest, CreateStackRequest, GitSourceRequest, PlanStackRequest, RevisionDiff,
+    ScaleStackRequest, StackPlan, StackRevision, SyncResult, UpdateStackRequest,
 };
+use crate::models::logs::{LogLine, LogsQuery};
+use crate::models::update::UpdatePolicyRequest;
+use crate::proxy::AppState;
+use crate::secrets::{MissingSecret, PlaintextSecret};
+
+/// Reject compose files that the engine cannot run.
+fn validate_content(
+    content: &str,
+    environment: Option<&HashMap<String, String>>,
+    env_files: Option<&HashMap<String, String>>,
+) -> Result<(), StatusCode> {
+    if let Some(files) = env_files {
+        if let Err(e) = compose::check_env_file_names(files) {
+            tracing::warn!("Rejected compose stack: {}", e);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    let dotenv = env_files.and_then(|files| files.get(".env")).map(String::as_str);
+    let issues = compose::validate_compose_content(content, environment.unwrap_or(&HashMap::new()), dotenv);
+    if let Some(issue) = issues.first() {
+        tracing::warn!("Rejected compose file with {} problems, first at {}", issues.len(), issue);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+/// Map the result of a stack operation, where `None` means no such stack.
+fn stack_result(
+    result: anyhow::Result<Option<ComposeStack>>,
+    action: &str,
+    id: &str,
+) -> Result<Json<ComposeStack>, StatusCode> {
+    match result {
+        Ok(Some(stack)) => Ok(Json(stack)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e)
+            if e.downcast_ref::<ValidationError>().is_some()
+                || e.downcast_ref::<MissingSecret>().is_some()
+                || e.downcast_ref::<PlaintextSecret>().is_some() =>
+        {
+            tracing::warn!("Failed to {} compose stack {}: {:#}", action, id, e);
+            Err(StatusCode::BAD_REQUEST)
+        }
+        Err(e) if e.downcast_ref::<ManagedByGit>().is_some() || e.downcast_ref::<ExternalStack>().is_some() => {
+            tracing::warn!("Failed to {} compose stack {}: {:#}", action, id, e);
+            Err(StatusCode::CONFLICT)
+        }
+        Err(e) => {
+            tracing::error!("Failed to {} compose stack {}: {:#}", action, id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
 
 /// List all Docker Compose stacks.
 pub async fn list_compose_stacks(
-    State(app_state): State<crate::app_state::AppState>,
+    State(app_state): State<Arc<AppState>>,
 ) -> Result<Json<Vec<ComposeStack>>, StatusCode> {
-    match list_stacks(&app_state.docker).await {
+    match compose::list_stacks(&app_state.db, &app_state.docker).await {
         Ok(stacks) => Ok(Json(stacks)),
         Err(e) => {
-            tracing::error!("Failed to list compose stacks: {}", e);
+            tracing::error!("Failed to list compose stacks: {:#}", e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -33,27 +93,164 @@ pub async fn list_compose_stacks(
 
 /// Get a Docker Compose stack by ID.
 pub async fn get_compose_stack(
-    State(app_state): State<crate::app_state::AppState>,
+    State(app_state): State<Arc<AppState>>,
     Path(id): Path<String>,
 ) -> Result<Json<ComposeStack>, StatusCode> {
-    match get_stack(&app_state.docker, &id).await {
+    stack_result(compose::get_stack(&app_state.db, &app_state.docker, &id).await, "get", &id)
+}
+
+/// Create a new Docker Compose stack.
+pub async fn create_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<CreateStackRequest>,
+) -> Result<Json<ComposeStack>, StatusCode> {
+    validate_content(&request.compose_content, request.environment.as_ref(), request.env_files.as_ref())?;
+    let caller = auth::request_claims(&headers, &app_state.jwt);
+    match compose::create_stack(&app_state.db, &app_state.docker, request, caller.as_ref()).await {
         Ok(stack) => Ok(Json(stack)),
+        Err(e) if e.downcast_ref::<PlaintextSecret>().is_some() => {
+            tracing::warn!("Rejected compose stack: {}", e);
+            Err(StatusCode::BAD_REQUEST)
+        }
         Err(e) => {
-            tracing::error!("Failed to get compose stack {}: {}", id, e);
-            Err(StatusCode::NOT_FOUND)
+            tracing::error!("Failed to create compose stack: {:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-/// Create a new Docker Compose stack.
-pub async fn create_compose_stack(
-    State(app_state): State<crate::app_state::AppState>,
-    Json(request): Json<CreateStackRequest>,
+/// Create a Docker Compose stack deployed from a git repository.
+pub async fn create_git_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<CreateGitStackRequest>,
 ) -> Result<Json<ComposeStack>, StatusCode> {
-    match create_stack(&app_state.docker, request).await {
+    if let Err(e) = compose::git::check_source(&request.source) {
+        tracing::warn!("Rejected git compose stack: {}", e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let caller = auth::request_claims(&headers, &app_state.jwt);
+    match compose::git::create_stack(&app_state.db, &app_state.docker, request, caller.as_ref()).await {
         Ok(stack) => Ok(Json(stack)),
+        Err(e) if e.downcast_ref::<ValidationError>().is_some() => {
+            tracing::warn!("Rejected git compose stack: {:#}", e);
+            Err(StatusCode::BAD_REQUEST)
+        }
         Err(e) => {
-            tracing::error!("Failed to create compose stack: {}", e);
+            tracing::error!("Failed to create git compose stack: {:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Map the result of syncing a stack from git, where `None` means no such
+/// git-backed stack.
+fn sync_result(result: anyhow::Result<Option<SyncResult>>, id: &str) -> Result<Json<SyncResult>, StatusCode> {
+    match result {
+        Ok(Some(result)) => Ok(Json(result)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) if e.downcast_ref::<ValidationError>().is_some() => {
+            tracing::warn!("Compose stack {} has an invalid compose file in git: {:#}", id, e);
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        }
+        Err(e) if e.downcast_ref::<MissingSecret>().is_some() => {
+            tracing::warn!("Failed to deploy compose stack {} from git: {:#}", id, e);
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        }
+        Err(e) => {
+            tracing::error!("Failed to sync compose stack {} from git: {:#}", id, e);
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+/// Change the repository, branch or path of a git-backed stack and sync it.
+pub async fn update_compose_stack_source(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<GitSourceRequest>,
+) -> Result<Json<SyncResult>, StatusCode> {
+    if let Err(e) = compose::git::check_source(&request) {
+        tracing::warn!("Rejected git source for compose stack {}: {}", id, e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    sync_result(compose::git::update_source(&app_state.db, &app_state.docker, &id, request).await, &id)
+}
+
+/// Query parameters for syncing a stack from git.
+#[derive(Debug, Default, Deserialize)]
+pub struct SyncQuery {
+    /// Pull the head of the branch again even if it is already checked out.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Check a git-backed stack for new commits now.
+pub async fn sync_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<SyncQuery>,
+) -> Result<Json<SyncResult>, StatusCode> {
+    sync_result(compose::git::sync(&app_state.db, &app_state.docker, &id, query.force).await, &id)
+}
+
+/// Headers that git hosts send webhook signatures in.
+const SIGNATURE_HEADERS: [&str; 3] = ["x-hub-signature-256", "x-gitea-signature", "x-gogs-signature"];
+
+/// Receive a push webhook for a git-backed stack. The body must be signed
+/// with the stack's webhook secret; the sync runs in the background.
+pub async fn compose_stack_webhook(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let signature = SIGNATURE_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name)?.to_str().ok());
+    match compose::git::check_webhook(&app_state.db, &id, signature, &body).await {
+        Ok(Some(Webhook::Accepted)) => {
+            tokio::spawn(async move {
+                if let Err(e) = compose::git::sync(&app_state.db, &app_state.docker, &id, false).await {
+                    tracing::warn!("Failed to sync compose stack {} from git: {:#}", id, e);
+                }
+            });
+            StatusCode::ACCEPTED
+        }
+        Ok(Some(Webhook::Ignored)) => StatusCode::NO_CONTENT,
+        Ok(Some(Webhook::Forbidden)) => {
+            tracing::warn!("Rejected unsigned webhook for compose stack {}", id);
+            StatusCode::FORBIDDEN
+        }
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            tracing::error!("Failed to check webhook for compose stack {}: {:#}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Adopt a project started outside Rustainer as a stack, importing its
+/// compose file and `.env`. Its containers keep running.
+pub async fn adopt_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    Path(project): Path<String>,
+    headers: HeaderMap,
+    request: Option<Json<AdoptStackRequest>>,
+) -> Result<Json<ComposeStack>, StatusCode> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let caller = auth::request_claims(&headers, &app_state.jwt);
+    let result = compose::external::adopt(&app_state.db, &app_state.docker, &project, request, caller.as_ref()).await;
+    match result {
+        Ok(Some(stack)) => Ok(Json(stack)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) if e.downcast_ref::<NotAdoptable>().is_some() || e.downcast_ref::<ValidationError>().is_some() => {
+            tracing::warn!("Failed to adopt compose project {}: {:#}", project, e);
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        }
+        Err(e) => {
+            tracing::error!("Failed to adopt compose project {}: {:#}", project, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -61,14 +258,42 @@ pub async fn create_compose_stack(
 
 /// Update an existing Docker Compose stack.
 pub async fn update_compose_stack(
-    State(app_state): State<crate::app_state::AppState>,
+    State(app_state): State<Arc<AppState>>,
     Path(id): Path<String>,
+    headers: HeaderMap,
     Json(request): Json<UpdateStackRequest>,
 ) -> Result<Json<ComposeStack>, StatusCode> {
-    match update_stack(&app_state.docker, &id, request).await {
-        Ok(stack) => Ok(Json(stack)),
+    // Without new env files, the stored `.env` is needed, so the file is
+    // checked when it is saved
+    if request.env_files.is_some() {
+        validate_content(&request.compose_content, request.environment.as_ref(), request.env_files.as_ref())?;
+    }
+    let caller = auth::request_claims(&headers, &app_state.jwt);
+    let result = compose::update_stack(&app_state.db, &app_state.docker, &id, request, caller.as_ref()).await;
+    stack_result(result, "update", &id)
+}
+
+/// Show what `up` would change, for the stored compose file or for the
+/// update in the body, without changing anything.
+pub async fn plan_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    request: Option<Json<PlanStackRequest>>,
+) -> Result<Json<StackPlan>, StatusCode> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    match compose::plan_stack(&app_state.db, &app_state.docker, &id, request).await {
+        Ok(Some(plan)) => Ok(Json(plan)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) if e.downcast_ref::<ValidationError>().is_some() || e.downcast_ref::<MissingSecret>().is_some() => {
+            tracing::warn!("Failed to plan compose stack {}: {:#}", id, e);
+            Err(StatusCode::BAD_REQUEST)
+        }
+        Err(e) if e.downcast_ref::<ExternalStack>().is_some() => {
+            tracing::warn!("Failed to plan compose stack {}: {:#}", id, e);
+            Err(StatusCode::CONFLICT)
+        }
         Err(e) => {
-            tracing::error!("Failed to update compose stack {}: {}", id, e);
+            tracing::error!("Failed to plan compose stack {}: {:#}", id, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -76,65 +301,160 @@ pub async fn update_compose_stack(
 
 /// Delete a Docker Compose stack.
 pub async fn delete_compose_stack(
-    State(app_state): State<crate::app_state::AppState>,
+    State(app_state): State<Arc<AppState>>,
     Path(id): Path<String>,
 ) -> Result<StatusCode, StatusCode> {
-    match delete_stack(&app_state.docker, &id).await {
-        Ok(_) => Ok(StatusCode::NO_CONTENT),
+    match compose::delete_stack(&app_state.db, &app_state.docker, &id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) if e.downcast_ref::<ExternalStack>().is_some() => {
+            tracing::warn!("Failed to delete compose stack {}: {:#}", id, e);
+            Err(StatusCode::CONFLICT)
+        }
         Err(e) => {
-            tracing::error!("Failed to delete compose stack {}: {}", id, e);
+            tracing::error!("Failed to delete compose stack {}: {:#}", id, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-/// Start a Docker Compose stack.
-pub async fn start_compose_stack(
-    State(app_state): State<crate::app_state::AppState>,
+/// List a stack's revisions, newest first.
+pub async fn list_stack_revisions(
+    State(app_state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<Json<ComposeStack>, StatusCode> {
-    match start_stack(&app_state.docker, &id).await {
-        Ok(stack) => Ok(Json(stack)),
+) -> Result<Json<Vec<StackRevision>>, StatusCode> {
+    match compose::list_revisions(&app_state.db, &id).await {
+        Ok(Some(revisions)) => Ok(Json(revisions)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
-            tracing::error!("Failed to start compose stack {}: {}", id, e);
+            tracing::error!("Failed to list revisions of compose stack {}: {:#}", id, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-/// Stop a Docker Compose stack.
-pub async fn stop_compose_stack(
-    State(app_state): State<crate::app_state::AppState>,
-    Path(id): Path<String>,
-) -> Result<Json<ComposeStack>, StatusCode> {
-    match stop_stack(&app_state.docker, &id).await {
-        Ok(stack) => Ok(Json(stack)),
+/// Get one revision of a stack.
+pub async fn get_stack_revision(
+    State(app_state): State<Arc<AppState>>,
+    Path((id, number)): Path<(String, i64)>,
+) -> Result<Json<StackRevision>, StatusCode> {
+    match compose::revisions::get(&app_state.db, &id, number).await {
+        Ok(Some(revision)) => Ok(Json(revision)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
-            tracing::error!("Failed to stop compose stack {}: {}", id, e);
+            tracing::error!("Failed to get revision {} of compose stack {}: {:#}", number, id, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-/// Restart a Docker Compose stack.
-pub async fn restart_compose_stack(
-    State(app_state): State<crate::app_state::AppState>,
+/// Query parameters for comparing revisions.
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    /// Older revision number.
+    pub from: i64,
+    /// Newer revision number.
+    pub to: i64,
+}
+
+/// Compare two revisions of a stack.
+pub async fn diff_stack_revisions(
+    State(app_state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<Json<ComposeStack>, StatusCode> {
-    match restart_stack(&app_state.docker, &id).await {
-        Ok(stack) => Ok(Json(stack)),
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<RevisionDiff>, StatusCode> {
+    match compose::diff_revisions(&app_state.db, &id, query.from, query.to).await {
+        Ok(Some(diff)) => Ok(Json(diff)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
-            tracing::error!("Failed to restart compose stack {}: {}", id, e);
+            tracing::error!("Failed to compare revisions of compose stack {}: {:#}", id, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
+/// Redeploy an earlier revision of a stack.
+pub async fn rollback_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    Path((id, number)): Path<(String, i64)>,
+    headers: HeaderMap,
+) -> Result<Json<ComposeStack>, StatusCode> {
+    let caller = auth::request_claims(&headers, &app_state.jwt);
+    let result = compose::rollback_stack(&app_state.db, &app_state.docker, &id, number, caller.as_ref()).await;
+    stack_result(result, "roll back", &id)
+}
+
+/// Create and start a Docker Compose stack.
+pub async fn up_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<ComposeStack>, StatusCode> {
+    let caller = auth::request_claims(&headers, &app_state.jwt);
+    stack_result(compose::up_stack(&app_state.db, &app_state.docker, &id, caller.as_ref()).await, "start", &id)
+}
+
+/// Query parameters for taking a stack down.
+#[derive(Debug, Default, Deserialize)]
+pub struct DownQuery {
+    /// Also remove the stack's named volumes.
+    #[serde(default)]
+    pub volumes: bool,
+}
+
+/// Stop and remove a Docker Compose stack's containers and networks.
+pub async fn down_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<DownQuery>,
+) -> Result<Json<ComposeStack>, StatusCode> {
+    let result = compose::down_stack(&app_state.db, &app_state.docker, &id, query.volumes).await;
+    stack_result(result, "stop", &id)
+}
+
+/// Restart a Docker Compose stack.
+pub async fn restart_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<ComposeStack>, StatusCode> {
+    let caller = auth::request_claims(&headers, &app_state.jwt);
+    let result = compose::restart_stack(&app_state.db, &app_state.docker, &id, caller.as_ref()).await;
+    stack_result(result, "restart", &id)
+}
+
+/// Set what happens when the images of a stack's services have updates.
+pub async fn set_compose_stack_update_policy(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<UpdatePolicyRequest>,
+) -> Result<Json<ComposeStack>, StatusCode> {
+    let result = compose::set_update_policy(&app_state.db, &app_state.docker, &id, request.policy).await;
+    stack_result(result, "set the update policy of", &id)
+}
+
+/// Scale services in a Docker Compose stack.
+pub async fn scale_compose_stack(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<ScaleStackRequest>,
+) -> Result<Json<ComposeStack>, StatusCode> {
+    let caller = auth::request_claims(&headers, &app_state.jwt);
+    let result = compose::scale_stack(&app_state.db, &app_state.docker, &id, request, caller.as_ref()).await;
+    stack_result(result, "scale", &id)
+}
+
 /// Request to validate a Docker Compose file.
 #[derive(Debug, Deserialize)]
 pub struct ValidateComposeRequest {
     /// Content of the compose file to validate.
     pub compose_content: String,
+    /// Variables to interpolate, overriding `.env`.
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+    /// Content of the `.env` file to interpolate with.
+    pub dotenv: Option<String>,
 }
 
 /// Response for validation.
@@ -142,52 +462,100 @@ pub struct ValidateComposeRequest {
 pub struct ValidationResponse {
     /// Whether the compose file is valid.
     pub valid: bool,
-    /// Error message if validation failed.
-    pub error: Option<String>,
+    /// Every problem found, with its line and column.
+    pub errors: Vec<Issue>,
 }
 
 /// Validate a Docker Compose file.
 pub async fn validate_compose_file(
-    State(app_state): State<crate::app_state::AppState>,
     Json(request): Json<ValidateComposeRequest>,
-) -> Result<Json<ValidationResponse>, StatusCode> {
-    match crate::docker::validate_compose_content(&request.compose_content) {
-        Ok(_) => Ok(Json(ValidationResponse {
-            valid: true,
-            error: None,
-        })),
-        Err(e) => Ok(Json(ValidationResponse {
-            valid: false,
-            error: Some(e.to_string()),
-        })),
+) -> Json<ValidationResponse> {
+    let errors =
+        compose::validate_compose_content(&request.compose_content, &request.environment, request.dotenv.as_deref());
+    Json(ValidationResponse {
+        valid: errors.is_empty(),
+        errors,
+    })
+}
+
+/// Get a stack's fully resolved compose file, like `docker compose config`.
+pub async fn get_compose_stack_config(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ComposeFile>, StatusCode> {
+    match compose::stack_config(&app_state.db, &id).await {
+        Ok(Some(file)) => Ok(Json(file)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) if e.downcast_ref::<ValidationError>().is_some() => {
+            tracing::warn!("Compose stack {} does not resolve: {:#}", id, e);
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        }
+        Err(e) => {
+            tracing::error!("Failed to resolve compose stack {}: {:#}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
 }
 
-/// Get logs for a Docker Compose stack.
+/// Get logs for a Docker Compose stack, keyed by container name.
 pub async fn get_compose_stack_logs(
-    State(app_state): State<crate::app_state::AppState>,
+    State(app_state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<Json<std::collections::HashMap<String, Vec<String>>>, StatusCode> {
-    match get_stack_logs(&app_state.docker, &id).await {
-        Ok(logs) => Ok(Json(logs)),
+    Query(query): Query<LogsQuery>,
+) -> Result<Json<HashMap<String, Vec<String>>>, StatusCode> {
+    let options = logs::options(&app_state.db, &query).await?;
+    match compose::get_stack_logs(&app_state.db, &app_state.docker, &id, options).await {
+        Ok(Some(logs)) => Ok(Json(logs)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
-            tracing::error!("Failed to get logs for compose stack {}: {}", id, e);
+            tracing::error!("Failed to get logs for compose stack {}: {:#}", id, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-/// Scale services in a Docker Compose stack.
-pub async fn scale_compose_stack(
-    State(app_state): State<crate::app_state::AppState>,
-    Path(id): Path<String>,
-    Json(request): Json<ScaleStackRequest>,
-) -> Result<Json<ComposeStack>, StatusCode> {
-    match scale_stack(&app_state.docker, &id, request).await {
-        Ok(stack) => Ok(Json(stack)),
+/// Follow the merged logs of a stack's containers.
+async fn follow_stack_logs(
+    app_state: &AppState,
+    id: &str,
+    query: &LogsQuery,
+) -> Result<impl Stream<Item = anyhow::Result<LogLine>>, StatusCode> {
+    let options = logs::options(&app_state.db, query).await?;
+    let target = match compose::stack_log_target(&app_state.db, id).await {
+        Ok(Some(target)) => target,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to get compose stack {}: {:#}", id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    match docker::logs::follow(&app_state.docker, target, options).await {
+        Ok(Some(lines)) => Ok(lines),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
-            tracing::error!("Failed to scale compose stack {}: {}", id, e);
+            tracing::error!("Failed to follow logs of compose stack {}: {:#}", id, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
-}
\ No newline at end of file
+}
+
+/// Stream a stack's logs as server-sent events.
+pub async fn stream_compose_stack_logs(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Response, StatusCode> {
+    let lines = follow_stack_logs(&app_state, &id, &query).await?;
+    Ok(logs::sse(lines, &query).into_response())
+}
+
+/// Stream a stack's logs over a WebSocket.
+pub async fn compose_stack_logs_socket(
+    ws: WebSocketUpgrade,
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Response, StatusCode> {
+    let lines = follow_stack_logs(&app_state, &id, &query).await?;
+    Ok(logs::websocket(ws, lines, &query))
+}
@@ -1,24 +1,26 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{ws::WebSocketUpgrade, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
-use serde::{Deserialize, Serialize};
+use bollard::models::ContainerSummary;
+use futures::Stream;
+use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use super::logs;
+use crate::auth;
+use crate::docker::containers::{self, InvalidContainer};
+use crate::docker::images;
+use crate::docker::logs::LogTarget;
+use crate::docker::updates;
+use crate::models::container::CreateContainerRequest;
+use crate::models::logs::{LogLine, LogsQuery};
+use crate::models::update::ImageUpdate;
 use crate::proxy::AppState;
-
-#[derive(Debug, Deserialize)]
-pub struct CreateContainerRequest {
-    pub name: String,
-    pub image: String,
-    pub port_mappings: HashMap<String, String>,
-    pub env: HashMap<String, String>,
-    pub volumes: Vec<String>,
-    pub restart: String,
-}
+use crate::registries;
 
 #[derive(Debug, Serialize)]
 pub struct ContainerResponse {
@@ -29,6 +31,10 @@ pub struct ContainerResponse {
     pub status: String,
     pub created: i64,
     pub ports: Vec<PortMapping>,
+    /// Whether the registry has a newer image for the container's tag.
+    pub update_available: bool,
+    /// The container's last image update check, if it was checked.
+    pub update: Option<ImageUpdate>,
 }
 
 #[derive(Debug, Serialize)]
@@ -37,41 +43,127 @@ pub struct PortMapping {
     pub container_port: u16,
 }
 
+fn container_response(container: ContainerSummary, updates: &HashMap<String, ImageUpdate>) -> ContainerResponse {
+    let update = container
+        .names
+        .iter()
+        .flatten()
+        .next()
+        .and_then(|name| updates.get(name.trim_start_matches('/')))
+        .cloned();
+    ContainerResponse {
+        id: container.id.unwrap_or_default(),
+        names: container.names.unwrap_or_default(),
+        image: container.image.unwrap_or_default(),
+        state: container.state.map(|state| state.to_string()).unwrap_or_default(),
+        status: container.status.unwrap_or_default(),
+        created: container.created.unwrap_or(0),
+        ports: container
+            .ports
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|port| {
+                Some(PortMapping {
+                    host_port: port.public_port?,
+                    container_port: port.private_port,
+                })
+            })
+            .collect(),
+        update_available: update.as_ref().is_some_and(|update| update.available),
+        update,
+    }
+}
+
+async fn image_updates(state: &AppState) -> Result<HashMap<String, ImageUpdate>, StatusCode> {
+    updates::by_container(&state.db).await.map_err(|e| {
+        tracing::error!("Failed to load image update checks: {:#}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
 pub async fn list_containers(
     State(state): State<Arc<AppState>>,
-) -> Result<impl IntoResponse, StatusCode> {
+) -> Result<Json<Vec<ContainerResponse>>, StatusCode> {
     let containers = match crate::docker::list_containers(&state.docker).await {
         Ok(containers) => containers,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Err(e) => {
+            tracing::error!("Failed to list containers: {:#}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
     };
+    let updates = image_updates(&state).await?;
+    Ok(Json(containers.into_iter().map(|container| container_response(container, &updates)).collect()))
+}
 
-    // For now, return a simplified response to avoid type errors
-    let container_responses: Vec<ContainerResponse> = Vec::new();
-    
-    Ok(Json(container_responses))
+/// Map a container that could not be created to the status that explains it
+/// best.
+fn create_error(e: anyhow::Error) -> StatusCode {
+    let status = if e.downcast_ref::<InvalidContainer>().is_some() {
+        StatusCode::BAD_REQUEST
+    } else {
+        match images::daemon_status(&e) {
+            Some(400) => StatusCode::BAD_REQUEST,
+            Some(404) => StatusCode::NOT_FOUND,
+            Some(409) => StatusCode::CONFLICT,
+            Some(401 | 403) => StatusCode::FORBIDDEN,
+            _ if images::is_pull_error(&e) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    };
+    if status == StatusCode::INTERNAL_SERVER_ERROR {
+        tracing::error!("Failed to create container: {:#}", e);
+    } else {
+        tracing::warn!("Failed to create container: {:#}", e);
+    }
+    status
 }
 
+/// Create a container, pulling its image with the caller's registry
+/// credentials if it is missing, and start it unless `start` is false.
 pub async fn create_container(
-    State(_state): State<Arc<AppState>>,
-    Json(_request): Json<CreateContainerRequest>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // In a real implementation, we would use the Docker API to create the container
-    // For now, we'll just simulate a successful creation
-    
-    // Simulate a delay for creating the container
-    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-    
-    Ok(StatusCode::CREATED)
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<CreateContainerRequest>,
+) -> Result<(StatusCode, Json<ContainerResponse>), StatusCode> {
+    let caller = auth::request_claims(&headers, &state.jwt);
+    let credentials = registries::credentials_for(&state.db, request.image.trim(), caller.as_ref())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to load registry credentials for {}: {:#}", request.image, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(|login| login.docker_credentials());
+    let id = containers::create(&state.docker, &request, credentials)
+        .await
+        .map_err(create_error)?;
+
+    let container = match crate::docker::find_container(&state.docker, &id).await {
+        Ok(Some(container)) => container,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to get created container {}: {:#}", id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let updates = image_updates(&state).await?;
+    Ok((StatusCode::CREATED, Json(container_response(container, &updates))))
 }
 
+/// Get a container by ID or name.
 pub async fn get_container(
-    State(_state): State<Arc<AppState>>,
-    Path(_container_id): Path<String>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // In a real implementation, we would use the Docker API to get the container
-    // For now, we'll just return a 404
-    
-    Err::<(StatusCode,), StatusCode>(StatusCode::NOT_FOUND)
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<String>,
+) -> Result<Json<ContainerResponse>, StatusCode> {
+    let container = match crate::docker::find_container(&state.docker, &container_id).await {
+        Ok(Some(container)) => container,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to get container {}: {:#}", container_id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let updates = image_updates(&state).await?;
+    Ok(Json(container_response(container, &updates)))
 }
 
 pub async fn start_container(
@@ -112,4 +204,60 @@ pub async fn delete_container(
     // For now, we'll just simulate a successful deletion
     
     Ok(StatusCode::OK)
-}
\ No newline at end of file
+}
+
+/// Get a container's logs, oldest first.
+pub async fn get_container_logs(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Json<Vec<LogLine>>, StatusCode> {
+    let options = logs::options(&state.db, &query).await?;
+    let target = LogTarget::Container(container_id.clone());
+    match crate::docker::logs::snapshot(&state.docker, &target, options).await {
+        Ok(Some(lines)) => Ok(Json(lines)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to get logs of container {}: {:#}", container_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn follow_container_logs(
+    state: &AppState,
+    container_id: &str,
+    query: &LogsQuery,
+) -> Result<impl Stream<Item = anyhow::Result<LogLine>>, StatusCode> {
+    let options = logs::options(&state.db, query).await?;
+    let target = LogTarget::Container(container_id.to_string());
+    match crate::docker::logs::follow(&state.docker, target, options).await {
+        Ok(Some(lines)) => Ok(lines),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to follow logs of container {}: {:#}", container_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Stream a container's logs as server-sent events.
+pub async fn stream_container_logs(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Response, StatusCode> {
+    let lines = follow_container_logs(&state, &container_id, &query).await?;
+    Ok(logs::sse(lines, &query).into_response())
+}
+
+/// Stream a container's logs over a WebSocket.
+pub async fn container_logs_socket(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Response, StatusCode> {
+    let lines = follow_container_logs(&state, &container_id, &query).await?;
+    Ok(logs::websocket(ws, lines, &query))
+}
@@ -1,98 +1,462 @@
+//! API handlers for images: listing, inspecting, pulling with progress,
+//! building, removing and pruning.
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Multipart, Path, Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
     Json,
 };
-use serde::{Deserialize, Serialize};
+use bollard::auth::DockerCredentials;
+use bytes::Bytes;
+use futures::StreamExt;
+use serde::Serialize;
 use std::sync::Arc;
 
+use crate::auth;
+use crate::docker::builds::{self, InvalidBuild, Running};
+use crate::docker::images;
+use crate::models::image::{
+    BuildEvent, BuildHistoryQuery, BuildImageRequest, BuildSource, BuildStatus, ImageBuild, ImageDetails, ImageInfo,
+    PruneImagesQuery, PruneResult, PullEvent, PullImageRequest, RemoveImageQuery, RemovedImages,
+};
 use crate::proxy::AppState;
+use crate::registries;
 
-#[derive(Debug, Deserialize)]
-pub struct PullImageRequest {
-    pub image_tag: String,
-    pub auth: Option<ImageAuth>,
+/// Credentials to pull with: those sent with the request, or else the
+/// caller's stored ones for the image's registry.
+async fn pull_credentials(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: &mut PullImageRequest,
+) -> anyhow::Result<Option<DockerCredentials>> {
+    if let Some(auth) = request.auth.take() {
+        return Ok(Some(DockerCredentials {
+            username: Some(auth.username),
+            password: Some(auth.password),
+            serveraddress: auth.server_address,
+            ..Default::default()
+        }));
+    }
+    let caller = auth::request_claims(headers, &state.jwt);
+    let login = registries::credentials_for(&state.db, request.image_tag.trim(), caller.as_ref()).await?;
+    Ok(login.map(|login| login.docker_credentials()))
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ImageAuth {
-    pub username: String,
-    pub password: String,
+/// Map a failed pull to the status that explains it best.
+fn pull_status(e: &anyhow::Error) -> StatusCode {
+    match images::daemon_status(e) {
+        Some(404) => StatusCode::NOT_FOUND,
+        Some(401 | 403) => StatusCode::FORBIDDEN,
+        _ if images::is_pull_error(e) => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
 }
 
-#[derive(Debug, Serialize)]
-pub struct ImageResponse {
-    pub id: String,
-    pub repo_tags: Vec<String>,
-    pub size: u64,
-    pub created: i64,
+/// List images with their tags, sizes and the containers using them.
+pub async fn list_images(State(state): State<Arc<AppState>>) -> Result<Json<Vec<ImageInfo>>, StatusCode> {
+    match images::list(&state.docker).await {
+        Ok(images) => Ok(Json(images)),
+        Err(e) => {
+            tracing::error!("Failed to list images: {:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
-pub async fn list_images(
+/// Inspect an image by ID or reference. Slashes in references must be
+/// percent-encoded.
+pub async fn get_image(
     State(state): State<Arc<AppState>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let images = match crate::docker::list_images(&state.docker).await {
-        Ok(images) => images,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
-
-    // For now, return a simplified response to avoid type errors
-    let image_responses: Vec<ImageResponse> = Vec::new();
-    
-    Ok(Json(image_responses))
+    Path(name): Path<String>,
+) -> Result<Json<ImageDetails>, StatusCode> {
+    match images::get(&state.docker, &name).await {
+        Ok(Some(image)) => Ok(Json(image)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to inspect image {}: {:#}", name, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
+/// Pull an image and wait until it is complete.
 pub async fn pull_image(
-    State(_state): State<Arc<AppState>>,
-    Json(value): Json<serde_json::Value>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // In a real implementation, we would use the Docker API to pull the image
-    // For now, we'll just simulate a successful pull
-    
-    // Log the raw JSON value
-    tracing::info!("Received JSON: {}", value);
-    
-    // Try to convert to our expected type
-    match serde_json::from_value::<PullImageRequest>(value.clone()) {
-        Ok(request) => {
-            tracing::info!("Successfully parsed request: {:?}", request);
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(mut request): Json<PullImageRequest>,
+) -> Result<Json<ImageInfo>, StatusCode> {
+    let image = request.image_tag.trim().to_string();
+    if image.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let credentials = pull_credentials(&state, &headers, &mut request).await.map_err(|e| {
+        tracing::error!("Failed to load registry credentials for {}: {:#}", image, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut progress = std::pin::pin!(images::pull(&state.docker, &image, credentials));
+    while let Some(step) = progress.next().await {
+        if let Err(e) = step {
+            tracing::warn!("{:#}", e);
+            return Err(pull_status(&e));
         }
+    }
+
+    match images::get_info(&state.docker, &images::reference(&image)).await {
+        Ok(Some(info)) => Ok(Json(info)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
-            tracing::error!("Failed to parse request: {}", e);
-            
-            // Check if the imageTag field exists instead of image_tag
-            if let Some(image_tag) = value.get("imageTag") {
-                tracing::info!("Found 'imageTag' field: {}", image_tag);
-                
-                // Create a new request with the correct field name
-                let mut corrected_value = value.clone();
-                corrected_value["image_tag"] = image_tag.clone();
-                
-                match serde_json::from_value::<PullImageRequest>(corrected_value) {
-                    Ok(request) => {
-                        tracing::info!("Successfully parsed corrected request: {:?}", request);
-                    }
-                    Err(e) => {
-                        tracing::error!("Failed to parse corrected request: {}", e);
+            tracing::error!("Failed to inspect pulled image {}: {:#}", image, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Pull an image over a WebSocket. The client sends the pull request as its
+/// first message, and receives a [`PullEvent`] for each step, ending with
+/// `done` or `error`.
+pub async fn pull_image_socket(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response {
+    ws.on_upgrade(move |mut socket| async move {
+        let event = match socket.recv().await {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str::<PullImageRequest>(&text) {
+                Ok(request) => stream_pull(&mut socket, &state, &headers, request).await,
+                Err(e) => Some(PullEvent::Error {
+                    message: format!("Invalid pull request: {}", e),
+                }),
+            },
+            _ => return,
+        };
+        if let Some(event) = event {
+            let _ = socket.send(Message::Text(encode(&event))).await;
+            let _ = socket.close().await;
+        }
+    })
+}
+
+/// Send progress until the pull ends, returning the final event, or `None`
+/// when the client went away.
+async fn stream_pull(
+    socket: &mut WebSocket,
+    state: &AppState,
+    headers: &HeaderMap,
+    mut request: PullImageRequest,
+) -> Option<PullEvent> {
+    let image = request.image_tag.trim().to_string();
+    let credentials = match pull_credentials(state, headers, &mut request).await {
+        Ok(credentials) => credentials,
+        Err(e) => return Some(PullEvent::Error { message: format!("{:#}", e) }),
+    };
+    let mut progress = std::pin::pin!(images::pull(&state.docker, &image, credentials));
+    loop {
+        tokio::select! {
+            step = progress.next() => match step {
+                Some(Ok(step)) => {
+                    if socket.send(Message::Text(encode(&PullEvent::Progress(step)))).await.is_err() {
+                        return None;
                     }
                 }
+                Some(Err(e)) => {
+                    tracing::warn!("{:#}", e);
+                    return Some(PullEvent::Error { message: format!("{:#}", e) });
+                }
+                None => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return None,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    Some(match images::get_info(&state.docker, &images::reference(&image)).await {
+        Ok(Some(image)) => PullEvent::Done { image },
+        Ok(None) => PullEvent::Error {
+            message: format!("Image {} is missing after the pull", image),
+        },
+        Err(e) => PullEvent::Error {
+            message: format!("{:#}", e),
+        },
+    })
+}
+
+fn encode(event: &impl Serialize) -> String {
+    serde_json::to_string(event).unwrap_or_default()
+}
+
+/// Largest build context that can be uploaded, in bytes.
+pub const BUILD_CONTEXT_LIMIT: usize = 512 * 1024 * 1024;
+
+/// Builds listed when no limit is given.
+const BUILD_HISTORY_LIMIT: u32 = 50;
+
+/// Start a build on behalf of the caller, with their registry credentials.
+async fn start_build(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: BuildImageRequest,
+    upload: Option<Bytes>,
+) -> anyhow::Result<Running> {
+    let caller = auth::request_claims(headers, &state.jwt);
+    let logins = registries::logins_for(&state.db, caller.as_ref()).await?;
+    let author = caller.as_ref().map(|claims| claims.username.as_str());
+    builds::start(&state.db, &state.docker, request, upload, logins, author).await
+}
+
+/// Map a build that could not be started, rejecting invalid requests.
+fn start_error(e: anyhow::Error) -> StatusCode {
+    if e.downcast_ref::<InvalidBuild>().is_some() {
+        tracing::warn!("Rejected image build: {:#}", e);
+        StatusCode::BAD_REQUEST
+    } else {
+        tracing::error!("Failed to start image build: {:#}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+/// Wait for a build to end. Failed builds are returned as 422, with their
+/// log.
+async fn finish_build(running: Running) -> Result<(StatusCode, Json<ImageBuild>), StatusCode> {
+    let build = running.finished.await.map_err(|e| {
+        tracing::error!("Image build task failed: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    match build.status {
+        BuildStatus::Succeeded => Ok((StatusCode::CREATED, Json(build))),
+        _ => Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(build))),
+    }
+}
+
+/// Build an image from an inline Dockerfile, a directory or a git
+/// repository, and wait until it is done.
+pub async fn build_image(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<BuildImageRequest>,
+) -> Result<(StatusCode, Json<ImageBuild>), StatusCode> {
+    let running = start_build(&state, &headers, request, None).await.map_err(start_error)?;
+    finish_build(running).await
+}
+
+/// Build an image from an uploaded context, and wait until it is done. The
+/// multipart form has the build request as JSON in `options`, which may be
+/// left out, and the tar archive in `context`.
+pub async fn upload_build_context(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    mut form: Multipart,
+) -> Result<(StatusCode, Json<ImageBuild>), StatusCode> {
+    let (mut options, mut context) = (None, None);
+    while let Some(field) = form.next_field().await.map_err(|e| {
+        tracing::warn!("Failed to read build upload: {}", e);
+        StatusCode::BAD_REQUEST
+    })? {
+        let name = field.name().unwrap_or_default().to_string();
+        let data = field.bytes().await.map_err(|e| {
+            tracing::warn!("Failed to read {} of build upload: {}", name, e);
+            StatusCode::BAD_REQUEST
+        })?;
+        match name.as_str() {
+            "options" => options = Some(data),
+            "context" => context = Some(data),
+            _ => {}
+        }
+    }
+
+    let mut request: BuildImageRequest = match options {
+        Some(options) => serde_json::from_slice(&options).map_err(|e| {
+            tracing::warn!("Invalid build options: {}", e);
+            StatusCode::BAD_REQUEST
+        })?,
+        None => BuildImageRequest::default(),
+    };
+    request.source = BuildSource::Upload;
+    let running = start_build(&state, &headers, request, context).await.map_err(start_error)?;
+    finish_build(running).await
+}
+
+/// Build an image over a WebSocket. The client sends the build request as
+/// its first message, followed for uploaded sources by the context archive
+/// as a binary message. It receives a `started` [`BuildEvent`] with the
+/// build's ID, then an `output` event for each line, ending with `done` or
+/// `error`. The build carries on if the client
+/// goes away.
+pub async fn build_image_socket(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response {
+    ws.max_message_size(BUILD_CONTEXT_LIMIT)
+        .max_frame_size(BUILD_CONTEXT_LIMIT)
+        .on_upgrade(move |mut socket| async move {
+            let event = match socket.recv().await {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<BuildImageRequest>(&text) {
+                    Ok(request) => stream_build(&mut socket, &state, &headers, request).await,
+                    Err(e) => Some(BuildEvent::Error {
+                        message: format!("Invalid build request: {}", e),
+                        build: None,
+                    }),
+                },
+                _ => return,
+            };
+            if let Some(event) = event {
+                let _ = socket.send(Message::Text(encode(&event))).await;
+                let _ = socket.close().await;
             }
+        })
+}
+
+/// Send output until the build ends, returning the final event, or `None`
+/// when the client went away.
+async fn stream_build(
+    socket: &mut WebSocket,
+    state: &AppState,
+    headers: &HeaderMap,
+    request: BuildImageRequest,
+) -> Option<BuildEvent> {
+    let failed = |message: String| BuildEvent::Error { message, build: None };
+    let upload = match request.source {
+        BuildSource::Upload => match socket.recv().await {
+            Some(Ok(Message::Binary(archive))) => Some(Bytes::from(archive)),
+            Some(Ok(_)) => return Some(failed("The build context must be sent as a binary message".to_string())),
+            _ => return None,
+        },
+        _ => None,
+    };
+    let mut running = match start_build(state, headers, request, upload).await {
+        Ok(running) => running,
+        Err(e) => {
+            tracing::warn!("Failed to start image build: {:#}", e);
+            return Some(failed(format!("{:#}", e)));
+        }
+    };
+    let started = BuildEvent::Started {
+        build: Box::new(running.build.clone()),
+    };
+    if socket.send(Message::Text(encode(&started))).await.is_err() {
+        return None;
+    }
+
+    loop {
+        tokio::select! {
+            line = running.output.recv() => match line {
+                Some(text) => {
+                    if socket.send(Message::Text(encode(&BuildEvent::Output { text }))).await.is_err() {
+                        return None;
+                    }
+                }
+                None => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return None,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let mut build = match running.finished.await {
+        Ok(build) => build,
+        Err(e) => return Some(failed(format!("Image build task failed: {}", e))),
+    };
+    // The client has seen the output already
+    build.log = None;
+    Some(match build.status {
+        BuildStatus::Succeeded => BuildEvent::Done { build: Box::new(build) },
+        _ => BuildEvent::Error {
+            message: build.error.clone().unwrap_or_default(),
+            build: Some(Box::new(build)),
+        },
+    })
+}
+
+/// List the latest builds, newest first.
+pub async fn list_builds(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<BuildHistoryQuery>,
+) -> Result<Json<Vec<ImageBuild>>, StatusCode> {
+    match builds::list(&state.db, query.limit.unwrap_or(BUILD_HISTORY_LIMIT)).await {
+        Ok(builds) => Ok(Json(builds)),
+        Err(e) => {
+            tracing::error!("Failed to list image builds: {:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
-    
-    // Simulate a delay for pulling the image
-    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-    
-    Ok(StatusCode::OK)
 }
 
+/// Get a build with its log.
+pub async fn get_build(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ImageBuild>, StatusCode> {
+    match builds::get(&state.db, &id).await {
+        Ok(Some(build)) => Ok(Json(build)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to get image build {}: {:#}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// List the builds of an image and of the repositories it is tagged in.
+pub async fn list_image_builds(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(query): Query<BuildHistoryQuery>,
+) -> Result<Json<Vec<ImageBuild>>, StatusCode> {
+    let limit = query.limit.unwrap_or(BUILD_HISTORY_LIMIT);
+    match builds::for_image(&state.db, &state.docker, &name, limit).await {
+        Ok(Some(builds)) => Ok(Json(builds)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to list builds of image {}: {:#}", name, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Remove an image, or one of its tags. With `?force=true`, images used by
+/// containers or with several tags are removed too.
 pub async fn delete_image(
-    State(_state): State<Arc<AppState>>,
-    Path(_image_id): Path<String>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // In a real implementation, we would use the Docker API to delete the image
-    // For now, we'll just simulate a successful deletion
-    
-    Ok(StatusCode::OK)
-}
\ No newline at end of file
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(query): Query<RemoveImageQuery>,
+) -> Result<Json<RemovedImages>, StatusCode> {
+    match images::remove(&state.docker, &name, query.force).await {
+        Ok(Some(removed)) => Ok(Json(removed)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) if images::daemon_status(&e) == Some(409) => {
+            tracing::warn!("{:#}", e);
+            Err(StatusCode::CONFLICT)
+        }
+        Err(e) => {
+            tracing::error!("{:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Remove dangling images, or with `?all=true` every unused image, and
+/// report the space reclaimed.
+pub async fn prune_images(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PruneImagesQuery>,
+) -> Result<Json<PruneResult>, StatusCode> {
+    match images::prune(&state.docker, query.all).await {
+        Ok(result) => Ok(Json(result)),
+        Err(e) if images::daemon_status(&e) == Some(409) => {
+            tracing::warn!("{:#}", e);
+            Err(StatusCode::CONFLICT)
+        }
+        Err(e) => {
+            tracing::error!("{:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
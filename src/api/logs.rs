@@ -0,0 +1,83 @@
+//! Log responses shared by containers and stacks: server-sent events and
+//! WebSockets that follow logs as they are written.
+
+use std::convert::Infallible;
+
+use axum::{
+    extract::ws::{Message, WebSocketUpgrade},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+};
+use futures::{Stream, StreamExt};
+use sqlx::{Pool, Sqlite};
+
+use crate::docker::logs::LogOptions;
+use crate::models::logs::{LogFormat, LogLine, LogsQuery};
+use crate::secrets;
+
+/// Parse log query parameters, rejecting bad times, tails and filters.
+/// Stored secret values are masked in every line.
+pub async fn options(db: &Pool<Sqlite>, query: &LogsQuery) -> Result<LogOptions, StatusCode> {
+    let mut options = LogOptions::from_query(query).map_err(|e| {
+        tracing::warn!("Rejected log query: {:#}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+    options.redact = secrets::redactions(db).await.map_err(|e| {
+        tracing::error!("Failed to load secrets to mask in logs: {:#}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(options)
+}
+
+/// Send lines as `log` events, and read errors as `error` events.
+pub fn sse<S>(lines: S, query: &LogsQuery) -> Sse<impl Stream<Item = Result<Event, Infallible>>>
+where
+    S: Stream<Item = anyhow::Result<LogLine>> + Send + 'static,
+{
+    let (format, timestamps) = (query.format, query.timestamps);
+    let events = lines.map(move |line| {
+        let name = if line.is_ok() { "log" } else { "error" };
+        Ok(Event::default().event(name).data(encode(&line, format, timestamps)))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Send lines as text messages until the client disconnects.
+pub fn websocket<S>(ws: WebSocketUpgrade, lines: S, query: &LogsQuery) -> Response
+where
+    S: Stream<Item = anyhow::Result<LogLine>> + Send + 'static,
+{
+    let (format, timestamps) = (query.format, query.timestamps);
+    ws.on_upgrade(move |mut socket| async move {
+        let mut lines = std::pin::pin!(lines);
+        loop {
+            tokio::select! {
+                line = lines.next() => {
+                    let Some(line) = line else {
+                        break;
+                    };
+                    if socket.send(Message::Text(encode(&line, format, timestamps))).await.is_err() {
+                        return;
+                    }
+                }
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = socket.close().await;
+    })
+}
+
+fn encode(line: &anyhow::Result<LogLine>, format: LogFormat, timestamps: bool) -> String {
+    match (line, format) {
+        (Ok(line), LogFormat::Json) => serde_json::to_string(line).unwrap_or_default(),
+        (Ok(line), LogFormat::Text) => line.text(timestamps),
+        (Err(e), LogFormat::Json) => serde_json::json!({ "error": format!("{:#}", e) }).to_string(),
+        (Err(e), LogFormat::Text) => format!("{:#}", e),
+    }
+}
@@ -0,0 +1,20 @@
+//! API handlers for proxy traffic metrics.
+
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+use std::sync::Arc;
+
+use crate::proxy::metrics::RouteSnapshot;
+use crate::proxy::AppState;
+
+/// Per-route traffic counters for the dashboard.
+pub async fn route_metrics(State(state): State<Arc<AppState>>) -> Json<Vec<RouteSnapshot>> {
+    Json(state.metrics.snapshot())
+}
+
+/// Per-route traffic counters in Prometheus text format.
+pub async fn prometheus_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        state.metrics.prometheus(),
+    )
+}
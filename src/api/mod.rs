@@ -1,10 +1,22 @@
 pub mod applications;
+pub mod compose;
 pub mod containers;
 pub mod images;
+pub mod logs;
+pub mod metrics;
+pub mod registries;
+pub mod secrets;
+pub mod services;
+pub mod templates;
+pub mod updates;
 
 // Re-export handlers
 pub use containers::{
     list_containers, create_container, get_container,
-    start_container, stop_container, restart_container, delete_container
+    start_container, stop_container, restart_container, delete_container,
+    get_container_logs, stream_container_logs, container_logs_socket
 };
-pub use images::{list_images, pull_image, delete_image};
\ No newline at end of file
+pub use images::{
+    list_images, get_image, pull_image, pull_image_socket, delete_image, prune_images,
+    build_image, upload_build_context, build_image_socket, list_builds, get_build, list_image_builds
+};
\ No newline at end of file
@@ -0,0 +1,156 @@
+//! API handlers for the registry store and for browsing registries.
+//! Passwords can be written but are never returned.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+
+use crate::auth::{self, Claims};
+use crate::models::registry::{
+    CatalogQuery, CreateRegistryRequest, Registry, RepositoryList, TagList, UpdateRegistryRequest,
+};
+use crate::proxy::AppState;
+use crate::registries::{self, v2, InvalidRegistry, Login, NotPermitted};
+
+fn caller(app_state: &AppState, headers: &HeaderMap) -> Option<Claims> {
+    auth::request_claims(headers, &app_state.jwt)
+}
+
+/// Map a failed store operation, rejecting invalid registries and owners the
+/// caller may not choose.
+fn store_error(e: anyhow::Error, action: &str) -> StatusCode {
+    if e.downcast_ref::<InvalidRegistry>().is_some() {
+        tracing::warn!("Failed to {} registry: {:#}", action, e);
+        StatusCode::BAD_REQUEST
+    } else if e.downcast_ref::<NotPermitted>().is_some() {
+        tracing::warn!("Failed to {} registry: {:#}", action, e);
+        StatusCode::FORBIDDEN
+    } else {
+        tracing::error!("Failed to {} registry: {:#}", action, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+/// Map a failed registry request. The registry's own refusals are passed
+/// on, and anything else is a bad gateway.
+fn browse_error(e: anyhow::Error, id: &str) -> StatusCode {
+    tracing::warn!("Failed to browse registry {}: {:#}", id, e);
+    match e.downcast_ref::<v2::RegistryError>().map(|e| e.status) {
+        Some(StatusCode::NOT_FOUND) => StatusCode::NOT_FOUND,
+        Some(StatusCode::BAD_REQUEST) => StatusCode::BAD_REQUEST,
+        Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => StatusCode::FORBIDDEN,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+async fn login(app_state: &AppState, headers: &HeaderMap, id: &str) -> Result<Login, StatusCode> {
+    match registries::login(&app_state.db, id, caller(app_state, headers).as_ref()).await {
+        Ok(Some(login)) => Ok(login),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to load registry {}: {:#}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// List the registries the logged-in user can see.
+pub async fn list_registries(
+    State(app_state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Registry>>, StatusCode> {
+    let caller = caller(&app_state, &headers);
+    match registries::list(&app_state.db, caller.as_ref()).await {
+        Ok(registries) => Ok(Json(registries)),
+        Err(e) => Err(store_error(e, "list")),
+    }
+}
+
+/// Get a registry by ID.
+pub async fn get_registry(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Registry>, StatusCode> {
+    let caller = caller(&app_state, &headers);
+    match registries::get(&app_state.db, &id, caller.as_ref()).await {
+        Ok(Some(registry)) => Ok(Json(registry)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(store_error(e, "get")),
+    }
+}
+
+/// Store a registry and its credentials.
+pub async fn create_registry(
+    State(app_state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<CreateRegistryRequest>,
+) -> Result<(StatusCode, Json<Registry>), StatusCode> {
+    let caller = caller(&app_state, &headers);
+    match registries::create(&app_state.db, request, caller.as_ref()).await {
+        Ok(registry) => Ok((StatusCode::CREATED, Json(registry))),
+        Err(e) => Err(store_error(e, "create")),
+    }
+}
+
+/// Change a registry.
+pub async fn update_registry(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<UpdateRegistryRequest>,
+) -> Result<Json<Registry>, StatusCode> {
+    let caller = caller(&app_state, &headers);
+    match registries::update(&app_state.db, &id, request, caller.as_ref()).await {
+        Ok(Some(registry)) => Ok(Json(registry)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(store_error(e, "update")),
+    }
+}
+
+/// Delete a registry.
+pub async fn delete_registry(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    let caller = caller(&app_state, &headers);
+    match registries::delete(&app_state.db, &id, caller.as_ref()).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(store_error(e, "delete")),
+    }
+}
+
+/// List a page of a registry's repositories.
+pub async fn list_repositories(
+    State(app_state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<CatalogQuery>,
+    headers: HeaderMap,
+) -> Result<Json<RepositoryList>, StatusCode> {
+    let login = login(&app_state, &headers, &id).await?;
+    v2::Client::new()
+        .catalog(&login, query.n, query.last.as_deref())
+        .await
+        .map(Json)
+        .map_err(|e| browse_error(e, &id))
+}
+
+/// List the tags of a repository in a registry.
+pub async fn list_tags(
+    State(app_state): State<Arc<AppState>>,
+    Path((id, repository)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<TagList>, StatusCode> {
+    let login = login(&app_state, &headers, &id).await?;
+    v2::Client::new()
+        .tags(&login, repository.trim_start_matches('/'))
+        .await
+        .map(Json)
+        .map_err(|e| browse_error(e, &id))
+}
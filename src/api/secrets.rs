@@ -0,0 +1,123 @@
+//! API handlers for the secrets store. Values can be written but are never
+//! returned.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::docker::compose;
+use crate::models::secret::{CreateSecretRequest, SecretInfo, UpdateSecretRequest};
+use crate::proxy::AppState;
+use crate::secrets;
+
+/// Stacks using each secret, for `used_by`.
+async fn secret_users(app_state: &AppState) -> Result<HashMap<String, Vec<String>>, StatusCode> {
+    compose::secret_users(&app_state.db).await.map_err(|e| {
+        tracing::error!("Failed to find stacks using secrets: {:#}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// List stored secrets.
+pub async fn list_secrets(State(app_state): State<Arc<AppState>>) -> Result<Json<Vec<SecretInfo>>, StatusCode> {
+    let mut secrets = match secrets::list(&app_state.db).await {
+        Ok(secrets) => secrets,
+        Err(e) => {
+            tracing::error!("Failed to list secrets: {:#}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut users = secret_users(&app_state).await?;
+    for secret in &mut secrets {
+        secret.used_by = users.remove(&secret.name).unwrap_or_default();
+    }
+    Ok(Json(secrets))
+}
+
+/// Get a secret's metadata.
+pub async fn get_secret(
+    State(app_state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<SecretInfo>, StatusCode> {
+    match secrets::get(&app_state.db, &name).await {
+        Ok(Some(mut secret)) => {
+            secret.used_by = secret_users(&app_state).await?.remove(&name).unwrap_or_default();
+            Ok(Json(secret))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to get secret {}: {:#}", name, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Store a new secret.
+pub async fn create_secret(
+    State(app_state): State<Arc<AppState>>,
+    Json(request): Json<CreateSecretRequest>,
+) -> Result<(StatusCode, Json<SecretInfo>), StatusCode> {
+    if let Err(e) = secrets::check_name(&request.name) {
+        tracing::warn!("Rejected secret: {}", e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match secrets::get(&app_state.db, &request.name).await {
+        Ok(Some(_)) => return Err(StatusCode::CONFLICT),
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!("Failed to check secret {}: {:#}", request.name, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    match secrets::create(&app_state.db, request).await {
+        Ok(secret) => Ok((StatusCode::CREATED, Json(secret))),
+        Err(e) => {
+            tracing::error!("Failed to create secret: {:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Change a secret's value or description. Stacks pick up a new value on
+/// their next `up`, which recreates the containers using it.
+pub async fn update_secret(
+    State(app_state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(request): Json<UpdateSecretRequest>,
+) -> Result<Json<SecretInfo>, StatusCode> {
+    match secrets::update(&app_state.db, &name, request).await {
+        Ok(Some(mut secret)) => {
+            secret.used_by = secret_users(&app_state).await?.remove(&name).unwrap_or_default();
+            Ok(Json(secret))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to update secret {}: {:#}", name, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Delete a secret that no stack uses.
+pub async fn delete_secret(
+    State(app_state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    if let Some(stacks) = secret_users(&app_state).await?.get(&name) {
+        tracing::warn!("Refused to delete secret {} used by stacks {}", name, stacks.join(", "));
+        return Err(StatusCode::CONFLICT);
+    }
+    match secrets::delete(&app_state.db, &name).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to delete secret {}: {:#}", name, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
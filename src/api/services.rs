@@ -1,23 +1,49 @@
 //! API handlers for service management.
 
-use crate::app_state::AppState;
-use crate::models::service::{CreateServiceRequest, Service, ServiceResponse, UpdateServiceRequest};
+use crate::models::service::{CreateServiceRequest, ServiceResponse, UpdateServiceRequest};
 use crate::docker::services;
+use crate::models::service::Middleware;
+use crate::proxy::{middleware, routing, AppState};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     Json,
 };
+use std::collections::HashMap;
 use std::sync::Arc;
 use uuid::Uuid;
 
+/// Reject middleware that would fail to compile into a route.
+fn validate_middleware(
+    middleware: Option<&Middleware>,
+    headers: Option<&HashMap<String, String>>,
+) -> Result<(), StatusCode> {
+    let empty = HashMap::new();
+    if let Some(middleware) = middleware {
+        if let Err(e) = middleware::compile(middleware, headers.unwrap_or(&empty)) {
+            tracing::warn!("Rejected service middleware: {}", e);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    Ok(())
+}
+
+/// Discovered services are managed through their container labels.
+fn reject_discovered(app_state: &AppState, service_id: &Uuid) -> Result<(), StatusCode> {
+    match app_state.discovered.get(service_id) {
+        Some(_) => Err(StatusCode::CONFLICT),
+        None => Ok(()),
+    }
+}
+
 /// List all services.
 pub async fn list_services(
-    State(app_state): State<AppState>,
+    State(app_state): State<Arc<AppState>>,
 ) -> Result<Json<Vec<ServiceResponse>>, StatusCode> {
     match services::list_services(&app_state.db).await {
         Ok(services) => {
             let responses: Vec<ServiceResponse> = services.into_iter()
+                .chain(app_state.discovered.list())
                 .map(|service| service.into())
                 .collect();
             Ok(Json(responses))
@@ -31,12 +57,15 @@ pub async fn list_services(
 
 /// Get a service by ID.
 pub async fn get_service(
-    State(app_state): State<AppState>,
+    State(app_state): State<Arc<AppState>>,
     Path(service_id): Path<Uuid>,
 ) -> Result<Json<ServiceResponse>, StatusCode> {
     match services::get_service(&app_state.db, &service_id).await {
         Ok(Some(service)) => Ok(Json(service.into())),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Ok(None) => match app_state.discovered.get(&service_id) {
+            Some(service) => Ok(Json(service.into())),
+            None => Err(StatusCode::NOT_FOUND),
+        },
         Err(e) => {
             tracing::error!("Failed to get service {}: {}", service_id, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
@@ -46,11 +75,16 @@ pub async fn get_service(
 
 /// Create a new service.
 pub async fn create_service(
-    State(app_state): State<AppState>,
+    State(app_state): State<Arc<AppState>>,
     Json(request): Json<CreateServiceRequest>,
 ) -> Result<Json<ServiceResponse>, StatusCode> {
+    validate_middleware(request.middleware.as_ref(), request.headers.as_ref())?;
+
     match services::create_service(&app_state.db, request).await {
-        Ok(service) => Ok(Json(service.into())),
+        Ok(service) => {
+            routing::reload(&app_state).await;
+            Ok(Json(service.into()))
+        }
         Err(e) => {
             tracing::error!("Failed to create service: {}", e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
@@ -60,12 +94,19 @@ pub async fn create_service(
 
 /// Update a service.
 pub async fn update_service(
-    State(app_state): State<AppState>,
+    State(app_state): State<Arc<AppState>>,
     Path(service_id): Path<Uuid>,
     Json(request): Json<UpdateServiceRequest>,
 ) -> Result<Json<ServiceResponse>, StatusCode> {
+    validate_middleware(request.middleware.as_ref(), request.headers.as_ref())?;
+
+    reject_discovered(&app_state, &service_id)?;
+
     match services::update_service(&app_state.db, &service_id, request).await {
-        Ok(Some(service)) => Ok(Json(service.into())),
+        Ok(Some(service)) => {
+            routing::reload(&app_state).await;
+            Ok(Json(service.into()))
+        }
         Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
             tracing::error!("Failed to update service {}: {}", service_id, e);
@@ -76,11 +117,16 @@ pub async fn update_service(
 
 /// Delete a service.
 pub async fn delete_service(
-    State(app_state): State<AppState>,
+    State(app_state): State<Arc<AppState>>,
     Path(service_id): Path<Uuid>,
 ) -> Result<StatusCode, StatusCode> {
+    reject_discovered(&app_state, &service_id)?;
+
     match services::delete_service(&app_state.db, &service_id).await {
-        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(true) => {
+            routing::reload(&app_state).await;
+            Ok(StatusCode::NO_CONTENT)
+        }
         Ok(false) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
             tracing::error!("Failed to delete service {}: {}", service_id, e);
@@ -91,11 +137,16 @@ pub async fn delete_service(
 
 /// Enable a service.
 pub async fn enable_service(
-    State(app_state): State<AppState>,
+    State(app_state): State<Arc<AppState>>,
     Path(service_id): Path<Uuid>,
 ) -> Result<Json<ServiceResponse>, StatusCode> {
+    reject_discovered(&app_state, &service_id)?;
+
     match services::enable_service(&app_state.db, &service_id).await {
-        Ok(Some(service)) => Ok(Json(service.into())),
+        Ok(Some(service)) => {
+            routing::reload(&app_state).await;
+            Ok(Json(service.into()))
+        }
         Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
             tracing::error!("Failed to enable service {}: {}", service_id, e);
@@ -106,11 +157,16 @@ pub async fn enable_service(
 
 /// Disable a service.
 pub async fn disable_service(
-    State(app_state): State<AppState>,
+    State(app_state): State<Arc<AppState>>,
     Path(service_id): Path<Uuid>,
 ) -> Result<Json<ServiceResponse>, StatusCode> {
+    reject_discovered(&app_state, &service_id)?;
+
     match services::disable_service(&app_state.db, &service_id).await {
-        Ok(Some(service)) => Ok(Json(service.into())),
+        Ok(Some(service)) => {
+            routing::reload(&app_state).await;
+            Ok(Json(service.into()))
+        }
         Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
             tracing::error!("Failed to disable service {}: {}", service_id, e);
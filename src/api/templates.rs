@@ -1,14 +1,16 @@
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     Json,
 };
 use std::sync::Arc;
 use uuid::Uuid;
-use bollard::Docker;
 
+use crate::auth;
 use crate::docker::templates;
-use crate::models::{
+use crate::proxy::AppState;
+use crate::secrets::MissingSecret;
+use crate::models::template::{
     ContainerTemplate, CreateTemplateRequest, UpdateTemplateRequest, DeployTemplateRequest,
 };
 
@@ -64,16 +66,24 @@ pub async fn delete_template(
     }
 }
 
-/// Deploy a container from a template
+/// Deploy a container from a template, pulling its image with the caller's
+/// registry credentials
 pub async fn deploy_template(
-    State(docker): State<Arc<Docker>>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(request): Json<DeployTemplateRequest>,
 ) -> Result<Json<String>, StatusCode> {
-    match templates::deploy_from_template(docker, request).await {
-        Ok(container_id) => Ok(Json(container_id)),
+    let caller = auth::request_claims(&headers, &state.jwt);
+    match templates::deploy_from_template(&state.docker, &state.db, request, caller.as_ref()).await {
+        Ok(Some(container_id)) => Ok(Json(container_id)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) if e.downcast_ref::<MissingSecret>().is_some() => {
+            tracing::warn!("Failed to deploy template: {}", e);
+            Err(StatusCode::BAD_REQUEST)
+        }
         Err(e) => {
-            tracing::error!("Failed to deploy template: {}", e);
+            tracing::error!("Failed to deploy template: {:#}", e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,55 @@
+//! API endpoints for image update checks.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::docker::updates::{self, NoRollback};
+use crate::models::update::ImageUpdate;
+use crate::proxy::AppState;
+
+/// List the last update check of every running container.
+pub async fn list_updates(State(state): State<Arc<AppState>>) -> Result<Json<Vec<ImageUpdate>>, StatusCode> {
+    match updates::list(&state.db).await {
+        Ok(updates) => Ok(Json(updates)),
+        Err(e) => {
+            tracing::error!("Failed to list image updates: {:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Check every running container for image updates now, and apply them as
+/// their policies say.
+pub async fn check_updates(State(state): State<Arc<AppState>>) -> Result<Json<Vec<ImageUpdate>>, StatusCode> {
+    match updates::check(&state).await {
+        Ok(updates) => Ok(Json(updates)),
+        Err(e) => {
+            tracing::error!("Failed to check for image updates: {:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Undo the last automatic update of a container.
+pub async fn rollback_update(
+    State(state): State<Arc<AppState>>,
+    Path(container): Path<String>,
+) -> Result<Json<ImageUpdate>, StatusCode> {
+    match updates::rollback(&state, &container).await {
+        Ok(Some(update)) => Ok(Json(update)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) if e.downcast_ref::<NoRollback>().is_some() => {
+            tracing::warn!("Failed to roll back container {}: {}", container, e);
+            Err(StatusCode::CONFLICT)
+        }
+        Err(e) => {
+            tracing::error!("Failed to roll back container {}: {:#}", container, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
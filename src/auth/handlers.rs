@@ -1,27 +1,56 @@
 use axum::{
     extract::{Json, State},
-    http::{header, Response, StatusCode},
-    response::IntoResponse,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::SqlitePool;
+use std::sync::Arc;
 
 use crate::{
     auth,
-    config::SharedJwtConfig,
     models::{User, user::UserRole},
+    proxy::{middleware, AppState},
 };
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     username: String,
     password: String,
+    /// Page of a proxied service that sent the browser here to log in.
+    #[serde(default)]
+    rd: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     token: String,
     user: UserInfo,
+    /// Where to send the browser to finish logging in to the page in `rd`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirect: Option<String>,
+}
+
+/// Request to log in to a proxied service with the current login.
+#[derive(Debug, Deserialize)]
+pub struct RedirectRequest {
+    rd: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RedirectResponse {
+    redirect: String,
+}
+
+/// Where to send a browser to log in to the proxied page `rd`, if it is on a
+/// route that takes Rustainer logins.
+fn redirect_for(state: &AppState, rd: &str, token: &str) -> Option<String> {
+    let table = state.routes.read().unwrap().clone();
+    let redirect = middleware::login_redirect(&table, rd, token);
+    if redirect.is_none() {
+        tracing::warn!("Ignoring login redirect to {}, which is not on a route with Rustainer logins", rd);
+    }
+    redirect
 }
 
 #[derive(Debug, Serialize)]
@@ -31,165 +60,118 @@ pub struct UserInfo {
     role: String,
 }
 
+/// A `users` row: id, username, password hash, role, created and updated.
+type UserRow = (String, String, String, String, DateTime<Utc>, DateTime<Utc>);
+
+fn unauthorized() -> Response {
+    (StatusCode::UNAUTHORIZED, Json(serde_json::json!({
+        "error": "Invalid username or password"
+    }))).into_response()
+}
+
 pub async fn login(
-    State(pool): State<SqlitePool>,
-    State(jwt_config): State<SharedJwtConfig>,
+    State(state): State<Arc<AppState>>,
     Json(login_req): Json<LoginRequest>,
-) -> impl IntoResponse {
+) -> Response {
     // Find user by username
-    let user = match sqlx::query_as!(
-        User,
+    let row: Option<UserRow> = match sqlx::query_as(
         r#"
-        SELECT 
-            id, 
-            username, 
-            password_hash, 
-            role as "role: String", 
-            created_at as "created_at: chrono::DateTime<chrono::Utc>", 
-            updated_at as "updated_at: chrono::DateTime<chrono::Utc>"
-        FROM users 
+        SELECT id, username, password_hash, role, created_at, updated_at
+        FROM users
         WHERE username = ?
-        "#,
-        login_req.username
+        "#
     )
-    .fetch_optional(&pool)
+    .bind(&login_req.username)
+    .fetch_optional(&state.db)
     .await
     {
-        Ok(Some(user)) => User {
-            id: user.id,
-            username: user.username,
-            password_hash: user.password_hash,
-            role: UserRole::from(user.role),
-            created_at: user.created_at,
-            updated_at: user.updated_at,
-        },
-        _ => {
-            return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({
-                "error": "Invalid username or password"
-            }))).into_response();
+        Ok(row) => row,
+        Err(e) => {
+            tracing::error!("Failed to look up user {}: {}", login_req.username, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
     };
 
-    // Verify password
-    let is_valid = match argon2::verify_encoded(&user.password_hash, login_req.password.as_bytes()) {
-        Ok(valid) => valid,
-        Err(_) => {
-            return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({
-                "error": "Invalid username or password"
-            }))).into_response();
-        }
+    let user = match row {
+        Some((id, username, password_hash, role, created_at, updated_at)) => User {
+            id,
+            username,
+            password_hash,
+            role: UserRole::from(role),
+            created_at,
+            updated_at,
+        },
+        None => return unauthorized(),
     };
 
-    if !is_valid {
-        return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({
-            "error": "Invalid username or password"
-        }))).into_response();
+    // Verify password
+    if !auth::verify_password(&login_req.password, &user.password_hash) {
+        return unauthorized();
     }
 
     // Generate JWT token
-    let token = match auth::create_token(&user, &jwt_config) {
+    let token = match auth::create_token(&user, &state.jwt) {
         Ok(token) => token,
-        Err(_) => {
+        Err(e) => {
+            tracing::error!("Failed to create token: {}", e);
             return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({
                 "error": "Failed to generate token"
             }))).into_response();
         }
     };
 
-    // Create response with cookie
-    let user_info = UserInfo {
-        id: user.id,
-        username: user.username,
-        role: user.role.to_string(),
-    };
-
+    let redirect = login_req.rd.as_deref().and_then(|rd| redirect_for(&state, rd, &token));
     let response = LoginResponse {
+        redirect,
         token: token.clone(),
-        user: user_info,
+        user: UserInfo {
+            id: user.id,
+            username: user.username,
+            role: user.role.to_string(),
+        },
     };
 
     // Build response with cookie
     let cookie = format!(
-        "auth_token={}; HttpOnly; Path=/; Max-Age={}",
-        token, jwt_config.expiration
+        "{}={}; HttpOnly; Path=/; Max-Age={}",
+        auth::TOKEN_COOKIE, token, state.jwt.expiration
     );
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::SET_COOKIE, cookie)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(serde_json::to_string(&response).unwrap())
-        .unwrap()
-        .into_response()
+    ([(header::SET_COOKIE, cookie)], Json(response)).into_response()
 }
 
 pub async fn logout() -> impl IntoResponse {
     // Clear the auth cookie
-    let cookie = "auth_token=; HttpOnly; Path=/; Max-Age=0";
-
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::SET_COOKIE, cookie)
-        .body(serde_json::to_string(&serde_json::json!({
-            "message": "Logged out successfully"
-        })).unwrap())
-        .unwrap()
-        .into_response()
+    let cookie = format!("{}=; HttpOnly; Path=/; Max-Age=0", auth::TOKEN_COOKIE);
+
+    ([(header::SET_COOKIE, cookie)], Json(serde_json::json!({
+        "message": "Logged out successfully"
+    })))
+}
+
+/// Log in to the proxied page `rd` with the current login, for browsers
+/// sent to the login page while already logged in.
+pub async fn login_redirect(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<RedirectRequest>,
+) -> Result<Json<RedirectResponse>, StatusCode> {
+    let token = auth::token_from_headers(&headers).ok_or(StatusCode::UNAUTHORIZED)?;
+    auth::validate_token(&token, &state.jwt).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let redirect = redirect_for(&state, &request.rd, &token).ok_or(StatusCode::BAD_REQUEST)?;
+    Ok(Json(RedirectResponse { redirect }))
 }
 
 pub async fn get_current_user(
-    claims: axum::extract::Extension<auth::Claims>,
-) -> impl IntoResponse {
-    Json(serde_json::json!({
-        "id": claims.sub,
-        "username": claims.username,
-        "role": claims.role,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<UserInfo>, StatusCode> {
+    let token = auth::token_from_headers(&headers).ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = auth::validate_token(&token, &state.jwt).map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+    Ok(Json(UserInfo {
+        id: claims.sub,
+        username: claims.username,
+        role: claims.role,
     }))
 }
-
-pub async fn get_users(
-    State(pool): State<SqlitePool>,
-    claims: axum::extract::Extension<auth::Claims>,
-) -> impl IntoResponse {
-    // Only admin can list users
-    if claims.role != "admin" {
-        return (StatusCode::FORBIDDEN, Json(serde_json::json!({
-            "error": "Insufficient permissions"
-        }))).into_response();
-    }
-
-    // Get all users
-    let users = match sqlx::query!(
-        r#"
-        SELECT id, username, role, created_at, updated_at
-        FROM users
-        ORDER BY username
-        "#
-    )
-    .fetch_all(&pool)
-    .await
-    {
-        Ok(users) => users,
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({
-                "error": "Failed to fetch users"
-            }))).into_response();
-        }
-    };
-
-    // Convert to JSON
-    let users = users
-        .into_iter()
-        .map(|user| {
-            serde_json::json!({
-                "id": user.id,
-                "username": user.username,
-                "role": user.role,
-                "created_at": user.created_at,
-                "updated_at": user.updated_at,
-            })
-        })
-        .collect::<Vec<_>>();
-
-    Json(users).into_response()
-}
\ No newline at end of file
@@ -1,7 +1,9 @@
 pub mod handlers;
-pub mod middleware;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::http::{header, HeaderMap};
 use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
@@ -47,4 +49,50 @@ pub fn validate_token(token: &str, jwt_config: &SharedJwtConfig) -> Result<Claim
     .context("Failed to validate JWT token")?;
     
     Ok(token_data.claims)
-}
\ No newline at end of file
+}
+
+/// Cookie the login token is stored in.
+pub const TOKEN_COOKIE: &str = "auth_token";
+
+/// Find a login token in the `Authorization` header or the auth cookie.
+pub fn token_from_headers(headers: &HeaderMap) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return Some(token.trim().to_string());
+    }
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == TOKEN_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Claims of the logged-in user making a request, if any.
+pub fn request_claims(headers: &HeaderMap, jwt_config: &SharedJwtConfig) -> Option<Claims> {
+    let token = token_from_headers(headers)?;
+    validate_token(&token, jwt_config).ok()
+}
+
+/// Hash a password with Argon2 for storage.
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+        .map_err(|e| anyhow!("Failed to generate salt: {}", e))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))
+}
+
+/// Check a password against a stored Argon2 hash.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
@@ -1,4 +1,6 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use argon2::password_hash::{PasswordHasher, SaltString};
+use argon2::Argon2;
 use sqlx::SqlitePool;
 use std::env;
 
@@ -15,8 +17,13 @@ async fn main() -> Result<()> {
         .await
         .context("Failed to connect to database")?;
 
-    // Generate simple password hash
-    let password_hash = format!("admin_password_hash_{}", rand::random::<u64>());
+    // Hash the default password
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+        .map_err(|e| anyhow!("Failed to generate salt: {}", e))?;
+    let password_hash = Argon2::default()
+        .hash_password(b"admin", &salt)
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))?
+        .to_string();
 
     // Update admin user password
     let result = sqlx::query(
@@ -8,6 +8,10 @@ pub struct Config {
     pub proxy: ProxyConfig,
     pub auth: AuthConfig,
     pub database: DatabaseConfig,
+    pub acme: AcmeConfig,
+    pub access_log: AccessLogConfig,
+    pub secrets: SecretsConfig,
+    pub updates: UpdatesConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +24,7 @@ pub struct ServerConfig {
 pub struct ProxyConfig {
     pub host: String,
     pub port: u16,
+    pub https_port: u16,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +38,36 @@ pub struct DatabaseConfig {
     pub url: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeConfig {
+    pub directory_url: String,
+    pub contact_email: Option<String>,
+    pub ca_cert_path: Option<String>, // extra root CA, e.g. Pebble's minica
+    pub check_interval: u64, // in seconds
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessLogConfig {
+    pub path: Option<String>, // "-" for stdout; unset disables the log
+    pub format: String,       // "json" or "combined"
+    pub max_size: u64,        // in bytes, 0 to only reopen on SIGHUP
+    pub keep: usize,          // rotated files to keep
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretsConfig {
+    #[serde(skip_serializing)]
+    pub key: Option<String>,  // base64 master key; overrides the key file
+    pub key_file: String,     // generated on first start when missing
+    pub runtime_dir: String,  // where secret files are written, ideally a tmpfs
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdatesConfig {
+    pub check_interval: u64,        // in seconds, 0 disables image update checks
+    pub notify_url: Option<String>, // webhook that update events are posted to
+}
+
 impl Config {
     pub fn from_env() -> Result<Self> {
         // Load .env file if it exists
@@ -53,6 +88,10 @@ impl Config {
                     .unwrap_or_else(|_| "80".to_string())
                     .parse()
                     .unwrap_or(80),
+                https_port: std::env::var("PROXY_HTTPS_PORT")
+                    .unwrap_or_else(|_| "443".to_string())
+                    .parse()
+                    .unwrap_or(443),
             },
             auth: AuthConfig {
                 jwt_secret: std::env::var("JWT_SECRET")
@@ -66,6 +105,41 @@ impl Config {
                 url: std::env::var("DATABASE_URL")
                     .unwrap_or_else(|_| "sqlite:data/rustainer.db".to_string()),
             },
+            acme: AcmeConfig {
+                directory_url: std::env::var("ACME_DIRECTORY_URL")
+                    .unwrap_or_else(|_| "https://acme-v02.api.letsencrypt.org/directory".to_string()),
+                contact_email: std::env::var("ACME_CONTACT_EMAIL").ok(),
+                ca_cert_path: std::env::var("ACME_CA_CERT").ok(),
+                check_interval: std::env::var("ACME_CHECK_INTERVAL")
+                    .unwrap_or_else(|_| "3600".to_string()) // 1 hour
+                    .parse()
+                    .unwrap_or(3600),
+            },
+            access_log: AccessLogConfig {
+                path: std::env::var("ACCESS_LOG_PATH").ok(),
+                format: std::env::var("ACCESS_LOG_FORMAT").unwrap_or_else(|_| "json".to_string()),
+                max_size: std::env::var("ACCESS_LOG_MAX_SIZE")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()
+                    .unwrap_or(0),
+                keep: std::env::var("ACCESS_LOG_KEEP")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .unwrap_or(5),
+            },
+            secrets: SecretsConfig {
+                key: std::env::var("SECRETS_KEY").ok(),
+                key_file: std::env::var("SECRETS_KEY_FILE").unwrap_or_else(|_| "data/secrets.key".to_string()),
+                runtime_dir: std::env::var("SECRETS_DIR")
+                    .unwrap_or_else(|_| "/dev/shm/rustainer/secrets".to_string()),
+            },
+            updates: UpdatesConfig {
+                check_interval: std::env::var("UPDATE_CHECK_INTERVAL")
+                    .unwrap_or_else(|_| "86400".to_string()) // 24 hours
+                    .parse()
+                    .unwrap_or(86400),
+                notify_url: std::env::var("UPDATE_NOTIFY_URL").ok().filter(|url| !url.is_empty()),
+            },
         };
 
         Ok(config)
@@ -62,6 +62,294 @@ async fn run_migrations(pool: &Pool<Sqlite>) -> Result<()> {
     .await
     .context("Failed to create applications table")?;
 
+    add_column_if_missing(pool, "applications", "ssl_enabled", "BOOLEAN NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "applications", "ssl_auto_generate", "BOOLEAN NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "applications", "target_label", "TEXT").await?;
+    add_column_if_missing(pool, "applications", "lb_strategy", "TEXT NOT NULL DEFAULT 'round_robin'").await?;
+    add_column_if_missing(pool, "applications", "health_check", "TEXT").await?;
+    add_route_columns(pool, "applications").await?;
+    rekey_by_domain_and_path(pool, "applications").await?;
+
+    // Create services table if it doesn't exist
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS services (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            domain TEXT NOT NULL UNIQUE,
+            service_type TEXT NOT NULL,
+            target TEXT NOT NULL,
+            port INTEGER NOT NULL,
+            ssl_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+            ssl_cert_path TEXT,
+            ssl_key_path TEXT,
+            ssl_auto_generate BOOLEAN NOT NULL DEFAULT FALSE,
+            headers TEXT,
+            enabled BOOLEAN NOT NULL DEFAULT TRUE,
+            created_at TIMESTAMP NOT NULL,
+            updated_at TIMESTAMP NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create services table")?;
+
+    add_column_if_missing(pool, "services", "lb_strategy", "TEXT NOT NULL DEFAULT 'round_robin'").await?;
+    add_column_if_missing(pool, "services", "health_check", "TEXT").await?;
+    add_column_if_missing(pool, "services", "middleware", "TEXT").await?;
+    add_route_columns(pool, "services").await?;
+    rekey_by_domain_and_path(pool, "services").await?;
+
+    // Create compose stacks table if it doesn't exist
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS stacks (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            environment TEXT,
+            created_at TIMESTAMP NOT NULL,
+            updated_at TIMESTAMP NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create stacks table")?;
+    add_column_if_missing(pool, "stacks", "env_files", "TEXT").await?;
+    add_column_if_missing(pool, "stacks", "compose_path", "TEXT").await?;
+    add_column_if_missing(pool, "stacks", "update_policy", "TEXT NOT NULL DEFAULT 'notify'").await?;
+
+    // Create the stack revision history table if it doesn't exist
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS stack_revisions (
+            stack_id TEXT NOT NULL,
+            number INTEGER NOT NULL,
+            compose_content TEXT NOT NULL,
+            environment TEXT NOT NULL,
+            env_files TEXT NOT NULL,
+            author TEXT,
+            message TEXT,
+            created_at TIMESTAMP NOT NULL,
+            PRIMARY KEY (stack_id, number)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create stack_revisions table")?;
+
+    // Create the git source table for stacks deployed from a repository
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS stack_sources (
+            stack_id TEXT PRIMARY KEY,
+            repository TEXT NOT NULL,
+            branch TEXT NOT NULL,
+            path TEXT NOT NULL,
+            username TEXT,
+            password TEXT,
+            ssh_key TEXT,
+            poll_interval INTEGER NOT NULL DEFAULT 0,
+            webhook_secret TEXT,
+            last_commit TEXT,
+            last_checked TIMESTAMP,
+            last_error TEXT
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create stack_sources table")?;
+
+    // Create the secrets store; values are sealed by the secrets module
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS secrets (
+            name TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            description TEXT,
+            created_at TIMESTAMP NOT NULL,
+            updated_at TIMESTAMP NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create secrets table")?;
+
+    // Create the registry store; passwords are sealed with the secrets key
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS registries (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            url TEXT NOT NULL,
+            host TEXT NOT NULL,
+            username TEXT,
+            password TEXT,
+            scope TEXT NOT NULL DEFAULT 'shared',
+            owner TEXT,
+            created_at TIMESTAMP NOT NULL,
+            updated_at TIMESTAMP NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create registries table")?;
+
+    // Create image build history table if it doesn't exist
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS image_builds (
+            id TEXT PRIMARY KEY,
+            tags TEXT NOT NULL,
+            source TEXT NOT NULL,
+            dockerfile TEXT,
+            target TEXT,
+            build_args TEXT NOT NULL,
+            labels TEXT NOT NULL,
+            builder TEXT NOT NULL,
+            status TEXT NOT NULL,
+            image_id TEXT,
+            error TEXT,
+            log TEXT NOT NULL DEFAULT '',
+            author TEXT,
+            started_at TIMESTAMP NOT NULL,
+            finished_at TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create image builds table")?;
+
+    // Create the image update check table, keyed by container name
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS image_updates (
+            container TEXT PRIMARY KEY,
+            container_id TEXT NOT NULL,
+            image TEXT NOT NULL,
+            project TEXT,
+            policy TEXT NOT NULL,
+            current_digest TEXT,
+            latest_digest TEXT,
+            available BOOLEAN NOT NULL DEFAULT 0,
+            error TEXT,
+            checked_at TIMESTAMP NOT NULL,
+            notified_digest TEXT,
+            skipped_digest TEXT,
+            rollback_image TEXT,
+            updated_at TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create image updates table")?;
+
+    Ok(())
+}
+
+/// Add the path routing columns shared by applications and services.
+async fn add_route_columns(pool: &Pool<Sqlite>, table: &str) -> Result<()> {
+    add_column_if_missing(pool, table, "path_prefix", "TEXT NOT NULL DEFAULT '/'").await?;
+    add_column_if_missing(pool, table, "strip_prefix", "BOOLEAN NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, table, "rewrite_prefix", "TEXT").await?;
+    add_column_if_missing(pool, table, "priority", "INTEGER NOT NULL DEFAULT 0").await?;
+    Ok(())
+}
+
+/// Replace the original `UNIQUE` constraint on `domain` with one on
+/// `(domain, path_prefix)`, so one domain can route different paths to
+/// different targets.
+///
+/// SQLite cannot drop a constraint, so the table is rebuilt without it and a
+/// unique index is created instead.
+async fn rekey_by_domain_and_path(pool: &Pool<Sqlite>, table: &str) -> Result<()> {
+    let index = format!("idx_{}_domain_path", table);
+    let indexed: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = ?"
+    )
+    .bind(&index)
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("Failed to inspect {} indexes", table))?;
+
+    if indexed > 0 {
+        return Ok(());
+    }
+
+    let create_sql: String = sqlx::query_scalar(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?"
+    )
+    .bind(table)
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("Failed to read {} schema", table))?;
+
+    // Strip the column-level UNIQUE from `domain`, whichever order it was written in
+    let rebuilt_sql = create_sql
+        .replacen("domain TEXT UNIQUE NOT NULL", "domain TEXT NOT NULL", 1)
+        .replacen("domain TEXT NOT NULL UNIQUE", "domain TEXT NOT NULL", 1)
+        .replacen(
+            &format!("CREATE TABLE {}", table),
+            &format!("CREATE TABLE {}_rebuild", table),
+            1,
+        );
+
+    let mut tx = pool.begin().await.context("Failed to start migration")?;
+    sqlx::query(&rebuilt_sql)
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Failed to create new {} table", table))?;
+    sqlx::query(&format!("INSERT INTO {0}_rebuild SELECT * FROM {0}", table))
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Failed to copy {} rows", table))?;
+    sqlx::query(&format!("DROP TABLE {}", table))
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Failed to drop old {} table", table))?;
+    sqlx::query(&format!("ALTER TABLE {0}_rebuild RENAME TO {0}", table))
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Failed to rename new {} table", table))?;
+    sqlx::query(&format!("CREATE UNIQUE INDEX {} ON {} (domain, path_prefix)", index, table))
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Failed to index {} routes", table))?;
+    tx.commit().await.context("Failed to commit migration")?;
+
+    Ok(())
+}
+
+/// Add a column to an existing table, for databases created before it existed.
+async fn add_column_if_missing(
+    pool: &Pool<Sqlite>,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let exists: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?"
+    )
+    .bind(table)
+    .bind(column)
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("Failed to inspect {} table", table))?;
+
+    if exists == 0 {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await
+            .with_context(|| format!("Failed to add {}.{} column", table, column))?;
+    }
+
     Ok(())
 }
 
@@ -79,8 +367,7 @@ pub async fn init_default_data(pool: &Pool<Sqlite>) -> Result<()> {
     if admin_exists == 0 {
         info!("Creating default admin user");
         
-        // Generate password hash (simplified for now)
-        let password_hash = format!("admin_password_hash_{}", rand::random::<u64>());
+        let password_hash = crate::auth::hash_password("admin")?;
 
         // Insert admin user
         sqlx::query(
@@ -98,5 +385,35 @@ pub async fn init_default_data(pool: &Pool<Sqlite>) -> Result<()> {
         .context("Failed to create admin user")?;
     }
 
+    // Older databases stored a placeholder that no password could match
+    sqlx::query("UPDATE users SET password_hash = ? WHERE username = 'admin' AND password_hash LIKE 'admin_password_hash_%'")
+        .bind(crate::auth::hash_password("admin")?)
+        .execute(pool)
+        .await
+        .context("Failed to reset placeholder admin password")?;
+
     Ok(())
-}
\ No newline at end of file
+}
+
+/// A migrated in-memory database for tests, with the secrets store keyed.
+#[cfg(test)]
+pub async fn test_pool() -> Pool<Sqlite> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    let config = crate::config::SecretsConfig {
+        key: Some(STANDARD.encode([7u8; 32])),
+        key_file: String::new(),
+        runtime_dir: "/tmp".to_string(),
+    };
+    crate::secrets::init(&config).unwrap();
+    // Every connection to :memory: opens a database of its own
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    run_migrations(&pool).await.unwrap();
+    pool
+}
@@ -0,0 +1,233 @@
+//! Application management functionality for Rustainer.
+//!
+//! Applications map a domain and path prefix to one container, or to every
+//! container with a label, on a fixed port.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sqlx::types::Json;
+use sqlx::{Pool, Sqlite};
+use uuid::Uuid;
+
+use crate::models::application::{CreateApplicationRequest, UpdateApplicationRequest};
+use crate::models::Application;
+use crate::proxy::routing::normalize_prefix;
+
+/// Create a new application
+pub async fn create_application(
+    db: &Pool<Sqlite>,
+    request: CreateApplicationRequest,
+) -> Result<Application> {
+    let now = Utc::now();
+    let application = Application {
+        id: Uuid::new_v4().to_string(),
+        name: request.name,
+        domain: request.domain,
+        container_id: request.container_id,
+        target_label: request.target_label,
+        container_port: request.container_port as i64,
+        path_prefix: normalize_prefix(request.path_prefix.as_deref().unwrap_or("/")),
+        strip_prefix: request.strip_prefix.unwrap_or(false),
+        rewrite_prefix: request.rewrite_prefix,
+        priority: request.priority.unwrap_or(0),
+        lb_strategy: request.lb_strategy.unwrap_or_default(),
+        health_check: request.health_check.map(Json),
+        enabled: true,
+        ssl_enabled: request.ssl_enabled.unwrap_or(false),
+        ssl_auto_generate: request.ssl_auto_generate.unwrap_or(false),
+        created_at: now,
+        updated_at: now,
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO applications (
+            id, name, domain, container_id, target_label, container_port,
+            path_prefix, strip_prefix, rewrite_prefix, priority,
+            lb_strategy, health_check, enabled, ssl_enabled, ssl_auto_generate,
+            created_at, updated_at
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#
+    )
+    .bind(&application.id)
+    .bind(&application.name)
+    .bind(&application.domain)
+    .bind(&application.container_id)
+    .bind(&application.target_label)
+    .bind(application.container_port)
+    .bind(&application.path_prefix)
+    .bind(application.strip_prefix)
+    .bind(&application.rewrite_prefix)
+    .bind(application.priority)
+    .bind(application.lb_strategy)
+    .bind(&application.health_check)
+    .bind(application.enabled)
+    .bind(application.ssl_enabled)
+    .bind(application.ssl_auto_generate)
+    .bind(application.created_at)
+    .bind(application.updated_at)
+    .execute(db)
+    .await
+    .context("Failed to insert application into database")?;
+
+    Ok(application)
+}
+
+/// Get an application by ID
+pub async fn get_application(db: &Pool<Sqlite>, id: &str) -> Result<Option<Application>> {
+    sqlx::query_as::<_, Application>(
+        r#"
+        SELECT
+            id, name, domain, container_id, target_label, container_port,
+            path_prefix, strip_prefix, rewrite_prefix, priority,
+            lb_strategy, health_check, enabled, ssl_enabled, ssl_auto_generate,
+            created_at, updated_at
+        FROM applications
+        WHERE id = ?
+        "#
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await
+    .context("Failed to fetch application from database")
+}
+
+/// List all applications
+pub async fn list_applications(db: &Pool<Sqlite>) -> Result<Vec<Application>> {
+    sqlx::query_as::<_, Application>(
+        r#"
+        SELECT
+            id, name, domain, container_id, target_label, container_port,
+            path_prefix, strip_prefix, rewrite_prefix, priority,
+            lb_strategy, health_check, enabled, ssl_enabled, ssl_auto_generate,
+            created_at, updated_at
+        FROM applications
+        ORDER BY name
+        "#
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to fetch applications from database")
+}
+
+/// Update an application
+pub async fn update_application(
+    db: &Pool<Sqlite>,
+    id: &str,
+    request: UpdateApplicationRequest,
+) -> Result<Option<Application>> {
+    let mut application = match get_application(db, id).await? {
+        Some(application) => application,
+        None => return Ok(None),
+    };
+
+    // Merge the provided fields into the stored application
+    if let Some(name) = request.name {
+        application.name = name;
+    }
+    if let Some(domain) = request.domain {
+        application.domain = domain;
+    }
+    if let Some(container_id) = request.container_id {
+        application.container_id = Some(container_id);
+    }
+    if let Some(target_label) = request.target_label {
+        application.target_label = Some(target_label).filter(|label| !label.is_empty());
+    }
+    if let Some(container_port) = request.container_port {
+        application.container_port = container_port as i64;
+    }
+    if let Some(path_prefix) = request.path_prefix {
+        application.path_prefix = normalize_prefix(&path_prefix);
+    }
+    if let Some(strip_prefix) = request.strip_prefix {
+        application.strip_prefix = strip_prefix;
+    }
+    if let Some(rewrite_prefix) = request.rewrite_prefix {
+        application.rewrite_prefix = Some(rewrite_prefix).filter(|prefix| !prefix.is_empty());
+    }
+    if let Some(priority) = request.priority {
+        application.priority = priority;
+    }
+    if let Some(lb_strategy) = request.lb_strategy {
+        application.lb_strategy = lb_strategy;
+    }
+    if let Some(health_check) = request.health_check {
+        application.health_check = Some(Json(health_check));
+    }
+    if let Some(enabled) = request.enabled {
+        application.enabled = enabled;
+    }
+    if let Some(ssl_enabled) = request.ssl_enabled {
+        application.ssl_enabled = ssl_enabled;
+    }
+    if let Some(ssl_auto_generate) = request.ssl_auto_generate {
+        application.ssl_auto_generate = ssl_auto_generate;
+    }
+    application.updated_at = Utc::now();
+
+    sqlx::query(
+        r#"
+        UPDATE applications SET
+            name = ?, domain = ?, container_id = ?, target_label = ?, container_port = ?,
+            path_prefix = ?, strip_prefix = ?, rewrite_prefix = ?, priority = ?,
+            lb_strategy = ?, health_check = ?, enabled = ?, ssl_enabled = ?,
+            ssl_auto_generate = ?, updated_at = ?
+        WHERE id = ?
+        "#
+    )
+    .bind(&application.name)
+    .bind(&application.domain)
+    .bind(&application.container_id)
+    .bind(&application.target_label)
+    .bind(application.container_port)
+    .bind(&application.path_prefix)
+    .bind(application.strip_prefix)
+    .bind(&application.rewrite_prefix)
+    .bind(application.priority)
+    .bind(application.lb_strategy)
+    .bind(&application.health_check)
+    .bind(application.enabled)
+    .bind(application.ssl_enabled)
+    .bind(application.ssl_auto_generate)
+    .bind(application.updated_at)
+    .bind(id)
+    .execute(db)
+    .await
+    .context("Failed to update application in database")?;
+
+    Ok(Some(application))
+}
+
+/// Delete an application
+pub async fn delete_application(db: &Pool<Sqlite>, id: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM applications WHERE id = ?")
+        .bind(id)
+        .execute(db)
+        .await
+        .context("Failed to delete application from database")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Enable or disable an application
+pub async fn set_application_enabled(
+    db: &Pool<Sqlite>,
+    id: &str,
+    enabled: bool,
+) -> Result<Option<Application>> {
+    let result = sqlx::query("UPDATE applications SET enabled = ?, updated_at = ? WHERE id = ?")
+        .bind(enabled)
+        .bind(Utc::now())
+        .bind(id)
+        .execute(db)
+        .await
+        .context("Failed to update application in database")?;
+
+    if result.rows_affected() > 0 {
+        get_application(db, id).await
+    } else {
+        Ok(None)
+    }
+}
@@ -0,0 +1,188 @@
+//! Build contexts: tar archives of a directory or of an inline Dockerfile,
+//! as the Docker CLI sends them.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Archive a directory, leaving out what its `.dockerignore` excludes. The
+/// Dockerfile and `.dockerignore` are always sent, as the daemon needs them.
+pub fn archive_dir(dir: &Path, dockerfile: &str) -> Result<Vec<u8>> {
+    let ignore = match fs::read_to_string(dir.join(".dockerignore")) {
+        Ok(content) => Ignore::parse(&content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ignore::default(),
+        Err(e) => return Err(e).context("Failed to read .dockerignore"),
+    };
+    let keep = [clean(dockerfile), ".dockerignore".to_string()];
+
+    let mut archive = tar::Builder::new(Vec::new());
+    archive.follow_symlinks(false);
+    add_dir(&mut archive, dir, "", &ignore, &keep)?;
+    archive.into_inner().context("Failed to write build context")
+}
+
+fn add_dir(archive: &mut tar::Builder<Vec<u8>>, root: &Path, prefix: &str, ignore: &Ignore, keep: &[String]) -> Result<()> {
+    let dir = root.join(prefix);
+    let mut entries = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        let is_dir = entry.file_type()?.is_dir();
+        let kept = keep.iter().any(|keep| *keep == path || (is_dir && keep.starts_with(&format!("{}/", path))));
+        let excluded = ignore.excludes(&path) && !kept;
+        if !excluded {
+            archive
+                .append_path_with_name(entry.path(), &path)
+                .with_context(|| format!("Failed to add {} to build context", path))?;
+        }
+        // Exceptions may bring back paths inside an excluded directory
+        if is_dir && (!excluded || ignore.has_exceptions()) {
+            add_dir(archive, root, &path, ignore, keep)?;
+        }
+    }
+    Ok(())
+}
+
+/// Archive holding only a Dockerfile.
+pub fn archive_dockerfile(content: &str) -> Result<Vec<u8>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+
+    let mut archive = tar::Builder::new(Vec::new());
+    archive
+        .append_data(&mut header, "Dockerfile", content.as_bytes())
+        .context("Failed to write build context")?;
+    archive.into_inner().context("Failed to write build context")
+}
+
+/// Patterns of a `.dockerignore` file. The last pattern matching a path
+/// decides, and `!` patterns bring paths back.
+#[derive(Debug, Default)]
+struct Ignore {
+    patterns: Vec<(bool, Vec<String>)>,
+}
+
+impl Ignore {
+    fn parse(content: &str) -> Self {
+        let patterns = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (exception, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern.trim()),
+                    None => (false, line),
+                };
+                let pattern = clean(pattern);
+                (!pattern.is_empty()).then(|| (exception, pattern.split('/').map(str::to_string).collect()))
+            })
+            .collect();
+        Ignore { patterns }
+    }
+
+    fn has_exceptions(&self) -> bool {
+        self.patterns.iter().any(|(exception, _)| *exception)
+    }
+
+    /// Whether a path, relative to the context, is left out. Paths in an
+    /// excluded directory are too.
+    fn excludes(&self, path: &str) -> bool {
+        let segments: Vec<&str> = path.split('/').collect();
+        let mut excluded = false;
+        for (exception, pattern) in &self.patterns {
+            let matched = (1..=segments.len()).any(|len| matches(pattern, &segments[..len]));
+            if matched {
+                excluded = !exception;
+            }
+        }
+        excluded
+    }
+}
+
+/// A path relative to the context root, without `./`, `..` or doubled
+/// slashes.
+fn clean(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+/// Match path segments against pattern segments, where `**` stands for any
+/// number of segments.
+fn matches(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => (0..=path.len()).any(|skip| matches(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path)) => glob(first.as_bytes(), segment.as_bytes()) && matches(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// Match one segment against a pattern with `*` and `?`.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| glob(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && glob(rest, &text[1..]),
+        Some((b'\\', [escaped, rest @ ..])) => text.first() == Some(escaped) && glob(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && glob(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dockerignore_patterns() {
+        let ignore = Ignore::parse("# build output\ntarget\n*.log\n**/node_modules\n!important.log\n./docs/*.md\n");
+        assert!(ignore.excludes("target"));
+        assert!(ignore.excludes("target/debug/app"));
+        assert!(ignore.excludes("debug.log"));
+        assert!(!ignore.excludes("logs/debug.log"));
+        assert!(!ignore.excludes("important.log"));
+        assert!(ignore.excludes("web/app/node_modules/react/index.js"));
+        assert!(ignore.excludes("docs/README.md"));
+        assert!(!ignore.excludes("docs/api/README.md"));
+        assert!(!ignore.excludes("src/main.rs"));
+    }
+
+    #[test]
+    fn test_archive_dir_skips_ignored_paths() {
+        let root = std::env::temp_dir().join(format!("rustainer-build-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::write(root.join(".dockerignore"), "target\n*.log\nDockerfile\n").unwrap();
+        fs::write(root.join("Dockerfile"), "FROM scratch\n").unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(root.join("build.log"), "").unwrap();
+        fs::write(root.join("target/debug/app"), "").unwrap();
+
+        let archive = archive_dir(&root, "Dockerfile").unwrap();
+        let mut names: Vec<String> = tar::Archive::new(archive.as_slice())
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().trim_end_matches('/').to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, [".dockerignore", "Dockerfile", "src", "src/main.rs"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
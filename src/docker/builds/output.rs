@@ -0,0 +1,132 @@
+//! Build output as plain text lines: the classic builder's messages as they
+//! are, and BuildKit's status updates in the style of `--progress=plain`.
+
+use bollard::models::{BuildInfo, BuildInfoAux};
+use std::collections::HashMap;
+
+/// Turns the messages of one build into lines, remembering the image ID.
+#[derive(Debug, Default)]
+pub struct Output {
+    /// Start of a line the classic builder has not finished yet.
+    partial: String,
+    /// BuildKit steps by digest.
+    steps: HashMap<String, Step>,
+    /// ID of the built image, once the daemon reports it.
+    pub image_id: Option<String>,
+}
+
+#[derive(Debug)]
+struct Step {
+    number: usize,
+    started: bool,
+    done: bool,
+}
+
+impl Output {
+    /// Lines for one message from the daemon.
+    pub fn lines(&mut self, info: BuildInfo) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(stream) = info.stream {
+            self.partial.push_str(&stream);
+            while let Some(end) = self.partial.find('\n') {
+                let line: String = self.partial.drain(..=end).collect();
+                lines.push(line.trim_end().to_string());
+            }
+        }
+        // Pull progress of base images, without the progress bars
+        let progress = info.progress_detail.is_some_and(|detail| detail.current.is_some());
+        if let Some(status) = info.status.filter(|_| !progress) {
+            lines.push(match info.id {
+                Some(id) => format!("{}: {}", id, status),
+                None => status,
+            });
+        }
+        match info.aux {
+            Some(BuildInfoAux::Default(image)) => {
+                if let Some(id) = image.id {
+                    self.image_id = Some(id);
+                }
+            }
+            Some(BuildInfoAux::BuildKit(status)) => {
+                for vertex in status.vertexes {
+                    let count = self.steps.len();
+                    let step = self.steps.entry(vertex.digest).or_insert(Step {
+                        number: count + 1,
+                        started: false,
+                        done: false,
+                    });
+                    if vertex.started.is_some() && !step.started {
+                        step.started = true;
+                        lines.push(format!("#{} {}", step.number, vertex.name));
+                    }
+                    if step.done {
+                        continue;
+                    }
+                    if vertex.cached {
+                        step.done = true;
+                        lines.push(format!("#{} CACHED", step.number));
+                    } else if !vertex.error.is_empty() {
+                        step.done = true;
+                        lines.push(format!("#{} ERROR: {}", step.number, vertex.error));
+                    } else if let (Some(started), Some(completed)) = (&vertex.started, &vertex.completed) {
+                        step.done = true;
+                        let millis = (completed.seconds - started.seconds) * 1000
+                            + i64::from(completed.nanos - started.nanos) / 1_000_000;
+                        lines.push(format!("#{} DONE {:.1}s", step.number, millis.max(0) as f64 / 1000.0));
+                    }
+                }
+                for status in status.statuses.into_iter().filter(|status| status.completed.is_some()) {
+                    if let Some(step) = self.steps.get(&status.vertex) {
+                        lines.push(format!("#{} {} done", step.number, status.id));
+                    }
+                }
+                for log in status.logs {
+                    let number = self.steps.get(&log.vertex).map_or(0, |step| step.number);
+                    let text = String::from_utf8_lossy(&log.msg);
+                    lines.extend(text.lines().map(|line| format!("#{} {}", number, line.trim_end())));
+                }
+                for warning in status.warnings {
+                    lines.push(format!("WARNING: {}", String::from_utf8_lossy(&warning.short)));
+                }
+            }
+            None => {}
+        }
+        lines
+    }
+
+    /// The rest of a line left when the build ended.
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.partial);
+        (!rest.trim().is_empty()).then(|| rest.trim_end().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::ImageId;
+
+    #[test]
+    fn test_classic_output_lines() {
+        let mut output = Output::default();
+        let message = |stream: &str| BuildInfo {
+            stream: Some(stream.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(output.lines(message("Step 1/2 : FROM alpine\n ---> 05455a08881e\nStep 2/2 : RUN ")), [
+            "Step 1/2 : FROM alpine",
+            " ---> 05455a08881e"
+        ]);
+        assert_eq!(output.lines(message("echo hi\n")), ["Step 2/2 : RUN echo hi"]);
+        assert!(output
+            .lines(BuildInfo {
+                aux: Some(BuildInfoAux::Default(ImageId {
+                    id: Some("sha256:abc".to_string())
+                })),
+                ..Default::default()
+            })
+            .is_empty());
+        assert_eq!(output.image_id.as_deref(), Some("sha256:abc"));
+        assert_eq!(output.finish(), None);
+    }
+}
@@ -0,0 +1,1386 @@
+//! Runs compose projects through the Docker API.
+//!
+//! Networks, volumes and containers are named and labelled the way
+//! `docker compose` does it, so a project started here can be inspected or
+//! taken down with the CLI, and the other way round.
+//!
+//! Changed services are updated in batches following `deploy.update_config`.
+//! Containers are drained from the domain proxy before they are stopped, and
+//! replaced ones are kept until their successors are up so a failed update
+//! can be rolled back.
+//!
+//! Secrets from the store are set in a container's environment through
+//! `x-secret-environment`, or written to files in the secrets runtime
+//! directory and bind mounted read-only, as `docker compose` does for file
+//! secrets.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bollard::models::{
+    ContainerCreateBody, ContainerStateStatusEnum, ContainerSummary, ContainerSummaryStateEnum,
+    EndpointIpamConfig, EndpointSettings, HealthConfig, HealthStatusEnum, HostConfig, HostConfigLogConfig,
+    Mount as DockerMount, MountTypeEnum, NetworkConnectRequest, NetworkCreateRequest, NetworkingConfig,
+    PortBinding, RestartPolicy, RestartPolicyNameEnum, VolumeCreateOptions,
+};
+use bollard::query_parameters::{
+    CreateContainerOptions, CreateImageOptions, InspectContainerOptions, ListContainersOptions,
+    ListNetworksOptions, ListVolumesOptions, RemoveContainerOptions, RemoveVolumeOptions,
+    RenameContainerOptions, RestartContainerOptions, StartContainerOptions, StopContainerOptions,
+};
+use bollard::Docker;
+use futures::future::{self, BoxFuture};
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use super::spec::{
+    parse_duration, resolve_path, ComposeFile, Condition, External, FailureAction, MountSource, SecretMount,
+    ServiceSpec, StringOrList, UpdateOrder,
+};
+use crate::registries::{self, Login};
+use crate::secrets::{self, Revealed};
+
+pub const PROJECT_LABEL: &str = "com.docker.compose.project";
+pub const SERVICE_LABEL: &str = "com.docker.compose.service";
+pub const NUMBER_LABEL: &str = "com.docker.compose.container-number";
+pub const ONEOFF_LABEL: &str = "com.docker.compose.oneoff";
+pub const CONFIG_HASH_LABEL: &str = "com.docker.compose.config-hash";
+pub const DEPENDS_ON_LABEL: &str = "com.docker.compose.depends_on";
+pub const WORKING_DIR_LABEL: &str = "com.docker.compose.project.working_dir";
+pub const CONFIG_FILES_LABEL: &str = "com.docker.compose.project.config_files";
+pub const NETWORK_LABEL: &str = "com.docker.compose.network";
+pub const VOLUME_LABEL: &str = "com.docker.compose.volume";
+
+/// How long `up` waits for a dependency to become healthy or finish.
+const DEPENDENCY_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Grace period before a stopping container is killed, as in `docker compose`.
+const DEFAULT_STOP_TIMEOUT: i32 = 10;
+
+/// How long an updated container must stay up before the update moves on,
+/// as in swarm.
+const DEFAULT_MONITOR: Duration = Duration::from_secs(5);
+
+/// How long an updated container may take to become healthy.
+const READY_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Takes containers out of load balancing before they are stopped, so their
+/// requests in flight can finish.
+pub trait Drain: Send + Sync {
+    /// Stop routing new requests to a container and wait for those in flight.
+    fn drain<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, ()>;
+    /// Route requests to a container again once it is back.
+    fn restore(&self, container_id: &str);
+}
+
+static DRAIN: OnceLock<Arc<dyn Drain>> = OnceLock::new();
+
+/// Register the load balancer containers are drained from.
+pub fn set_drain(drain: Arc<dyn Drain>) {
+    let _ = DRAIN.set(drain);
+}
+
+async fn drain(container_id: &str) {
+    if let Some(drain) = DRAIN.get() {
+        drain.drain(container_id).await;
+    }
+}
+
+fn restore(container_id: &str) {
+    if let Some(drain) = DRAIN.get() {
+        drain.restore(container_id);
+    }
+}
+
+/// A compose file bound to a project name and directory.
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub name: String,
+    /// Directory that relative bind mounts are resolved against.
+    pub dir: PathBuf,
+    pub config_file: PathBuf,
+    pub file: ComposeFile,
+    /// Decrypted store secrets used by the services, by their key in the
+    /// file. Loaded before the project is deployed.
+    pub secrets: BTreeMap<String, Revealed>,
+    /// Shared registry credentials images are pulled with, by registry host.
+    pub registries: HashMap<String, Login>,
+}
+
+impl Project {
+    pub fn new(name: &str, config_file: PathBuf, file: ComposeFile) -> Self {
+        let dir = config_file.parent().map(Path::to_path_buf).unwrap_or_default();
+        Self {
+            name: project_name(name),
+            dir,
+            config_file,
+            file,
+            secrets: BTreeMap::new(),
+            registries: HashMap::new(),
+        }
+    }
+
+    /// Docker name of a network from the `networks` section, or `default`.
+    pub fn network_name(&self, key: &str) -> String {
+        match self.file.networks.get(key).cloned().flatten() {
+            Some(spec) => resource_name(&self.name, key, spec.name.as_deref(), &spec.external),
+            None => format!("{}_{}", self.name, key),
+        }
+    }
+
+    /// Docker name of a volume from the `volumes` section.
+    pub fn volume_name(&self, key: &str) -> String {
+        match self.file.volumes.get(key).cloned().flatten() {
+            Some(spec) => resource_name(&self.name, key, spec.name.as_deref(), &spec.external),
+            None => format!("{}_{}", self.name, key),
+        }
+    }
+
+    /// Name of a service's container, `{project}-{service}-{number}`.
+    pub fn container_name(&self, service: &str, number: u32) -> String {
+        match self.file.services.get(service).and_then(|s| s.container_name.clone()) {
+            Some(name) => name,
+            None => format!("{}-{}-{}", self.name, service, number),
+        }
+    }
+
+    /// Networks the project's services join, keyed by their compose name.
+    pub fn used_networks(&self) -> Vec<String> {
+        let mut used: Vec<String> = self
+            .file
+            .services
+            .values()
+            .filter(|service| service.network_mode.is_none())
+            .flat_map(|service| service.networks().into_keys())
+            .collect();
+        used.sort();
+        used.dedup();
+        used
+    }
+
+    /// [`config_hash`] of a service, also covering the secrets it uses so
+    /// that rotating one recreates its containers.
+    pub fn service_hash(&self, name: &str) -> Result<String> {
+        let service = self
+            .file
+            .services
+            .get(name)
+            .ok_or_else(|| anyhow!("No service named {}", name))?;
+        let hash = config_hash(service)?;
+        let digests: Vec<&str> = service
+            .secret_keys()
+            .iter()
+            .filter_map(|key| self.secrets.get(key))
+            .map(|secret| secret.digest.as_str())
+            .collect();
+        if digests.is_empty() {
+            return Ok(hash);
+        }
+        let combined = format!("{}:{}", hash, digests.join(","));
+        Ok(hex(ring::digest::digest(&ring::digest::SHA256, combined.as_bytes()).as_ref()))
+    }
+
+    fn secret(&self, key: &str) -> Result<&Revealed> {
+        self.secrets
+            .get(key)
+            .ok_or_else(|| anyhow!("Secret {} was not loaded from the secrets store", key))
+    }
+
+    /// Host file a store secret is written to for one service.
+    fn secret_path(&self, service: &str, mount: &SecretMount) -> Result<PathBuf> {
+        let file = mount.container_path().trim_start_matches('/').replace('/', "_");
+        Ok(secrets::runtime_dir()?.join(&self.name).join(service).join(file))
+    }
+
+    /// Write the store secrets a service mounts to the runtime directory,
+    /// readable only through the mount.
+    pub fn write_secret_files(&self, service_name: &str) -> Result<()> {
+        use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+
+        let Some(service) = self.file.services.get(service_name) else {
+            return Ok(());
+        };
+        for mount in service.secret_mounts() {
+            if self.file.secrets.get(&mount.source).cloned().flatten().is_some_and(|spec| spec.file.is_some()) {
+                continue;
+            }
+            let secret = self.secret(&mount.source)?;
+            let path = self.secret_path(service_name, &mount)?;
+            let dir = path.parent().unwrap_or(&path);
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+            // Replace the file rather than rewrite it, so running containers
+            // keep the value they started with
+            let staged = path.with_extension("new");
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&staged)
+                .with_context(|| format!("Failed to create {}", staged.display()))?;
+            std::io::Write::write_all(&mut file, secret.value.as_bytes())
+                .with_context(|| format!("Failed to write {}", staged.display()))?;
+            let (uid, gid) = mount.owner()?;
+            if uid.is_some() || gid.is_some() {
+                std::os::unix::fs::chown(&staged, uid, gid)
+                    .with_context(|| format!("Failed to change the owner of {}", staged.display()))?;
+            }
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mount.file_mode()?))
+                .with_context(|| format!("Failed to set the mode of {}", staged.display()))?;
+            std::fs::rename(&staged, &path).with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Remove every secret file written for the project.
+    fn remove_secret_files(&self) -> Result<()> {
+        let Ok(runtime_dir) = secrets::runtime_dir() else {
+            return Ok(());
+        };
+        let dir = runtime_dir.join(&self.name);
+        match std::fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", dir.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn labels(&self) -> HashMap<String, String> {
+        HashMap::from([(PROJECT_LABEL.to_string(), self.name.clone())])
+    }
+
+    /// The request that creates one container of a service.
+    pub fn container_body(&self, service_name: &str, number: u32) -> Result<ContainerCreateBody> {
+        let service = self
+            .file
+            .services
+            .get(service_name)
+            .ok_or_else(|| anyhow!("No service named {}", service_name))?;
+
+        let mut labels: HashMap<String, String> = service
+            .labels
+            .entries('=')
+            .into_iter()
+            .map(|(key, value)| (key, value.unwrap_or_default()))
+            .collect();
+        labels.extend(self.labels());
+        labels.insert(SERVICE_LABEL.to_string(), service_name.to_string());
+        labels.insert(NUMBER_LABEL.to_string(), number.to_string());
+        labels.insert(ONEOFF_LABEL.to_string(), "False".to_string());
+        labels.insert(CONFIG_HASH_LABEL.to_string(), self.service_hash(service_name)?);
+        labels.insert(WORKING_DIR_LABEL.to_string(), self.dir.display().to_string());
+        labels.insert(CONFIG_FILES_LABEL.to_string(), self.config_file.display().to_string());
+        labels.insert(
+            DEPENDS_ON_LABEL.to_string(),
+            service
+                .dependencies()
+                .iter()
+                .map(|(name, dependency)| format!("{}:{}:false", name, condition_name(dependency.condition)))
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+
+        let mut env: Vec<String> = service
+            .environment
+            .entries('=')
+            .into_iter()
+            .filter_map(|(key, value)| Some(format!("{}={}", key, value?)))
+            .collect();
+        for (variable, key) in &service.secret_environment {
+            env.push(format!("{}={}", variable, self.secret(key)?.value));
+        }
+
+        let mut exposed_ports = HashMap::new();
+        let mut port_bindings: HashMap<String, Option<Vec<PortBinding>>> = HashMap::new();
+        for port in &service.ports {
+            for mapping in port.mappings()? {
+                let key = format!("{}/{}", mapping.target, mapping.protocol);
+                exposed_ports.insert(key.clone(), HashMap::new());
+                port_bindings
+                    .entry(key)
+                    .or_insert_with(|| Some(Vec::new()))
+                    .get_or_insert_with(Vec::new)
+                    .push(PortBinding {
+                        host_ip: mapping.host_ip,
+                        host_port: mapping.published,
+                    });
+            }
+        }
+        for port in &service.expose {
+            let port = port.to_string();
+            let key = if port.contains('/') { port } else { format!("{}/tcp", port) };
+            exposed_ports.insert(key, HashMap::new());
+        }
+
+        let mut mounts = Vec::new();
+        for volume in &service.volumes {
+            let mount = volume.mount()?;
+            let (typ, source) = match &mount.source {
+                MountSource::Volume(name) => (MountTypeEnum::VOLUME, Some(self.volume_name(name))),
+                MountSource::Anonymous => (MountTypeEnum::VOLUME, None),
+                MountSource::Bind(path) => (MountTypeEnum::BIND, Some(self.resolve_bind(path))),
+                MountSource::Tmpfs => (MountTypeEnum::TMPFS, None),
+            };
+            mounts.push(DockerMount {
+                target: Some(mount.target),
+                source,
+                typ: Some(typ),
+                read_only: Some(mount.read_only),
+                ..Default::default()
+            });
+        }
+
+        for mount in service.secret_mounts() {
+            let source = match self.file.secrets.get(&mount.source).cloned().flatten().and_then(|spec| spec.file) {
+                Some(file) => file,
+                None => self.secret_path(service_name, &mount)?.display().to_string(),
+            };
+            mounts.push(DockerMount {
+                target: Some(mount.container_path()),
+                source: Some(source),
+                typ: Some(MountTypeEnum::BIND),
+                read_only: Some(true),
+                ..Default::default()
+            });
+        }
+
+        let stop_timeout = stop_timeout(service)?;
+        let networks = service.networks();
+        let (network_mode, networking_config) = match &service.network_mode {
+            Some(mode) => {
+                let mode = match mode.strip_prefix("service:") {
+                    Some(other) => format!("container:{}", self.container_name(other, 1)),
+                    None => mode.clone(),
+                };
+                (Some(mode), None)
+            }
+            None => {
+                // Only the first network can be set at creation, the rest
+                // are connected before the container starts
+                let endpoints = networks
+                    .iter()
+                    .take(1)
+                    .map(|(key, _)| (self.network_name(key), self.endpoint(service_name, service, key)))
+                    .collect();
+                let primary = networks.keys().next().map(|key| self.network_name(key));
+                (primary, Some(NetworkingConfig { endpoints_config: Some(endpoints) }))
+            }
+        };
+
+        let host_config = HostConfig {
+            port_bindings: Some(port_bindings),
+            mounts: Some(mounts),
+            restart_policy: service.restart.as_deref().map(restart_policy).transpose()?,
+            network_mode,
+            privileged: Some(service.privileged),
+            readonly_rootfs: Some(service.read_only),
+            init: service.init,
+            cap_add: Some(service.cap_add.clone()),
+            cap_drop: Some(service.cap_drop.clone()),
+            dns: service.dns.as_ref().map(StringOrList::to_vec),
+            extra_hosts: Some(
+                service
+                    .extra_hosts
+                    .entries(':')
+                    .into_iter()
+                    .map(|(host, ip)| format!("{}:{}", host, ip.unwrap_or_default()))
+                    .collect(),
+            ),
+            tmpfs: service.tmpfs.as_ref().map(|tmpfs| {
+                tmpfs
+                    .to_vec()
+                    .into_iter()
+                    .map(|path| match path.split_once(':') {
+                        Some((path, options)) => (path.to_string(), options.to_string()),
+                        None => (path, String::new()),
+                    })
+                    .collect()
+            }),
+            sysctls: Some(
+                service
+                    .sysctls
+                    .entries('=')
+                    .into_iter()
+                    .map(|(key, value)| (key, value.unwrap_or_default()))
+                    .collect(),
+            ),
+            log_config: service.logging.as_ref().map(|logging| HostConfigLogConfig {
+                typ: logging.driver.clone(),
+                config: Some(logging.options.clone().into_iter().collect()),
+            }),
+            ..Default::default()
+        };
+
+        Ok(ContainerCreateBody {
+            image: service.image.clone(),
+            cmd: service.command.as_ref().map(|c| c.to_vec()).transpose()?,
+            entrypoint: service.entrypoint.as_ref().map(|c| c.to_vec()).transpose()?,
+            env: Some(env),
+            labels: Some(labels),
+            exposed_ports: Some(exposed_ports),
+            hostname: service.hostname.clone(),
+            working_dir: service.working_dir.clone(),
+            user: service.user.clone(),
+            tty: Some(service.tty),
+            open_stdin: Some(service.stdin_open),
+            stop_signal: service.stop_signal.clone(),
+            stop_timeout: Some(stop_timeout as i64),
+            healthcheck: service.healthcheck.as_ref().map(health_config).transpose()?,
+            host_config: Some(host_config),
+            networking_config,
+            ..Default::default()
+        })
+    }
+
+    /// Endpoint settings for a service on one of its networks. The service
+    /// name is always an alias, so other services can reach it by name.
+    fn endpoint(&self, service_name: &str, service: &ServiceSpec, key: &str) -> EndpointSettings {
+        let network = service.networks().remove(key).unwrap_or_default();
+        let mut aliases = vec![service_name.to_string()];
+        aliases.extend(network.aliases);
+        EndpointSettings {
+            aliases: Some(aliases),
+            ipam_config: network.ipv4_address.map(|address| EndpointIpamConfig {
+                ipv4_address: Some(address),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Make a bind source absolute, relative to the project directory.
+    fn resolve_bind(&self, path: &str) -> String {
+        resolve_path(&self.dir, path)
+    }
+}
+
+/// Normalise a name the way `docker compose` does for project names.
+pub fn project_name(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '_' || *c == '-')
+        .collect::<String>()
+        .trim_start_matches(['_', '-'])
+        .to_string()
+}
+
+/// Name of a top-level network or volume. External resources keep their own
+/// name, others are prefixed with the project unless `name` is set.
+fn resource_name(project: &str, key: &str, name: Option<&str>, external: &Option<External>) -> String {
+    if let Some(name) = External::name(external).or(name) {
+        return name.to_string();
+    }
+    if External::is_external(external) {
+        key.to_string()
+    } else {
+        format!("{}_{}", project, key)
+    }
+}
+
+/// A digest of a service's configuration, used to tell when its containers
+/// need to be recreated. How they are updated is left out of it.
+pub fn config_hash(service: &ServiceSpec) -> Result<String> {
+    let mut service = service.clone();
+    if let Some(deploy) = service.deploy.as_mut() {
+        deploy.update_config = None;
+    }
+    let json = serde_json::to_vec(&service).context("Failed to serialize service")?;
+    Ok(hex(ring::digest::digest(&ring::digest::SHA256, &json).as_ref()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn condition_name(condition: Condition) -> &'static str {
+    match condition {
+        Condition::Started => "service_started",
+        Condition::Healthy => "service_healthy",
+        Condition::CompletedSuccessfully => "service_completed_successfully",
+    }
+}
+
+fn restart_policy(policy: &str) -> Result<RestartPolicy> {
+    let (name, retries) = match policy.split_once(':') {
+        Some((name, retries)) => (name, Some(retries.parse().map_err(|_| anyhow!("Invalid restart policy {}", policy))?)),
+        None => (policy, None),
+    };
+    let name = match name {
+        "no" => RestartPolicyNameEnum::NO,
+        "always" => RestartPolicyNameEnum::ALWAYS,
+        "unless-stopped" => RestartPolicyNameEnum::UNLESS_STOPPED,
+        "on-failure" => RestartPolicyNameEnum::ON_FAILURE,
+        _ => bail!("Invalid restart policy {}", policy),
+    };
+    Ok(RestartPolicy {
+        name: Some(name),
+        maximum_retry_count: retries,
+    })
+}
+
+fn health_config(healthcheck: &super::spec::HealthcheckSpec) -> Result<HealthConfig> {
+    let nanos = |value: &Option<String>| -> Result<Option<i64>> {
+        value
+            .as_deref()
+            .map(|value| parse_duration(value).map(|d| d.as_nanos() as i64))
+            .transpose()
+    };
+    let test = if healthcheck.disable {
+        Some(vec!["NONE".to_string()])
+    } else {
+        healthcheck.test.as_ref().map(|test| match test {
+            StringOrList::One(command) => vec!["CMD-SHELL".to_string(), command.clone()],
+            StringOrList::Many(test) => test.clone(),
+        })
+    };
+    Ok(HealthConfig {
+        test,
+        interval: nanos(&healthcheck.interval)?,
+        timeout: nanos(&healthcheck.timeout)?,
+        retries: healthcheck.retries,
+        start_period: nanos(&healthcheck.start_period)?,
+        ..Default::default()
+    })
+}
+
+fn stop_timeout(service: &ServiceSpec) -> Result<i32> {
+    match &service.stop_grace_period {
+        Some(period) => Ok(parse_duration(period)?.as_secs() as i32),
+        None => Ok(DEFAULT_STOP_TIMEOUT),
+    }
+}
+
+/// A service's `update_config` with defaults applied.
+#[derive(Debug, Clone, PartialEq)]
+struct UpdatePolicy {
+    /// Containers per batch.
+    parallelism: usize,
+    delay: Duration,
+    monitor: Duration,
+    max_failure_ratio: f64,
+    failure_action: FailureAction,
+    order: UpdateOrder,
+}
+
+impl UpdatePolicy {
+    /// The policy of a service. Start-first is refused for services that
+    /// publish host ports, as the new container could not bind them while
+    /// the old one runs.
+    fn of(service: &ServiceSpec) -> Result<Self> {
+        let config = service.update_config();
+        let order = config.order.unwrap_or_default();
+        if order == UpdateOrder::StartFirst {
+            for port in &service.ports {
+                if let Some(published) = port.mappings()?.into_iter().find_map(|mapping| mapping.published) {
+                    bail!("start-first cannot be used while publishing host port {}; use stop-first", published);
+                }
+            }
+        }
+        let duration = |value: &Option<String>, default: Duration| -> Result<Duration> {
+            Ok(value.as_deref().map(parse_duration).transpose()?.unwrap_or(default))
+        };
+        Ok(Self {
+            parallelism: match config.parallelism.unwrap_or(1) {
+                0 => usize::MAX,
+                n => n as usize,
+            },
+            delay: duration(&config.delay, Duration::ZERO)?,
+            monitor: duration(&config.monitor, DEFAULT_MONITOR)?,
+            max_failure_ratio: config.max_failure_ratio.unwrap_or(0.0),
+            failure_action: config.failure_action.unwrap_or_default(),
+            order,
+        })
+    }
+
+    /// Whether `failures` out of `total` containers is more than tolerated.
+    fn exceeded(&self, failures: usize, total: usize) -> bool {
+        failures as f64 > self.max_failure_ratio * total as f64
+    }
+}
+
+/// Every container of a project, including stopped ones.
+pub async fn containers(docker: &Docker, project: &str) -> Result<Vec<ContainerSummary>> {
+    let options = ListContainersOptions {
+        all: true,
+        filters: Some(HashMap::from([(
+            "label".to_string(),
+            vec![format!("{}={}", PROJECT_LABEL, project), format!("{}=False", ONEOFF_LABEL)],
+        )])),
+        ..Default::default()
+    };
+    docker
+        .list_containers(Some(options))
+        .await
+        .with_context(|| format!("Failed to list containers of project {}", project))
+}
+
+/// A label of a listed container.
+pub fn label<'a>(container: &'a ContainerSummary, key: &str) -> Option<&'a str> {
+    container.labels.as_ref()?.get(key).map(String::as_str)
+}
+
+/// The replica number of a listed container.
+pub fn container_number(container: &ContainerSummary) -> u32 {
+    label(container, NUMBER_LABEL).and_then(|n| n.parse().ok()).unwrap_or(0)
+}
+
+/// Whether a listed container is running or restarting.
+pub fn is_running(container: &ContainerSummary) -> bool {
+    matches!(
+        container.state,
+        Some(ContainerSummaryStateEnum::RUNNING) | Some(ContainerSummaryStateEnum::RESTARTING)
+    )
+}
+
+/// Create networks and volumes, then create or update every service's
+/// containers in dependency order and start them. Containers of services
+/// that are no longer in the file are removed.
+pub async fn up(docker: &Docker, project: &Project) -> Result<()> {
+    ensure_networks(docker, project).await?;
+    ensure_volumes(docker, project).await?;
+
+    let existing = containers(docker, &project.name).await?;
+    for container in &existing {
+        let service = label(container, SERVICE_LABEL).unwrap_or_default();
+        if !project.file.services.contains_key(service) {
+            remove_container(docker, container, DEFAULT_STOP_TIMEOUT).await?;
+        }
+    }
+
+    for name in project.file.start_order()? {
+        let service = &project.file.services[&name];
+        wait_for_dependencies(docker, project, service).await?;
+        converge(docker, project, &name, service.replicas()).await?;
+    }
+    Ok(())
+}
+
+/// Stop and remove every container in reverse dependency order, then the
+/// project's networks, and its volumes when asked to.
+pub async fn down(docker: &Docker, project: &Project, remove_volumes: bool) -> Result<()> {
+    let mut existing = containers(docker, &project.name).await?;
+    let order = project.file.start_order()?;
+    // Unknown services first, then dependents before their dependencies
+    existing.sort_by_key(|container| {
+        let service = label(container, SERVICE_LABEL).unwrap_or_default();
+        std::cmp::Reverse(order.iter().position(|name| name == service).map_or(0, |i| i + 1))
+    });
+    for container in &existing {
+        let service = label(container, SERVICE_LABEL).unwrap_or_default();
+        let timeout = match project.file.services.get(service) {
+            Some(spec) => stop_timeout(spec)?,
+            None => DEFAULT_STOP_TIMEOUT,
+        };
+        remove_container(docker, container, timeout).await?;
+    }
+    project.remove_secret_files()?;
+
+    let filters = HashMap::from([("label".to_string(), vec![format!("{}={}", PROJECT_LABEL, project.name)])]);
+    let networks = docker
+        .list_networks(Some(ListNetworksOptions { filters: Some(filters.clone()) }))
+        .await
+        .context("Failed to list project networks")?;
+    for network in networks {
+        if let Some(name) = network.name {
+            info!("Removing network {}", name);
+            docker
+                .remove_network(&name)
+                .await
+                .with_context(|| format!("Failed to remove network {}", name))?;
+        }
+    }
+
+    if remove_volumes {
+        let volumes = docker
+            .list_volumes(Some(ListVolumesOptions { filters: Some(filters) }))
+            .await
+            .context("Failed to list project volumes")?;
+        for volume in volumes.volumes.unwrap_or_default() {
+            info!("Removing volume {}", volume.name);
+            docker
+                .remove_volume(&volume.name, None::<RemoveVolumeOptions>)
+                .await
+                .with_context(|| format!("Failed to remove volume {}", volume.name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Restart every existing container in dependency order. Each service is
+/// restarted in batches as its `update_config` says, waiting for every batch
+/// to come back up before the next.
+pub async fn restart(docker: &Docker, project: &Project) -> Result<()> {
+    let existing = containers(docker, &project.name).await?;
+    for name in project.file.start_order()? {
+        let service = &project.file.services[&name];
+        wait_for_dependencies(docker, project, service).await?;
+        let policy = UpdatePolicy::of(service)?;
+        let timeout = stop_timeout(service)?;
+        project.write_secret_files(&name)?;
+        let own: Vec<&ContainerSummary> =
+            existing.iter().filter(|c| label(c, SERVICE_LABEL) == Some(name.as_str())).collect();
+        for (index, batch) in own.chunks(policy.parallelism).enumerate() {
+            if index > 0 {
+                tokio::time::sleep(policy.delay).await;
+            }
+            future::try_join_all(batch.iter().map(|container| restart_container(docker, container, timeout, &policy)))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn restart_container(docker: &Docker, container: &ContainerSummary, timeout: i32, policy: &UpdatePolicy) -> Result<()> {
+    let id = container.id.as_deref().ok_or_else(|| anyhow!("Container has no ID"))?;
+    let name = display_name(container);
+    drain(id).await;
+    let restarted = docker
+        .restart_container(id, Some(RestartContainerOptions { t: Some(timeout), ..Default::default() }))
+        .await
+        .with_context(|| format!("Failed to restart container {}", name));
+    restore(id);
+    restarted?;
+    wait_ready(docker, id, name, policy.monitor).await
+}
+
+/// Run a service with a different number of containers until the next `up`.
+pub async fn scale(docker: &Docker, project: &Project, service: &str, replicas: u32) -> Result<()> {
+    let spec = project
+        .file
+        .services
+        .get(service)
+        .ok_or_else(|| anyhow!("No service named {}", service))?;
+    if spec.container_name.is_some() && replicas > 1 {
+        bail!("Service {} sets container_name and cannot be scaled", service);
+    }
+    if replicas > 0 {
+        ensure_networks(docker, project).await?;
+        ensure_volumes(docker, project).await?;
+    }
+    converge(docker, project, service, replicas).await
+}
+
+/// Whether a container has to be recreated to match its service's config
+/// hash, or the image its tag now points to.
+pub fn is_outdated(container: &ContainerSummary, hash: &str, image_id: Option<&str>) -> bool {
+    label(container, CONFIG_HASH_LABEL) != Some(hash)
+        || image_id.is_some_and(|id| container.image_id.as_deref() != Some(id))
+}
+
+/// ID of the local image a reference points to, if it is present.
+pub async fn local_image_id(docker: &Docker, image: &str) -> Option<String> {
+    docker.inspect_image(image).await.ok()?.id
+}
+
+/// Make a service run exactly `replicas` up to date containers.
+async fn converge(docker: &Docker, project: &Project, name: &str, replicas: u32) -> Result<()> {
+    let service = &project.file.services[name];
+    let hash = project.service_hash(name)?;
+    let timeout = stop_timeout(service)?;
+
+    let mut image_id = None;
+    if replicas > 0 {
+        project.write_secret_files(name)?;
+        if let Some(image) = &service.image {
+            pull_if_missing(docker, project, image).await?;
+            image_id = local_image_id(docker, image).await;
+        }
+    }
+
+    // Running containers that are out of date are replaced by a rolling
+    // update once the missing ones are up; stopped ones can simply go.
+    let mut current = BTreeMap::new();
+    let mut outdated = Vec::new();
+    for container in containers(docker, &project.name).await? {
+        if label(&container, SERVICE_LABEL) != Some(name) {
+            continue;
+        }
+        let number = container_number(&container);
+        if number == 0 || number > replicas {
+            remove_container(docker, &container, timeout).await?;
+        } else if !is_outdated(&container, &hash, image_id.as_deref()) {
+            current.insert(number, container);
+        } else if is_running(&container) {
+            outdated.push(container);
+        } else {
+            remove_container(docker, &container, timeout).await?;
+        }
+    }
+
+    for number in 1..=replicas {
+        let id = match current.get(&number) {
+            Some(container) if is_running(container) => continue,
+            Some(container) => container.id.clone().unwrap_or_default(),
+            None if outdated.iter().any(|c| container_number(c) == number) => continue,
+            None => create_container(docker, project, name, number, &project.container_name(name, number)).await?,
+        };
+        docker
+            .start_container(&id, None::<StartContainerOptions>)
+            .await
+            .with_context(|| format!("Failed to start container {} of service {}", number, name))?;
+    }
+
+    if !outdated.is_empty() {
+        rolling_update(docker, project, name, &outdated).await?;
+    }
+    Ok(())
+}
+
+/// A container swapped out by a rolling update. The old container is kept,
+/// stopped, until the update is over so it can be brought back.
+#[derive(Debug, Default)]
+struct Replacement {
+    /// The name the service's container goes by.
+    name: String,
+    old_id: String,
+    /// Whether the old container was stopped.
+    stopped: bool,
+    /// The name the old container was moved to.
+    backup: Option<String>,
+    new_id: Option<String>,
+}
+
+/// Replace a service's outdated containers batch by batch. When more of them
+/// fail to come up than `max_failure_ratio` allows, `failure_action` decides
+/// whether to carry on, stop where the update is, or roll it all back.
+async fn rolling_update(docker: &Docker, project: &Project, name: &str, outdated: &[ContainerSummary]) -> Result<()> {
+    let policy = UpdatePolicy::of(&project.file.services[name])?;
+    let mut replaced = Vec::new();
+    let mut failures = Vec::new();
+
+    for (index, batch) in outdated.chunks(policy.parallelism).enumerate() {
+        if index > 0 {
+            tokio::time::sleep(policy.delay).await;
+        }
+        info!("Updating {} container(s) of service {}", batch.len(), name);
+        let results = future::join_all(batch.iter().map(|old| replace_container(docker, project, name, old, &policy))).await;
+        for (replacement, result) in results {
+            if let Err(e) = result {
+                warn!("Failed to update container {}: {:#}", replacement.name, e);
+                failures.push(e);
+            }
+            replaced.push(replacement);
+        }
+
+        if !policy.exceeded(failures.len(), outdated.len()) {
+            continue;
+        }
+        match policy.failure_action {
+            FailureAction::Continue => {}
+            FailureAction::Pause => {
+                discard_backups(docker, &replaced).await?;
+                return Err(failures.swap_remove(0).context(format!("Update of service {} paused", name)));
+            }
+            FailureAction::Rollback => {
+                roll_back(docker, name, &replaced).await?;
+                let error = failures.swap_remove(0);
+                return Err(error.context(format!("Update of service {} failed and was rolled back", name)));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        warn!("{} container(s) of service {} failed to update", failures.len(), name);
+    }
+    discard_backups(docker, &replaced).await
+}
+
+async fn replace_container(
+    docker: &Docker,
+    project: &Project,
+    service: &str,
+    old: &ContainerSummary,
+    policy: &UpdatePolicy,
+) -> (Replacement, Result<()>) {
+    let number = container_number(old);
+    let mut replacement = Replacement {
+        name: project.container_name(service, number),
+        old_id: old.id.clone().unwrap_or_default(),
+        ..Default::default()
+    };
+    let result = replace(docker, project, service, number, policy, &mut replacement).await;
+    (replacement, result)
+}
+
+/// Swap one container for a new one, in the order the policy asks for.
+async fn replace(
+    docker: &Docker,
+    project: &Project,
+    service: &str,
+    number: u32,
+    policy: &UpdatePolicy,
+    replacement: &mut Replacement,
+) -> Result<()> {
+    let timeout = stop_timeout(&project.file.services[service])?;
+    match policy.order {
+        UpdateOrder::StopFirst => {
+            retire(docker, replacement, timeout).await?;
+            let id = create_container(docker, project, service, number, &replacement.name).await?;
+            replacement.new_id = Some(id.clone());
+            start(docker, &id, &replacement.name).await?;
+            wait_ready(docker, &id, &replacement.name, policy.monitor).await
+        }
+        UpdateOrder::StartFirst => {
+            let next = format!("{}-next", replacement.name);
+            let id = create_container(docker, project, service, number, &next).await?;
+            replacement.new_id = Some(id.clone());
+            start(docker, &id, &next).await?;
+            wait_ready(docker, &id, &next, policy.monitor).await?;
+            retire(docker, replacement, timeout).await?;
+            rename(docker, &id, &replacement.name).await
+        }
+    }
+}
+
+/// Drain and stop a container being replaced, and move it out of the way of
+/// its successor's name.
+async fn retire(docker: &Docker, replacement: &mut Replacement, timeout: i32) -> Result<()> {
+    drain(&replacement.old_id).await;
+    docker
+        .stop_container(&replacement.old_id, Some(StopContainerOptions { t: Some(timeout), ..Default::default() }))
+        .await
+        .with_context(|| format!("Failed to stop container {}", replacement.name))?;
+    replacement.stopped = true;
+
+    let short: String = replacement.old_id.chars().take(12).collect();
+    let backup = format!("{}-{}", replacement.name, short);
+    rename(docker, &replacement.old_id, &backup).await?;
+    replacement.backup = Some(backup);
+    Ok(())
+}
+
+/// Undo a rolling update: remove the new containers and bring the old ones
+/// back under their names.
+async fn roll_back(docker: &Docker, service: &str, replaced: &[Replacement]) -> Result<()> {
+    warn!("Rolling back the update of service {}", service);
+    for replacement in replaced.iter().rev() {
+        if let Some(id) = &replacement.new_id {
+            drain(id).await;
+            docker
+                .remove_container(id, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+                .await
+                .with_context(|| format!("Failed to remove the new container {}", replacement.name))?;
+        }
+        if replacement.backup.is_some() {
+            rename(docker, &replacement.old_id, &replacement.name).await?;
+        }
+        if replacement.stopped {
+            start(docker, &replacement.old_id, &replacement.name).await?;
+        }
+        restore(&replacement.old_id);
+    }
+    Ok(())
+}
+
+/// Remove the old containers kept for a rollback.
+async fn discard_backups(docker: &Docker, replaced: &[Replacement]) -> Result<()> {
+    for replacement in replaced.iter().filter(|r| r.stopped) {
+        docker
+            .remove_container(&replacement.old_id, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+            .await
+            .with_context(|| format!("Failed to remove the old container {}", replacement.name))?;
+    }
+    Ok(())
+}
+
+/// Wait until a new container is healthy, or has kept running for `monitor`
+/// when it has no healthcheck.
+pub async fn wait_ready(docker: &Docker, id: &str, name: &str, monitor: Duration) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        let state = docker
+            .inspect_container(id, None::<InspectContainerOptions>)
+            .await
+            .with_context(|| format!("Failed to inspect container {}", name))?
+            .state
+            .unwrap_or_default();
+        match state.status {
+            // One-off services are done once they exit cleanly
+            Some(ContainerStateStatusEnum::EXITED) if state.exit_code == Some(0) => return Ok(()),
+            Some(ContainerStateStatusEnum::EXITED) | Some(ContainerStateStatusEnum::DEAD) => {
+                bail!("Container {} exited with code {}", name, state.exit_code.unwrap_or(-1))
+            }
+            Some(ContainerStateStatusEnum::RESTARTING) => bail!("Container {} keeps restarting", name),
+            _ => {}
+        }
+
+        let monitored = started.elapsed() >= monitor;
+        match state.health.and_then(|health| health.status) {
+            Some(HealthStatusEnum::UNHEALTHY) => bail!("Container {} is unhealthy", name),
+            Some(HealthStatusEnum::STARTING) => {}
+            _ if monitored => return Ok(()),
+            _ => {}
+        }
+        if started.elapsed() > READY_TIMEOUT {
+            bail!("Container {} did not become healthy within {:?}", name, READY_TIMEOUT);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn start(docker: &Docker, id: &str, name: &str) -> Result<()> {
+    docker
+        .start_container(id, None::<StartContainerOptions>)
+        .await
+        .with_context(|| format!("Failed to start container {}", name))
+}
+
+async fn rename(docker: &Docker, id: &str, name: &str) -> Result<()> {
+    docker
+        .rename_container(id, RenameContainerOptions { name: name.to_string() })
+        .await
+        .with_context(|| format!("Failed to rename container to {}", name))
+}
+
+async fn create_container(
+    docker: &Docker,
+    project: &Project,
+    service: &str,
+    number: u32,
+    container_name: &str,
+) -> Result<String> {
+    info!("Creating container {}", container_name);
+    let body = project.container_body(service, number)?;
+    let options = CreateContainerOptions {
+        name: Some(container_name.to_string()),
+        ..Default::default()
+    };
+    let id = docker
+        .create_container(Some(options), body)
+        .await
+        .with_context(|| format!("Failed to create container {}", container_name))?
+        .id;
+
+    let spec = &project.file.services[service];
+    if spec.network_mode.is_none() {
+        for key in spec.networks().keys().skip(1) {
+            let network = project.network_name(key);
+            let request = NetworkConnectRequest {
+                container: Some(id.clone()),
+                endpoint_config: Some(project.endpoint(service, spec, key)),
+            };
+            docker
+                .connect_network(&network, request)
+                .await
+                .with_context(|| format!("Failed to connect {} to network {}", container_name, network))?;
+        }
+    }
+    Ok(id)
+}
+
+/// A listed container's name, or its ID when it has none.
+fn display_name(container: &ContainerSummary) -> &str {
+    container
+        .names
+        .iter()
+        .flatten()
+        .next()
+        .map(|name| name.trim_start_matches('/'))
+        .or(container.id.as_deref())
+        .unwrap_or_default()
+}
+
+async fn remove_container(docker: &Docker, container: &ContainerSummary, timeout: i32) -> Result<()> {
+    let id = container.id.as_deref().ok_or_else(|| anyhow!("Container has no ID"))?;
+    let name = display_name(container);
+    info!("Removing container {}", name);
+
+    if is_running(container) {
+        drain(id).await;
+        docker
+            .stop_container(id, Some(StopContainerOptions { t: Some(timeout), ..Default::default() }))
+            .await
+            .with_context(|| format!("Failed to stop container {}", name))?;
+    }
+    docker
+        .remove_container(id, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+        .await
+        .with_context(|| format!("Failed to remove container {}", name))
+}
+
+async fn ensure_networks(docker: &Docker, project: &Project) -> Result<()> {
+    for key in project.used_networks() {
+        let name = project.network_name(&key);
+        let spec = project.file.networks.get(&key).cloned().flatten().unwrap_or_default();
+        if docker.inspect_network(&name, None::<bollard::query_parameters::InspectNetworkOptions>).await.is_ok() {
+            continue;
+        }
+        if External::is_external(&spec.external) {
+            bail!("External network {} does not exist", name);
+        }
+
+        info!("Creating network {}", name);
+        let mut labels: HashMap<String, String> = spec
+            .labels
+            .entries('=')
+            .into_iter()
+            .map(|(key, value)| (key, value.unwrap_or_default()))
+            .collect();
+        labels.extend(project.labels());
+        labels.insert(NETWORK_LABEL.to_string(), key.clone());
+        let request = NetworkCreateRequest {
+            name: name.clone(),
+            driver: spec.driver.clone(),
+            internal: Some(spec.internal),
+            attachable: Some(spec.attachable),
+            options: Some(spec.driver_opts.clone().into_iter().collect()),
+            labels: Some(labels),
+            ..Default::default()
+        };
+        docker
+            .create_network(request)
+            .await
+            .with_context(|| format!("Failed to create network {}", name))?;
+    }
+    Ok(())
+}
+
+async fn ensure_volumes(docker: &Docker, project: &Project) -> Result<()> {
+    for (key, spec) in &project.file.volumes {
+        let name = project.volume_name(key);
+        let spec = spec.clone().unwrap_or_default();
+        if docker.inspect_volume(&name).await.is_ok() {
+            continue;
+        }
+        if External::is_external(&spec.external) {
+            bail!("External volume {} does not exist", name);
+        }
+
+        info!("Creating volume {}", name);
+        let mut labels: HashMap<String, String> = spec
+            .labels
+            .entries('=')
+            .into_iter()
+            .map(|(key, value)| (key, value.unwrap_or_default()))
+            .collect();
+        labels.extend(project.labels());
+        labels.insert(VOLUME_LABEL.to_string(), key.clone());
+        let options = VolumeCreateOptions {
+            name: Some(name.clone()),
+            driver: spec.driver.clone(),
+            driver_opts: Some(spec.driver_opts.clone().into_iter().collect()),
+            labels: Some(labels),
+            ..Default::default()
+        };
+        docker
+            .create_volume(options)
+            .await
+            .with_context(|| format!("Failed to create volume {}", name))?;
+    }
+    Ok(())
+}
+
+/// Pull an image unless it is already present, with the stored credentials
+/// of its registry.
+async fn pull_if_missing(docker: &Docker, project: &Project, image: &str) -> Result<()> {
+    if docker.inspect_image(image).await.is_ok() {
+        return Ok(());
+    }
+
+    info!("Pulling image {}", image);
+    let options = CreateImageOptions {
+        from_image: Some(image.to_string()),
+        ..Default::default()
+    };
+    let credentials = project.registries.get(&registries::image_host(image)).map(Login::docker_credentials);
+    let mut stream = docker.create_image(Some(options), None, credentials);
+    while let Some(progress) = stream.next().await {
+        progress.with_context(|| format!("Failed to pull image {}", image))?;
+    }
+    Ok(())
+}
+
+/// Block until the dependencies of a service meet their conditions.
+async fn wait_for_dependencies(docker: &Docker, project: &Project, service: &ServiceSpec) -> Result<()> {
+    for (name, dependency) in service.dependencies() {
+        if dependency.condition == Condition::Started {
+            continue;
+        }
+
+        let deadline = Instant::now() + DEPENDENCY_TIMEOUT;
+        loop {
+            if dependency_ready(docker, project, &name, dependency.condition).await? {
+                break;
+            }
+            if Instant::now() > deadline {
+                bail!("Timed out waiting for service {} to be {}", name, condition_name(dependency.condition));
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+    Ok(())
+}
+
+async fn dependency_ready(docker: &Docker, project: &Project, name: &str, condition: Condition) -> Result<bool> {
+    let existing = containers(docker, &project.name).await?;
+    for container in existing.iter().filter(|c| label(c, SERVICE_LABEL) == Some(name)) {
+        let id = container.id.as_deref().unwrap_or_default();
+        let state = docker
+            .inspect_container(id, None::<InspectContainerOptions>)
+            .await
+            .with_context(|| format!("Failed to inspect container of service {}", name))?
+            .state
+            .unwrap_or_default();
+
+        match condition {
+            Condition::Started => {}
+            Condition::Healthy => match state.health.and_then(|h| h.status) {
+                Some(HealthStatusEnum::HEALTHY) => {}
+                Some(HealthStatusEnum::UNHEALTHY) => bail!("Dependency {} is unhealthy", name),
+                Some(HealthStatusEnum::STARTING) => return Ok(false),
+                _ => bail!("Dependency {} has no healthcheck", name),
+            },
+            Condition::CompletedSuccessfully => match state.status {
+                Some(ContainerStateStatusEnum::EXITED) | Some(ContainerStateStatusEnum::DEAD) => {
+                    if state.exit_code != Some(0) {
+                        bail!("Dependency {} exited with code {}", name, state.exit_code.unwrap_or(-1));
+                    }
+                }
+                _ => return Ok(false),
+            },
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+services:
+  web:
+    image: nginx
+    ports: ["8080:80"]
+    volumes: ["data:/data", "./html:/html:ro"]
+    networks:
+      front:
+        aliases: [www]
+      back:
+  cache:
+    image: redis
+    network_mode: service:web
+networks:
+  front:
+  back:
+    external: true
+volumes:
+  data:
+"#;
+
+    #[test]
+    fn test_names_and_labels_follow_compose() {
+        let file = ComposeFile::parse(FILE, &Default::default()).unwrap();
+        let project = Project::new("My Stack", PathBuf::from("/srv/stack/compose.yml"), file);
+
+        assert_eq!(project.name, "mystack");
+        assert_eq!(project.network_name("front"), "mystack_front");
+        assert_eq!(project.network_name("back"), "back");
+        assert_eq!(project.network_name("default"), "mystack_default");
+        assert_eq!(project.volume_name("data"), "mystack_data");
+        assert_eq!(project.container_name("web", 2), "mystack-web-2");
+
+        let body = project.container_body("web", 2).unwrap();
+        let labels = body.labels.unwrap();
+        assert_eq!(labels[PROJECT_LABEL], "mystack");
+        assert_eq!(labels[SERVICE_LABEL], "web");
+        assert_eq!(labels[NUMBER_LABEL], "2");
+        assert_eq!(labels[CONFIG_HASH_LABEL].len(), 64);
+
+        let host = body.host_config.unwrap();
+        assert_eq!(host.network_mode.as_deref(), Some("back"));
+        let mounts = host.mounts.unwrap();
+        assert_eq!(mounts[0].source.as_deref(), Some("mystack_data"));
+        assert_eq!(mounts[1].source.as_deref(), Some("/srv/stack/html"));
+        let binding = &host.port_bindings.unwrap()["80/tcp"];
+        assert_eq!(binding.as_ref().unwrap()[0].host_port.as_deref(), Some("8080"));
+
+        let cache = project.container_body("cache", 1).unwrap().host_config.unwrap();
+        assert_eq!(cache.network_mode.as_deref(), Some("container:mystack-web-1"));
+    }
+
+    #[test]
+    fn test_update_policy_defaults_and_batches() {
+        let content = "\
+services:
+  web:
+    image: nginx
+    deploy:
+      update_config: {parallelism: 0, delay: 10s, order: start-first, max_failure_ratio: 0.5}
+  api:
+    image: api
+";
+        let file = ComposeFile::parse(content, &Default::default()).unwrap();
+
+        let api = UpdatePolicy::of(&file.services["api"]).unwrap();
+        assert_eq!(api.parallelism, 1);
+        assert_eq!(api.monitor, DEFAULT_MONITOR);
+        assert_eq!((api.order, api.failure_action), (UpdateOrder::StopFirst, FailureAction::Rollback));
+        assert!(api.exceeded(1, 4));
+
+        let web = UpdatePolicy::of(&file.services["web"]).unwrap();
+        assert_eq!((web.parallelism, web.delay), (usize::MAX, Duration::from_secs(10)));
+        assert_eq!(web.order, UpdateOrder::StartFirst);
+        assert!(!web.exceeded(2, 4));
+        assert!(web.exceeded(3, 4));
+
+        // Changing only how a service updates does not recreate it
+        let mut changed = file.services["web"].clone();
+        changed.deploy.as_mut().unwrap().update_config = None;
+        assert_eq!(config_hash(&changed).unwrap(), config_hash(&file.services["web"]).unwrap());
+
+        // A new container could not bind the old one's host port
+        let mut published = file.services["web"].clone();
+        published.ports = serde_yaml::from_str("[\"443\", \"127.0.0.1:8443:443\"]").unwrap();
+        let e = UpdatePolicy::of(&published).unwrap_err();
+        assert_eq!(e.to_string(), "start-first cannot be used while publishing host port 8443; use stop-first");
+        published.ports = serde_yaml::from_str("[\"443\"]").unwrap();
+        assert_eq!(UpdatePolicy::of(&published).unwrap().order, UpdateOrder::StartFirst);
+    }
+
+    #[test]
+    fn test_secrets_reach_env_and_mounts() {
+        let content = r#"
+services:
+  web:
+    image: nginx
+    secrets: [{source: cert, target: tls/cert.pem}]
+    x-secret-environment:
+      DB_PASSWORD: db
+secrets:
+  db: {external: true}
+  cert: {file: ./cert.pem}
+"#;
+        let mut file = ComposeFile::parse(content, &Default::default()).unwrap();
+        file.resolve("stack", Path::new("/srv/stack"), &Default::default(), |_| Ok(String::new())).unwrap();
+        let mut project = Project::new("stack", PathBuf::from("/srv/stack/compose.yml"), file);
+        assert!(project.container_body("web", 1).is_err());
+
+        let reveal = |value: &str, digest: &str| Revealed {
+            value: value.to_string(),
+            digest: digest.to_string(),
+        };
+        project.secrets.insert("db".to_string(), reveal("hunter22", "d1"));
+        let body = project.container_body("web", 1).unwrap();
+        assert!(body.env.unwrap().contains(&"DB_PASSWORD=hunter22".to_string()));
+        let mount = body.host_config.unwrap().mounts.unwrap().remove(0);
+        assert_eq!(mount.source.as_deref(), Some("/srv/stack/cert.pem"));
+        assert_eq!(mount.target.as_deref(), Some("/run/secrets/tls/cert.pem"));
+        assert_eq!(mount.read_only, Some(true));
+        assert!(!format!("{:?}", project).contains("hunter22"));
+
+        // A rotated secret changes the hash, so the containers are recreated
+        let hash = project.service_hash("web").unwrap();
+        assert_ne!(hash, config_hash(&project.file.services["web"]).unwrap());
+        project.secrets.insert("db".to_string(), reveal("hunter23", "d2"));
+        assert_ne!(project.service_hash("web").unwrap(), hash);
+    }
+}
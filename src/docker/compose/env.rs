@@ -0,0 +1,282 @@
+//! Variable interpolation and `.env` files.
+//!
+//! Compose files may refer to variables as `$VAR` or `${VAR}`, with the
+//! `:-`, `-`, `:?`, `?`, `:+` and `+` modifiers, and `$$` for a literal `$`.
+//! A stack's variables are its `.env` file, overridden by the environment
+//! stored with the stack. Rustainer's own process environment is never used,
+//! so stacks cannot read the server's secrets.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// Variables available to a compose file.
+pub type Variables = BTreeMap<String, String>;
+
+/// Build a stack's variables from its `.env` file and stored environment.
+/// Errors carry the 1-based line of the `.env` file.
+pub fn variables(dotenv: Option<&str>, environment: &HashMap<String, String>) -> Result<Variables, (usize, String)> {
+    let stored: Variables = environment.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    let mut variables = match dotenv {
+        Some(content) => parse(content, &stored)?,
+        None => Variables::new(),
+    };
+    variables.extend(stored);
+    Ok(variables)
+}
+
+/// Substitute variables in a string.
+pub fn interpolate(input: &str, variables: &Variables) -> Result<String, String> {
+    let invalid = || format!("invalid interpolation format in {:?}", input);
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(tail) = after.strip_prefix('$') {
+            out.push('$');
+            rest = tail;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            let end = closing_brace(braced).ok_or_else(invalid)?;
+            out.push_str(&expand(&braced[..end], variables).ok_or_else(invalid)??);
+            rest = &braced[end + 1..];
+        } else {
+            let len = name_len(after);
+            if len == 0 {
+                return Err(invalid());
+            }
+            out.push_str(variables.get(&after[..len]).map_or("", String::as_str));
+            rest = &after[len..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Expand the inside of `${...}`, or `None` if it is malformed.
+fn expand(body: &str, variables: &Variables) -> Option<Result<String, String>> {
+    let len = name_len(body);
+    if len == 0 {
+        return None;
+    }
+    let (name, modifier) = body.split_at(len);
+    let value = variables.get(name);
+    let empty = value.is_none_or(String::is_empty);
+
+    let (op, arg) = ["-", "?", "+"]
+        .iter()
+        .find_map(|op| {
+            modifier
+                .strip_prefix(&format!(":{}", op))
+                .map(|arg| (format!(":{}", op), arg))
+                .or_else(|| modifier.strip_prefix(op).map(|arg| (op.to_string(), arg)))
+        })
+        .unwrap_or_default();
+    if op.is_empty() && !modifier.is_empty() {
+        return None;
+    }
+
+    let unset = if op.starts_with(':') { empty } else { value.is_none() };
+    Some(match op.trim_start_matches(':') {
+        "" => Ok(value.cloned().unwrap_or_default()),
+        "-" if unset => interpolate(arg, variables),
+        "?" if unset => {
+            let message = match interpolate(arg, variables) {
+                Ok(message) => message,
+                Err(e) => return Some(Err(e)),
+            };
+            if message.is_empty() {
+                Err(format!("required variable {} is missing a value", name))
+            } else {
+                Err(format!("required variable {} is missing a value: {}", name, message))
+            }
+        }
+        "+" if !unset => interpolate(arg, variables),
+        "+" => Ok(String::new()),
+        _ => Ok(value.cloned().unwrap_or_default()),
+    })
+}
+
+/// Index of the `}` that closes a `${`, allowing nested `${...}`.
+fn closing_brace(braced: &str) -> Option<usize> {
+    let bytes = braced.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'$' if bytes.get(i + 1) == Some(&b'$') => i += 1,
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                depth += 1;
+                i += 1;
+            }
+            b'}' if depth == 0 => return Some(i),
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Length of the variable name at the start of `s`.
+fn name_len(s: &str) -> usize {
+    let mut chars = s.char_indices();
+    match chars.next() {
+        Some((_, c)) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return 0,
+    }
+    chars
+        .find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_'))
+        .map_or(s.len(), |(i, _)| i)
+}
+
+/// Parse an env file. Values may be single-quoted (literal), double-quoted
+/// (with escapes, possibly over several lines) or bare, and are interpolated
+/// with earlier entries and then `variables`. A bare `KEY` takes its value
+/// from `variables`, and is skipped if there is none.
+pub fn parse(content: &str, variables: &Variables) -> Result<Variables, (usize, String)> {
+    let mut parsed = Variables::new();
+    let mut lines = content.lines().enumerate().map(|(i, line)| (i + 1, line));
+    while let Some((number, line)) = lines.next() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+        let lookup = |parsed: &Variables| -> Variables {
+            let mut scope = variables.clone();
+            scope.extend(parsed.iter().map(|(k, v)| (k.clone(), v.clone())));
+            scope
+        };
+
+        let Some((key, value)) = line.split_once('=') else {
+            let key = line.trim();
+            if name_len(key) != key.len() {
+                return Err((number, format!("invalid variable name {:?}", key)));
+            }
+            if let Some(value) = variables.get(key) {
+                parsed.insert(key.to_string(), value.clone());
+            }
+            continue;
+        };
+        let key = key.trim_end();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)) {
+            return Err((number, format!("invalid variable name {:?}", key)));
+        }
+        let value = value.trim_start();
+
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            let mut text = quoted.to_string();
+            while !text.contains('\'') {
+                let (_, next) = lines.next().ok_or((number, "unterminated single quote".to_string()))?;
+                text.push('\n');
+                text.push_str(next);
+            }
+            text[..text.find('\'').unwrap_or(text.len())].to_string()
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            let mut text = quoted.to_string();
+            let end = loop {
+                if let Some(end) = unescaped_quote(&text) {
+                    break end;
+                }
+                let (_, next) = lines.next().ok_or((number, "unterminated double quote".to_string()))?;
+                text.push('\n');
+                text.push_str(next);
+            };
+            let unescaped = unescape(&text[..end]);
+            interpolate(&unescaped, &lookup(&parsed)).map_err(|e| (number, e))?
+        } else {
+            let bare = match value.find(" #").or_else(|| value.find("\t#")) {
+                Some(comment) => &value[..comment],
+                None => value,
+            };
+            interpolate(bare.trim_end(), &lookup(&parsed)).map_err(|e| (number, e))?
+        };
+        parsed.insert(key.to_string(), value);
+    }
+    Ok(parsed)
+}
+
+/// Index of the first `"` not preceded by a backslash escape.
+fn unescaped_quote(text: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Resolve escapes in a double-quoted value. `\$` becomes `$$`, so it stays
+/// a literal `$` through interpolation.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('$') => out.push_str("$$"),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate_modifiers() {
+        let vars = Variables::from([
+            ("TAG".to_string(), "1.25".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+        let run = |input: &str| interpolate(input, &vars);
+
+        assert_eq!(run("nginx:$TAG-${TAG}").unwrap(), "nginx:1.25-1.25");
+        assert_eq!(run("${EMPTY:-dflt} ${EMPTY-dflt} ${UNSET-dflt}").unwrap(), "dflt  dflt");
+        assert_eq!(run("${UNSET:-${TAG:+v$TAG}}").unwrap(), "v1.25");
+        assert_eq!(run("${EMPTY+set} ${EMPTY:+set}").unwrap(), "set ");
+        assert_eq!(run("cost: $$5").unwrap(), "cost: $5");
+        assert_eq!(run("${EMPTY?}").unwrap(), "");
+        assert_eq!(run("${EMPTY:?need a tag}").unwrap_err(), "required variable EMPTY is missing a value: need a tag");
+        assert!(run("${TAG").is_err());
+        assert!(run("$1").is_err());
+        assert!(run("${TAG/x}").is_err());
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let content = "\
+# comment
+export HOST=db.local   # inline comment
+URL=postgres://${HOST}:${PORT:-5432}
+SINGLE='literal $HOST'
+DOUBLE=\"line one
+line \\\"two\\\" \\$HOME\"
+FROM_STACK
+MISSING
+";
+        let vars = Variables::from([("FROM_STACK".to_string(), "yes".to_string())]);
+        let parsed = parse(content, &vars).unwrap();
+        assert_eq!(parsed["HOST"], "db.local");
+        assert_eq!(parsed["URL"], "postgres://db.local:5432");
+        assert_eq!(parsed["SINGLE"], "literal $HOST");
+        assert_eq!(parsed["DOUBLE"], "line one\nline \"two\" $HOME");
+        assert_eq!(parsed["FROM_STACK"], "yes");
+        assert!(!parsed.contains_key("MISSING"));
+        assert_eq!(parse("A=1\nbad key=2\n", &vars).unwrap_err().0, 2);
+
+        let stored = HashMap::from([("HOST".to_string(), "override".to_string())]);
+        assert_eq!(variables(Some(content), &stored).unwrap()["HOST"], "override");
+    }
+}
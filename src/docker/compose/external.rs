@@ -0,0 +1,386 @@
+//! Compose projects started outside Rustainer.
+//!
+//! Containers started by `docker compose` carry the project name, working
+//! directory and compose files as labels. Projects that no stack owns are
+//! listed as read-only external stacks, with their status built from the
+//! containers alone. Adopting one imports its compose file and `.env` as a
+//! new stack under the same project name, so the running containers become
+//! the stack's containers.
+
+use anyhow::{Context, Result};
+use bollard::models::ContainerSummary;
+use bollard::query_parameters::ListContainersOptions;
+use bollard::Docker;
+use chrono::{DateTime, Utc};
+use serde_yaml::{Mapping, Value};
+use sqlx::{Pool, Sqlite};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use super::engine::{self, label, CONFIG_FILES_LABEL, ONEOFF_LABEL, PROJECT_LABEL, SERVICE_LABEL, WORKING_DIR_LABEL};
+use super::{spec, DOTENV_FILE};
+use crate::auth::Claims;
+use crate::docker::updates;
+use crate::models::compose::{AdoptStackRequest, ComposeService, ComposeStack, CreateStackRequest, StackStatus};
+use crate::models::update::{ImageUpdate, UpdatePolicy};
+
+/// Returned when changing a project that Rustainer does not manage.
+#[derive(Debug)]
+pub struct ExternalStack(pub String);
+
+impl std::fmt::Display for ExternalStack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "project {} was started outside Rustainer; adopt it to manage it", self.0)
+    }
+}
+
+impl std::error::Error for ExternalStack {}
+
+/// Returned when a project cannot be adopted as it is.
+#[derive(Debug)]
+pub struct NotAdoptable(pub String);
+
+impl std::fmt::Display for NotAdoptable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotAdoptable {}
+
+/// Projects of stacks that Rustainer manages.
+async fn managed_projects(db: &Pool<Sqlite>) -> Result<HashSet<String>> {
+    Ok(super::all_records(db)
+        .await?
+        .iter()
+        .map(|record| engine::project_name(&record.name))
+        .collect())
+}
+
+/// Containers of compose projects, by project, leaving out one-off
+/// containers of `docker compose run`.
+async fn project_containers(docker: &Docker, project: Option<&str>) -> Result<BTreeMap<String, Vec<ContainerSummary>>> {
+    let filter = match project {
+        Some(project) => format!("{}={}", PROJECT_LABEL, project),
+        None => PROJECT_LABEL.to_string(),
+    };
+    let options = ListContainersOptions {
+        all: true,
+        filters: Some(HashMap::from([("label".to_string(), vec![filter])])),
+        ..Default::default()
+    };
+    let containers = docker
+        .list_containers(Some(options))
+        .await
+        .context("Failed to list compose containers")?;
+
+    let mut projects: BTreeMap<String, Vec<ContainerSummary>> = BTreeMap::new();
+    for container in containers {
+        if label(&container, ONEOFF_LABEL) == Some("True") {
+            continue;
+        }
+        if let Some(project) = label(&container, PROJECT_LABEL) {
+            projects.entry(project.to_string()).or_default().push(container);
+        }
+    }
+    Ok(projects)
+}
+
+/// List projects that have containers but no stack.
+pub async fn list(db: &Pool<Sqlite>, docker: &Docker) -> Result<Vec<ComposeStack>> {
+    let managed = managed_projects(db).await?;
+    let updates = updates::by_container(db).await?;
+    Ok(project_containers(docker, None)
+        .await?
+        .into_iter()
+        .filter(|(project, _)| !managed.contains(project))
+        .map(|(project, containers)| external_status(&project, &containers, &updates))
+        .collect())
+}
+
+/// Get an external project by name.
+pub async fn get(db: &Pool<Sqlite>, docker: &Docker, project: &str) -> Result<Option<ComposeStack>> {
+    if managed_projects(db).await?.contains(project) {
+        return Ok(None);
+    }
+    let mut projects = project_containers(docker, Some(project)).await?;
+    let updates = updates::by_container(db).await?;
+    Ok(projects
+        .remove(project)
+        .map(|containers| external_status(project, &containers, &updates)))
+}
+
+/// Import an external project's compose file and `.env` as a stack by
+/// `author`. Its containers are left running; the next `up` recreates them
+/// with Rustainer's labels.
+pub async fn adopt(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    project: &str,
+    request: AdoptStackRequest,
+    caller: Option<&Claims>,
+) -> Result<Option<ComposeStack>> {
+    if managed_projects(db).await?.contains(project) {
+        return Ok(None);
+    }
+    let Some(containers) = project_containers(docker, Some(project)).await?.remove(project) else {
+        return Ok(None);
+    };
+
+    let name = request.name.unwrap_or_else(|| project.to_string());
+    if engine::project_name(&name) != project {
+        return Err(NotAdoptable(format!("stack name {:?} does not give project name {}", name, project)).into());
+    }
+    let first = &containers[0];
+    let (Some(working_dir), Some(config_files)) = (label(first, WORKING_DIR_LABEL), label(first, CONFIG_FILES_LABEL))
+    else {
+        return Err(NotAdoptable(format!("project {} has no working directory or compose file labels", project)).into());
+    };
+    let config_files: Vec<&str> = config_files.split(',').filter(|path| !path.is_empty()).collect();
+    let [config_file] = config_files[..] else {
+        return Err(NotAdoptable(format!(
+            "project {} uses {} compose files; merge them into one first",
+            project,
+            config_files.len()
+        ))
+        .into());
+    };
+
+    let content = fs::read_to_string(config_file)
+        .map_err(|e| NotAdoptable(format!("cannot read compose file {}: {}", config_file, e)))?;
+    let dotenv_path = Path::new(working_dir).join(DOTENV_FILE);
+    let dotenv = match fs::read_to_string(&dotenv_path) {
+        Ok(dotenv) => Some(dotenv),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dotenv_path.display())),
+    };
+
+    let images: BTreeMap<String, String> = containers
+        .iter()
+        .filter_map(|c| Some((label(c, SERVICE_LABEL)?.to_string(), c.image.clone()?)))
+        .collect();
+    let content = rewrite(&content, Path::new(working_dir), &images)
+        .map_err(|e| NotAdoptable(format!("cannot adopt compose file {}: {:#}", config_file, e)))?;
+
+    let request = CreateStackRequest {
+        name,
+        compose_content: content,
+        start: false,
+        environment: None,
+        env_files: Some(dotenv.map(|dotenv| (DOTENV_FILE.to_string(), dotenv)).into_iter().collect()),
+        message: Some(request.message.unwrap_or_else(|| format!("Adopted from {}", config_file))),
+        update_policy: UpdatePolicy::default(),
+    };
+    Ok(Some(super::create_stack(db, docker, request, caller).await?))
+}
+
+/// Make a compose file work from the stack directory. Relative host paths
+/// are made absolute against the project's working directory, and services
+/// that were built get the image their containers run. The file is only
+/// rewritten when something had to change.
+fn rewrite(content: &str, working_dir: &Path, images: &BTreeMap<String, String>) -> Result<String> {
+    let mut root: Value = serde_yaml::from_str(content).context("Invalid YAML")?;
+    root.apply_merge().context("Invalid merge key")?;
+    let mut changed = false;
+    let absolute = |value: &mut Value| match value.as_str().filter(|path| is_relative(path)) {
+        Some(path) => {
+            *value = Value::String(spec::resolve_path(working_dir, path));
+            true
+        }
+        None => false,
+    };
+
+    if let Some(secrets) = root.get_mut("secrets").and_then(Value::as_mapping_mut) {
+        for secret in secrets.values_mut() {
+            if let Some(file) = secret.get_mut("file") {
+                changed |= absolute(file);
+            }
+        }
+    }
+
+    let services = root.get_mut("services").and_then(Value::as_mapping_mut);
+    for (name, service) in services.into_iter().flat_map(Mapping::iter_mut) {
+        let Some(service) = service.as_mapping_mut() else {
+            continue;
+        };
+        if let Some(volumes) = service.get_mut("volumes").and_then(Value::as_sequence_mut) {
+            for volume in volumes {
+                match volume {
+                    Value::String(short) => {
+                        if let Some((source, rest)) = short.split_once(':') {
+                            if is_relative(source) && source.starts_with('.') {
+                                *short = format!("{}:{}", spec::resolve_path(working_dir, source), rest);
+                                changed = true;
+                            }
+                        }
+                    }
+                    Value::Mapping(long) if long.get("type").and_then(Value::as_str) == Some("bind") => {
+                        if let Some(source) = long.get_mut("source") {
+                            changed |= absolute(source);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        match service.get_mut("env_file") {
+            Some(Value::Sequence(files)) => {
+                for file in files {
+                    match file {
+                        Value::Mapping(long) => {
+                            if let Some(path) = long.get_mut("path") {
+                                changed |= absolute(path);
+                            }
+                        }
+                        file => changed |= absolute(file),
+                    }
+                }
+            }
+            Some(file) => changed |= absolute(file),
+            None => {}
+        }
+        let built = service.contains_key("build") && !service.contains_key("image");
+        if let (true, Some(image)) = (built, name.as_str().and_then(|name| images.get(name))) {
+            service.insert(Value::from("image"), Value::from(image.as_str()));
+            changed = true;
+        }
+    }
+
+    if !changed {
+        return Ok(content.to_string());
+    }
+    serde_yaml::to_string(&root).context("Failed to write compose file")
+}
+
+/// Whether a host path is relative to the project directory. Paths starting
+/// with a variable are left alone, as they are only known once interpolated.
+fn is_relative(path: &str) -> bool {
+    !(path.is_empty() || path.starts_with('/') || path.starts_with('~') || path.starts_with('$'))
+}
+
+/// Build the API view of an external project from its containers.
+fn external_status(project: &str, containers: &[ContainerSummary], updates: &HashMap<String, ImageUpdate>) -> ComposeStack {
+    let created = |c: &ContainerSummary| c.created.and_then(|created| DateTime::<Utc>::from_timestamp(created, 0));
+    let mut services: BTreeMap<&str, Vec<&ContainerSummary>> = BTreeMap::new();
+    for container in containers {
+        services
+            .entry(label(container, SERVICE_LABEL).unwrap_or_default())
+            .or_default()
+            .push(container);
+    }
+
+    let services: Vec<ComposeService> = services
+        .into_iter()
+        .map(|(name, mut own)| {
+            own.sort_by_key(|c| engine::container_number(c));
+            let running: Vec<_> = own.iter().filter(|c| engine::is_running(c)).collect();
+            let shown = running.first().copied().unwrap_or(&own[0]);
+            let collect = |items: &mut dyn Iterator<Item = String>| -> Vec<String> {
+                items.collect::<BTreeSet<_>>().into_iter().collect()
+            };
+            ComposeService {
+                name: name.to_string(),
+                image: shown.image.clone().unwrap_or_default(),
+                status: match running.is_empty() {
+                    false => "running".to_string(),
+                    true => shown.state.map(|s| s.to_string()).unwrap_or_default(),
+                },
+                container_id: shown.id.clone(),
+                replicas: own.len() as u32,
+                running: running.len() as u32,
+                ports: Some(collect(&mut own.iter().flat_map(|c| c.ports.iter().flatten()).map(|port| {
+                    let protocol = port.typ.map(|typ| typ.to_string()).unwrap_or_else(|| "tcp".to_string());
+                    match port.public_port {
+                        Some(public) => format!("{}:{}/{}", public, port.private_port, protocol),
+                        None => format!("{}/{}", port.private_port, protocol),
+                    }
+                }))),
+                volumes: Some(collect(
+                    &mut own.iter().flat_map(|c| c.mounts.iter().flatten()).filter_map(|m| m.destination.clone()),
+                )),
+                networks: Some(collect(&mut own.iter().flat_map(|c| {
+                    c.network_settings
+                        .iter()
+                        .flat_map(|settings| settings.networks.iter().flatten())
+                        .map(|(name, _)| name.clone())
+                }))),
+                environment: None,
+                depends_on: Some(
+                    label(shown, engine::DEPENDS_ON_LABEL)
+                        .unwrap_or_default()
+                        .split(',')
+                        .filter_map(|dependency| dependency.split(':').next())
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                ),
+                update_available: running.iter().any(|c| updates::available(updates, c)),
+            }
+        })
+        .collect();
+
+    let running = services.iter().filter(|service| service.running > 0).count();
+    let update_available = services.iter().any(|service| service.update_available);
+    ComposeStack {
+        id: project.to_string(),
+        name: project.to_string(),
+        project: project.to_string(),
+        file_path: containers
+            .first()
+            .and_then(|c| label(c, CONFIG_FILES_LABEL))
+            .unwrap_or_default()
+            .to_string(),
+        status: match running {
+            0 => StackStatus::Down,
+            n if n == services.len() => StackStatus::Up,
+            _ => StackStatus::Partial,
+        },
+        created_at: containers.iter().filter_map(created).min().unwrap_or_default(),
+        updated_at: containers.iter().filter_map(created).max().unwrap_or_default(),
+        services,
+        environment: None,
+        env_files: Vec::new(),
+        version: None,
+        git: None,
+        external: true,
+        update_policy: UpdatePolicy::default(),
+        update_available,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_makes_host_paths_absolute() {
+        let dir = Path::new("/home/me/blog");
+        let images = BTreeMap::from([("web".to_string(), "blog-web".to_string())]);
+
+        let untouched = "services:\n  db:\n    image: postgres  # pinned later\n    volumes: [\"data:/var/lib/postgresql/data\"]\n";
+        assert_eq!(rewrite(untouched, dir, &images).unwrap(), untouched);
+
+        let content = r#"
+services:
+  web:
+    build: .
+    env_file: [./web.env, {path: /etc/shared.env}]
+    volumes:
+      - ./html:/usr/share/nginx/html:ro
+      - logs:/var/log
+      - {type: bind, source: ../certs, target: /certs}
+secrets:
+  token: {file: ./token.txt}
+"#;
+        let rewritten: Value = serde_yaml::from_str(&rewrite(content, dir, &images).unwrap()).unwrap();
+        let web = &rewritten["services"]["web"];
+        assert_eq!(web["image"], "blog-web");
+        assert_eq!(web["env_file"][0], "/home/me/blog/web.env");
+        assert_eq!(web["env_file"][1]["path"], "/etc/shared.env");
+        assert_eq!(web["volumes"][0], "/home/me/blog/html:/usr/share/nginx/html:ro");
+        assert_eq!(web["volumes"][1], "logs:/var/log");
+        assert_eq!(web["volumes"][2]["source"], "/home/me/certs");
+        assert_eq!(rewritten["secrets"]["token"]["file"], "/home/me/blog/token.txt");
+    }
+}
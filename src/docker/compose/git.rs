@@ -0,0 +1,746 @@
+//! Stacks deployed from git repositories.
+//!
+//! A git-backed stack keeps a checkout of its branch in `repo/` inside the
+//! stack directory, and its compose file is a path in that checkout. The
+//! branch is fetched on the stack's poll interval, when a signed webhook
+//! arrives or on request. A new head commit is validated before it is checked
+//! out, recorded as a revision and, if the stack is running, deployed. The
+//! engine only recreates services whose configuration changed.
+//!
+//! Repositories are fetched with the `git` CLI. HTTPS credentials are passed
+//! as an `Authorization` header and SSH keys through a temporary key file, so
+//! neither ends up in the checkout's config. Both, and webhook secrets, are
+//! encrypted in the database with the secrets key.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bollard::Docker;
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use sqlx::{sqlite::SqliteRow, Pool, Row, Sqlite};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::spec::ComposeFile;
+use super::{engine, StackRecord, DOTENV_FILE};
+use crate::auth::Claims;
+use crate::secrets;
+use crate::models::compose::{ComposeStack, CreateGitStackRequest, GitSource, GitSourceRequest, SyncResult};
+use crate::proxy::AppState;
+
+/// Directory of the checkout inside a stack's directory.
+const REPO_DIR: &str = "repo";
+
+/// How often sources are checked for being due a poll.
+const POLL_TICK: Duration = Duration::from_secs(15);
+
+/// Transports that repositories may use. Others, like `ext::`, can run
+/// commands.
+const ALLOWED_PROTOCOLS: &str = "file:git:http:https:ssh";
+
+/// Syncs run one at a time, so a webhook and a poll cannot check out the same
+/// stack at once.
+static SYNCS: Mutex<()> = Mutex::const_new(());
+
+/// Returned when saving a stack whose compose file comes from git.
+#[derive(Debug)]
+pub struct ManagedByGit(pub String);
+
+impl std::fmt::Display for ManagedByGit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stack is deployed from {}; push changes there instead", self.0)
+    }
+}
+
+impl std::error::Error for ManagedByGit {}
+
+/// What to do with a webhook delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Webhook {
+    /// The signature is valid, so the stack should be synced.
+    Accepted,
+    /// The push was to another branch.
+    Ignored,
+    /// The stack has no webhook secret or the signature is wrong.
+    Forbidden,
+}
+
+/// A row of the `stack_sources` table.
+#[derive(Debug, Clone, Default)]
+struct Source {
+    stack_id: String,
+    repository: String,
+    branch: String,
+    path: String,
+    username: Option<String>,
+    password: Option<String>,
+    ssh_key: Option<String>,
+    poll_interval: u64,
+    webhook_secret: Option<String>,
+    commit: Option<String>,
+    checked_at: Option<DateTime<Utc>>,
+    error: Option<String>,
+}
+
+impl Source {
+    /// Apply a request, keeping secrets it leaves out and removing empty ones.
+    fn update(&mut self, request: GitSourceRequest) {
+        let keep = |current: &mut Option<String>, new: Option<String>| {
+            if let Some(value) = new {
+                *current = Some(value).filter(|value| !value.is_empty());
+            }
+        };
+        self.repository = request.repository;
+        self.branch = request.branch;
+        self.path = request.path;
+        self.poll_interval = request.poll_interval;
+        keep(&mut self.username, request.username);
+        keep(&mut self.password, request.password);
+        keep(&mut self.ssh_key, request.ssh_key);
+        keep(&mut self.webhook_secret, request.webhook_secret);
+    }
+
+    fn info(&self) -> GitSource {
+        GitSource {
+            repository: self.repository.clone(),
+            branch: self.branch.clone(),
+            path: self.path.clone(),
+            poll_interval: self.poll_interval,
+            credentials: self.password.is_some() || self.ssh_key.is_some(),
+            webhook: self.webhook_secret.is_some(),
+            commit: self.commit.clone(),
+            checked_at: self.checked_at,
+            error: self.error.clone(),
+        }
+    }
+
+    /// Path of the compose file relative to the stack directory.
+    fn compose_path(&self) -> String {
+        format!("{}/{}", REPO_DIR, self.path)
+    }
+
+    /// Path of the `.env` file next to the compose file in the repository.
+    fn dotenv_path(&self) -> String {
+        match self.path.rsplit_once('/') {
+            Some((dir, _)) => format!("{}/{}", dir, DOTENV_FILE),
+            None => DOTENV_FILE.to_string(),
+        }
+    }
+}
+
+/// Check that a repository, branch and path are safe to pass to git.
+pub fn check_source(request: &GitSourceRequest) -> Result<()> {
+    if request.repository.is_empty() || request.repository.starts_with('-') {
+        bail!("Invalid repository URL {:?}", request.repository);
+    }
+    let branch = &request.branch;
+    let bad_branch = branch.is_empty()
+        || branch.starts_with('-')
+        || branch.starts_with('/')
+        || branch.ends_with('/')
+        || branch.ends_with(".lock")
+        || branch.contains("..")
+        || branch.contains("@{")
+        || branch.chars().any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c));
+    if bad_branch {
+        bail!("Invalid branch name {:?}", branch);
+    }
+    let path = Path::new(&request.path);
+    let inside = path.components().count() > 0 && path.components().all(|c| matches!(c, Component::Normal(_)));
+    if !inside || request.path.contains('\\') {
+        bail!("Invalid compose file path {:?}", request.path);
+    }
+    Ok(())
+}
+
+/// Check a webhook signature, either `sha256=<hex>` or bare hex, against the
+/// HMAC-SHA256 of the body.
+pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let hex = signature.trim();
+    let hex = hex.strip_prefix("sha256=").unwrap_or(hex);
+    let Some(tag) = decode_hex(hex) else {
+        return false;
+    };
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::verify(&key, body, &tag).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Shortened commit hash for messages.
+fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(7)]
+}
+
+/// A checkout of a source's branch.
+struct Checkout<'a> {
+    dir: PathBuf,
+    source: &'a Source,
+    key_file: Option<PathBuf>,
+}
+
+impl<'a> Checkout<'a> {
+    /// Open the checkout in `dir`, creating the repository if needed.
+    async fn open(dir: PathBuf, source: &'a Source) -> Result<Checkout<'a>> {
+        let mut checkout = Checkout {
+            dir,
+            source,
+            key_file: None,
+        };
+        if let Some(key) = &source.ssh_key {
+            let path = std::env::temp_dir().join(format!("rustainer-git-key-{}", Uuid::new_v4()));
+            write_private(&path, key)?;
+            checkout.key_file = Some(path);
+        }
+        if !checkout.dir.join(".git").exists() {
+            fs::create_dir_all(&checkout.dir)
+                .with_context(|| format!("Failed to create {}", checkout.dir.display()))?;
+            checkout.run(&["init", "-q"]).await?;
+        }
+        Ok(checkout)
+    }
+
+    fn command(&self, args: &[&str]) -> Result<Command> {
+        let mut command = Command::new("git");
+        command
+            .args(args)
+            .current_dir(&self.dir)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_ALLOW_PROTOCOL", ALLOWED_PROTOCOLS)
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+        if let Some(password) = &self.source.password {
+            let username = self.source.username.as_deref().unwrap_or("git");
+            let credentials = STANDARD.encode(format!("{}:{}", username, password));
+            command
+                .env("GIT_CONFIG_COUNT", "1")
+                .env("GIT_CONFIG_KEY_0", "http.extraHeader")
+                .env("GIT_CONFIG_VALUE_0", format!("Authorization: Basic {}", credentials));
+        }
+        if let Some(key_file) = &self.key_file {
+            let key_file = key_file.to_str().context("Key file path is not valid UTF-8")?;
+            let quoted = shlex::try_quote(key_file).context("Key file path cannot be quoted")?;
+            command.env(
+                "GIT_SSH_COMMAND",
+                format!(
+                    "ssh -i {} -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new -o BatchMode=yes",
+                    quoted
+                ),
+            );
+        }
+        Ok(command)
+    }
+
+    async fn output(&self, args: &[&str]) -> Result<std::process::Output> {
+        self.command(args)?
+            .output()
+            .await
+            .with_context(|| format!("Failed to run git {}", args[0]))
+    }
+
+    /// Run git and return its trimmed output, failing with its error message.
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        let output = self.output(args).await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("git {} failed: {}", args[0], stderr.trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Fetch the head of the branch, returning its commit.
+    async fn fetch(&self) -> Result<String> {
+        let refspec = format!("refs/heads/{}", self.source.branch);
+        self.run(&["fetch", "-q", "--depth", "1", "--no-tags", "--", &self.source.repository, &refspec])
+            .await?;
+        self.run(&["rev-parse", "--verify", "FETCH_HEAD^{commit}"]).await
+    }
+
+    /// Content of a file at a commit, or `None` if it does not exist.
+    async fn read(&self, commit: &str, path: &str) -> Result<Option<String>> {
+        let object = format!("{}:{}", commit, path);
+        if !self.output(&["cat-file", "-e", &object]).await?.status.success() {
+            return Ok(None);
+        }
+        let output = self.output(&["cat-file", "blob", &object]).await?;
+        if !output.status.success() {
+            bail!("{} is not a file", path);
+        }
+        let content = String::from_utf8(output.stdout).with_context(|| format!("{} is not valid UTF-8", path))?;
+        Ok(Some(content))
+    }
+
+    /// Check out a commit. Untracked files, such as data in relative bind
+    /// mounts, are left alone.
+    async fn checkout(&self, commit: &str) -> Result<()> {
+        self.run(&["checkout", "-q", "--force", "--detach", commit]).await?;
+        Ok(())
+    }
+
+    /// Author name and subject of a commit.
+    async fn describe(&self, commit: &str) -> Result<(String, String)> {
+        let line = self.run(&["log", "-1", "--format=%an%x00%s", commit]).await?;
+        let (author, subject) = line.split_once('\0').unwrap_or((&line, ""));
+        Ok((author.to_string(), subject.to_string()))
+    }
+}
+
+impl Drop for Checkout<'_> {
+    fn drop(&mut self) {
+        if let Some(key_file) = &self.key_file {
+            let _ = fs::remove_file(key_file);
+        }
+    }
+}
+
+/// Write a file that only the owner can read.
+fn write_private(path: &Path, content: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    // ssh refuses keys without a final newline
+    let content = if content.ends_with('\n') { content.to_string() } else { format!("{}\n", content) };
+    file.write_all(content.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Services added, changed or removed between two resolved compose files.
+fn changed_services(before: Option<&ComposeFile>, after: &ComposeFile) -> Result<Vec<String>> {
+    let mut changed = BTreeSet::new();
+    for (name, service) in &after.services {
+        let old = before.and_then(|file| file.services.get(name));
+        if old.map(engine::config_hash).transpose()? != Some(engine::config_hash(service)?) {
+            changed.insert(name.clone());
+        }
+    }
+    if let Some(before) = before {
+        changed.extend(before.services.keys().filter(|name| !after.services.contains_key(*name)).cloned());
+    }
+    Ok(changed.into_iter().collect())
+}
+
+/// Fetch a stack's branch and check out its head, recording it as a revision.
+/// Returns `None` if the head is already checked out, unless `force` is set.
+async fn pull(
+    db: &Pool<Sqlite>,
+    record: &mut StackRecord,
+    source: &Source,
+    force: bool,
+) -> Result<Option<SyncResult>> {
+    let checkout = Checkout::open(record.root()?.join(REPO_DIR), source).await?;
+    let commit = checkout.fetch().await?;
+    if !force && source.commit.as_deref() == Some(commit.as_str()) {
+        return Ok(None);
+    }
+
+    let content = checkout
+        .read(&commit, &source.path)
+        .await?
+        .ok_or_else(|| anyhow!("{} does not exist on {} at {}", source.path, source.branch, short(&commit)))?;
+    let dotenv = checkout.read(&commit, &source.dotenv_path()).await?;
+    let variables = super::stack_variables(dotenv.as_deref(), &record.environment)?;
+    ComposeFile::parse(&content, &variables)?;
+
+    let before = record.project().ok().map(|project| project.file);
+    checkout.checkout(&commit).await?;
+    record.compose_path = Some(source.compose_path());
+    let changed_services = changed_services(before.as_ref(), &record.project()?.file)?;
+
+    record.updated_at = Utc::now();
+    sqlx::query("UPDATE stacks SET compose_path = ?, updated_at = ? WHERE id = ?")
+        .bind(&record.compose_path)
+        .bind(record.updated_at)
+        .bind(&record.id)
+        .execute(db)
+        .await
+        .context("Failed to update stack in database")?;
+    let (author, subject) = checkout.describe(&commit).await?;
+    let message = format!("{} {}", short(&commit), subject);
+    record.record_revision(db, Some(&author), Some(&message)).await?;
+
+    Ok(Some(SyncResult {
+        commit,
+        changed_services,
+        deployed: false,
+    }))
+}
+
+/// Create a stack from a git repository, deployed by `caller`. The branch is
+/// fetched and validated before the stack is saved.
+pub async fn create_stack(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    request: CreateGitStackRequest,
+    caller: Option<&Claims>,
+) -> Result<ComposeStack> {
+    check_source(&request.source)?;
+    super::check_project_name(db, &request.name).await?;
+
+    let mut record = StackRecord::new(request.name, request.environment.unwrap_or_default(), None);
+    record.update_policy = request.update_policy;
+    let mut source = Source {
+        stack_id: record.id.clone(),
+        ..Source::default()
+    };
+    source.update(request.source);
+
+    let _sync = SYNCS.lock().await;
+    let pulled = match pull(db, &mut record, &source, true).await {
+        Ok(pulled) => pulled,
+        Err(e) => {
+            super::revisions::delete_all(db, &record.id).await?;
+            let root = record.root()?;
+            if root.exists() {
+                fs::remove_dir_all(&root).with_context(|| format!("Failed to remove {}", root.display()))?;
+            }
+            return Err(e);
+        }
+    };
+    source.commit = pulled.map(|pulled| pulled.commit);
+    source.checked_at = Some(Utc::now());
+    super::insert_record(db, &record).await?;
+    save_source(db, &source).await?;
+    record.git = Some(source.info());
+
+    if request.start {
+        engine::up(docker, &record.deploy_project(db, caller).await?).await?;
+    }
+    super::stack_status(db, docker, &record).await
+}
+
+/// Point a git-backed stack at a new repository, branch or path, and sync it.
+pub async fn update_source(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    stack_id: &str,
+    request: GitSourceRequest,
+) -> Result<Option<SyncResult>> {
+    check_source(&request)?;
+    {
+        let _sync = SYNCS.lock().await;
+        let Some(mut source) = load(db, stack_id).await? else {
+            return Ok(None);
+        };
+        source.update(request);
+        save_source(db, &source).await?;
+    }
+    sync(db, docker, stack_id, true).await
+}
+
+/// Check a git-backed stack for a new commit, checking it out and deploying
+/// it if the stack is running. `force` pulls the head again even if it is
+/// already checked out. Returns `None` if the stack is not deployed from git.
+pub async fn sync(db: &Pool<Sqlite>, docker: &Docker, stack_id: &str, force: bool) -> Result<Option<SyncResult>> {
+    let _sync = SYNCS.lock().await;
+    let Some(mut record) = super::get_record(db, stack_id).await? else {
+        return Ok(None);
+    };
+    let Some(mut source) = load(db, stack_id).await? else {
+        return Ok(None);
+    };
+
+    let result: Result<SyncResult> = async {
+        let Some(mut result) = pull(db, &mut record, &source, force).await? else {
+            return Ok(SyncResult {
+                commit: source.commit.clone().unwrap_or_default(),
+                changed_services: Vec::new(),
+                deployed: false,
+            });
+        };
+        let project = record.project()?;
+        if !engine::containers(docker, &project.name).await?.is_empty() {
+            engine::up(docker, &record.deploy_project(db, None).await?).await?;
+            result.deployed = true;
+        }
+        Ok(result)
+    }
+    .await;
+
+    source.checked_at = Some(Utc::now());
+    match &result {
+        Ok(result) => {
+            source.commit = Some(result.commit.clone());
+            source.error = None;
+        }
+        Err(e) => source.error = Some(format!("{:#}", e)),
+    }
+    save_source(db, &source).await?;
+    result.map(Some)
+}
+
+/// Decide what to do with a webhook for a stack, given its signature header
+/// and raw body. Returns `None` if the stack is not deployed from git.
+pub async fn check_webhook(
+    db: &Pool<Sqlite>,
+    stack_id: &str,
+    signature: Option<&str>,
+    body: &[u8],
+) -> Result<Option<Webhook>> {
+    let Some(source) = load(db, stack_id).await? else {
+        return Ok(None);
+    };
+    let (Some(secret), Some(signature)) = (&source.webhook_secret, signature) else {
+        return Ok(Some(Webhook::Forbidden));
+    };
+    if !verify_signature(secret, body, signature) {
+        return Ok(Some(Webhook::Forbidden));
+    }
+
+    // Push events name their branch; anything else just triggers a sync
+    let pushed = serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|event| event.get("ref")?.as_str().map(str::to_string));
+    if pushed.is_some_and(|pushed| pushed != format!("refs/heads/{}", source.branch)) {
+        return Ok(Some(Webhook::Ignored));
+    }
+    Ok(Some(Webhook::Accepted))
+}
+
+/// Poll git-backed stacks on their intervals in the background.
+pub fn spawn_poller(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(POLL_TICK);
+        loop {
+            ticker.tick().await;
+            let due = match due_sources(&state.db).await {
+                Ok(due) => due,
+                Err(e) => {
+                    warn!("Failed to load git sources: {:#}", e);
+                    continue;
+                }
+            };
+            for stack_id in due {
+                if let Err(e) = sync(&state.db, &state.docker, &stack_id, false).await {
+                    warn!("Failed to sync stack {} from git: {:#}", stack_id, e);
+                }
+            }
+        }
+    });
+}
+
+/// The public view of a stack's source, if it is deployed from git.
+pub async fn source_info(db: &Pool<Sqlite>, stack_id: &str) -> Result<Option<GitSource>> {
+    Ok(load(db, stack_id).await?.map(|source| source.info()))
+}
+
+/// Remove a stack's source.
+pub async fn delete_source(db: &Pool<Sqlite>, stack_id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM stack_sources WHERE stack_id = ?")
+        .bind(stack_id)
+        .execute(db)
+        .await
+        .context("Failed to delete stack source from database")?;
+    Ok(())
+}
+
+/// Encrypt credentials stored before they were sealed.
+pub async fn seal_credentials(db: &Pool<Sqlite>) -> Result<()> {
+    let rows = sqlx::query("SELECT * FROM stack_sources")
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch stack sources from database")?;
+    for row in &rows {
+        save_source(db, &source_from_row(row)?).await?;
+    }
+    Ok(())
+}
+
+async fn load(db: &Pool<Sqlite>, stack_id: &str) -> Result<Option<Source>> {
+    let row = sqlx::query("SELECT * FROM stack_sources WHERE stack_id = ?")
+        .bind(stack_id)
+        .fetch_optional(db)
+        .await
+        .context("Failed to fetch stack source from database")?;
+    row.as_ref().map(source_from_row).transpose()
+}
+
+/// Stacks whose poll interval has passed since they were last checked.
+async fn due_sources(db: &Pool<Sqlite>) -> Result<Vec<String>> {
+    let rows = sqlx::query("SELECT * FROM stack_sources WHERE poll_interval > 0")
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch stack sources from database")?;
+    let now = Utc::now();
+    let mut due = Vec::new();
+    for row in &rows {
+        let source = source_from_row(row)?;
+        let next = source
+            .checked_at
+            .map(|checked| checked + chrono::Duration::seconds(source.poll_interval as i64));
+        if next.is_none_or(|next| next <= now) {
+            due.push(source.stack_id);
+        }
+    }
+    Ok(due)
+}
+
+async fn save_source(db: &Pool<Sqlite>, source: &Source) -> Result<()> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO stack_sources (stack_id, repository, branch, path, username, password, ssh_key, poll_interval, webhook_secret, last_commit, last_checked, last_error) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&source.stack_id)
+    .bind(&source.repository)
+    .bind(&source.branch)
+    .bind(&source.path)
+    .bind(&source.username)
+    .bind(seal_credential(source, "password", &source.password)?)
+    .bind(seal_credential(source, "ssh_key", &source.ssh_key)?)
+    .bind(source.poll_interval as i64)
+    .bind(seal_credential(source, "webhook_secret", &source.webhook_secret)?)
+    .bind(&source.commit)
+    .bind(source.checked_at)
+    .bind(&source.error)
+    .execute(db)
+    .await
+    .context("Failed to save stack source in database")?;
+    Ok(())
+}
+
+/// Encrypt a credential for the `stack_sources` table.
+fn seal_credential(source: &Source, column: &str, value: &Option<String>) -> Result<Option<String>> {
+    value
+        .as_deref()
+        .map(|value| secrets::seal(&credential_context(&source.stack_id, column), value))
+        .transpose()
+}
+
+/// Decrypt a credential from the `stack_sources` table. Ones stored before
+/// they were encrypted are read as they are, and sealed on the next save.
+fn unseal_credential(row: &SqliteRow, stack_id: &str, column: &str) -> Result<Option<String>> {
+    let stored: Option<String> = row.try_get(column)?;
+    stored
+        .map(|stored| secrets::unseal(&credential_context(stack_id, column), &stored))
+        .transpose()
+}
+
+fn credential_context(stack_id: &str, column: &str) -> String {
+    format!("stack_sources.{}:{}", column, stack_id)
+}
+
+fn source_from_row(row: &SqliteRow) -> Result<Source> {
+    let poll_interval: i64 = row.try_get("poll_interval")?;
+    let stack_id: String = row.try_get("stack_id")?;
+    Ok(Source {
+        password: unseal_credential(row, &stack_id, "password")?,
+        ssh_key: unseal_credential(row, &stack_id, "ssh_key")?,
+        webhook_secret: unseal_credential(row, &stack_id, "webhook_secret")?,
+        stack_id,
+        repository: row.try_get("repository")?,
+        branch: row.try_get("branch")?,
+        path: row.try_get("path")?,
+        username: row.try_get("username")?,
+        poll_interval: poll_interval.max(0) as u64,
+        commit: row.try_get("last_commit")?,
+        checked_at: row.try_get("last_checked")?,
+        error: row.try_get("last_error")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_local_repository() {
+        let root = std::env::temp_dir().join(format!("rustainer-git-{}", Uuid::new_v4()));
+        let (origin, work) = (root.join("origin.git"), root.join("work"));
+        fs::create_dir_all(&work).unwrap();
+        git(&root, &["init", "-q", "--bare", "origin.git"]);
+        git(&work, &["init", "-q"]);
+        fs::create_dir_all(work.join("deploy")).unwrap();
+        fs::write(work.join("deploy/compose.yml"), "services:\n  web:\n    image: nginx:${TAG}\n").unwrap();
+        fs::write(work.join("deploy/.env"), "TAG=1.25\n").unwrap();
+        git(&work, &["add", "."]);
+        git(&work, &["commit", "-q", "-m", "Add web"]);
+        git(&work, &["push", "-q", origin.to_str().unwrap(), "HEAD:refs/heads/main"]);
+
+        let mut source = Source::default();
+        source.update(GitSourceRequest {
+            repository: format!("file://{}", origin.display()),
+            branch: "main".to_string(),
+            path: "deploy/compose.yml".to_string(),
+            username: None,
+            password: None,
+            ssh_key: None,
+            poll_interval: 0,
+            webhook_secret: None,
+        });
+        check_source(&GitSourceRequest { branch: "--upload-pack=x".to_string(), ..request(&source) }).unwrap_err();
+        check_source(&GitSourceRequest { path: "../compose.yml".to_string(), ..request(&source) }).unwrap_err();
+
+        let checkout = Checkout::open(root.join("stack/repo"), &source).await.unwrap();
+        let first = checkout.fetch().await.unwrap();
+        assert_eq!(checkout.read(&first, &source.dotenv_path()).await.unwrap().as_deref(), Some("TAG=1.25\n"));
+        assert_eq!(checkout.read(&first, "missing.yml").await.unwrap(), None);
+        checkout.checkout(&first).await.unwrap();
+        assert!(root.join("stack").join(source.compose_path()).exists());
+        assert_eq!(checkout.describe(&first).await.unwrap(), ("Test".to_string(), "Add web".to_string()));
+
+        fs::write(work.join("deploy/.env"), "TAG=1.27\n").unwrap();
+        git(&work, &["commit", "-q", "-am", "Bump web"]);
+        git(&work, &["push", "-q", origin.to_str().unwrap(), "HEAD:refs/heads/main"]);
+        let second = checkout.fetch().await.unwrap();
+        assert_ne!(first, second);
+        checkout.checkout(&second).await.unwrap();
+        assert_eq!(fs::read_to_string(root.join("stack/repo/deploy/.env")).unwrap(), "TAG=1.27\n");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    fn request(source: &Source) -> GitSourceRequest {
+        GitSourceRequest {
+            repository: source.repository.clone(),
+            branch: source.branch.clone(),
+            path: source.path.clone(),
+            username: None,
+            password: None,
+            ssh_key: None,
+            poll_interval: 0,
+            webhook_secret: None,
+        }
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        // Example from GitHub's webhook documentation
+        let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        assert!(verify_signature("It's a Secret to Everybody", b"Hello, World!", signature));
+        assert!(verify_signature("It's a Secret to Everybody", b"Hello, World!", &signature[7..]));
+        assert!(!verify_signature("It's a Secret to Everybody", b"Hello, World?", signature));
+        assert!(!verify_signature("wrong", b"Hello, World!", signature));
+        assert!(!verify_signature("It's a Secret to Everybody", b"Hello, World!", "sha256=zz"));
+    }
+}
@@ -0,0 +1,977 @@
+//! Docker Compose integration for Rustainer.
+//!
+//! Stacks are stored in the `stacks` table, with their compose file in
+//! `data/compose/{id}/compose.yml`. That directory is also the project
+//! directory that relative bind mounts are resolved against. Stacks are run
+//! by the native engine in [`engine`], without the `docker compose` CLI.
+//! Env files given with a stack are stored in the same directory, and its
+//! `.env` supplies variables for [`env`] interpolation. Every save is kept
+//! in the stack's [`revisions`] history. Stacks deployed from a repository
+//! keep a [`git`] checkout there instead, with the compose file inside it.
+//! Secrets that services use are read from the [`crate::secrets`] store
+//! only when a stack is deployed. Projects started outside Rustainer are
+//! listed as read-only [`external`] stacks until they are adopted. Each
+//! stack's update policy decides what [`crate::docker::updates`] does when
+//! its images have updates.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bollard::models::ContainerSummaryStateEnum;
+use bollard::Docker;
+use chrono::Utc;
+use sqlx::{sqlite::SqliteRow, Pool, Row, Sqlite};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
+
+use super::logs::{self, LogOptions, LogTarget};
+use super::updates;
+use crate::auth::Claims;
+use crate::registries;
+use crate::secrets;
+use crate::models::compose::{
+    ComposeService, ComposeStack, CreateStackRequest, GitSource, PlanStackRequest, RevisionDiff, ScaleStackRequest,
+    StackPlan, StackRevision, StackStatus, UpdateStackRequest,
+};
+use crate::models::update::UpdatePolicy;
+
+pub mod engine;
+pub mod env;
+pub mod external;
+pub mod git;
+pub mod plan;
+pub mod revisions;
+pub mod spec;
+pub mod validate;
+
+use engine::Project;
+use env::Variables;
+use spec::{ComposeFile, Condition};
+
+/// Directory where compose files are stored.
+const COMPOSE_DIR: &str = "./data/compose";
+
+/// Name of the compose file inside a stack's directory.
+const COMPOSE_FILE: &str = "compose.yml";
+
+/// Env file that supplies variables for interpolation.
+const DOTENV_FILE: &str = ".env";
+
+/// Columns of the `stacks` table.
+const STACK_COLUMNS: &str = "id, name, environment, env_files, compose_path, created_at, updated_at, update_policy";
+
+/// A row of the `stacks` table.
+struct StackRecord {
+    id: String,
+    name: String,
+    environment: HashMap<String, String>,
+    env_files: Vec<String>,
+    /// Compose file relative to [`StackRecord::root`], when it is not
+    /// `compose.yml`.
+    compose_path: Option<String>,
+    created_at: chrono::DateTime<Utc>,
+    updated_at: chrono::DateTime<Utc>,
+    update_policy: UpdatePolicy,
+    /// Repository the stack is deployed from, loaded from `stack_sources`.
+    git: Option<GitSource>,
+}
+
+impl StackRecord {
+    fn new(name: String, environment: HashMap<String, String>, compose_path: Option<String>) -> Self {
+        let now = Utc::now();
+        StackRecord {
+            id: Uuid::new_v4().to_string(),
+            name,
+            environment,
+            env_files: Vec::new(),
+            compose_path,
+            created_at: now,
+            updated_at: now,
+            update_policy: UpdatePolicy::default(),
+            git: None,
+        }
+    }
+
+    /// Directory holding everything stored for the stack.
+    fn root(&self) -> Result<PathBuf> {
+        Ok(std::env::current_dir()
+            .context("Failed to read working directory")?
+            .join(COMPOSE_DIR)
+            .join(&self.id))
+    }
+
+    /// Project directory, which holds the compose file.
+    fn dir(&self) -> Result<PathBuf> {
+        let path = self.file_path()?;
+        Ok(path.parent().map_or_else(|| path.clone(), Path::to_path_buf))
+    }
+
+    fn file_path(&self) -> Result<PathBuf> {
+        Ok(self.root()?.join(self.compose_path.as_deref().unwrap_or(COMPOSE_FILE)))
+    }
+
+    /// The stack's `.env` file overridden by its stored environment.
+    fn variables(&self) -> Result<Variables> {
+        let path = self.dir()?.join(DOTENV_FILE);
+        let dotenv = match fs::read_to_string(&path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        stack_variables(dotenv.as_deref(), &self.environment)
+    }
+
+    /// Contents of the stack's env files, keyed by path.
+    fn read_env_files(&self) -> Result<HashMap<String, String>> {
+        let dir = self.dir()?;
+        let mut files = HashMap::new();
+        for name in &self.env_files {
+            let path = dir.join(name);
+            let content = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            files.insert(name.clone(), content);
+        }
+        Ok(files)
+    }
+
+    /// Record the stack as it is stored now as a new revision.
+    async fn record_revision(&self, db: &Pool<Sqlite>, author: Option<&str>, message: Option<&str>) -> Result<()> {
+        let path = self.file_path()?;
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read compose file {}", path.display()))?;
+        let env_files = self.read_env_files()?;
+        revisions::record(db, &self.id, &content, &self.environment, &env_files, author, message).await?;
+        Ok(())
+    }
+
+    /// Write the stack's files and save its row, keeping the stored env files
+    /// when none are given.
+    async fn save(
+        &mut self,
+        db: &Pool<Sqlite>,
+        compose_content: &str,
+        env_files: Option<&HashMap<String, String>>,
+    ) -> Result<()> {
+        self.updated_at = Utc::now();
+        write_compose_file(self, compose_content)?;
+        if let Some(files) = env_files {
+            write_env_files(self, files)?;
+        }
+
+        sqlx::query("UPDATE stacks SET environment = ?, env_files = ?, updated_at = ? WHERE id = ?")
+            .bind(serde_json::to_string(&self.environment)?)
+            .bind(serde_json::to_string(&self.env_files)?)
+            .bind(self.updated_at)
+            .bind(&self.id)
+            .execute(db)
+            .await
+            .context("Failed to update stack in database")?;
+        Ok(())
+    }
+
+    /// Read, interpolate and resolve the stack's compose file.
+    fn project(&self) -> Result<Project> {
+        let path = self.file_path()?;
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read compose file {}", path.display()))?;
+        self.draft_project(&content, None)
+    }
+
+    /// The stack's project with the secrets its services use decrypted and
+    /// the registry credentials of `caller` loaded, ready to be deployed.
+    /// Without a caller, only shared registries are used.
+    async fn deploy_project(&self, db: &Pool<Sqlite>, caller: Option<&Claims>) -> Result<Project> {
+        let mut project = self.project()?;
+        reveal_secrets(db, &mut project).await?;
+        project.registries = registries::logins_for(db, caller).await?;
+        Ok(project)
+    }
+
+    /// Interpolate and resolve a compose file as if it were saved with the
+    /// stack, along with `env_files` in place of the stored ones.
+    fn draft_project(&self, content: &str, env_files: Option<&HashMap<String, String>>) -> Result<Project> {
+        let dir = self.dir()?;
+        let variables = match env_files {
+            Some(files) => stack_variables(files.get(DOTENV_FILE).map(String::as_str), &self.environment)?,
+            None => self.variables()?,
+        };
+        let staged: HashMap<String, &String> = env_files
+            .into_iter()
+            .flatten()
+            .map(|(name, content)| (spec::resolve_path(&dir, name), content))
+            .collect();
+        let replaced: Vec<String> = match env_files {
+            Some(_) => self.env_files.iter().map(|name| spec::resolve_path(&dir, name)).collect(),
+            None => Vec::new(),
+        };
+
+        let mut file = ComposeFile::parse(content, &variables)?;
+        file.resolve(&engine::project_name(&self.name), &dir, &variables, |path| {
+            match staged.get(path) {
+                Some(content) => Ok(content.to_string()),
+                None if replaced.iter().any(|name| name == path) => Err(std::io::ErrorKind::NotFound.into()),
+                None => fs::read_to_string(path),
+            }
+        })?;
+        Ok(Project::new(&self.name, self.file_path()?, file))
+    }
+}
+
+/// Decrypt the store secrets a project's services use.
+pub async fn reveal_secrets(db: &Pool<Sqlite>, project: &mut Project) -> Result<()> {
+    let used: BTreeSet<String> = project.file.services.values().flat_map(|service| service.secret_keys()).collect();
+    for key in used {
+        let spec = project.file.secrets.get(&key).cloned().flatten().unwrap_or_default();
+        let Some(name) = spec.store_name(&key) else {
+            continue;
+        };
+        let revealed = secrets::reveal(db, name)
+            .await?
+            .ok_or_else(|| secrets::MissingSecret(name.to_string()))?;
+        project.secrets.insert(key, revealed);
+    }
+    Ok(())
+}
+
+/// Names of the stacks using each stored secret.
+pub async fn secret_users(db: &Pool<Sqlite>) -> Result<HashMap<String, Vec<String>>> {
+    let mut users: HashMap<String, Vec<String>> = HashMap::new();
+    for record in all_records(db).await? {
+        let Ok(project) = record.project() else {
+            continue;
+        };
+        let names: BTreeSet<String> = project
+            .file
+            .services
+            .values()
+            .flat_map(|service| service.secret_keys())
+            .filter_map(|key| {
+                let spec = project.file.secrets.get(&key).cloned().flatten().unwrap_or_default();
+                spec.store_name(&key).map(str::to_string)
+            })
+            .collect();
+        for name in names {
+            users.entry(name).or_default().push(record.name.clone());
+        }
+    }
+    Ok(users)
+}
+
+/// Write the secret files of stacks that have containers again, as the
+/// runtime directory is emptied when the host restarts.
+pub async fn restore_secret_files(db: &Pool<Sqlite>, docker: &Docker) -> Result<()> {
+    for record in all_records(db).await? {
+        let Ok(project) = record.project() else {
+            continue;
+        };
+        let mounts_secrets = project.file.services.values().any(|service| !service.secrets.is_empty());
+        if !mounts_secrets || engine::containers(docker, &project.name).await?.is_empty() {
+            continue;
+        }
+        let project = record.deploy_project(db, None).await?;
+        for name in project.file.services.keys() {
+            project.write_secret_files(name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reject environment values and env files that hold the value of a stored
+/// secret, so that stacks and their revisions only refer to secrets by name.
+pub async fn check_plaintext_secrets(
+    db: &Pool<Sqlite>,
+    environment: Option<&HashMap<String, String>>,
+    env_files: Option<&HashMap<String, String>>,
+) -> Result<()> {
+    let values = secrets::redactions(db).await?;
+    let variables = environment
+        .into_iter()
+        .flatten()
+        .map(|(variable, value)| (format!("Environment variable {}", variable), value.as_str()));
+    let files = env_files
+        .into_iter()
+        .flatten()
+        .map(|(name, content)| (format!("Env file {}", name), content.as_str()));
+    match secrets::find_plaintext(variables.chain(files), &values) {
+        Some(field) => Err(secrets::PlaintextSecret(field).into()),
+        None => Ok(()),
+    }
+}
+
+/// Username of `caller`, recorded as the author of stack revisions.
+fn author(caller: Option<&Claims>) -> Option<&str> {
+    caller.map(|claims| claims.username.as_str())
+}
+
+/// Variables for a stack from the content of its `.env` file.
+fn stack_variables(dotenv: Option<&str>, environment: &HashMap<String, String>) -> Result<Variables> {
+    env::variables(dotenv, environment).map_err(|(line, message)| anyhow!("Invalid .env at line {}: {}", line, message))
+}
+
+/// List all Docker Compose stacks.
+pub async fn list_stacks(db: &Pool<Sqlite>, docker: &Docker) -> Result<Vec<ComposeStack>> {
+    let records = all_records(db).await?;
+    let mut stacks = Vec::with_capacity(records.len());
+    for record in &records {
+        stacks.push(stack_status(db, docker, record).await?);
+    }
+    stacks.extend(external::list(db, docker).await?);
+    Ok(stacks)
+}
+
+/// Get a Docker Compose stack by ID, or an external project by name.
+pub async fn get_stack(db: &Pool<Sqlite>, docker: &Docker, id: &str) -> Result<Option<ComposeStack>> {
+    match get_record(db, id).await? {
+        Some(record) => Ok(Some(stack_status(db, docker, &record).await?)),
+        None => external::get(db, docker, id).await,
+    }
+}
+
+/// Create a new Docker Compose stack, saved and deployed by `caller`.
+pub async fn create_stack(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    request: CreateStackRequest,
+    caller: Option<&Claims>,
+) -> Result<ComposeStack> {
+    let environment = request.environment.unwrap_or_default();
+    let env_files = request.env_files.unwrap_or_default();
+    check_env_file_names(&env_files)?;
+    check_plaintext_secrets(db, Some(&environment), Some(&env_files)).await?;
+    let variables = stack_variables(env_files.get(DOTENV_FILE).map(String::as_str), &environment)?;
+    ComposeFile::parse(&request.compose_content, &variables)?;
+    check_project_name(db, &request.name).await?;
+
+    let mut record = StackRecord::new(request.name, environment, None);
+    record.update_policy = request.update_policy;
+    write_compose_file(&record, &request.compose_content)?;
+    write_env_files(&mut record, &env_files)?;
+    insert_record(db, &record).await?;
+    record.record_revision(db, author(caller), request.message.as_deref()).await?;
+
+    if request.start {
+        engine::up(docker, &record.deploy_project(db, caller).await?).await?;
+    }
+    stack_status(db, docker, &record).await
+}
+
+/// Replace a stack's compose file as a new revision by `caller`, applying it
+/// when asked to.
+pub async fn update_stack(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    id: &str,
+    request: UpdateStackRequest,
+    caller: Option<&Claims>,
+) -> Result<Option<ComposeStack>> {
+    let Some(mut record) = managed_record(db, docker, id).await? else {
+        return Ok(None);
+    };
+    if let Some(source) = &record.git {
+        return Err(git::ManagedByGit(source.repository.clone()).into());
+    }
+    check_plaintext_secrets(db, request.environment.as_ref(), request.env_files.as_ref()).await?;
+    // Stacks created before revisions were kept start their history here
+    if revisions::latest(db, id).await? == 0 {
+        record.record_revision(db, None, Some("Before revision history")).await?;
+    }
+
+    if let Some(environment) = request.environment {
+        record.environment = environment;
+    }
+    let variables = match &request.env_files {
+        Some(files) => {
+            check_env_file_names(files)?;
+            stack_variables(files.get(DOTENV_FILE).map(String::as_str), &record.environment)?
+        }
+        None => record.variables()?,
+    };
+    ComposeFile::parse(&request.compose_content, &variables)?;
+
+    record.save(db, &request.compose_content, request.env_files.as_ref()).await?;
+    record.record_revision(db, author(caller), request.message.as_deref()).await?;
+
+    if request.restart {
+        engine::up(docker, &record.deploy_project(db, caller).await?).await?;
+    }
+    Ok(Some(stack_status(db, docker, &record).await?))
+}
+
+/// Work out what `up` would change in a stack, for its stored compose file
+/// or for an update that has not been saved.
+pub async fn plan_stack(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    id: &str,
+    request: PlanStackRequest,
+) -> Result<Option<StackPlan>> {
+    let Some(mut record) = managed_record(db, docker, id).await? else {
+        return Ok(None);
+    };
+    if let Some(files) = &request.env_files {
+        check_env_file_names(files)?;
+    }
+    if let Some(environment) = request.environment {
+        record.environment = environment;
+    }
+    let content = match request.compose_content {
+        Some(content) => content,
+        None => {
+            let path = record.file_path()?;
+            fs::read_to_string(&path).with_context(|| format!("Failed to read compose file {}", path.display()))?
+        }
+    };
+    let mut project = record.draft_project(&content, request.env_files.as_ref())?;
+    reveal_secrets(db, &mut project).await?;
+    Ok(Some(plan::plan(docker, &project).await?))
+}
+
+/// Take a stack down and delete it. Its volumes are kept.
+pub async fn delete_stack(db: &Pool<Sqlite>, docker: &Docker, id: &str) -> Result<bool> {
+    let Some(record) = managed_record(db, docker, id).await? else {
+        return Ok(false);
+    };
+    engine::down(docker, &record.project()?, false).await?;
+
+    sqlx::query("DELETE FROM stacks WHERE id = ?")
+        .bind(&record.id)
+        .execute(db)
+        .await
+        .context("Failed to delete stack from database")?;
+    revisions::delete_all(db, &record.id).await?;
+    git::delete_source(db, &record.id).await?;
+
+    let dir = record.root()?;
+    fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+    Ok(true)
+}
+
+/// List a stack's revisions, newest first.
+pub async fn list_revisions(db: &Pool<Sqlite>, id: &str) -> Result<Option<Vec<StackRevision>>> {
+    match get_record(db, id).await? {
+        Some(record) => Ok(Some(revisions::list(db, &record.id).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Compare two revisions of a stack.
+pub async fn diff_revisions(db: &Pool<Sqlite>, id: &str, from: i64, to: i64) -> Result<Option<RevisionDiff>> {
+    let from = revisions::get(db, id, from).await?;
+    let to = revisions::get(db, id, to).await?;
+    Ok(from.zip(to).map(|(from, to)| revisions::diff(&from, &to)))
+}
+
+/// Save an earlier revision as a new one by `caller`, and redeploy it.
+pub async fn rollback_stack(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    id: &str,
+    number: i64,
+    caller: Option<&Claims>,
+) -> Result<Option<ComposeStack>> {
+    let Some(mut record) = managed_record(db, docker, id).await? else {
+        return Ok(None);
+    };
+    let Some(revision) = revisions::get(db, id, number).await? else {
+        return Ok(None);
+    };
+
+    record.environment = revision.environment;
+    record.save(db, &revision.compose_content, Some(&revision.env_files)).await?;
+    let message = format!("Roll back to revision {}", number);
+    record.record_revision(db, author(caller), Some(&message)).await?;
+
+    engine::up(docker, &record.deploy_project(db, caller).await?).await?;
+    Ok(Some(stack_status(db, docker, &record).await?))
+}
+
+/// Create and start everything in a stack, pulling images as `caller`.
+pub async fn up_stack(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    id: &str,
+    caller: Option<&Claims>,
+) -> Result<Option<ComposeStack>> {
+    let Some(record) = managed_record(db, docker, id).await? else {
+        return Ok(None);
+    };
+    engine::up(docker, &record.deploy_project(db, caller).await?).await?;
+    Ok(Some(stack_status(db, docker, &record).await?))
+}
+
+/// Remove a stack's containers and networks, and optionally its volumes.
+pub async fn down_stack(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    id: &str,
+    remove_volumes: bool,
+) -> Result<Option<ComposeStack>> {
+    let Some(record) = managed_record(db, docker, id).await? else {
+        return Ok(None);
+    };
+    engine::down(docker, &record.project()?, remove_volumes).await?;
+    Ok(Some(stack_status(db, docker, &record).await?))
+}
+
+/// Restart a stack's containers in dependency order, pulling images as
+/// `caller`.
+pub async fn restart_stack(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    id: &str,
+    caller: Option<&Claims>,
+) -> Result<Option<ComposeStack>> {
+    let Some(record) = managed_record(db, docker, id).await? else {
+        return Ok(None);
+    };
+    engine::restart(docker, &record.deploy_project(db, caller).await?).await?;
+    Ok(Some(stack_status(db, docker, &record).await?))
+}
+
+/// Set what happens when the images of a stack's services have updates.
+pub async fn set_update_policy(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    id: &str,
+    policy: UpdatePolicy,
+) -> Result<Option<ComposeStack>> {
+    let Some(mut record) = managed_record(db, docker, id).await? else {
+        return Ok(None);
+    };
+    sqlx::query("UPDATE stacks SET update_policy = ? WHERE id = ?")
+        .bind(policy.as_str())
+        .bind(&record.id)
+        .execute(db)
+        .await
+        .context("Failed to update stack in database")?;
+    record.update_policy = policy;
+    Ok(Some(stack_status(db, docker, &record).await?))
+}
+
+/// The ID and update policy of every stack, by project name.
+pub async fn update_policies(db: &Pool<Sqlite>) -> Result<HashMap<String, (String, UpdatePolicy)>> {
+    Ok(all_records(db)
+        .await?
+        .into_iter()
+        .map(|record| (engine::project_name(&record.name), (record.id, record.update_policy)))
+        .collect())
+}
+
+/// Scale services in a Docker Compose stack, pulling images as `caller`. The
+/// compose file is not changed, so the next `up` goes back to its replica
+/// counts.
+pub async fn scale_stack(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    id: &str,
+    request: ScaleStackRequest,
+    caller: Option<&Claims>,
+) -> Result<Option<ComposeStack>> {
+    let Some(record) = managed_record(db, docker, id).await? else {
+        return Ok(None);
+    };
+    let project = record.deploy_project(db, caller).await?;
+    for name in request.services.keys() {
+        if !project.file.services.contains_key(name) {
+            bail!("Stack has no service named {}", name);
+        }
+    }
+    for (name, replicas) in &request.services {
+        engine::scale(docker, &project, name, *replicas).await?;
+    }
+    Ok(Some(stack_status(db, docker, &record).await?))
+}
+
+/// The stack's compose file with variables interpolated, env files read and
+/// short syntaxes expanded, as `docker compose config` prints it.
+pub async fn stack_config(db: &Pool<Sqlite>, id: &str) -> Result<Option<ComposeFile>> {
+    match get_record(db, id).await? {
+        Some(record) => Ok(Some(record.project()?.file)),
+        None => Ok(None),
+    }
+}
+
+/// What to read a stack's logs from, with services colored in file order.
+pub async fn stack_log_target(db: &Pool<Sqlite>, id: &str) -> Result<Option<LogTarget>> {
+    let Some(record) = get_record(db, id).await? else {
+        return Ok(None);
+    };
+    let services = match record.project() {
+        Ok(project) => project.file.services.keys().cloned().collect(),
+        Err(_) => Vec::new(),
+    };
+    Ok(Some(LogTarget::Project {
+        name: engine::project_name(&record.name),
+        services,
+    }))
+}
+
+/// Get the logs of every container in a stack, keyed by container name.
+pub async fn get_stack_logs(
+    db: &Pool<Sqlite>,
+    docker: &Docker,
+    id: &str,
+    options: LogOptions,
+) -> Result<Option<HashMap<String, Vec<String>>>> {
+    let Some(target) = stack_log_target(db, id).await? else {
+        return Ok(None);
+    };
+
+    let mut logs: HashMap<String, Vec<String>> = HashMap::new();
+    for line in logs::snapshot(docker, &target, options).await?.unwrap_or_default() {
+        logs.entry(line.container).or_default().push(line.message);
+    }
+    Ok(Some(logs))
+}
+
+/// Build the API view of a stack from its file and its running containers.
+async fn stack_status(db: &Pool<Sqlite>, docker: &Docker, record: &StackRecord) -> Result<ComposeStack> {
+    let path = record.file_path()?;
+    let project_name = engine::project_name(&record.name);
+    let containers = engine::containers(docker, &project_name).await?;
+    let updates = updates::by_container(db).await?;
+    let file = record.project().ok().map(|project| project.file);
+
+    let mut stack = ComposeStack {
+        id: record.id.clone(),
+        name: record.name.clone(),
+        project: project_name,
+        file_path: path.display().to_string(),
+        status: StackStatus::Error,
+        created_at: record.created_at,
+        updated_at: record.updated_at,
+        services: Vec::new(),
+        environment: Some(record.environment.clone()),
+        env_files: record.env_files.clone(),
+        version: None,
+        git: record.git.clone(),
+        external: false,
+        update_policy: record.update_policy,
+        update_available: false,
+    };
+    let Some(file) = file else {
+        return Ok(stack);
+    };
+    stack.version = file.version.clone();
+
+    // Services that others wait on to finish are expected to stop
+    let one_shot: Vec<String> = file
+        .services
+        .values()
+        .flat_map(|service| service.dependencies())
+        .filter(|(_, dependency)| dependency.condition == Condition::CompletedSuccessfully)
+        .map(|(name, _)| name)
+        .collect();
+
+    let mut any_running = false;
+    let mut all_running = true;
+    for (name, service) in &file.services {
+        let mut own: Vec<_> = containers
+            .iter()
+            .filter(|c| engine::label(c, engine::SERVICE_LABEL) == Some(name.as_str()))
+            .collect();
+        own.sort_by_key(|c| engine::container_number(c));
+        let running: Vec<_> = own
+            .iter()
+            .filter(|c| c.state == Some(ContainerSummaryStateEnum::RUNNING))
+            .collect();
+
+        any_running |= !running.is_empty();
+        if running.is_empty() && !one_shot.contains(name) {
+            all_running = false;
+        }
+
+        let status = match (own.first(), running.first()) {
+            (_, Some(_)) => "running".to_string(),
+            (Some(container), None) => container.state.map(|s| s.to_string()).unwrap_or_default(),
+            (None, None) => "not created".to_string(),
+        };
+
+        stack.services.push(ComposeService {
+            name: name.clone(),
+            image: service.image.clone().unwrap_or_default(),
+            status,
+            container_id: running.first().or(own.first().as_ref()).and_then(|c| c.id.clone()),
+            replicas: service.replicas(),
+            running: running.len() as u32,
+            ports: Some(
+                service
+                    .ports
+                    .iter()
+                    .flat_map(|port| port.mappings().unwrap_or_default())
+                    .map(|m| match m.published {
+                        Some(published) => format!("{}:{}/{}", published, m.target, m.protocol),
+                        None => format!("{}/{}", m.target, m.protocol),
+                    })
+                    .collect(),
+            ),
+            volumes: Some(
+                service
+                    .volumes
+                    .iter()
+                    .filter_map(|volume| volume.mount().ok())
+                    .map(|mount| mount.target)
+                    .collect(),
+            ),
+            networks: Some(service.networks().into_keys().collect()),
+            environment: Some(
+                service
+                    .environment
+                    .entries('=')
+                    .into_iter()
+                    .map(|(key, value)| (key, value.unwrap_or_default()))
+                    .collect(),
+            ),
+            depends_on: Some(service.dependencies().into_keys().collect()),
+            update_available: running.iter().any(|c| updates::available(&updates, c)),
+        });
+    }
+    stack.update_available = stack.services.iter().any(|service| service.update_available);
+
+    stack.status = match (any_running, all_running) {
+        (false, _) => StackStatus::Down,
+        (true, true) => StackStatus::Up,
+        (true, false) => StackStatus::Partial,
+    };
+    Ok(stack)
+}
+
+/// Check a compose file without storing it, returning every problem found.
+/// Variables come from `environment` and the content of a `.env` file.
+pub fn validate_compose_content(
+    content: &str,
+    environment: &HashMap<String, String>,
+    dotenv: Option<&str>,
+) -> Vec<validate::Issue> {
+    match env::variables(dotenv, environment) {
+        Ok(variables) => validate::validate(content, &variables),
+        Err((line, message)) => vec![validate::Issue {
+            path: DOTENV_FILE.to_string(),
+            line,
+            column: 1,
+            message,
+        }],
+    }
+}
+
+/// Check that env file names are relative paths inside the stack directory.
+pub fn check_env_file_names(files: &HashMap<String, String>) -> Result<()> {
+    for name in files.keys() {
+        let path = Path::new(name);
+        let inside = path.components().count() > 0 && path.components().all(|c| matches!(c, Component::Normal(_)));
+        if !inside || path == Path::new(COMPOSE_FILE) {
+            bail!("Invalid env file name {:?}", name);
+        }
+    }
+    Ok(())
+}
+
+/// Check that a new stack's name gives it a project name of its own.
+async fn check_project_name(db: &Pool<Sqlite>, name: &str) -> Result<()> {
+    let project = engine::project_name(name);
+    if project.is_empty() {
+        bail!("Stack name {:?} has no usable characters", name);
+    }
+    for existing in all_records(db).await? {
+        if engine::project_name(&existing.name) == project {
+            bail!("Stack {} already uses project name {}", existing.name, project);
+        }
+    }
+    Ok(())
+}
+
+async fn insert_record(db: &Pool<Sqlite>, record: &StackRecord) -> Result<()> {
+    sqlx::query(&format!("INSERT INTO stacks ({}) VALUES (?, ?, ?, ?, ?, ?, ?, ?)", STACK_COLUMNS))
+        .bind(&record.id)
+        .bind(&record.name)
+        .bind(serde_json::to_string(&record.environment)?)
+        .bind(serde_json::to_string(&record.env_files)?)
+        .bind(&record.compose_path)
+        .bind(record.created_at)
+        .bind(record.updated_at)
+        .bind(record.update_policy.as_str())
+        .execute(db)
+        .await
+        .context("Failed to insert stack into database")?;
+    Ok(())
+}
+
+/// Replace the stack's env files, removing ones that are no longer given.
+fn write_env_files(record: &mut StackRecord, files: &HashMap<String, String>) -> Result<()> {
+    let dir = record.dir()?;
+    for name in &record.env_files {
+        if !files.contains_key(name) {
+            let path = dir.join(name);
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("Failed to remove {}", path.display()));
+                }
+                _ => {}
+            }
+        }
+    }
+    for (name, content) in files {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    record.env_files = files.keys().cloned().collect();
+    record.env_files.sort();
+    Ok(())
+}
+
+fn write_compose_file(record: &StackRecord, content: &str) -> Result<()> {
+    let path = record.file_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Get a stack's record for changing it, refusing external projects rather
+/// than reporting them as not found.
+async fn managed_record(db: &Pool<Sqlite>, docker: &Docker, id: &str) -> Result<Option<StackRecord>> {
+    if let Some(record) = get_record(db, id).await? {
+        return Ok(Some(record));
+    }
+    if external::get(db, docker, id).await?.is_some() {
+        return Err(external::ExternalStack(id.to_string()).into());
+    }
+    Ok(None)
+}
+
+async fn get_record(db: &Pool<Sqlite>, id: &str) -> Result<Option<StackRecord>> {
+    let row = sqlx::query(&format!("SELECT {} FROM stacks WHERE id = ?", STACK_COLUMNS))
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .context("Failed to fetch stack from database")?;
+    let Some(mut record) = row.as_ref().map(record_from_row).transpose()? else {
+        return Ok(None);
+    };
+    record.git = git::source_info(db, &record.id).await?;
+    Ok(Some(record))
+}
+
+async fn all_records(db: &Pool<Sqlite>) -> Result<Vec<StackRecord>> {
+    let rows = sqlx::query(&format!("SELECT {} FROM stacks ORDER BY name", STACK_COLUMNS))
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch stacks from database")?;
+    let mut records = rows.iter().map(record_from_row).collect::<Result<Vec<_>>>()?;
+    for record in &mut records {
+        record.git = git::source_info(db, &record.id).await?;
+    }
+    Ok(records)
+}
+
+/// Build a stack record from a `stacks` row.
+fn record_from_row(row: &SqliteRow) -> Result<StackRecord> {
+    let environment: Option<String> = row.try_get("environment")?;
+    let env_files: Option<String> = row.try_get("env_files")?;
+    let update_policy: String = row.try_get("update_policy")?;
+    Ok(StackRecord {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        environment: environment
+            .map(|json| serde_json::from_str(&json))
+            .transpose()
+            .context("Failed to deserialize stack environment")?
+            .unwrap_or_default(),
+        env_files: env_files
+            .map(|json| serde_json::from_str(&json))
+            .transpose()
+            .context("Failed to deserialize stack env files")?
+            .unwrap_or_default(),
+        compose_path: row.try_get("compose_path")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+        update_policy: UpdatePolicy::parse(&update_policy).unwrap_or_default(),
+        git: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::registry::{CreateRegistryRequest, RegistryScope};
+    use crate::models::secret::CreateSecretRequest;
+
+    #[tokio::test]
+    async fn test_stack_rows_hold_no_secret_material() {
+        let db = crate::db::test_pool().await;
+        let request = CreateSecretRequest {
+            name: "db_password".to_string(),
+            value: "hunter22-long".to_string(),
+            description: None,
+        };
+        secrets::create(&db, request).await.unwrap();
+
+        // Literal copies of the secret are refused before anything is saved
+        let environment = HashMap::from([("DB_PASSWORD".to_string(), "hunter22-long".to_string())]);
+        let e = check_plaintext_secrets(&db, Some(&environment), None).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<secrets::PlaintextSecret>().unwrap().0, "Environment variable DB_PASSWORD");
+        let env_files = HashMap::from([(".env".to_string(), "PASSWORD=hunter22-long\n".to_string())]);
+        let e = check_plaintext_secrets(&db, None, Some(&env_files)).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<secrets::PlaintextSecret>().unwrap().0, "Env file .env");
+
+        // A stack that refers to the secret by name stores only the name
+        let content = "services:\n  app:\n    image: app\n    x-secret-environment:\n      DB_PASSWORD: db_password\nsecrets:\n  db_password:\n    external: true\n";
+        let environment = HashMap::from([("TAG".to_string(), "1.25".to_string())]);
+        let env_files = HashMap::from([(".env".to_string(), "USER=app\n".to_string())]);
+        check_plaintext_secrets(&db, Some(&environment), Some(&env_files)).await.unwrap();
+        let record = StackRecord::new("blog".to_string(), environment, None);
+        insert_record(&db, &record).await.unwrap();
+        revisions::record(&db, &record.id, content, &record.environment, &env_files, None, None)
+            .await
+            .unwrap();
+        let stored: Vec<String> = sqlx::query_scalar(
+            "SELECT environment FROM stacks UNION ALL SELECT compose_content || environment || env_files FROM stack_revisions",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(stored.len(), 2);
+        assert!(stored.iter().all(|row| !row.contains("hunter22")), "{:?}", stored);
+    }
+
+    #[tokio::test]
+    async fn test_deploys_use_the_callers_registries() {
+        let db = crate::db::test_pool().await;
+        let claims = |username: &str| Claims {
+            sub: username.to_string(),
+            username: username.to_string(),
+            role: "operator".to_string(),
+            exp: 0,
+            iat: 0,
+        };
+        let (alice, bob) = (claims("alice"), claims("bob"));
+        let registry = |name: &str, url: &str, scope| CreateRegistryRequest {
+            name: name.to_string(),
+            url: url.to_string(),
+            username: Some(name.to_lowercase()),
+            password: Some("hunter22".to_string()),
+            scope,
+            owner: None,
+        };
+        registries::create(&db, registry("Alice", "https://registry.example.com", RegistryScope::User), Some(&alice))
+            .await
+            .unwrap();
+        registries::create(&db, registry("Shared", "https://ghcr.io", RegistryScope::Shared), None)
+            .await
+            .unwrap();
+
+        let record = StackRecord::new("private".to_string(), HashMap::new(), None);
+        write_compose_file(&record, "services:\n  app:\n    image: registry.example.com/team/app\n").unwrap();
+        let hosts = |project: Project| project.registries.into_keys().collect::<BTreeSet<_>>();
+        let deployed_by_alice = record.deploy_project(&db, Some(&alice)).await.unwrap();
+        let deployed_by_bob = record.deploy_project(&db, Some(&bob)).await.unwrap();
+        let deployed_by_webhook = record.deploy_project(&db, None).await.unwrap();
+        fs::remove_dir_all(record.root().unwrap()).unwrap();
+
+        assert_eq!(hosts(deployed_by_alice), BTreeSet::from(["ghcr.io".to_string(), "registry.example.com".to_string()]));
+        assert_eq!(hosts(deployed_by_bob), BTreeSet::from(["ghcr.io".to_string()]));
+        assert_eq!(hosts(deployed_by_webhook), BTreeSet::from(["ghcr.io".to_string()]));
+    }
+}
@@ -0,0 +1,438 @@
+//! Dry runs of `up`.
+//!
+//! A plan compares a project with the containers, networks and volumes that
+//! exist for it, and says what [`engine::up`] would change and why. Reasons
+//! come from the labels and inspected configuration of the existing
+//! containers, so a change can be planned before it is saved.
+
+use anyhow::{Context, Result};
+use bollard::models::{ContainerSummary, ImageInspect, Mount, MountTypeEnum, PortMap};
+use bollard::query_parameters::{InspectContainerOptions, InspectNetworkOptions, ListNetworksOptions, ListVolumesOptions};
+use bollard::Docker;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::engine::{self, Project, CONFIG_HASH_LABEL, PROJECT_LABEL, SERVICE_LABEL};
+use super::spec::External;
+use crate::models::compose::{PlanAction, ResourceAction, ResourcePlan, ServicePlan, StackPlan};
+
+/// Prefix of the labels that compose sets itself.
+const COMPOSE_LABEL_PREFIX: &str = "com.docker.compose.";
+
+/// Work out what `up` would do to a project, without changing anything.
+pub async fn plan(docker: &Docker, project: &Project) -> Result<StackPlan> {
+    let existing = engine::containers(docker, &project.name).await?;
+    let mut services = Vec::new();
+    for name in project.file.services.keys() {
+        let own: Vec<&ContainerSummary> = existing
+            .iter()
+            .filter(|c| engine::label(c, SERVICE_LABEL) == Some(name.as_str()))
+            .collect();
+        services.push(plan_service(docker, project, name, &own).await?);
+    }
+
+    let mut removed: BTreeMap<&str, u32> = BTreeMap::new();
+    for container in &existing {
+        let service = engine::label(container, SERVICE_LABEL).unwrap_or_default();
+        if !project.file.services.contains_key(service) {
+            *removed.entry(service).or_default() += 1;
+        }
+    }
+    services.extend(removed.into_iter().map(|(name, containers)| ServicePlan {
+        name: name.to_string(),
+        action: PlanAction::Remove,
+        reasons: vec!["service is no longer in the compose file".to_string()],
+        replicas: 0,
+        containers,
+    }));
+
+    Ok(StackPlan {
+        services,
+        networks: plan_networks(docker, project).await?,
+        volumes: plan_volumes(docker, project).await?,
+    })
+}
+
+async fn plan_service(
+    docker: &Docker,
+    project: &Project,
+    name: &str,
+    own: &[&ContainerSummary],
+) -> Result<ServicePlan> {
+    let service = &project.file.services[name];
+    let replicas = service.replicas();
+    let mut plan = ServicePlan {
+        name: name.to_string(),
+        action: PlanAction::Unchanged,
+        reasons: Vec::new(),
+        replicas,
+        containers: own.len() as u32,
+    };
+    let kept: Vec<&ContainerSummary> = own
+        .iter()
+        .copied()
+        .filter(|c| (1..=replicas).contains(&engine::container_number(c)))
+        .collect();
+
+    if replicas > 0 {
+        let image = match &service.image {
+            Some(reference) => docker.inspect_image(reference).await.ok(),
+            None => None,
+        };
+        if let (Some(reference), None) = (&service.image, &image) {
+            plan.reasons.push(format!("image {} is not present and will be pulled", reference));
+            if !kept.is_empty() {
+                plan.action = PlanAction::Recreate;
+            }
+        }
+
+        let hash = project.service_hash(name)?;
+        let image_id = image.as_ref().and_then(|image| image.id.as_deref());
+        if let Some(container) = kept.iter().find(|c| engine::is_outdated(c, &hash, image_id)) {
+            plan.action = PlanAction::Recreate;
+            plan.reasons.extend(differences(docker, project, name, container, image.as_ref()).await?);
+        }
+    }
+
+    if own.is_empty() {
+        if replicas > 0 {
+            plan.action = PlanAction::Create;
+        }
+        return Ok(plan);
+    }
+    if kept.len() != own.len() || kept.len() as u32 != replicas {
+        plan.reasons.push(format!("scale from {} to {} containers", own.len(), replicas));
+        if plan.action == PlanAction::Unchanged {
+            plan.action = PlanAction::Scale;
+        }
+    }
+    let stopped = kept.iter().filter(|c| !engine::is_running(c)).count();
+    if stopped > 0 && plan.action == PlanAction::Unchanged {
+        plan.action = PlanAction::Start;
+        plan.reasons.push(format!("{} of {} containers are stopped", stopped, kept.len()));
+    }
+    Ok(plan)
+}
+
+/// Why a container no longer matches its service, from comparing it with the
+/// container the service would create now.
+async fn differences(
+    docker: &Docker,
+    project: &Project,
+    name: &str,
+    container: &ContainerSummary,
+    image: Option<&ImageInspect>,
+) -> Result<Vec<String>> {
+    if engine::label(container, CONFIG_HASH_LABEL).is_none() {
+        return Ok(vec!["container was not created from a compose file".to_string()]);
+    }
+    let id = container.id.as_deref().unwrap_or_default();
+    let current = docker
+        .inspect_container(id, None::<InspectContainerOptions>)
+        .await
+        .with_context(|| format!("Failed to inspect container of service {}", name))?;
+    let body = project.container_body(name, engine::container_number(container))?;
+    let config = current.config.unwrap_or_default();
+    let host = current.host_config.unwrap_or_default();
+    let new_host = body.host_config.clone().unwrap_or_default();
+    // Docker fills in env, labels and the command from the image, so those
+    // defaults are not changes made in the compose file
+    let defaults = match &current.image {
+        Some(id) => docker.inspect_image(id).await.ok().and_then(|image| image.config),
+        None => None,
+    }
+    .unwrap_or_default();
+
+    let mut reasons = Vec::new();
+    let old_image = config.image.clone().unwrap_or_default();
+    let new_image = body.image.clone().unwrap_or_default();
+    let new_id = image.and_then(|image| image.id.as_deref());
+    if old_image != new_image {
+        reasons.push(format!("image changed from {} to {}", old_image, new_image));
+    } else if let (Some(old_id), Some(new_id)) = (current.image.as_deref(), new_id) {
+        if old_id != new_id {
+            reasons.push(format!(
+                "image {} has a new digest ({} to {})",
+                new_image,
+                short_id(old_id),
+                short_id(new_id)
+            ));
+        }
+    }
+
+    let env = changed_keys(&env_map(&config.env), &env_map(&body.env), &env_map(&defaults.env));
+    if !env.is_empty() {
+        reasons.push(format!("environment changed: {}", env.join(", ")));
+    }
+    let labels = changed_keys(
+        &label_map(&config.labels),
+        &label_map(&body.labels),
+        &label_map(&defaults.labels),
+    );
+    if !labels.is_empty() {
+        reasons.push(format!("labels changed: {}", labels.join(", ")));
+    }
+    reasons.extend(set_change("ports", port_set(&host.port_bindings), port_set(&new_host.port_bindings)));
+    reasons.extend(set_change("volumes", mount_set(&host.mounts), mount_set(&new_host.mounts)));
+
+    if config.cmd != body.cmd.clone().or(defaults.cmd) {
+        reasons.push("command changed".to_string());
+    }
+    if config.entrypoint != body.entrypoint.clone().or(defaults.entrypoint) {
+        reasons.push("entrypoint changed".to_string());
+    }
+
+    let service = &project.file.services[name];
+    if service.network_mode.is_some() {
+        if host.network_mode != new_host.network_mode {
+            reasons.push("network mode changed".to_string());
+        }
+    } else {
+        let old_networks: BTreeSet<String> = current
+            .network_settings
+            .and_then(|settings| settings.networks)
+            .map(|networks| networks.into_keys().collect())
+            .unwrap_or_default();
+        let new_networks = service.networks().keys().map(|key| project.network_name(key)).collect();
+        reasons.extend(set_change("networks", old_networks, new_networks));
+    }
+
+    let policy = |host: &bollard::models::HostConfig| {
+        host.restart_policy
+            .as_ref()
+            .and_then(|policy| policy.name)
+            .map(|name| name.to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "no".to_string())
+    };
+    if policy(&host) != policy(&new_host) {
+        reasons.push(format!("restart policy changed from {} to {}", policy(&host), policy(&new_host)));
+    }
+
+    if reasons.is_empty() {
+        reasons.push("other settings changed".to_string());
+    }
+    Ok(reasons)
+}
+
+/// Keys that were added, changed or removed, ignoring removed keys whose
+/// value comes from `defaults`.
+fn changed_keys(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+    defaults: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut keys = BTreeSet::new();
+    for (key, value) in new {
+        if old.get(key) != Some(value) {
+            keys.insert(key.clone());
+        }
+    }
+    for (key, value) in old {
+        if !new.contains_key(key) && defaults.get(key) != Some(value) {
+            keys.insert(key.clone());
+        }
+    }
+    keys.into_iter().collect()
+}
+
+fn env_map(env: &Option<Vec<String>>) -> BTreeMap<String, String> {
+    env.iter()
+        .flatten()
+        .map(|entry| match entry.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => (entry.clone(), String::new()),
+        })
+        .collect()
+}
+
+/// Labels other than the ones compose sets itself.
+fn label_map(labels: &Option<HashMap<String, String>>) -> BTreeMap<String, String> {
+    labels
+        .iter()
+        .flatten()
+        .filter(|(key, _)| !key.starts_with(COMPOSE_LABEL_PREFIX))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+fn port_set(bindings: &Option<PortMap>) -> BTreeSet<String> {
+    let mut ports = BTreeSet::new();
+    for (port, bindings) in bindings.iter().flatten() {
+        for binding in bindings.iter().flatten() {
+            let host_ip = binding.host_ip.as_deref().unwrap_or_default();
+            let host_port = binding.host_port.as_deref().unwrap_or_default();
+            ports.insert(match host_ip {
+                "" => format!("{}:{}", host_port, port),
+                ip => format!("{}:{}:{}", ip, host_port, port),
+            });
+        }
+    }
+    ports
+}
+
+fn mount_set(mounts: &Option<Vec<Mount>>) -> BTreeSet<String> {
+    mounts
+        .iter()
+        .flatten()
+        .map(|mount| {
+            let source = match (mount.typ, &mount.source) {
+                (Some(MountTypeEnum::TMPFS), _) => "tmpfs".to_string(),
+                (_, Some(source)) => source.clone(),
+                (_, None) => "anonymous volume".to_string(),
+            };
+            let target = mount.target.as_deref().unwrap_or_default();
+            let mode = if mount.read_only == Some(true) { ":ro" } else { "" };
+            format!("{}:{}{}", source, target, mode)
+        })
+        .collect()
+}
+
+/// Describe the entries added to and removed from a set, if any.
+fn set_change(what: &str, old: BTreeSet<String>, new: BTreeSet<String>) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    let added = new.difference(&old).map(|entry| format!("+{}", entry));
+    let removed = old.difference(&new).map(|entry| format!("-{}", entry));
+    Some(format!("{} changed: {}", what, added.chain(removed).collect::<Vec<_>>().join(", ")))
+}
+
+/// The start of an image ID, as `docker images` shows it.
+fn short_id(id: &str) -> &str {
+    let id = id.strip_prefix("sha256:").unwrap_or(id);
+    &id[..id.len().min(12)]
+}
+
+async fn plan_networks(docker: &Docker, project: &Project) -> Result<Vec<ResourcePlan>> {
+    let used = project.used_networks();
+    let mut plans = Vec::new();
+    for key in &used {
+        let name = project.network_name(key);
+        let spec = project.file.networks.get(key).cloned().flatten().unwrap_or_default();
+        let external = External::is_external(&spec.external);
+        let (action, reasons) = match docker.inspect_network(&name, None::<InspectNetworkOptions>).await {
+            Ok(_) if external => (ResourceAction::Unchanged, Vec::new()),
+            Ok(network) => {
+                let mut reasons = Vec::new();
+                let driver = spec.driver.as_deref().unwrap_or("bridge");
+                let current = network.driver.as_deref().unwrap_or("bridge");
+                if current != driver {
+                    reasons.push(format!("driver is {} instead of {}", current, driver));
+                }
+                if network.internal.unwrap_or(false) != spec.internal {
+                    reasons.push(format!("internal is {} instead of {}", !spec.internal, spec.internal));
+                }
+                if network.attachable.unwrap_or(false) != spec.attachable {
+                    reasons.push(format!("attachable is {} instead of {}", !spec.attachable, spec.attachable));
+                }
+                reasons.extend(option_changes(&spec.driver_opts, network.options.unwrap_or_default()));
+                resource_action(reasons)
+            }
+            Err(_) if external => (ResourceAction::Missing, vec!["external network does not exist".to_string()]),
+            Err(_) => (ResourceAction::Create, Vec::new()),
+        };
+        plans.push(ResourcePlan { name, action, reasons });
+    }
+
+    let used: BTreeSet<String> = used.iter().map(|key| project.network_name(key)).collect();
+    let options = ListNetworksOptions { filters: Some(project_filter(project)) };
+    let existing = docker
+        .list_networks(Some(options))
+        .await
+        .context("Failed to list project networks")?;
+    for name in existing.into_iter().filter_map(|network| network.name) {
+        if !used.contains(&name) {
+            plans.push(unused(name));
+        }
+    }
+    Ok(plans)
+}
+
+async fn plan_volumes(docker: &Docker, project: &Project) -> Result<Vec<ResourcePlan>> {
+    let mut declared = BTreeSet::new();
+    let mut plans = Vec::new();
+    for (key, spec) in &project.file.volumes {
+        let name = project.volume_name(key);
+        let spec = spec.clone().unwrap_or_default();
+        let external = External::is_external(&spec.external);
+        let (action, reasons) = match docker.inspect_volume(&name).await {
+            Ok(_) if external => (ResourceAction::Unchanged, Vec::new()),
+            Ok(volume) => {
+                let mut reasons = Vec::new();
+                let driver = spec.driver.as_deref().unwrap_or("local");
+                if volume.driver != driver {
+                    reasons.push(format!("driver is {} instead of {}", volume.driver, driver));
+                }
+                reasons.extend(option_changes(&spec.driver_opts, volume.options));
+                resource_action(reasons)
+            }
+            Err(_) if external => (ResourceAction::Missing, vec!["external volume does not exist".to_string()]),
+            Err(_) => (ResourceAction::Create, Vec::new()),
+        };
+        declared.insert(name.clone());
+        plans.push(ResourcePlan { name, action, reasons });
+    }
+
+    let options = ListVolumesOptions { filters: Some(project_filter(project)) };
+    let existing = docker
+        .list_volumes(Some(options))
+        .await
+        .context("Failed to list project volumes")?;
+    for volume in existing.volumes.unwrap_or_default() {
+        if !declared.contains(&volume.name) {
+            plans.push(unused(volume.name));
+        }
+    }
+    Ok(plans)
+}
+
+/// Driver options from the compose file that the resource does not have.
+/// Options Docker adds itself are not compared.
+fn option_changes(wanted: &BTreeMap<String, String>, current: HashMap<String, String>) -> Vec<String> {
+    wanted
+        .iter()
+        .filter(|(key, value)| current.get(*key) != Some(*value))
+        .map(|(key, _)| format!("driver option {} differs", key))
+        .collect()
+}
+
+fn resource_action(reasons: Vec<String>) -> (ResourceAction, Vec<String>) {
+    if reasons.is_empty() {
+        (ResourceAction::Unchanged, reasons)
+    } else {
+        (ResourceAction::Outdated, reasons)
+    }
+}
+
+fn unused(name: String) -> ResourcePlan {
+    ResourcePlan {
+        name,
+        action: ResourceAction::Unused,
+        reasons: vec!["no longer in the compose file".to_string()],
+    }
+}
+
+fn project_filter(project: &Project) -> HashMap<String, Vec<String>> {
+    HashMap::from([("label".to_string(), vec![format!("{}={}", PROJECT_LABEL, project.name)])])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_keys_ignore_image_defaults() {
+        let map = |entries: &[(&str, &str)]| -> BTreeMap<String, String> {
+            entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let old = map(&[("PATH", "/usr/bin"), ("TAG", "1"), ("GONE", "x"), ("SAME", "y")]);
+        let new = map(&[("TAG", "2"), ("SAME", "y"), ("NEW", "z")]);
+        let defaults = map(&[("PATH", "/usr/bin")]);
+        assert_eq!(changed_keys(&old, &new, &defaults), vec!["GONE", "NEW", "TAG"]);
+
+        let old = BTreeSet::from(["80:80/tcp".to_string()]);
+        let new = BTreeSet::from(["8080:80/tcp".to_string()]);
+        assert_eq!(set_change("ports", old.clone(), new).unwrap(), "ports changed: +8080:80/tcp, -80:80/tcp");
+        assert_eq!(set_change("ports", old.clone(), old), None);
+    }
+}
@@ -0,0 +1,260 @@
+//! Revision history of compose stacks.
+//!
+//! Every save of a stack records its compose file, environment and env files
+//! in the `stack_revisions` table. Rolling back saves an earlier revision
+//! again as a new one, so the history only ever grows.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sqlx::{sqlite::SqliteRow, Pool, Row, Sqlite};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::models::compose::{EnvChange, RevisionDiff, StackRevision};
+
+/// Lines of context around each change in a unified diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Line pairs above which diffs fall back to replacing the whole file.
+const DIFF_LIMIT: usize = 16_000_000;
+
+/// Save a new revision of a stack.
+pub async fn record(
+    db: &Pool<Sqlite>,
+    stack_id: &str,
+    compose_content: &str,
+    environment: &HashMap<String, String>,
+    env_files: &HashMap<String, String>,
+    author: Option<&str>,
+    message: Option<&str>,
+) -> Result<StackRevision> {
+    let revision = StackRevision {
+        number: latest(db, stack_id).await? + 1,
+        author: author.map(str::to_string),
+        message: message.map(str::to_string),
+        created_at: Utc::now(),
+        compose_content: compose_content.to_string(),
+        environment: environment.clone(),
+        env_files: env_files.clone(),
+    };
+
+    sqlx::query(
+        "INSERT INTO stack_revisions (stack_id, number, compose_content, environment, env_files, author, message, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(stack_id)
+    .bind(revision.number)
+    .bind(&revision.compose_content)
+    .bind(serde_json::to_string(&revision.environment)?)
+    .bind(serde_json::to_string(&revision.env_files)?)
+    .bind(&revision.author)
+    .bind(&revision.message)
+    .bind(revision.created_at)
+    .execute(db)
+    .await
+    .context("Failed to insert stack revision into database")?;
+    Ok(revision)
+}
+
+/// Number of the latest revision of a stack, or 0 if it has none.
+pub async fn latest(db: &Pool<Sqlite>, stack_id: &str) -> Result<i64> {
+    let number: Option<i64> = sqlx::query_scalar("SELECT MAX(number) FROM stack_revisions WHERE stack_id = ?")
+        .bind(stack_id)
+        .fetch_one(db)
+        .await
+        .context("Failed to fetch stack revisions from database")?;
+    Ok(number.unwrap_or(0))
+}
+
+/// All revisions of a stack, newest first.
+pub async fn list(db: &Pool<Sqlite>, stack_id: &str) -> Result<Vec<StackRevision>> {
+    let rows = sqlx::query("SELECT * FROM stack_revisions WHERE stack_id = ? ORDER BY number DESC")
+        .bind(stack_id)
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch stack revisions from database")?;
+    rows.iter().map(revision_from_row).collect()
+}
+
+/// Get one revision of a stack.
+pub async fn get(db: &Pool<Sqlite>, stack_id: &str, number: i64) -> Result<Option<StackRevision>> {
+    let row = sqlx::query("SELECT * FROM stack_revisions WHERE stack_id = ? AND number = ?")
+        .bind(stack_id)
+        .bind(number)
+        .fetch_optional(db)
+        .await
+        .context("Failed to fetch stack revision from database")?;
+    row.as_ref().map(revision_from_row).transpose()
+}
+
+/// Delete every revision of a stack.
+pub async fn delete_all(db: &Pool<Sqlite>, stack_id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM stack_revisions WHERE stack_id = ?")
+        .bind(stack_id)
+        .execute(db)
+        .await
+        .context("Failed to delete stack revisions from database")?;
+    Ok(())
+}
+
+fn revision_from_row(row: &SqliteRow) -> Result<StackRevision> {
+    let environment: String = row.try_get("environment")?;
+    let env_files: String = row.try_get("env_files")?;
+    Ok(StackRevision {
+        number: row.try_get("number")?,
+        author: row.try_get("author")?,
+        message: row.try_get("message")?,
+        created_at: row.try_get("created_at")?,
+        compose_content: row.try_get("compose_content")?,
+        environment: serde_json::from_str(&environment).context("Failed to deserialize revision environment")?,
+        env_files: serde_json::from_str(&env_files).context("Failed to deserialize revision env files")?,
+    })
+}
+
+/// Compare two revisions.
+pub fn diff(from: &StackRevision, to: &StackRevision) -> RevisionDiff {
+    let keys: BTreeSet<&String> = from.environment.keys().chain(to.environment.keys()).collect();
+    let environment = keys
+        .into_iter()
+        .filter(|key| from.environment.get(*key) != to.environment.get(*key))
+        .map(|key| EnvChange {
+            key: key.clone(),
+            from: from.environment.get(key).cloned(),
+            to: to.environment.get(key).cloned(),
+        })
+        .collect();
+
+    let names: BTreeSet<&String> = from.env_files.keys().chain(to.env_files.keys()).collect();
+    let env_files = names
+        .into_iter()
+        .filter_map(|name| {
+            let old = from.env_files.get(name).map_or("", String::as_str);
+            let new = to.env_files.get(name).map_or("", String::as_str);
+            let diff = unified_diff(old, new, name, name);
+            (!diff.is_empty()).then(|| (name.clone(), diff))
+        })
+        .collect();
+
+    RevisionDiff {
+        from: from.number,
+        to: to.number,
+        compose: unified_diff(&from.compose_content, &to.compose_content, "compose.yml", "compose.yml"),
+        environment,
+        env_files,
+    }
+}
+
+/// One line of an edit script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Remove,
+    Add,
+}
+
+/// Line edits turning `old` into `new`, from their longest common subsequence.
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Edit, &'a str)> {
+    if old.len().saturating_mul(new.len()) > DIFF_LIMIT {
+        let removed = old.iter().map(|line| (Edit::Remove, *line));
+        return removed.chain(new.iter().map(|line| (Edit::Add, *line))).collect();
+    }
+
+    // lcs[i][j] is the common length of old[i..] and new[j..]
+    let width = new.len() + 1;
+    let mut lcs = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut script = Vec::with_capacity(old.len() + new.len());
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            script.push((Edit::Keep, old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            script.push((Edit::Remove, old[i]));
+            i += 1;
+        } else {
+            script.push((Edit::Add, new[j]));
+            j += 1;
+        }
+    }
+    script
+}
+
+/// A unified diff of two texts, or an empty string when they are equal.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let script = edits(&old_lines, &new_lines);
+    let changed: Vec<usize> = (0..script.len()).filter(|&i| script[i].0 != Edit::Keep).collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // Group changes whose context overlaps into hunks of script indices
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &index in &changed {
+        let start = index.saturating_sub(DIFF_CONTEXT);
+        let end = (index + DIFF_CONTEXT + 1).min(script.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", old_name, new_name);
+    for (start, end) in hunks {
+        let count = |edits: &[(Edit, &str)], skip: Edit| edits.iter().filter(|(edit, _)| *edit != skip).count();
+        let old_before = count(&script[..start], Edit::Add);
+        let new_before = count(&script[..start], Edit::Remove);
+        let old_len = count(&script[start..end], Edit::Add);
+        let new_len = count(&script[start..end], Edit::Remove);
+        // Empty ranges are numbered by the line they follow
+        let line = |before: usize, len: usize| if len == 0 { before } else { before + 1 };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            line(old_before, old_len),
+            old_len,
+            line(new_before, new_len),
+            new_len
+        ));
+        for (edit, text) in &script[start..end] {
+            let sign = match edit {
+                Edit::Keep => ' ',
+                Edit::Remove => '-',
+                Edit::Add => '+',
+            };
+            out.push(sign);
+            out.push_str(text);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "services:\n  web:\n    image: nginx:1.25\n    ports: [\"80:80\"]\n  a: 1\n  b: 2\n  c: 3\n  d: 4\n  e: 5\n  f: 6\n  g: 7\n";
+        let new = old.replace("1.25", "1.27") + "  h: 8\n";
+        let diff = unified_diff(old, &new, "compose.yml", "compose.yml");
+        assert_eq!(
+            diff,
+            "--- a/compose.yml\n+++ b/compose.yml\n\
+             @@ -1,6 +1,6 @@\n services:\n   web:\n-    image: nginx:1.25\n+    image: nginx:1.27\n     ports: [\"80:80\"]\n   a: 1\n   b: 2\n\
+             @@ -9,3 +9,4 @@\n   e: 5\n   f: 6\n   g: 7\n+  h: 8\n"
+        );
+        assert_eq!(unified_diff(old, old, "a", "b"), "");
+        assert_eq!(unified_diff("", "x\n", "a", "a"), "--- a/a\n+++ b/a\n@@ -0,0 +1,1 @@\n+x\n");
+    }
+}
@@ -0,0 +1,990 @@
+//! Typed model of a compose file.
+//!
+//! Covers the parts of the Compose Specification that Rustainer can run.
+//! Fields with a short and a long syntax accept both and are normalised by
+//! accessor methods, so the engine only deals with one shape.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path};
+use std::time::Duration;
+
+use super::env::{self, Variables};
+use super::validate::{self, ValidationError};
+
+/// A parsed compose file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ComposeFile {
+    #[serde(default, deserialize_with = "scalar_string", skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub services: BTreeMap<String, ServiceSpec>,
+    #[serde(default)]
+    pub networks: BTreeMap<String, Option<NetworkSpec>>,
+    #[serde(default)]
+    pub volumes: BTreeMap<String, Option<VolumeSpec>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, Option<SecretSpec>>,
+}
+
+/// One service of a compose file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceSpec {
+    pub image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<serde_yaml::Value>,
+    pub command: Option<Command>,
+    pub entrypoint: Option<Command>,
+    #[serde(default)]
+    pub environment: ListOrMap,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_file: Option<EnvFiles>,
+    #[serde(default)]
+    pub labels: ListOrMap,
+    #[serde(default)]
+    pub ports: Vec<PortSpec>,
+    #[serde(default)]
+    pub expose: Vec<Scalar>,
+    #[serde(default)]
+    pub volumes: Vec<VolumeMount>,
+    pub networks: Option<ServiceNetworks>,
+    pub network_mode: Option<String>,
+    pub depends_on: Option<DependsOn>,
+    pub restart: Option<String>,
+    pub container_name: Option<String>,
+    pub hostname: Option<String>,
+    pub working_dir: Option<String>,
+    #[serde(default, deserialize_with = "scalar_string")]
+    pub user: Option<String>,
+    pub stop_signal: Option<String>,
+    #[serde(default, deserialize_with = "scalar_string")]
+    pub stop_grace_period: Option<String>,
+    pub healthcheck: Option<HealthcheckSpec>,
+    pub deploy: Option<DeploySpec>,
+    pub scale: Option<u32>,
+    #[serde(default)]
+    pub privileged: bool,
+    #[serde(default)]
+    pub tty: bool,
+    #[serde(default)]
+    pub stdin_open: bool,
+    #[serde(default)]
+    pub read_only: bool,
+    pub init: Option<bool>,
+    #[serde(default)]
+    pub cap_add: Vec<String>,
+    #[serde(default)]
+    pub cap_drop: Vec<String>,
+    pub dns: Option<StringOrList>,
+    #[serde(default)]
+    pub extra_hosts: ListOrMap,
+    pub tmpfs: Option<StringOrList>,
+    #[serde(default)]
+    pub sysctls: ListOrMap,
+    pub logging: Option<LoggingSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secrets: Vec<ServiceSecret>,
+    /// Environment variables set from secrets, by the top-level secret key.
+    #[serde(
+        default,
+        rename = "x-secret-environment",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub secret_environment: BTreeMap<String, String>,
+}
+
+/// A YAML scalar used where compose accepts strings, numbers or booleans.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Scalar {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl std::fmt::Display for Scalar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scalar::Bool(b) => write!(f, "{}", b),
+            Scalar::Int(i) => write!(f, "{}", i),
+            Scalar::Float(x) => write!(f, "{}", x),
+            Scalar::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// `command` and `entrypoint`: a shell-style string or an exec array.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Command {
+    Shell(String),
+    Exec(Vec<String>),
+}
+
+impl Command {
+    pub fn to_vec(&self) -> Result<Vec<String>> {
+        match self {
+            Command::Shell(line) => shlex::split(line).ok_or_else(|| anyhow!("Cannot split command {:?}", line)),
+            Command::Exec(args) => Ok(args.clone()),
+        }
+    }
+}
+
+/// A single string or a list of them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StringOrList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl StringOrList {
+    pub fn to_vec(&self) -> Vec<String> {
+        match self {
+            StringOrList::One(value) => vec![value.clone()],
+            StringOrList::Many(values) => values.clone(),
+        }
+    }
+}
+
+/// `KEY=value` lists or `KEY: value` maps, as used by `environment` and
+/// `labels`. A missing value means "unset" (or "take from the host").
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ListOrMap {
+    List(Vec<String>),
+    Map(BTreeMap<String, Option<Scalar>>),
+}
+
+impl Default for ListOrMap {
+    fn default() -> Self {
+        ListOrMap::Map(BTreeMap::new())
+    }
+}
+
+impl ListOrMap {
+    /// Entries in key order, split on `sep` for the list syntax.
+    pub fn entries(&self, sep: char) -> BTreeMap<String, Option<String>> {
+        match self {
+            ListOrMap::List(items) => items
+                .iter()
+                .map(|item| match item.split_once(sep) {
+                    Some((key, value)) => (key.trim().to_string(), Some(value.to_string())),
+                    None => (item.trim().to_string(), None),
+                })
+                .collect(),
+            ListOrMap::Map(map) => map
+                .iter()
+                .map(|(key, value)| (key.clone(), value.as_ref().map(Scalar::to_string)))
+                .collect(),
+        }
+    }
+}
+
+/// `env_file`: one path, or a list of paths and `{path, required}` entries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EnvFiles {
+    One(String),
+    Many(Vec<EnvFile>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EnvFile {
+    Path(String),
+    Long {
+        path: String,
+        #[serde(default = "default_true")]
+        required: bool,
+    },
+}
+
+/// A published or exposed port.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PortSpec {
+    Short(Scalar),
+    Long {
+        target: u16,
+        published: Option<Scalar>,
+        host_ip: Option<String>,
+        #[serde(default = "default_protocol")]
+        protocol: String,
+    },
+}
+
+fn default_protocol() -> String {
+    "tcp".to_string()
+}
+
+/// A normalised port mapping. `published` is `None` for a random host port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMapping {
+    pub target: u16,
+    pub published: Option<String>,
+    pub host_ip: Option<String>,
+    pub protocol: String,
+}
+
+impl PortSpec {
+    /// Parse `[[ip:]published:]target[/protocol]` or the long syntax. Ranges
+    /// expand to one mapping per port.
+    pub fn mappings(&self) -> Result<Vec<PortMapping>> {
+        let (spec, protocol) = match self {
+            PortSpec::Long { target, published, host_ip, protocol } => {
+                return Ok(vec![PortMapping {
+                    target: *target,
+                    published: published.as_ref().map(Scalar::to_string),
+                    host_ip: host_ip.clone(),
+                    protocol: protocol.clone(),
+                }]);
+            }
+            PortSpec::Short(spec) => {
+                let spec = spec.to_string();
+                match spec.split_once('/') {
+                    Some((ports, protocol)) => (ports.to_string(), protocol.to_string()),
+                    None => (spec, default_protocol()),
+                }
+            }
+        };
+
+        // The host IP may itself contain colons when it is IPv6 in brackets
+        let (host_ip, rest) = match spec.strip_prefix('[') {
+            Some(rest) => {
+                let (ip, rest) = rest.split_once("]:").ok_or_else(|| anyhow!("Invalid port {:?}", spec))?;
+                (Some(ip.to_string()), rest.to_string())
+            }
+            None => {
+                let parts: Vec<&str> = spec.split(':').collect();
+                match parts.len() {
+                    1 | 2 => (None, spec.clone()),
+                    3 => (Some(parts[0].to_string()), format!("{}:{}", parts[1], parts[2])),
+                    _ => bail!("Invalid port {:?}", spec),
+                }
+            }
+        };
+
+        let (published, target) = match rest.split_once(':') {
+            Some((published, target)) => (Some(published.to_string()).filter(|p| !p.is_empty()), target.to_string()),
+            None => (None, rest),
+        };
+
+        let targets = port_range(&target)?;
+        let published: Vec<Option<String>> = match &published {
+            Some(published) if published.contains('-') => {
+                let range = port_range(published)?;
+                if range.len() != targets.len() {
+                    bail!("Port ranges {:?} and {:?} differ in length", published, target);
+                }
+                range.into_iter().map(|p| Some(p.to_string())).collect()
+            }
+            _ => vec![published; targets.len()],
+        };
+
+        Ok(targets
+            .into_iter()
+            .zip(published)
+            .map(|(target, published)| PortMapping {
+                target,
+                published,
+                host_ip: host_ip.clone(),
+                protocol: protocol.clone(),
+            })
+            .collect())
+    }
+}
+
+fn port_range(spec: &str) -> Result<Vec<u16>> {
+    let parse = |p: &str| p.trim().parse::<u16>().map_err(|_| anyhow!("Invalid port {:?}", spec));
+    match spec.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if start > end {
+                bail!("Invalid port range {:?}", spec);
+            }
+            Ok((start..=end).collect())
+        }
+        None => Ok(vec![parse(spec)?]),
+    }
+}
+
+/// A volume or bind mount on a service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum VolumeMount {
+    Short(String),
+    Long {
+        #[serde(rename = "type", default = "default_mount_type")]
+        kind: String,
+        source: Option<String>,
+        target: String,
+        #[serde(default)]
+        read_only: bool,
+    },
+}
+
+fn default_mount_type() -> String {
+    "volume".to_string()
+}
+
+/// What a mount attaches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountSource {
+    /// A named volume from the top-level `volumes` section.
+    Volume(String),
+    /// An anonymous volume.
+    Anonymous,
+    /// A host path, relative paths resolved against the project directory.
+    Bind(String),
+    Tmpfs,
+}
+
+/// A normalised mount.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub source: MountSource,
+    pub target: String,
+    pub read_only: bool,
+}
+
+impl VolumeMount {
+    pub fn mount(&self) -> Result<Mount> {
+        match self {
+            VolumeMount::Short(spec) => {
+                let parts: Vec<&str> = spec.split(':').collect();
+                let (source, target, mode) = match parts.as_slice() {
+                    [target] => (None, *target, None),
+                    [source, target] => (Some(*source), *target, None),
+                    [source, target, mode] => (Some(*source), *target, Some(*mode)),
+                    _ => bail!("Invalid volume {:?}", spec),
+                };
+                let source = match source {
+                    None => MountSource::Anonymous,
+                    Some(source) if is_path(source) => MountSource::Bind(source.to_string()),
+                    Some(source) => MountSource::Volume(source.to_string()),
+                };
+                let read_only = mode.is_some_and(|mode| mode.split(',').any(|m| m == "ro"));
+                Ok(Mount { source, target: target.to_string(), read_only })
+            }
+            VolumeMount::Long { kind, source, target, read_only } => {
+                let source = match (kind.as_str(), source) {
+                    ("volume", Some(source)) => MountSource::Volume(source.clone()),
+                    ("volume", None) => MountSource::Anonymous,
+                    ("bind", Some(source)) => MountSource::Bind(source.clone()),
+                    ("bind", None) => bail!("Bind mount for {} has no source", target),
+                    ("tmpfs", _) => MountSource::Tmpfs,
+                    (other, _) => bail!("Unsupported mount type {}", other),
+                };
+                Ok(Mount { source, target: target.clone(), read_only: *read_only })
+            }
+        }
+    }
+}
+
+fn is_path(source: &str) -> bool {
+    source.starts_with('/') || source.starts_with('.') || source.starts_with('~')
+}
+
+/// A service's networks, with optional aliases.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ServiceNetworks {
+    List(Vec<String>),
+    Map(BTreeMap<String, Option<ServiceNetwork>>),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceNetwork {
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub ipv4_address: Option<String>,
+}
+
+/// `depends_on` in its list or condition form.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DependsOn {
+    List(Vec<String>),
+    Map(BTreeMap<String, Dependency>),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Dependency {
+    #[serde(default)]
+    pub condition: Condition,
+    #[serde(default = "default_true")]
+    pub required: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// When a dependency counts as ready.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Condition {
+    #[default]
+    #[serde(rename = "service_started")]
+    Started,
+    #[serde(rename = "service_healthy")]
+    Healthy,
+    #[serde(rename = "service_completed_successfully")]
+    CompletedSuccessfully,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HealthcheckSpec {
+    pub test: Option<StringOrList>,
+    #[serde(default, deserialize_with = "scalar_string")]
+    pub interval: Option<String>,
+    #[serde(default, deserialize_with = "scalar_string")]
+    pub timeout: Option<String>,
+    pub retries: Option<i64>,
+    #[serde(default, deserialize_with = "scalar_string")]
+    pub start_period: Option<String>,
+    #[serde(default)]
+    pub disable: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeploySpec {
+    pub replicas: Option<u32>,
+    pub update_config: Option<UpdateConfig>,
+}
+
+/// How `up` replaces the running containers of a changed service.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateConfig {
+    /// Containers replaced at once; 0 replaces them all together.
+    pub parallelism: Option<u32>,
+    /// Pause between two batches.
+    #[serde(default, deserialize_with = "scalar_string")]
+    pub delay: Option<String>,
+    pub failure_action: Option<FailureAction>,
+    /// How long a new container must keep running, or stay healthy, to count as updated.
+    #[serde(default, deserialize_with = "scalar_string")]
+    pub monitor: Option<String>,
+    /// Share of failed containers tolerated before `failure_action` applies.
+    pub max_failure_ratio: Option<f64>,
+    pub order: Option<UpdateOrder>,
+}
+
+/// What to do when replaced containers fail to come up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureAction {
+    Continue,
+    #[default]
+    Rollback,
+    Pause,
+}
+
+/// Whether the old container stops before or after its replacement starts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateOrder {
+    #[default]
+    StopFirst,
+    StartFirst,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoggingSpec {
+    pub driver: Option<String>,
+    #[serde(default, deserialize_with = "scalar_map")]
+    pub options: BTreeMap<String, String>,
+}
+
+/// A top-level network.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetworkSpec {
+    pub driver: Option<String>,
+    #[serde(default, deserialize_with = "scalar_map")]
+    pub driver_opts: BTreeMap<String, String>,
+    pub external: Option<External>,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub internal: bool,
+    #[serde(default)]
+    pub attachable: bool,
+    #[serde(default)]
+    pub labels: ListOrMap,
+}
+
+/// A top-level volume.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VolumeSpec {
+    pub driver: Option<String>,
+    #[serde(default, deserialize_with = "scalar_map")]
+    pub driver_opts: BTreeMap<String, String>,
+    pub external: Option<External>,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub labels: ListOrMap,
+}
+
+/// A top-level secret. Without `file` or `environment`, the value comes from
+/// the secrets store, under `name` or the secret's key.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecretSpec {
+    pub name: Option<String>,
+    pub file: Option<String>,
+    pub environment: Option<String>,
+    pub external: Option<External>,
+}
+
+impl SecretSpec {
+    /// Name of the secret in the store, unless it is read from a file.
+    pub fn store_name<'a>(&'a self, key: &'a str) -> Option<&'a str> {
+        if self.file.is_some() || self.environment.is_some() {
+            return None;
+        }
+        Some(External::name(&self.external).or(self.name.as_deref()).unwrap_or(key))
+    }
+}
+
+/// A secret mounted into a service's containers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ServiceSecret {
+    Short(String),
+    Long(SecretMount),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecretMount {
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, deserialize_with = "scalar_string", skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    #[serde(default, deserialize_with = "scalar_string", skip_serializing_if = "Option::is_none")]
+    pub gid: Option<String>,
+    #[serde(default, deserialize_with = "scalar_string", skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+impl SecretMount {
+    /// Where the file appears in the container, under `/run/secrets` unless
+    /// the target is absolute.
+    pub fn container_path(&self) -> String {
+        let target = self.target.as_deref().unwrap_or(&self.source);
+        if target.starts_with('/') {
+            target.to_string()
+        } else {
+            format!("/run/secrets/{}", target)
+        }
+    }
+
+    /// File mode, written in octal as compose does. Defaults to 0444.
+    pub fn file_mode(&self) -> Result<u32> {
+        match self.mode.as_deref() {
+            None => Ok(0o444),
+            Some(mode) => {
+                let digits = mode.strip_prefix("0o").unwrap_or(mode);
+                match u32::from_str_radix(digits, 8) {
+                    Ok(mode) if mode <= 0o777 => Ok(mode),
+                    _ => bail!("Invalid secret mode {:?}", mode),
+                }
+            }
+        }
+    }
+
+    /// Owner to give the file, as `(uid, gid)`.
+    pub fn owner(&self) -> Result<(Option<u32>, Option<u32>)> {
+        let parse = |id: &Option<String>, what: &str| {
+            id.as_deref()
+                .map(|id| id.parse::<u32>().map_err(|_| anyhow!("Invalid secret {} {:?}", what, id)))
+                .transpose()
+        };
+        Ok((parse(&self.uid, "uid")?, parse(&self.gid, "gid")?))
+    }
+}
+
+/// `external: true`, or the legacy `external: { name: ... }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum External {
+    Flag(bool),
+    Named { name: String },
+}
+
+impl ComposeFile {
+    /// Interpolate, parse and validate a compose file. Invalid files fail
+    /// with a [`ValidationError`] listing every problem found.
+    pub fn parse(content: &str, variables: &Variables) -> Result<Self> {
+        validate::check(content, variables).map_err(|issues| ValidationError(issues).into())
+    }
+
+    /// Resolve a parsed file the way `docker compose config` does. `env_file`
+    /// entries are read into `environment`, values missing from it are taken
+    /// from `variables`, and short syntaxes are rewritten in their long form
+    /// with bind mounts made absolute against `dir`. Env files are read by
+    /// their absolute path with `read`.
+    pub fn resolve(
+        &mut self,
+        project: &str,
+        dir: &Path,
+        variables: &Variables,
+        read: impl Fn(&str) -> std::io::Result<String>,
+    ) -> Result<()> {
+        self.name = Some(project.to_string());
+        for secret in self.secrets.values_mut().flatten() {
+            if let Some(file) = &secret.file {
+                secret.file = Some(resolve_path(dir, file));
+            }
+        }
+        for (name, service) in self.services.iter_mut() {
+            service
+                .resolve(dir, variables, &read)
+                .with_context(|| format!("Failed to resolve service {}", name))?;
+        }
+        Ok(())
+    }
+
+    /// Service names ordered so every service comes after its dependencies.
+    pub fn start_order(&self) -> Result<Vec<String>> {
+        let mut order = Vec::with_capacity(self.services.len());
+        let mut done = BTreeSet::new();
+        let mut visiting = BTreeSet::new();
+
+        fn visit(
+            file: &ComposeFile,
+            name: &str,
+            done: &mut BTreeSet<String>,
+            visiting: &mut BTreeSet<String>,
+            order: &mut Vec<String>,
+        ) -> Result<()> {
+            if done.contains(name) {
+                return Ok(());
+            }
+            if !visiting.insert(name.to_string()) {
+                bail!("Dependency cycle involving service {}", name);
+            }
+            if let Some(service) = file.services.get(name) {
+                for dependency in service.dependencies().keys() {
+                    visit(file, dependency, done, visiting, order)?;
+                }
+            }
+            visiting.remove(name);
+            done.insert(name.to_string());
+            order.push(name.to_string());
+            Ok(())
+        }
+
+        for name in self.services.keys() {
+            visit(self, name, &mut done, &mut visiting, &mut order)?;
+        }
+        Ok(order)
+    }
+}
+
+impl ServiceSpec {
+    fn resolve(
+        &mut self,
+        dir: &Path,
+        variables: &Variables,
+        read: &dyn Fn(&str) -> std::io::Result<String>,
+    ) -> Result<()> {
+        let mut environment: BTreeMap<String, Option<Scalar>> = BTreeMap::new();
+        for (path, required) in self.env_files() {
+            let path = resolve_path(dir, &path);
+            let content = match read(&path) {
+                Ok(content) => content,
+                Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).with_context(|| format!("Failed to read env file {}", path)),
+            };
+            let values = env::parse(&content, variables)
+                .map_err(|(line, message)| anyhow!("Invalid env file {} at line {}: {}", path, line, message))?;
+            environment.extend(values.into_iter().map(|(key, value)| (key, Some(Scalar::Str(value)))));
+        }
+        for (key, value) in self.environment.entries('=') {
+            match value.or_else(|| variables.get(&key).cloned()) {
+                Some(value) => {
+                    environment.insert(key, Some(Scalar::Str(value)));
+                }
+                None => {
+                    environment.entry(key).or_insert(None);
+                }
+            }
+        }
+        self.environment = ListOrMap::Map(environment);
+        self.env_file = None;
+
+        self.labels = ListOrMap::Map(
+            self.labels
+                .entries('=')
+                .into_iter()
+                .map(|(key, value)| (key, value.map(Scalar::Str)))
+                .collect(),
+        );
+        if self.depends_on.is_some() {
+            self.depends_on = Some(DependsOn::Map(self.dependencies()));
+        }
+        if self.network_mode.is_none() && self.networks.is_some() {
+            let networks = self.networks().into_iter().map(|(name, network)| (name, Some(network)));
+            self.networks = Some(ServiceNetworks::Map(networks.collect()));
+        }
+
+        let mut ports = Vec::with_capacity(self.ports.len());
+        for port in &self.ports {
+            ports.extend(port.mappings()?.into_iter().map(|mapping| PortSpec::Long {
+                target: mapping.target,
+                published: mapping.published.map(Scalar::Str),
+                host_ip: mapping.host_ip,
+                protocol: mapping.protocol,
+            }));
+        }
+        self.ports = ports;
+
+        let mut volumes = Vec::with_capacity(self.volumes.len());
+        for volume in &self.volumes {
+            let mount = volume.mount()?;
+            let (kind, source) = match mount.source {
+                MountSource::Volume(name) => ("volume", Some(name)),
+                MountSource::Anonymous => ("volume", None),
+                MountSource::Bind(path) => ("bind", Some(resolve_path(dir, &path))),
+                MountSource::Tmpfs => ("tmpfs", None),
+            };
+            volumes.push(VolumeMount::Long {
+                kind: kind.to_string(),
+                source,
+                target: mount.target,
+                read_only: mount.read_only,
+            });
+        }
+        self.volumes = volumes;
+        Ok(())
+    }
+
+    /// Env files to read, and whether each must exist.
+    pub fn env_files(&self) -> Vec<(String, bool)> {
+        match &self.env_file {
+            None => Vec::new(),
+            Some(EnvFiles::One(path)) => vec![(path.clone(), true)],
+            Some(EnvFiles::Many(files)) => files
+                .iter()
+                .map(|file| match file {
+                    EnvFile::Path(path) => (path.clone(), true),
+                    EnvFile::Long { path, required } => (path.clone(), *required),
+                })
+                .collect(),
+        }
+    }
+
+    /// Dependencies and the condition each must meet.
+    pub fn dependencies(&self) -> BTreeMap<String, Dependency> {
+        match &self.depends_on {
+            None => BTreeMap::new(),
+            Some(DependsOn::List(names)) => names.iter().map(|n| (n.clone(), Dependency::default())).collect(),
+            Some(DependsOn::Map(map)) => map.clone(),
+        }
+    }
+
+    /// Networks to join; services without any join `default`.
+    pub fn networks(&self) -> BTreeMap<String, ServiceNetwork> {
+        match &self.networks {
+            None => BTreeMap::from([("default".to_string(), ServiceNetwork::default())]),
+            Some(ServiceNetworks::List(names)) => names.iter().map(|n| (n.clone(), ServiceNetwork::default())).collect(),
+            Some(ServiceNetworks::Map(map)) => map
+                .iter()
+                .map(|(name, config)| (name.clone(), config.clone().unwrap_or_default()))
+                .collect(),
+        }
+    }
+
+    /// Desired number of containers.
+    pub fn replicas(&self) -> u32 {
+        self.deploy
+            .as_ref()
+            .and_then(|deploy| deploy.replicas)
+            .or(self.scale)
+            .unwrap_or(1)
+    }
+
+    /// Secrets mounted as files, in their long form.
+    pub fn secret_mounts(&self) -> Vec<SecretMount> {
+        self.secrets
+            .iter()
+            .map(|secret| match secret {
+                ServiceSecret::Short(source) => SecretMount {
+                    source: source.clone(),
+                    ..Default::default()
+                },
+                ServiceSecret::Long(mount) => mount.clone(),
+            })
+            .collect()
+    }
+
+    /// Keys of the top-level secrets the service uses.
+    pub fn secret_keys(&self) -> BTreeSet<String> {
+        self.secret_mounts()
+            .into_iter()
+            .map(|mount| mount.source)
+            .chain(self.secret_environment.values().cloned())
+            .collect()
+    }
+
+    /// How changed containers are replaced.
+    pub fn update_config(&self) -> UpdateConfig {
+        self.deploy
+            .as_ref()
+            .and_then(|deploy| deploy.update_config.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl External {
+    pub fn is_external(external: &Option<External>) -> bool {
+        matches!(external, Some(External::Flag(true)) | Some(External::Named { .. }))
+    }
+
+    pub fn name(external: &Option<External>) -> Option<&str> {
+        match external {
+            Some(External::Named { name }) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// Make a host path absolute, relative to the project directory.
+pub fn resolve_path(dir: &Path, path: &str) -> String {
+    if let Some(rest) = path.strip_prefix('~') {
+        let home = std::env::var("HOME").unwrap_or_default();
+        return format!("{}{}", home, rest);
+    }
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return path.display().to_string();
+    }
+    let mut resolved = dir.to_path_buf();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            other => resolved.push(other),
+        }
+    }
+    resolved.display().to_string()
+}
+
+/// Accept any scalar where a string is expected, as compose does for values
+/// like `user: 1000` or `version: 3.8`.
+fn scalar_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(Option::<Scalar>::deserialize(deserializer)?.map(|value| value.to_string()))
+}
+
+/// A map of option values, which are often written as numbers.
+fn scalar_map<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error> {
+    let map = Option::<BTreeMap<String, Option<Scalar>>>::deserialize(deserializer)?.unwrap_or_default();
+    Ok(map
+        .into_iter()
+        .map(|(key, value)| (key, value.map(|v| v.to_string()).unwrap_or_default()))
+        .collect())
+}
+
+/// Parse a compose duration such as `1m30s`, `500ms` or `10` (seconds).
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return Ok(Duration::from_secs_f64(secs));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(|| anyhow!("Duration {:?} is missing a unit", value))?;
+        let number: f64 = rest[..digits].parse().map_err(|_| anyhow!("Invalid duration {:?}", value))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            "us" | "µs" => 0.000_001,
+            "ns" => 0.000_000_001,
+            unit => bail!("Unknown duration unit {:?} in {:?}", unit, value),
+        };
+        total += Duration::from_secs_f64(number * scale);
+        rest = &rest[unit_len..];
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+services:
+  web:
+    image: nginx:alpine
+    command: nginx -g "daemon off;"
+    ports: ["8080:80", "127.0.0.1:9000-9001:9000-9001/udp", 443]
+    volumes: ["data:/data:ro", "./html:/usr/share/nginx/html"]
+    environment:
+      MODE: production
+      WORKERS: 4
+    depends_on:
+      db:
+        condition: service_healthy
+  db:
+    image: postgres:16
+    deploy:
+      replicas: 2
+    healthcheck:
+      test: ["CMD", "pg_isready"]
+      interval: 1m30s
+volumes:
+  data:
+"#;
+
+    #[test]
+    fn test_parse_normalises_short_syntax() {
+        let file = ComposeFile::parse(FILE, &Variables::new()).unwrap();
+        let web = &file.services["web"];
+
+        assert_eq!(web.command.as_ref().unwrap().to_vec().unwrap(), vec!["nginx", "-g", "daemon off;"]);
+        assert_eq!(web.environment.entries('=')["WORKERS"], Some("4".to_string()));
+
+        let ports: Vec<PortMapping> = web.ports.iter().flat_map(|p| p.mappings().unwrap()).collect();
+        assert_eq!(ports.len(), 4);
+        assert_eq!(ports[0].published.as_deref(), Some("8080"));
+        assert_eq!(ports[2].host_ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(ports[2].protocol, "udp");
+        assert_eq!(ports[3].published, None);
+
+        let mounts: Vec<Mount> = web.volumes.iter().map(|v| v.mount().unwrap()).collect();
+        assert_eq!(mounts[0].source, MountSource::Volume("data".to_string()));
+        assert!(mounts[0].read_only);
+        assert_eq!(mounts[1].source, MountSource::Bind("./html".to_string()));
+
+        assert_eq!(file.services["db"].replicas(), 2);
+        assert_eq!(web.dependencies()["db"].condition, Condition::Healthy);
+        assert_eq!(file.start_order().unwrap(), vec!["db", "web"]);
+        assert_eq!(parse_duration("1m30s").unwrap(), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn test_check_rejects_bad_references() {
+        let missing = "services:\n  web:\n    image: x\n    depends_on: [db]\n";
+        assert!(ComposeFile::parse(missing, &Variables::new()).is_err());
+
+        let cycle = "services:\n  a:\n    image: x\n    depends_on: [b]\n  b:\n    image: x\n    depends_on: [a]\n";
+        assert!(ComposeFile::parse(cycle, &Variables::new()).unwrap_err().to_string().contains("cycle"));
+
+        let volume = "services:\n  web:\n    image: x\n    volumes: [\"data:/data\"]\n";
+        assert!(ComposeFile::parse(volume, &Variables::new()).is_err());
+    }
+}
@@ -0,0 +1,1228 @@
+//! Compose file validation with source positions.
+//!
+//! Variables are interpolated while the YAML is read, so problems in the
+//! result still point at the value they came from. The file is then checked
+//! against the Compose Specification schema: known keys, value types,
+//! durations and sizes. Files that pass are then loaded and checked for
+//! references to undefined services, networks and volumes, dependency cycles
+//! and port conflicts. Every problem is reported with the YAML line and
+//! column it was found at, so editors can point at it.
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+
+use super::env::{self, Variables};
+use super::spec::{parse_duration, ComposeFile, DependsOn, External, MountSource, ServiceNetworks, UpdateOrder};
+
+/// One problem in a compose file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    /// Dotted path to the offending value, such as `services.web.ports[0]`.
+    pub path: String,
+    /// 1-based line of the value.
+    pub line: usize,
+    /// 1-based column of the value.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+        } else {
+            write!(f, "line {}, column {}: {} {}", self.line, self.column, self.path, self.message)
+        }
+    }
+}
+
+/// Every problem found in an invalid compose file.
+#[derive(Debug, Clone)]
+pub struct ValidationError(pub Vec<Issue>);
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let issues: Vec<String> = self.0.iter().map(Issue::to_string).collect();
+        write!(f, "Invalid compose file: {}", issues.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Validate a compose file, returning every problem found.
+pub fn validate(content: &str, variables: &Variables) -> Vec<Issue> {
+    check(content, variables).err().unwrap_or_default()
+}
+
+/// Interpolate, validate and load a compose file.
+pub fn check(content: &str, variables: &Variables) -> Result<ComposeFile, Vec<Issue>> {
+    let mut root = match parse_tree(content, variables)? {
+        Some(root) => root,
+        None => return Err(vec![issue_at(1, 0, &[], "compose file is empty")]),
+    };
+
+    let mut issues = Vec::new();
+    check_node(&mut root, &COMPOSE, &mut Vec::new(), &mut issues);
+    if !issues.is_empty() {
+        issues.sort_by_key(|issue| (issue.line, issue.column));
+        return Err(issues);
+    }
+
+    let file: ComposeFile = match serde_yaml::from_value(root.to_value()) {
+        Ok(file) => file,
+        Err(e) => return Err(vec![issue_at(1, 0, &[], &format!("Failed to parse compose file: {}", e))]),
+    };
+    let mut semantic = Semantic { root: &root, issues: Vec::new() };
+    semantic.check(&file);
+    if semantic.issues.is_empty() {
+        Ok(file)
+    } else {
+        let mut issues = semantic.issues;
+        issues.sort_by_key(|issue| (issue.line, issue.column));
+        Err(issues)
+    }
+}
+
+/// One step of a path into the document.
+#[derive(Debug, Clone)]
+enum Seg {
+    Key(String),
+    Index(usize),
+}
+
+fn key(name: &str) -> Seg {
+    Seg::Key(name.to_string())
+}
+
+fn path_string(path: &[Seg]) -> String {
+    let mut out = String::new();
+    for seg in path {
+        match seg {
+            Seg::Key(name) if out.is_empty() => out.push_str(name),
+            Seg::Key(name) => {
+                out.push('.');
+                out.push_str(name);
+            }
+            Seg::Index(index) => out.push_str(&format!("[{}]", index)),
+        }
+    }
+    out
+}
+
+/// Build an issue from a yaml-rust marker, whose columns are 0-based.
+fn issue_at(line: usize, col: usize, path: &[Seg], message: &str) -> Issue {
+    Issue {
+        path: path_string(path),
+        line: line.max(1),
+        column: col + 1,
+        message: message.to_string(),
+    }
+}
+
+/// A YAML scalar resolved with the core schema.
+#[derive(Debug, Clone, PartialEq)]
+enum Scalar {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl Scalar {
+    fn resolve(value: String, style: TScalarStyle) -> Self {
+        if style != TScalarStyle::Plain {
+            return Scalar::Str(value);
+        }
+        match value.as_str() {
+            "" | "~" | "null" | "Null" | "NULL" => Scalar::Null,
+            "true" | "True" | "TRUE" => Scalar::Bool(true),
+            "false" | "False" | "FALSE" => Scalar::Bool(false),
+            _ => {
+                if let Ok(int) = value.parse() {
+                    Scalar::Int(int)
+                } else if let Some(int) = value.strip_prefix("0x").and_then(|hex| i64::from_str_radix(hex, 16).ok()) {
+                    Scalar::Int(int)
+                } else if let Ok(float) = value.parse::<f64>() {
+                    Scalar::Float(float)
+                } else {
+                    Scalar::Str(value)
+                }
+            }
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Scalar::Null => String::new(),
+            Scalar::Bool(b) => b.to_string(),
+            Scalar::Int(i) => i.to_string(),
+            Scalar::Float(x) => x.to_string(),
+            Scalar::Str(s) => s.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Scalar(Scalar),
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+}
+
+/// A YAML node and where it starts.
+#[derive(Debug, Clone)]
+struct Node {
+    kind: Kind,
+    line: usize,
+    col: usize,
+}
+
+impl Node {
+    fn child(&self, seg: &Seg) -> Option<&Node> {
+        match (&self.kind, seg) {
+            (Kind::Map(entries), Seg::Key(name)) => entries
+                .iter()
+                .find(|(key, _)| matches!(&key.kind, Kind::Scalar(s) if s.text() == *name))
+                .map(|(_, value)| value),
+            (Kind::Seq(items), Seg::Index(index)) => items.get(*index),
+            _ => None,
+        }
+    }
+
+    /// The deepest node along a path, for positioning issues.
+    fn locate(&self, path: &[Seg]) -> &Node {
+        let mut node = self;
+        for seg in path {
+            match node.child(seg) {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+        node
+    }
+
+    fn to_value(&self) -> serde_yaml::Value {
+        use serde_yaml::Value;
+        match &self.kind {
+            Kind::Scalar(Scalar::Null) => Value::Null,
+            Kind::Scalar(Scalar::Bool(b)) => Value::Bool(*b),
+            Kind::Scalar(Scalar::Int(i)) => Value::Number((*i).into()),
+            Kind::Scalar(Scalar::Float(x)) => Value::Number((*x).into()),
+            Kind::Scalar(Scalar::Str(s)) => Value::String(s.clone()),
+            Kind::Seq(items) => Value::Sequence(items.iter().map(Node::to_value).collect()),
+            Kind::Map(entries) => Value::Mapping(entries.iter().map(|(k, v)| (k.to_value(), v.to_value())).collect()),
+        }
+    }
+
+    /// Resolve `<<` merge keys in place. Keys set on the mapping itself win
+    /// over merged ones, and earlier merged mappings win over later ones.
+    fn merge(&mut self) {
+        match &mut self.kind {
+            Kind::Scalar(_) => {}
+            Kind::Seq(items) => items.iter_mut().for_each(Node::merge),
+            Kind::Map(entries) => {
+                let mut merged = Vec::new();
+                entries.retain(|(key, value)| {
+                    let is_merge = matches!(&key.kind, Kind::Scalar(Scalar::Str(s)) if s == "<<");
+                    if is_merge {
+                        match &value.kind {
+                            Kind::Map(_) => merged.push(value.clone()),
+                            Kind::Seq(items) => merged.extend(items.iter().cloned()),
+                            Kind::Scalar(_) => {}
+                        }
+                    }
+                    !is_merge
+                });
+                for source in merged {
+                    if let Kind::Map(source) = source.kind {
+                        for (key, value) in source {
+                            let text = key_text(&key);
+                            if !entries.iter().any(|(k, _)| key_text(k) == text) {
+                                entries.push((key, value));
+                            }
+                        }
+                    }
+                }
+                for (_, value) in entries.iter_mut() {
+                    value.merge();
+                }
+            }
+        }
+    }
+}
+
+fn key_text(node: &Node) -> Option<String> {
+    match &node.kind {
+        Kind::Scalar(scalar) => Some(scalar.text()),
+        _ => None,
+    }
+}
+
+/// Builds a [`Node`] tree from parser events, interpolating scalar values.
+struct TreeBuilder<'a> {
+    variables: &'a Variables,
+    stack: Vec<(Node, usize, Option<Node>)>,
+    anchors: HashMap<usize, Node>,
+    root: Option<Node>,
+    issues: Vec<Issue>,
+}
+
+impl TreeBuilder<'_> {
+    /// Whether the next scalar is a mapping key.
+    fn expects_key(&self) -> bool {
+        matches!(self.stack.last(), Some((Node { kind: Kind::Map(_), .. }, _, None)))
+    }
+
+    /// Path to the value being built.
+    fn path(&self) -> Vec<Seg> {
+        self.stack
+            .iter()
+            .filter_map(|(node, _, pending_key)| match &node.kind {
+                Kind::Map(_) => pending_key.as_ref().map(|key| Seg::Key(key_text(key).unwrap_or_default())),
+                Kind::Seq(items) => Some(Seg::Index(items.len())),
+                Kind::Scalar(_) => None,
+            })
+            .collect()
+    }
+
+    /// Interpolated values stay strings; the schema converts them to numbers
+    /// or booleans where those are expected.
+    fn scalar(&mut self, value: String, style: TScalarStyle, mark: Marker) -> Scalar {
+        if self.expects_key() || !value.contains('$') {
+            return Scalar::resolve(value, style);
+        }
+        match env::interpolate(&value, self.variables) {
+            Ok(value) => Scalar::Str(value),
+            Err(message) => {
+                self.issues.push(issue_at(mark.line(), mark.col(), &self.path(), &message));
+                Scalar::Str(value)
+            }
+        }
+    }
+
+    fn insert(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+        match self.stack.last_mut() {
+            None => self.root = Some(node),
+            Some((parent, _, pending_key)) => match &mut parent.kind {
+                Kind::Seq(items) => items.push(node),
+                Kind::Map(entries) => match pending_key.take() {
+                    Some(key) => entries.push((key, node)),
+                    None => *pending_key = Some(node),
+                },
+                Kind::Scalar(_) => {}
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for TreeBuilder<'_> {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let at = |kind| Node { kind, line: mark.line(), col: mark.col() };
+        match event {
+            Event::Scalar(value, style, anchor, _) => {
+                let scalar = self.scalar(value, style, mark);
+                self.insert(at(Kind::Scalar(scalar)), anchor)
+            }
+            Event::SequenceStart(anchor) => self.stack.push((at(Kind::Seq(Vec::new())), anchor, None)),
+            Event::MappingStart(anchor) => self.stack.push((at(Kind::Map(Vec::new())), anchor, None)),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, anchor, _)) = self.stack.pop() {
+                    self.insert(node, anchor);
+                }
+            }
+            Event::Alias(anchor) => {
+                let node = self.anchors.get(&anchor).cloned().unwrap_or_else(|| at(Kind::Scalar(Scalar::Null)));
+                self.insert(node, 0);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parse the first YAML document into an interpolated tree with merge keys
+/// resolved.
+fn parse_tree(content: &str, variables: &Variables) -> Result<Option<Node>, Vec<Issue>> {
+    let mut builder = TreeBuilder {
+        variables,
+        stack: Vec::new(),
+        anchors: HashMap::new(),
+        root: None,
+        issues: Vec::new(),
+    };
+    let mut parser = Parser::new(content.chars());
+    parser.load(&mut builder, false).map_err(|e| {
+        // The message ends with the position, which is reported separately
+        let message = e.to_string();
+        let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(info, _)| info);
+        vec![issue_at(e.marker().line(), e.marker().col(), &[], message)]
+    })?;
+    if !builder.issues.is_empty() {
+        return Err(builder.issues);
+    }
+    let mut root = builder.root;
+    if let Some(root) = &mut root {
+        if matches!(root.kind, Kind::Scalar(Scalar::Null)) {
+            return Ok(None);
+        }
+        root.merge();
+    }
+    Ok(root)
+}
+
+/// The expected shape of a value.
+#[derive(Debug, Clone, Copy)]
+enum Schema {
+    Any,
+    /// Any non-null scalar.
+    Str,
+    Bool,
+    Int,
+    Number,
+    Duration,
+    /// A byte size such as `512m`, or a number of bytes.
+    Size,
+    Enum(&'static [&'static str]),
+    List(&'static Schema),
+    /// A mapping from names to values.
+    Dict(&'static Schema),
+    /// A mapping with known keys. `x-` extension keys are always allowed.
+    Object(&'static [(&'static str, Schema)]),
+    OneOf(&'static [Schema]),
+    Nullable(&'static Schema),
+}
+
+const STRINGS: Schema = Schema::List(&Schema::Str);
+const STRING_OR_LIST: Schema = Schema::OneOf(&[Schema::Str, STRINGS]);
+const LIST_OR_DICT: Schema = Schema::OneOf(&[STRINGS, Schema::Dict(&Schema::Nullable(&Schema::Str))]);
+const COMMAND: Schema = Schema::Nullable(&STRING_OR_LIST);
+const EXTERNAL: Schema = Schema::OneOf(&[Schema::Bool, Schema::Object(&[("name", Schema::Str)])]);
+
+const COMPOSE: Schema = Schema::Object(&[
+    ("version", Schema::Str),
+    ("name", Schema::Str),
+    ("include", Schema::Any),
+    ("services", Schema::Dict(&SERVICE)),
+    ("networks", Schema::Dict(&Schema::Nullable(&NETWORK))),
+    ("volumes", Schema::Dict(&Schema::Nullable(&VOLUME))),
+    ("secrets", Schema::Dict(&Schema::Nullable(&SECRET))),
+    ("configs", Schema::Dict(&Schema::Any)),
+    ("models", Schema::Dict(&Schema::Any)),
+]);
+
+const NETWORK: Schema = Schema::Object(&[
+    ("name", Schema::Str),
+    ("driver", Schema::Str),
+    ("driver_opts", Schema::Dict(&Schema::Nullable(&Schema::Str))),
+    ("ipam", Schema::Any),
+    ("external", EXTERNAL),
+    ("internal", Schema::Bool),
+    ("enable_ipv4", Schema::Bool),
+    ("enable_ipv6", Schema::Bool),
+    ("attachable", Schema::Bool),
+    ("labels", LIST_OR_DICT),
+]);
+
+const VOLUME: Schema = Schema::Object(&[
+    ("name", Schema::Str),
+    ("driver", Schema::Str),
+    ("driver_opts", Schema::Dict(&Schema::Nullable(&Schema::Str))),
+    ("external", EXTERNAL),
+    ("labels", LIST_OR_DICT),
+]);
+
+const SECRET: Schema = Schema::Object(&[
+    ("name", Schema::Str),
+    ("file", Schema::Str),
+    ("environment", Schema::Str),
+    ("external", EXTERNAL),
+    ("labels", LIST_OR_DICT),
+]);
+
+const SERVICE_SECRET: Schema = Schema::OneOf(&[
+    Schema::Str,
+    Schema::Object(&[
+        ("source", Schema::Str),
+        ("target", Schema::Str),
+        ("uid", Schema::Str),
+        ("gid", Schema::Str),
+        ("mode", Schema::Str),
+    ]),
+]);
+
+const PORT: Schema = Schema::OneOf(&[
+    Schema::Str,
+    Schema::Object(&[
+        ("name", Schema::Str),
+        ("mode", Schema::Str),
+        ("host_ip", Schema::Str),
+        ("target", Schema::Int),
+        ("published", Schema::Str),
+        ("protocol", Schema::Str),
+        ("app_protocol", Schema::Str),
+    ]),
+]);
+
+const SERVICE_VOLUME: Schema = Schema::OneOf(&[
+    Schema::Str,
+    Schema::Object(&[
+        ("type", Schema::Enum(&["bind", "volume", "tmpfs", "npipe", "cluster", "image"])),
+        ("source", Schema::Str),
+        ("target", Schema::Str),
+        ("read_only", Schema::Bool),
+        ("consistency", Schema::Str),
+        (
+            "bind",
+            Schema::Object(&[
+                ("propagation", Schema::Str),
+                ("create_host_path", Schema::Bool),
+                ("recursive", Schema::Str),
+                ("selinux", Schema::Enum(&["z", "Z"])),
+            ]),
+        ),
+        ("volume", Schema::Object(&[("nocopy", Schema::Bool), ("subpath", Schema::Str)])),
+        ("tmpfs", Schema::Object(&[("size", Schema::Size), ("mode", Schema::Number)])),
+        ("image", Schema::Object(&[("subpath", Schema::Str)])),
+    ]),
+]);
+
+const SERVICE_NETWORKS: Schema = Schema::OneOf(&[
+    STRINGS,
+    Schema::Dict(&Schema::Nullable(&Schema::Object(&[
+        ("aliases", STRINGS),
+        ("ipv4_address", Schema::Str),
+        ("ipv6_address", Schema::Str),
+        ("link_local_ips", STRINGS),
+        ("mac_address", Schema::Str),
+        ("driver_opts", Schema::Dict(&Schema::Nullable(&Schema::Str))),
+        ("priority", Schema::Int),
+        ("gw_priority", Schema::Int),
+    ]))),
+]);
+
+const ENV_FILE: Schema = Schema::OneOf(&[
+    Schema::Str,
+    Schema::Object(&[("path", Schema::Str), ("required", Schema::Bool), ("format", Schema::Str)]),
+]);
+
+const DEPENDS_ON: Schema = Schema::OneOf(&[
+    STRINGS,
+    Schema::Dict(&Schema::Object(&[
+        (
+            "condition",
+            Schema::Enum(&["service_started", "service_healthy", "service_completed_successfully"]),
+        ),
+        ("restart", Schema::Bool),
+        ("required", Schema::Bool),
+    ])),
+]);
+
+const HEALTHCHECK: Schema = Schema::Object(&[
+    ("disable", Schema::Bool),
+    ("interval", Schema::Duration),
+    ("retries", Schema::Int),
+    ("test", STRING_OR_LIST),
+    ("timeout", Schema::Duration),
+    ("start_period", Schema::Duration),
+    ("start_interval", Schema::Duration),
+]);
+
+const RESOURCES: Schema = Schema::Object(&[
+    ("cpus", Schema::Number),
+    ("memory", Schema::Size),
+    ("pids", Schema::Int),
+    ("devices", Schema::Any),
+    ("generic_resources", Schema::Any),
+]);
+
+const UPDATE_CONFIG: Schema = Schema::Object(&[
+    ("parallelism", Schema::Int),
+    ("delay", Schema::Duration),
+    ("failure_action", Schema::Enum(&["continue", "rollback", "pause"])),
+    ("monitor", Schema::Duration),
+    ("max_failure_ratio", Schema::Number),
+    ("order", Schema::Enum(&["stop-first", "start-first"])),
+]);
+
+const DEPLOY: Schema = Schema::Object(&[
+    ("mode", Schema::Str),
+    ("replicas", Schema::Int),
+    ("labels", LIST_OR_DICT),
+    ("endpoint_mode", Schema::Str),
+    ("resources", Schema::Object(&[("limits", RESOURCES), ("reservations", RESOURCES)])),
+    (
+        "restart_policy",
+        Schema::Object(&[
+            ("condition", Schema::Enum(&["none", "on-failure", "any"])),
+            ("delay", Schema::Duration),
+            ("max_attempts", Schema::Int),
+            ("window", Schema::Duration),
+        ]),
+    ),
+    ("update_config", UPDATE_CONFIG),
+    ("rollback_config", Schema::Any),
+    ("placement", Schema::Any),
+]);
+
+const SERVICE: Schema = Schema::Object(&[
+    ("annotations", LIST_OR_DICT),
+    ("attach", Schema::Bool),
+    ("blkio_config", Schema::Any),
+    ("build", Schema::OneOf(&[Schema::Str, Schema::Dict(&Schema::Any)])),
+    ("cap_add", STRINGS),
+    ("cap_drop", STRINGS),
+    ("cgroup", Schema::Enum(&["host", "private"])),
+    ("cgroup_parent", Schema::Str),
+    ("command", COMMAND),
+    ("configs", Schema::List(&Schema::OneOf(&[Schema::Str, Schema::Dict(&Schema::Any)]))),
+    ("container_name", Schema::Str),
+    ("cpu_count", Schema::Int),
+    ("cpu_percent", Schema::Int),
+    ("cpu_period", Schema::Int),
+    ("cpu_quota", Schema::Int),
+    ("cpu_rt_period", Schema::Duration),
+    ("cpu_rt_runtime", Schema::Duration),
+    ("cpu_shares", Schema::Int),
+    ("cpus", Schema::Number),
+    ("cpuset", Schema::Str),
+    ("credential_spec", Schema::Any),
+    ("depends_on", DEPENDS_ON),
+    ("deploy", DEPLOY),
+    ("develop", Schema::Any),
+    ("device_cgroup_rules", STRINGS),
+    ("devices", Schema::List(&Schema::OneOf(&[Schema::Str, Schema::Dict(&Schema::Any)]))),
+    ("dns", STRING_OR_LIST),
+    ("dns_opt", STRINGS),
+    ("dns_search", STRING_OR_LIST),
+    ("domainname", Schema::Str),
+    ("entrypoint", COMMAND),
+    ("env_file", Schema::OneOf(&[Schema::Str, Schema::List(&ENV_FILE)])),
+    ("environment", LIST_OR_DICT),
+    ("expose", STRINGS),
+    ("extends", Schema::OneOf(&[Schema::Str, Schema::Dict(&Schema::Any)])),
+    ("external_links", STRINGS),
+    ("extra_hosts", LIST_OR_DICT),
+    ("gpus", Schema::Any),
+    ("group_add", STRINGS),
+    ("healthcheck", HEALTHCHECK),
+    ("hostname", Schema::Str),
+    ("image", Schema::Str),
+    ("init", Schema::Bool),
+    ("ipc", Schema::Str),
+    ("isolation", Schema::Str),
+    ("label_file", STRING_OR_LIST),
+    ("labels", LIST_OR_DICT),
+    ("links", STRINGS),
+    (
+        "logging",
+        Schema::Object(&[("driver", Schema::Str), ("options", Schema::Dict(&Schema::Nullable(&Schema::Str)))]),
+    ),
+    ("mac_address", Schema::Str),
+    ("mem_limit", Schema::Size),
+    ("mem_reservation", Schema::Size),
+    ("mem_swappiness", Schema::Int),
+    ("memswap_limit", Schema::Size),
+    ("network_mode", Schema::Str),
+    ("networks", SERVICE_NETWORKS),
+    ("oom_kill_disable", Schema::Bool),
+    ("oom_score_adj", Schema::Int),
+    ("pid", Schema::Nullable(&Schema::Str)),
+    ("pids_limit", Schema::Int),
+    ("platform", Schema::Str),
+    ("ports", Schema::List(&PORT)),
+    ("post_start", Schema::Any),
+    ("pre_stop", Schema::Any),
+    ("privileged", Schema::Bool),
+    ("profiles", STRINGS),
+    ("provider", Schema::Any),
+    ("pull_policy", Schema::Str),
+    ("read_only", Schema::Bool),
+    ("restart", Schema::Str),
+    ("runtime", Schema::Str),
+    ("scale", Schema::Int),
+    ("secrets", Schema::List(&SERVICE_SECRET)),
+    ("security_opt", STRINGS),
+    ("shm_size", Schema::Size),
+    ("stdin_open", Schema::Bool),
+    ("stop_grace_period", Schema::Duration),
+    ("stop_signal", Schema::Str),
+    ("storage_opt", Schema::Dict(&Schema::Any)),
+    ("sysctls", LIST_OR_DICT),
+    ("tmpfs", STRING_OR_LIST),
+    ("tty", Schema::Bool),
+    ("ulimits", Schema::Dict(&Schema::OneOf(&[Schema::Int, Schema::Object(&[("soft", Schema::Int), ("hard", Schema::Int)])]))),
+    ("user", Schema::Str),
+    ("userns_mode", Schema::Str),
+    ("uts", Schema::Str),
+    ("volumes", Schema::List(&SERVICE_VOLUME)),
+    ("volumes_from", STRINGS),
+    ("working_dir", Schema::Str),
+    ("x-secret-environment", Schema::Dict(&Schema::Str)),
+]);
+
+impl Schema {
+    /// Whether a node has the right kind of value to be checked further.
+    fn accepts_kind(&self, node: &Node) -> bool {
+        match (self, &node.kind) {
+            (Schema::Any, _) => true,
+            (Schema::Nullable(_), Kind::Scalar(Scalar::Null)) => true,
+            (Schema::Nullable(inner), _) => inner.accepts_kind(node),
+            (Schema::OneOf(options), _) => options.iter().any(|option| option.accepts_kind(node)),
+            (Schema::List(_), Kind::Seq(_)) => true,
+            (Schema::Dict(_) | Schema::Object(_), Kind::Map(_)) => true,
+            (Schema::List(_) | Schema::Dict(_) | Schema::Object(_), _) => false,
+            (_, Kind::Scalar(scalar)) => *scalar != Scalar::Null,
+            _ => false,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Schema::Any => "any value".to_string(),
+            Schema::Str => "a string".to_string(),
+            Schema::Bool => "a boolean".to_string(),
+            Schema::Int => "an integer".to_string(),
+            Schema::Number => "a number".to_string(),
+            Schema::Duration => "a duration such as 1m30s".to_string(),
+            Schema::Size => "a size such as 512m".to_string(),
+            Schema::Enum(values) => format!("one of {}", values.join(", ")),
+            Schema::List(_) => "a list".to_string(),
+            Schema::Dict(_) | Schema::Object(_) => "a mapping".to_string(),
+            Schema::Nullable(inner) => inner.describe(),
+            Schema::OneOf(options) => {
+                let mut described: Vec<String> = options.iter().map(Schema::describe).collect();
+                described.dedup();
+                described.join(" or ")
+            }
+        }
+    }
+}
+
+/// Check a node against a schema. Strings, such as interpolated values, are
+/// converted in place where a number or boolean is expected.
+fn check_node(node: &mut Node, schema: &Schema, path: &mut Vec<Seg>, issues: &mut Vec<Issue>) {
+    let (line, col) = (node.line, node.col);
+    let fail = |issues: &mut Vec<Issue>, path: &[Seg], message: String| issues.push(issue_at(line, col, path, &message));
+
+    let coerced = match (schema, &node.kind) {
+        (Schema::Bool, Kind::Scalar(Scalar::Str(s))) => s.parse().ok().map(Scalar::Bool),
+        (Schema::Int, Kind::Scalar(Scalar::Str(s))) => s.parse().ok().map(Scalar::Int),
+        (Schema::Number, Kind::Scalar(Scalar::Str(s))) => {
+            s.parse().ok().map(Scalar::Int).or_else(|| s.parse().ok().map(Scalar::Float))
+        }
+        _ => None,
+    };
+    if let Some(scalar) = coerced {
+        node.kind = Kind::Scalar(scalar);
+    }
+
+    let scalar = match &node.kind {
+        Kind::Scalar(scalar) => Some(scalar),
+        _ => None,
+    };
+
+    match schema {
+        Schema::Any => {}
+        Schema::Nullable(inner) => {
+            if scalar != Some(&Scalar::Null) {
+                check_node(node, inner, path, issues);
+            }
+        }
+        Schema::Str => {
+            if !matches!(scalar, Some(s) if *s != Scalar::Null) {
+                fail(issues, path, format!("must be {}", schema.describe()));
+            }
+        }
+        Schema::Bool => match scalar {
+            Some(Scalar::Bool(_)) => {}
+            _ => fail(issues, path, format!("must be {}", schema.describe())),
+        },
+        Schema::Int => match scalar {
+            Some(Scalar::Int(_)) => {}
+            _ => fail(issues, path, format!("must be {}", schema.describe())),
+        },
+        Schema::Number => match scalar {
+            Some(Scalar::Int(_) | Scalar::Float(_)) => {}
+            _ => fail(issues, path, format!("must be {}", schema.describe())),
+        },
+        Schema::Duration => match scalar {
+            Some(s) if *s != Scalar::Null => {
+                if let Err(e) = parse_duration(&s.text()) {
+                    fail(issues, path, format!("must be {}: {}", schema.describe(), e));
+                }
+            }
+            _ => fail(issues, path, format!("must be {}", schema.describe())),
+        },
+        Schema::Size => match scalar {
+            Some(Scalar::Int(n)) if *n >= 0 => {}
+            Some(Scalar::Str(s)) if parse_size(s).is_some() => {}
+            Some(s) if *s != Scalar::Null => fail(issues, path, format!("{:?} is not {}", s.text(), schema.describe())),
+            _ => fail(issues, path, format!("must be {}", schema.describe())),
+        },
+        Schema::Enum(values) => match scalar {
+            Some(s) if values.contains(&s.text().as_str()) => {}
+            Some(s) if *s != Scalar::Null => fail(issues, path, format!("{:?} must be {}", s.text(), schema.describe())),
+            _ => fail(issues, path, format!("must be {}", schema.describe())),
+        },
+        Schema::List(item) => match &mut node.kind {
+            Kind::Seq(items) => {
+                for (index, child) in items.iter_mut().enumerate() {
+                    path.push(Seg::Index(index));
+                    check_node(child, item, path, issues);
+                    path.pop();
+                }
+            }
+            _ => fail(issues, path, format!("must be {}", schema.describe())),
+        },
+        Schema::Dict(value) => match &mut node.kind {
+            Kind::Map(entries) => {
+                for (key, child) in entries {
+                    path.push(Seg::Key(key_text(key).unwrap_or_default()));
+                    check_node(child, value, path, issues);
+                    path.pop();
+                }
+            }
+            _ => fail(issues, path, format!("must be {}", schema.describe())),
+        },
+        Schema::Object(fields) => match &mut node.kind {
+            Kind::Map(entries) => {
+                for (key, child) in entries {
+                    let name = key_text(key).unwrap_or_default();
+                    let known = fields.iter().any(|(field, _)| *field == name);
+                    if name.starts_with("x-") && !known {
+                        continue;
+                    }
+                    match fields.iter().find(|(field, _)| *field == name) {
+                        Some((_, field)) => {
+                            path.push(Seg::Key(name));
+                            check_node(child, field, path, issues);
+                            path.pop();
+                        }
+                        None => issues.push(issue_at(
+                            key.line,
+                            key.col,
+                            path,
+                            &format!("additional property {} is not allowed", name),
+                        )),
+                    }
+                }
+            }
+            _ => fail(issues, path, format!("must be {}", schema.describe())),
+        },
+        Schema::OneOf(options) => {
+            let mut candidates = Vec::new();
+            let applicable: Vec<&Schema> = options.iter().filter(|option| option.accepts_kind(node)).collect();
+            for option in applicable {
+                let mut found = Vec::new();
+                check_node(node, option, path, &mut found);
+                if found.is_empty() {
+                    return;
+                }
+                candidates.push(found);
+            }
+            match candidates.len() {
+                1 => issues.extend(candidates.remove(0)),
+                _ => fail(issues, path, format!("must be {}", schema.describe())),
+            }
+        }
+    }
+}
+
+/// Parse a byte size such as `512m`, `1.5gb` or `1024`.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim().to_ascii_lowercase();
+    let digits = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let number: f64 = value[..digits].parse().ok()?;
+    let scale: u64 = match value[digits..].trim() {
+        "" | "b" => 1,
+        "k" | "kb" => 1 << 10,
+        "m" | "mb" => 1 << 20,
+        "g" | "gb" => 1 << 30,
+        "t" | "tb" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * scale as f64) as u64)
+}
+
+/// Host IP and service of each published `(protocol, port)`.
+type PublishedPorts = HashMap<(String, u16), Vec<(Option<String>, String)>>;
+
+/// Checks that need the whole loaded file.
+struct Semantic<'a> {
+    root: &'a Node,
+    issues: Vec<Issue>,
+}
+
+impl Semantic<'_> {
+    fn report(&mut self, path: &[Seg], message: String) {
+        let node = self.root.locate(path);
+        self.issues.push(issue_at(node.line, node.col, path, &message));
+    }
+
+    fn check(&mut self, file: &ComposeFile) {
+        if file.services.is_empty() {
+            self.report(&[key("services")], "must define at least one service".to_string());
+        }
+
+        let mut published: PublishedPorts = HashMap::new();
+
+        for (name, service) in &file.services {
+            let base = [key("services"), key(name)];
+            let at = |segs: &[Seg]| -> Vec<Seg> { base.iter().cloned().chain(segs.iter().cloned()).collect() };
+
+            if service.image.as_deref() == Some("") {
+                self.report(&at(&[key("image")]), "must not be empty".to_string());
+            }
+            if service.image.is_none() {
+                let message = if service.build.is_some() {
+                    "uses build, which is not supported; set an image"
+                } else {
+                    "must set an image"
+                };
+                self.report(&base, message.to_string());
+            }
+            if service.container_name.is_some() && service.replicas() > 1 {
+                self.report(&at(&[key("container_name")]), "cannot be set on a service with several replicas".to_string());
+            }
+            let start_first = service.update_config().order == Some(UpdateOrder::StartFirst);
+            let order = at(&[key("deploy"), key("update_config"), key("order")]);
+            if start_first && service.container_name.is_some() {
+                self.report(&order, "start-first cannot be used with container_name".to_string());
+            }
+            if let Some(restart) = &service.restart {
+                let valid = match restart.strip_prefix("on-failure") {
+                    Some(rest) => rest.is_empty() || rest.strip_prefix(':').is_some_and(|n| n.parse::<u32>().is_ok()),
+                    None => matches!(restart.as_str(), "no" | "always" | "unless-stopped"),
+                };
+                if !valid {
+                    self.report(
+                        &at(&[key("restart")]),
+                        format!("{:?} must be no, always, unless-stopped or on-failure[:max-retries]", restart),
+                    );
+                }
+            }
+
+            let dependencies: Vec<(Seg, String)> = match &service.depends_on {
+                None => Vec::new(),
+                Some(DependsOn::List(names)) => names.iter().enumerate().map(|(i, n)| (Seg::Index(i), n.clone())).collect(),
+                Some(DependsOn::Map(map)) => map.keys().map(|n| (key(n), n.clone())).collect(),
+            };
+            for (seg, dependency) in dependencies {
+                if !file.services.contains_key(&dependency) {
+                    self.report(&at(&[key("depends_on"), seg]), format!("refers to undefined service {}", dependency));
+                }
+            }
+
+            match &service.network_mode {
+                Some(mode) => {
+                    if service.networks.is_some() {
+                        self.report(&at(&[key("network_mode")]), "cannot be combined with networks".to_string());
+                    }
+                    if let Some(other) = mode.strip_prefix("service:") {
+                        if !file.services.contains_key(other) {
+                            self.report(&at(&[key("network_mode")]), format!("refers to undefined service {}", other));
+                        }
+                    }
+                }
+                None => {
+                    let networks: Vec<(Seg, String)> = match &service.networks {
+                        None => Vec::new(),
+                        Some(ServiceNetworks::List(names)) => {
+                            names.iter().enumerate().map(|(i, n)| (Seg::Index(i), n.clone())).collect()
+                        }
+                        Some(ServiceNetworks::Map(map)) => map.keys().map(|n| (key(n), n.clone())).collect(),
+                    };
+                    for (seg, network) in networks {
+                        if network != "default" && !file.networks.contains_key(&network) {
+                            self.report(&at(&[key("networks"), seg]), format!("refers to undefined network {}", network));
+                        }
+                    }
+                }
+            }
+
+            for (index, volume) in service.volumes.iter().enumerate() {
+                let path = at(&[key("volumes"), Seg::Index(index)]);
+                match volume.mount() {
+                    Ok(mount) => {
+                        if let MountSource::Volume(volume) = mount.source {
+                            if !file.volumes.contains_key(&volume) {
+                                self.report(&path, format!("refers to undefined volume {}", volume));
+                            }
+                        }
+                    }
+                    Err(e) => self.report(&path, e.to_string()),
+                }
+            }
+
+            for (index, mount) in service.secret_mounts().iter().enumerate() {
+                let path = at(&[key("secrets"), Seg::Index(index)]);
+                if !file.secrets.contains_key(&mount.source) {
+                    self.report(&path, format!("refers to undefined secret {}", mount.source));
+                }
+                if let Err(e) = mount.file_mode().and(mount.owner()) {
+                    self.report(&path, e.to_string());
+                }
+            }
+            for (variable, secret) in &service.secret_environment {
+                let path = at(&[key("x-secret-environment"), key(variable)]);
+                match file.secrets.get(secret) {
+                    None => self.report(&path, format!("refers to undefined secret {}", secret)),
+                    Some(spec) if spec.as_ref().is_some_and(|spec| spec.store_name(secret).is_none()) => {
+                        self.report(&path, format!("secret {} must come from the secrets store", secret));
+                    }
+                    Some(_) => {}
+                }
+                if service.environment.entries('=').iter().any(|(name, _)| name == variable) {
+                    self.report(&path, format!("{} is also set in environment", variable));
+                }
+            }
+
+            for (index, port) in service.ports.iter().enumerate() {
+                let path = at(&[key("ports"), Seg::Index(index)]);
+                let mappings = match port.mappings() {
+                    Ok(mappings) => mappings,
+                    Err(e) => {
+                        self.report(&path, e.to_string());
+                        continue;
+                    }
+                };
+                for mapping in mappings {
+                    let Some(port) = mapping.published.as_deref().and_then(|p| p.parse::<u16>().ok()) else {
+                        continue;
+                    };
+                    if start_first {
+                        self.report(&order, format!("start-first cannot be used while publishing host port {}; use stop-first", port));
+                    }
+                    if service.replicas() > 1 {
+                        self.report(
+                            &path,
+                            format!("publishes port {}/{} but the service has {} replicas", port, mapping.protocol, service.replicas()),
+                        );
+                    }
+                    let users = published.entry((mapping.protocol.clone(), port)).or_default();
+                    let clash = users.iter().find(|(ip, _)| {
+                        let any = |ip: &Option<String>| matches!(ip.as_deref(), None | Some("0.0.0.0") | Some("::"));
+                        any(ip) || any(&mapping.host_ip) || *ip == mapping.host_ip
+                    });
+                    if let Some((_, other)) = clash {
+                        let message = format!("port {}/{} is already published by service {}", port, mapping.protocol, other);
+                        self.report(&path, message);
+                    }
+                    users.push((mapping.host_ip.clone(), name.clone()));
+                }
+            }
+        }
+
+        for (name, secret) in &file.secrets {
+            let Some(secret) = secret else {
+                continue;
+            };
+            if secret.environment.is_some() {
+                let path = [key("secrets"), key(name), key("environment")];
+                self.report(&path, "is not supported; keep the value in the secrets store instead".to_string());
+            }
+            if secret.file.is_some() && External::is_external(&secret.external) {
+                self.report(&[key("secrets"), key(name), key("file")], "cannot be combined with external".to_string());
+            }
+            if let Some(store) = secret.store_name(name) {
+                if let Err(e) = crate::secrets::check_name(store) {
+                    self.report(&[key("secrets"), key(name)], e.to_string());
+                }
+            }
+        }
+
+        if let Some(name) = find_cycle(file) {
+            self.report(
+                &[key("services"), key(&name), key("depends_on")],
+                format!("dependency cycle involving service {}", name),
+            );
+        }
+    }
+}
+
+/// A service on a `depends_on` cycle, if there is one.
+fn find_cycle(file: &ComposeFile) -> Option<String> {
+    fn visit(file: &ComposeFile, name: &str, done: &mut BTreeSet<String>, visiting: &mut Vec<String>) -> Option<String> {
+        if done.contains(name) {
+            return None;
+        }
+        if visiting.iter().any(|v| v == name) {
+            return Some(name.to_string());
+        }
+        visiting.push(name.to_string());
+        if let Some(service) = file.services.get(name) {
+            for dependency in service.dependencies().keys() {
+                if let Some(found) = visit(file, dependency, done, visiting) {
+                    return Some(found);
+                }
+            }
+        }
+        visiting.pop();
+        done.insert(name.to_string());
+        None
+    }
+
+    let mut done = BTreeSet::new();
+    file.services
+        .keys()
+        .find_map(|name| visit(file, name, &mut done, &mut Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(content: &str) -> Vec<(usize, usize, String)> {
+        validate(content, &Variables::new())
+            .into_iter()
+            .map(|issue| (issue.line, issue.column, issue.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_schema_errors_have_positions() {
+        let content = "\
+x-defaults: &defaults
+  restart: always
+services:
+  web:
+    <<: *defaults
+    image: nginx
+    ports: {http: 80}
+    healthcheck:
+      interval: 10 parsecs
+    shm_size: lots
+    colour: blue
+";
+        let found = problems(content);
+        assert_eq!(found.len(), 4, "{:?}", found);
+        assert_eq!((found[0].0, found[0].1), (7, 12));
+        assert!(found[0].2.contains("services.web.ports must be a list"));
+        assert_eq!(found[1].0, 9);
+        assert!(found[1].2.contains("duration"));
+        assert_eq!(found[2].0, 10);
+        assert_eq!((found[3].0, found[3].1), (11, 5));
+        assert!(found[3].2.contains("additional property colour"));
+    }
+
+    #[test]
+    fn test_start_first_needs_free_names_and_ports() {
+        let content = "\
+services:
+  web:
+    image: nginx
+    ports: [\"8080:80\", \"443\"]
+    deploy:
+      update_config:
+        order: start-first
+  api:
+    image: api
+    container_name: api
+    deploy:
+      update_config: {order: start-first, parallelism: 2}
+";
+        let found = problems(content);
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert_eq!((found[0].0, found[0].1), (7, 16));
+        assert!(found[0].2.contains("start-first cannot be used while publishing host port 8080"));
+        assert!(found[1].2.contains("start-first cannot be used with container_name"));
+
+        let found = problems("services:\n  web:\n    image: nginx\n    deploy:\n      update_config: {monitor: soon}\n");
+        assert!(found[0].2.contains("update_config.monitor must be a duration"), "{:?}", found);
+    }
+
+    #[test]
+    fn test_secret_references() {
+        let content = "\
+services:
+  web:
+    image: nginx
+    environment: {TOKEN: plain}
+    secrets: [db_password, {source: cert, mode: 0440}, missing]
+    x-secret-environment:
+      DB_PASSWORD: db_password
+      TOKEN: api_token
+      KEY: cert
+secrets:
+  db_password: {external: true}
+  api_token: {name: api-token}
+  cert: {file: ./cert.pem}
+  legacy: {environment: TOKEN}
+";
+        let found = problems(content);
+        assert_eq!(found.len(), 4, "{:?}", found);
+        assert!(found[0].2.contains("secrets[2] refers to undefined secret missing"));
+        assert!(found[1].2.contains("x-secret-environment.TOKEN TOKEN is also set in environment"));
+        assert!(found[2].2.contains("x-secret-environment.KEY secret cert must come from the secrets store"));
+        assert!(found[3].2.contains("secrets.legacy.environment is not supported"));
+
+        let found = problems("services:\n  web:\n    image: nginx\n    x-secret-environment: [TOKEN]\n");
+        assert!(found[0].2.contains("x-secret-environment must be a mapping"), "{:?}", found);
+    }
+
+    #[test]
+    fn test_references_and_port_conflicts() {
+        let content = "\
+services:
+  web:
+    image: nginx
+    ports: [\"8080:80\"]
+    depends_on: [db, cache]
+  api:
+    image: api
+    ports: [\"127.0.0.1:8080:3000\"]
+    networks: [back]
+    volumes: [\"data:/data\"]
+";
+        let found = problems(content);
+        assert_eq!(found.len(), 5, "{:?}", found);
+        assert!(found[0].2.contains("port 8080/tcp is already published by service api"));
+        assert!(found[1].2.contains("depends_on[0] refers to undefined service db"));
+        assert!(found[2].2.contains("depends_on[1] refers to undefined service cache"));
+        assert_eq!((found[3].0, found[3].1), (9, 16));
+        assert!(found[3].2.contains("undefined network back"));
+        assert!(found[4].2.contains("undefined volume data"));
+        assert!(problems("services:\n  web:\n    image: nginx\n    restart: on-failure:3\n").is_empty());
+    }
+
+    #[test]
+    fn test_interpolated_values_keep_positions() {
+        let content = "\
+services:
+  web:
+    image: nginx:${TAG:-latest}
+    ports: [\"${PORT}:80\"]
+    read_only: ${READ_ONLY}
+  db:
+    image: ${DB_IMAGE:?set DB_IMAGE}
+    deploy:
+      replicas: ${REPLICAS}
+";
+        let mut vars = Variables::from([
+            ("PORT".to_string(), "8080".to_string()),
+            ("READ_ONLY".to_string(), "true".to_string()),
+            ("REPLICAS".to_string(), "2".to_string()),
+        ]);
+        let issues = validate(content, &vars);
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!((issues[0].line, issues[0].column), (7, 12));
+        assert_eq!(issues[0].path, "services.db.image");
+        assert!(issues[0].message.ends_with("missing a value: set DB_IMAGE"));
+
+        vars.insert("DB_IMAGE".to_string(), "postgres".to_string());
+        let file = check(content, &vars).unwrap();
+        assert_eq!(file.services["web"].image.as_deref(), Some("nginx:latest"));
+        assert!(file.services["web"].read_only);
+        assert_eq!(file.services["db"].replicas(), 2);
+
+        vars.insert("REPLICAS".to_string(), "two".to_string());
+        let issues = validate(content, &vars);
+        assert_eq!((issues[0].line, issues[0].path.as_str()), (9, "services.db.deploy.replicas"));
+    }
+}
@@ -1,291 +1,677 @@
-use std::collections::HashMap;
-use std::sync::Arc;
-
-use bollard::container::{
-    Config, CreateContainerOptions, ListContainersOptions, LogOutput, LogsOptions, RemoveContainerOptions,
-    RestartContainerOptions, StartContainerOptions, StatsOptions, StopContainerOptions,
+//! Creating standalone containers with the options of `docker run`.
+//!
+//! A request is checked in full before anything is sent to the daemon, so a
+//! mistake in one option is reported along with the others instead of
+//! leaving a half-made container behind. The image is pulled if it is not
+//! in the local store. The first network is joined when the container is
+//! created and the others before it starts, as the daemon only takes one
+//! network at creation.
+
+use anyhow::{Context, Result};
+use bollard::auth::DockerCredentials;
+use bollard::models::{
+    ContainerCreateBody, DeviceMapping, EndpointIpamConfig, EndpointSettings, HealthConfig, HostConfig,
+    HostConfigLogConfig, Mount, MountTypeEnum, NetworkConnectRequest, NetworkingConfig, PortBinding,
+    ResourcesUlimits, RestartPolicy as DockerRestartPolicy, RestartPolicyNameEnum,
 };
-use bollard::models::{ContainerStateStatusEnum, HostConfig, PortBinding};
+use bollard::query_parameters::{CreateContainerOptions, RemoveContainerOptions, StartContainerOptions};
 use bollard::Docker;
-use chrono::{DateTime, TimeZone, Utc};
-use futures::stream::StreamExt;
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tracing::{info, warn};
+
+use super::compose::spec::parse_duration;
+use super::compose::validate::parse_size;
+use super::images;
+use crate::models::container::{
+    CreateContainerRequest, HealthcheckRequest, NetworkRequest, ResourcesRequest, RestartPolicy, VolumeKind,
+};
 
-use crate::models::{Container, ContainerLogs, ContainerStats, CreateContainerRequest};
-use crate::models::container::PortMapping;
+/// Smallest memory limit the daemon accepts.
+const MIN_MEMORY: i64 = 6 << 20;
 
-/// List all containers
-pub async fn list_containers(docker: &Docker) -> anyhow::Result<Vec<Container>> {
-    let options = Some(ListContainersOptions::<String> {
-        all: true,
-        ..Default::default()
-    });
-
-    let containers = docker.list_containers(options).await?;
-
-    let result = containers
-        .into_iter()
-        .map(|c| {
-            // Extract container names (remove leading slash)
-            let names = c
-                .names
-                .unwrap_or_default()
-                .into_iter()
-                .map(|name| name.trim_start_matches('/').to_string())
-                .collect();
-
-            // Extract port mappings
-            let ports = c
-                .ports
-                .unwrap_or_default()
-                .into_iter()
-                .filter_map(|port| {
-                    if let Some(protocol) = port.typ {
-                        Some(PortMapping {
-                            container_port: port.private_port as u16,
-                            host_port: port.public_port.map(|p| p as u16),
-                            protocol: protocol.to_string(),
-                        })
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-
-            // Convert created timestamp to DateTime
-            let created = Utc.timestamp_opt(c.created.unwrap_or(0), 0).unwrap();
-
-            Container {
-                id: c.id.unwrap_or_default(),
-                names,
-                image: c.image.unwrap_or_default(),
-                status: c.status.unwrap_or_default(),
-                state: c.state.map_or_else(String::new, |s| s.to_string()),
-                created,
-                ports,
-            }
-        })
-        .collect();
+/// A request to create a container that is not valid, with every problem
+/// found in it.
+#[derive(Debug)]
+pub struct InvalidContainer(pub Vec<String>);
 
-    Ok(result)
+impl std::fmt::Display for InvalidContainer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.join("; "))
+    }
 }
 
-/// Start a container
-pub async fn start_container(docker: &Docker, id: &str) -> anyhow::Result<()> {
-    docker
-        .start_container(id, None::<StartContainerOptions<String>>)
-        .await?;
-    Ok(())
-}
+impl std::error::Error for InvalidContainer {}
 
-/// Stop a container
-pub async fn stop_container(docker: &Docker, id: &str) -> anyhow::Result<()> {
-    docker
-        .stop_container(id, None::<StopContainerOptions>)
-        .await?;
-    Ok(())
+/// A checked request, in the daemon's terms.
+#[derive(Debug)]
+pub struct Prepared {
+    pub name: Option<String>,
+    pub body: ContainerCreateBody,
+    /// Networks to connect after the container is created.
+    pub networks: Vec<(String, EndpointSettings)>,
 }
 
-/// Restart a container
-pub async fn restart_container(docker: &Docker, id: &str) -> anyhow::Result<()> {
-    docker
-        .restart_container(id, None::<RestartContainerOptions>)
-        .await?;
-    Ok(())
-}
+/// Check a request and turn it into the daemon's create request.
+pub fn prepare(request: &CreateContainerRequest) -> Result<Prepared> {
+    let mut problems = Vec::new();
 
-/// Get container logs
-pub async fn get_container_logs(docker: &Docker, id: &str) -> anyhow::Result<ContainerLogs> {
-    let options = Some(LogsOptions::<String> {
-        stdout: true,
-        stderr: true,
-        tail: "100".to_string(),
-        ..Default::default()
-    });
-
-    let mut logs_stream = docker.logs(id, options);
-    let mut log_lines = Vec::new();
-
-    while let Some(log_result) = logs_stream.next().await {
-        match log_result {
-            Ok(log_output) => {
-                let line = match log_output {
-                    LogOutput::StdOut { message } => String::from_utf8_lossy(&message).to_string(),
-                    LogOutput::StdErr { message } => String::from_utf8_lossy(&message).to_string(),
-                    _ => continue,
-                };
-                log_lines.push(line);
+    let image = request.image.trim();
+    if image.is_empty() {
+        problems.push("image is required".to_string());
+    }
+    let name = request.name.as_deref().map(str::trim).filter(|name| !name.is_empty());
+    if let Some(name) = name {
+        if !is_valid_name(name) {
+            problems.push(format!("invalid container name {:?}", name));
+        }
+    }
+    if let Some(dir) = &request.working_dir {
+        if !dir.starts_with('/') {
+            problems.push(format!("working_dir {:?} must be an absolute path", dir));
+        }
+    }
+    if request.command.as_ref().is_some_and(|command| command.iter().any(|arg| arg.contains('\0'))) {
+        problems.push("command must not contain NUL characters".to_string());
+    }
+
+    let mut env: Vec<String> = Vec::new();
+    for (key, value) in &request.env {
+        if key.is_empty() || key.contains('=') || key.contains('\0') {
+            problems.push(format!("invalid environment variable name {:?}", key));
+        } else {
+            env.push(format!("{}={}", key, value));
+        }
+    }
+    env.sort();
+    if request.labels.keys().any(|key| key.trim().is_empty()) {
+        problems.push("label names must not be empty".to_string());
+    }
+
+    let mut exposed_ports = HashMap::new();
+    let mut port_bindings: HashMap<String, Option<Vec<PortBinding>>> = HashMap::new();
+    let mut published = HashSet::new();
+    for port in &request.ports {
+        if port.container_port == 0 {
+            problems.push("container_port must be between 1 and 65535".to_string());
+            continue;
+        }
+        if !matches!(port.protocol.as_str(), "tcp" | "udp" | "sctp") {
+            problems.push(format!("invalid protocol {:?} for port {}", port.protocol, port.container_port));
+            continue;
+        }
+        if let Some(ip) = &port.host_ip {
+            if ip.parse::<IpAddr>().is_err() {
+                problems.push(format!("invalid host_ip {:?} for port {}", ip, port.container_port));
             }
-            Err(e) => {
-                tracing::error!("Error getting logs: {}", e);
+        }
+        if let Some(host_port) = port.host_port.filter(|port| *port != 0) {
+            if !published.insert((host_port, port.host_ip.clone(), port.protocol.clone())) {
+                problems.push(format!("host port {}/{} is published twice", host_port, port.protocol));
             }
         }
+        let key = format!("{}/{}", port.container_port, port.protocol);
+        exposed_ports.insert(key.clone(), HashMap::new());
+        port_bindings
+            .entry(key)
+            .or_insert_with(|| Some(Vec::new()))
+            .get_or_insert_with(Vec::new)
+            .push(PortBinding {
+                host_ip: port.host_ip.clone(),
+                host_port: port.host_port.map(|port| port.to_string()),
+            });
     }
 
-    Ok(ContainerLogs {
-        id: id.to_string(),
-        logs: log_lines,
-    })
-}
-
-/// Create a new container
-pub async fn create_container(
-    docker: &Docker,
-    request: CreateContainerRequest,
-) -> anyhow::Result<Container> {
-    // Parse port bindings
-    let mut port_bindings = HashMap::new();
-    if let Some(ports) = &request.ports {
-        for port_mapping in ports {
-            let parts: Vec<&str> = port_mapping.split(':').collect();
-            if parts.len() == 2 {
-                let host_port = parts[0].parse::<u16>()?;
-                let container_port = parts[1].parse::<u16>()?;
-                let key = format!("{}/tcp", container_port);
-                
-                port_bindings.insert(
-                    key,
-                    Some(vec![PortBinding {
-                        host_ip: Some("0.0.0.0".to_string()),
-                        host_port: Some(host_port.to_string()),
-                    }]),
-                );
+    let mut mounts = Vec::new();
+    let mut targets = HashSet::new();
+    for volume in &request.volumes {
+        if !volume.target.starts_with('/') {
+            problems.push(format!("volume target {:?} must be an absolute path", volume.target));
+            continue;
+        }
+        if !targets.insert(volume.target.trim_end_matches('/').to_string()) {
+            problems.push(format!("{} is mounted twice", volume.target));
+        }
+        let source = volume.source.as_deref().map(str::trim).filter(|source| !source.is_empty());
+        let typ = match (volume.kind, source) {
+            (VolumeKind::Bind, Some(path)) if path.starts_with('/') => MountTypeEnum::BIND,
+            (VolumeKind::Bind, _) => {
+                problems.push(format!("bind mount on {} needs an absolute host path as source", volume.target));
+                continue;
+            }
+            (VolumeKind::Volume, Some(name)) if !is_valid_name(name) => {
+                problems.push(format!("invalid volume name {:?}", name));
+                continue;
             }
+            (VolumeKind::Volume, _) => MountTypeEnum::VOLUME,
+            (VolumeKind::Tmpfs, Some(_)) => {
+                problems.push(format!("tmpfs mount on {} cannot have a source", volume.target));
+                continue;
+            }
+            (VolumeKind::Tmpfs, None) => MountTypeEnum::TMPFS,
+        };
+        mounts.push(Mount {
+            target: Some(volume.target.clone()),
+            source: source.map(str::to_string),
+            typ: Some(typ),
+            read_only: Some(volume.read_only),
+            ..Default::default()
+        });
+    }
+    for path in request.tmpfs.keys() {
+        if !path.starts_with('/') {
+            problems.push(format!("tmpfs path {:?} must be an absolute path", path));
+        } else if !targets.insert(path.trim_end_matches('/').to_string()) {
+            problems.push(format!("{} is mounted twice", path));
+        }
+    }
+
+    let restart_policy = restart_policy(request, &mut problems);
+    let resources = resources(&request.resources, &mut problems);
+
+    for capability in request.cap_add.iter().chain(&request.cap_drop) {
+        if !is_valid_capability(capability) {
+            problems.push(format!("invalid capability {:?}", capability));
         }
     }
 
-    // Parse volume bindings
-    let mut binds = Vec::new();
-    if let Some(volumes) = &request.volumes {
-        for volume_mapping in volumes {
-            binds.push(volume_mapping.clone());
+    let mut devices = Vec::new();
+    for device in &request.devices {
+        if !device.path_on_host.starts_with('/') {
+            problems.push(format!("device {:?} must be an absolute path", device.path_on_host));
+            continue;
+        }
+        let in_container = device.path_in_container.clone().unwrap_or_else(|| device.path_on_host.clone());
+        if !in_container.starts_with('/') {
+            problems.push(format!("device path {:?} in the container must be absolute", in_container));
+            continue;
+        }
+        let permissions = device.permissions.clone().unwrap_or_else(|| "rwm".to_string());
+        if permissions.is_empty() || !permissions.chars().all(|c| matches!(c, 'r' | 'w' | 'm')) {
+            problems.push(format!("invalid permissions {:?} for device {}", permissions, device.path_on_host));
+            continue;
         }
+        devices.push(DeviceMapping {
+            path_on_host: Some(device.path_on_host.clone()),
+            path_in_container: Some(in_container),
+            cgroup_permissions: Some(permissions),
+        });
     }
-    // Create container
-    let options = CreateContainerOptions {
-        name: request.name,
-        platform: None,
+
+    let (network_mode, endpoints) = networks(request, &mut problems);
+
+    let healthcheck = request.healthcheck.as_ref().map(|healthcheck| health_config(healthcheck, &mut problems));
+
+    let log_config = match &request.log {
+        Some(log) if log.driver.trim().is_empty() => {
+            problems.push("log driver must not be empty".to_string());
+            None
+        }
+        Some(log) => Some(HostConfigLogConfig {
+            typ: Some(log.driver.trim().to_string()),
+            config: Some(log.options.clone()),
+        }),
+        None => None,
+    };
+
+    let mut ulimits = Vec::new();
+    let mut ulimit_names = HashSet::new();
+    for ulimit in &request.ulimits {
+        if ulimit.name.trim().is_empty() {
+            problems.push("ulimit names must not be empty".to_string());
+        } else if !ulimit_names.insert(ulimit.name.as_str()) {
+            problems.push(format!("ulimit {} is set twice", ulimit.name));
+        } else if ulimit.soft > ulimit.hard && ulimit.hard >= 0 {
+            problems.push(format!("soft limit of ulimit {} is above its hard limit", ulimit.name));
+        } else {
+            ulimits.push(ResourcesUlimits {
+                name: Some(ulimit.name.clone()),
+                soft: Some(ulimit.soft),
+                hard: Some(ulimit.hard),
+            });
+        }
+    }
+
+    if request.sysctls.keys().any(|key| key.trim().is_empty() || key.contains('=')) {
+        problems.push("sysctl names must not be empty or contain =".to_string());
+    }
+    for server in &request.dns.servers {
+        if server.parse::<IpAddr>().is_err() {
+            problems.push(format!("invalid DNS server {:?}", server));
+        }
+    }
+    let mut extra_hosts = Vec::new();
+    for (host, ip) in &request.extra_hosts {
+        if host.is_empty() || host.contains(':') || host.contains(char::is_whitespace) {
+            problems.push(format!("invalid extra host name {:?}", host));
+        } else if ip != "host-gateway" && ip.parse::<IpAddr>().is_err() {
+            problems.push(format!("invalid address {:?} for extra host {}", ip, host));
+        } else {
+            extra_hosts.push(format!("{}:{}", host, ip));
+        }
+    }
+    extra_hosts.sort();
+
+    if !problems.is_empty() {
+        return Err(InvalidContainer(problems).into());
+    }
+
+    let (primary, networks) = match endpoints.split_first() {
+        Some(((name, endpoint), rest)) => (Some((name.clone(), endpoint.clone())), rest.to_vec()),
+        None => (None, Vec::new()),
+    };
+    let host_config = HostConfig {
+        port_bindings: Some(port_bindings),
+        mounts: Some(mounts),
+        restart_policy: Some(restart_policy),
+        network_mode: primary.as_ref().map(|(name, _)| name.clone()).or(network_mode),
+        privileged: Some(request.privileged),
+        readonly_rootfs: Some(request.read_only),
+        init: request.init,
+        cap_add: Some(request.cap_add.clone()),
+        cap_drop: Some(request.cap_drop.clone()),
+        devices: Some(devices),
+        ulimits: Some(ulimits),
+        sysctls: Some(request.sysctls.clone()),
+        tmpfs: Some(request.tmpfs.clone()),
+        dns: Some(request.dns.servers.clone()),
+        dns_search: Some(request.dns.search.clone()),
+        dns_options: Some(request.dns.options.clone()),
+        extra_hosts: Some(extra_hosts),
+        log_config,
+        ..resources
     };
 
-    let config = Config {
-        image: Some(request.image),
-        env: request.env,
-        host_config: Some(HostConfig {
-            port_bindings: Some(port_bindings),
-            binds: Some(binds),
+    Ok(Prepared {
+        name: name.map(str::to_string),
+        body: ContainerCreateBody {
+            image: Some(image.to_string()),
+            cmd: request.command.clone(),
+            entrypoint: request.entrypoint.clone(),
+            working_dir: request.working_dir.clone(),
+            user: request.user.clone().filter(|user| !user.is_empty()),
+            hostname: request.hostname.clone().filter(|hostname| !hostname.is_empty()),
+            env: Some(env),
+            labels: Some(request.labels.clone()),
+            exposed_ports: Some(exposed_ports),
+            healthcheck,
+            host_config: Some(host_config),
+            networking_config: primary.map(|(name, endpoint)| NetworkingConfig {
+                endpoints_config: Some(HashMap::from([(name, endpoint)])),
+            }),
             ..Default::default()
-        }),
+        },
+        networks,
+    })
+}
+
+/// Create a container, and start it if asked to. Returns its ID. A container
+/// that could not be set up is removed again.
+pub async fn create(
+    docker: &Docker,
+    request: &CreateContainerRequest,
+    credentials: Option<DockerCredentials>,
+) -> Result<String> {
+    let prepared = prepare(request)?;
+    let image = request.image.trim();
+    let label = prepared.name.clone().unwrap_or_else(|| image.to_string());
+    if docker.inspect_image(image).await.is_err() {
+        info!("Pulling image {}", image);
+        let mut progress = Box::pin(images::pull(docker, image, credentials));
+        while let Some(step) = progress.next().await {
+            step?;
+        }
+    }
+
+    let options = CreateContainerOptions {
+        name: prepared.name.clone(),
         ..Default::default()
     };
+    let id = docker
+        .create_container(Some(options), prepared.body)
+        .await
+        .with_context(|| format!("Failed to create container {}", label))?
+        .id;
+    info!("Created container {} ({})", label, &id[..12.min(id.len())]);
+
+    if let Err(e) = set_up(docker, &id, &label, prepared.networks, request.start).await {
+        let options = RemoveContainerOptions {
+            force: true,
+            ..Default::default()
+        };
+        if let Err(remove) = docker.remove_container(&id, Some(options)).await {
+            warn!("Failed to remove container {} that could not be set up: {}", label, remove);
+        }
+        return Err(e);
+    }
+    Ok(id)
+}
 
-    let response = docker.create_container(Some(options), config).await?;
+async fn set_up(
+    docker: &Docker,
+    id: &str,
+    name: &str,
+    networks: Vec<(String, EndpointSettings)>,
+    start: bool,
+) -> Result<()> {
+    for (network, endpoint) in networks {
+        let request = NetworkConnectRequest {
+            container: Some(id.to_string()),
+            endpoint_config: Some(endpoint),
+        };
+        docker
+            .connect_network(&network, request)
+            .await
+            .with_context(|| format!("Failed to connect {} to network {}", name, network))?;
+    }
+    if start {
+        docker
+            .start_container(id, None::<StartContainerOptions>)
+            .await
+            .with_context(|| format!("Failed to start container {}", name))?;
+    }
+    Ok(())
+}
 
-    // Start the container
-    docker
-        .start_container(&response.id, None::<StartContainerOptions<String>>)
-        .await?;
+/// Whether a container or volume name is one the daemon accepts.
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
 
-    // Get container details
-    let containers = list_containers(docker).await?;
-    let container = containers
-        .into_iter()
-        .find(|c| c.id == response.id)
-        .ok_or_else(|| anyhow::anyhow!("Container not found after creation"))?;
+fn is_valid_capability(capability: &str) -> bool {
+    let name = capability.strip_prefix("CAP_").unwrap_or(capability);
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
 
-    Ok(container)
+fn restart_policy(request: &CreateContainerRequest, problems: &mut Vec<String>) -> DockerRestartPolicy {
+    let restart = &request.restart;
+    let name = match restart.policy {
+        RestartPolicy::No => RestartPolicyNameEnum::NO,
+        RestartPolicy::Always => RestartPolicyNameEnum::ALWAYS,
+        RestartPolicy::UnlessStopped => RestartPolicyNameEnum::UNLESS_STOPPED,
+        RestartPolicy::OnFailure => RestartPolicyNameEnum::ON_FAILURE,
+    };
+    match restart.max_retries {
+        Some(_) if restart.policy != RestartPolicy::OnFailure => {
+            problems.push("max_retries only applies to the on-failure restart policy".to_string())
+        }
+        Some(retries) if retries < 0 => problems.push("max_retries must not be negative".to_string()),
+        _ => {}
+    }
+    DockerRestartPolicy {
+        name: Some(name),
+        maximum_retry_count: restart.max_retries,
+    }
 }
 
-/// Get container stats
-pub async fn get_container_stats(docker: &Docker, id: &str) -> anyhow::Result<ContainerStats> {
-    let options = Some(StatsOptions {
-        stream: false,
+/// Limits as host config fields, to be completed by the caller.
+fn resources(resources: &ResourcesRequest, problems: &mut Vec<String>) -> HostConfig {
+    let mut size = |field: &str, value: &Option<String>| -> Option<i64> {
+        let value = value.as_deref()?;
+        match parse_size(value).and_then(|bytes| i64::try_from(bytes).ok()) {
+            Some(bytes) => Some(bytes),
+            None => {
+                problems.push(format!("invalid {} {:?}", field, value));
+                None
+            }
+        }
+    };
+    let memory = size("memory", &resources.memory);
+    let memory_reservation = size("memory_reservation", &resources.memory_reservation);
+    let memory_swap = match resources.memory_swap.as_deref().map(str::trim) {
+        Some("-1") => Some(-1),
+        _ => size("memory_swap", &resources.memory_swap),
+    };
+
+    if memory.is_some_and(|memory| memory < MIN_MEMORY) {
+        problems.push("memory must be at least 6m".to_string());
+    }
+    if let (Some(memory), Some(reservation)) = (memory, memory_reservation) {
+        if reservation > memory {
+            problems.push("memory_reservation must not be above memory".to_string());
+        }
+    }
+    match (memory, memory_swap) {
+        (None, Some(_)) => problems.push("memory_swap needs memory to be set".to_string()),
+        (Some(memory), Some(swap)) if swap != -1 && swap < memory => {
+            problems.push("memory_swap must not be below memory".to_string())
+        }
+        _ => {}
+    }
+    let nano_cpus = match resources.cpus {
+        Some(cpus) if !cpus.is_finite() || cpus <= 0.0 => {
+            problems.push("cpus must be above 0".to_string());
+            None
+        }
+        Some(cpus) => Some((cpus * 1e9) as i64),
+        None => None,
+    };
+    if resources.cpu_shares.is_some_and(|shares| shares < 2) {
+        problems.push("cpu_shares must be at least 2".to_string());
+    }
+    if let Some(cpuset) = &resources.cpuset_cpus {
+        let valid = cpuset.split(',').all(|part| {
+            let (start, end) = part.split_once('-').unwrap_or((part, part));
+            matches!((start.trim().parse::<u32>(), end.trim().parse::<u32>()), (Ok(start), Ok(end)) if start <= end)
+        });
+        if !valid {
+            problems.push(format!("invalid cpuset_cpus {:?}", cpuset));
+        }
+    }
+
+    HostConfig {
+        nano_cpus,
+        cpu_shares: resources.cpu_shares,
+        cpuset_cpus: resources.cpuset_cpus.clone(),
+        memory,
+        memory_reservation,
+        memory_swap,
+        pids_limit: resources.pids_limit,
         ..Default::default()
-    });
+    }
+}
 
-    let mut stats_stream = docker.stats(id, options);
-    let stats = match stats_stream.next().await {
-        Some(Ok(stats)) => stats,
-        Some(Err(e)) => return Err(anyhow::anyhow!("Failed to get stats: {}", e)),
-        None => return Err(anyhow::anyhow!("No stats available")),
+/// The network mode, and the endpoint of each network to join in order.
+fn networks(
+    request: &CreateContainerRequest,
+    problems: &mut Vec<String>,
+) -> (Option<String>, Vec<(String, EndpointSettings)>) {
+    let mode = request.network_mode.as_deref().map(str::trim).filter(|mode| !mode.is_empty());
+    let isolated = match mode {
+        Some("host" | "none") => true,
+        Some(mode) if mode.starts_with("container:") => {
+            if mode.len() == "container:".len() {
+                problems.push("network_mode container: needs a container name".to_string());
+            }
+            true
+        }
+        _ => false,
     };
+    if isolated && !request.networks.is_empty() {
+        problems.push(format!("networks cannot be joined with network_mode {}", mode.unwrap_or_default()));
+    }
+    if isolated && !request.ports.is_empty() {
+        problems.push(format!("ports cannot be published with network_mode {}", mode.unwrap_or_default()));
+    }
 
-    // Calculate CPU usage percentage
-    let cpu_delta = stats.cpu_stats.as_ref()
-        .and_then(|cpu| cpu.cpu_usage.as_ref())
-        .and_then(|usage| usage.total_usage)
-        .unwrap_or(0) as f64 -
-        stats.precpu_stats.as_ref()
-        .and_then(|cpu| cpu.cpu_usage.as_ref())
-        .and_then(|usage| usage.total_usage)
-        .unwrap_or(0) as f64;
-    
-    let system_delta = stats.cpu_stats.as_ref()
-        .and_then(|cpu| cpu.system_cpu_usage)
-        .unwrap_or(0) as f64 -
-        stats.precpu_stats.as_ref()
-        .and_then(|cpu| cpu.system_cpu_usage)
-        .unwrap_or(0) as f64;
-    
-    let cpu_count = stats.cpu_stats.as_ref()
-        .and_then(|cpu| cpu.online_cpus)
-        .unwrap_or(1) as f64;
-
-    let cpu_usage_percent = if system_delta > 0.0 && cpu_delta > 0.0 {
-        (cpu_delta / system_delta) * cpu_count * 100.0
-    } else {
-        0.0
+    let mut seen = HashSet::new();
+    let mut endpoints = Vec::new();
+    for network in &request.networks {
+        let name = network.name.trim();
+        if name.is_empty() {
+            problems.push("network names must not be empty".to_string());
+            continue;
+        }
+        if !seen.insert(name) {
+            problems.push(format!("network {} is joined twice", name));
+            continue;
+        }
+        endpoints.push((name.to_string(), endpoint(network, problems)));
+    }
+    (mode.map(str::to_string), endpoints)
+}
+
+fn endpoint(network: &NetworkRequest, problems: &mut Vec<String>) -> EndpointSettings {
+    if network.aliases.iter().any(|alias| alias.trim().is_empty()) {
+        problems.push(format!("aliases on network {} must not be empty", network.name));
+    }
+    let ipv4 = network.ipv4_address.clone().filter(|address| !address.is_empty());
+    let ipv6 = network.ipv6_address.clone().filter(|address| !address.is_empty());
+    if let Some(address) = ipv4.as_ref().filter(|address| address.parse::<Ipv4Addr>().is_err()) {
+        problems.push(format!("invalid ipv4_address {:?} on network {}", address, network.name));
+    }
+    if let Some(address) = ipv6.as_ref().filter(|address| address.parse::<Ipv6Addr>().is_err()) {
+        problems.push(format!("invalid ipv6_address {:?} on network {}", address, network.name));
+    }
+    let static_ip = ipv4.is_some() || ipv6.is_some();
+    EndpointSettings {
+        aliases: Some(network.aliases.clone()).filter(|aliases| !aliases.is_empty()),
+        ipam_config: static_ip.then(|| EndpointIpamConfig {
+            ipv4_address: ipv4,
+            ipv6_address: ipv6,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn health_config(healthcheck: &HealthcheckRequest, problems: &mut Vec<String>) -> HealthConfig {
+    let mut nanos = |field: &str, value: &Option<String>| -> Option<i64> {
+        let value = value.as_deref()?;
+        match parse_duration(value) {
+            // The daemon takes 0 as its default and rejects anything else
+            // under a millisecond
+            Ok(duration) if !duration.is_zero() && duration.as_millis() < 1 => {
+                problems.push(format!("healthcheck {} must be at least 1ms", field));
+                None
+            }
+            Ok(duration) => Some(duration.as_nanos() as i64),
+            Err(_) => {
+                problems.push(format!("invalid healthcheck {} {:?}", field, value));
+                None
+            }
+        }
     };
+    let interval = nanos("interval", &healthcheck.interval);
+    let timeout = nanos("timeout", &healthcheck.timeout);
+    let start_period = nanos("start_period", &healthcheck.start_period);
 
-    // Calculate memory usage percentage
-    let memory_usage_bytes = stats.memory_stats.as_ref()
-        .and_then(|mem| mem.usage)
-        .unwrap_or(0);
-    
-    let memory_limit = stats.memory_stats.as_ref()
-        .and_then(|mem| mem.limit)
-        .unwrap_or(1);
-    
-    let memory_usage_percent = (memory_usage_bytes as f64 / memory_limit as f64) * 100.0;
-
-    // Calculate network I/O
-    // Calculate network I/O
-    let mut network_input_bytes = 0;
-    let mut network_output_bytes = 0;
-    // Skip network stats for now as the structure is complex
-    // We'll implement this properly in a future update
-
-    // Calculate block I/O
-    let mut block_input_bytes = 0;
-    let mut block_output_bytes = 0;
-    if let Some(blkio) = stats.blkio_stats.as_ref() {
-        if let Some(io_stats) = &blkio.io_service_bytes_recursive {
-            for io_stat in io_stats {
-                if let Some(op) = &io_stat.op {
-                    if let Some(value) = io_stat.value {
-                        match op.as_str() {
-                            "Read" => block_input_bytes += value,
-                            "Write" => block_output_bytes += value,
-                            _ => {}
-                        }
-                    }
-                }
+    let test = if healthcheck.disable {
+        Some(vec!["NONE".to_string()])
+    } else {
+        match healthcheck.test.first().map(String::as_str) {
+            None => None,
+            Some("CMD" | "CMD-SHELL") if healthcheck.test.len() > 1 => Some(healthcheck.test.clone()),
+            Some("CMD" | "CMD-SHELL") => {
+                problems.push("healthcheck test needs a command after CMD or CMD-SHELL".to_string());
+                None
+            }
+            Some(_) => {
+                problems.push("healthcheck test must start with CMD or CMD-SHELL".to_string());
+                None
             }
         }
+    };
+    if healthcheck.retries.is_some_and(|retries| retries < 0) {
+        problems.push("healthcheck retries must not be negative".to_string());
     }
+    HealthConfig {
+        test,
+        interval,
+        timeout,
+        retries: healthcheck.retries,
+        start_period,
+        ..Default::default()
+    }
+}
 
-    // Get process count
-    let process_count = stats.pids_stats.as_ref()
-        .and_then(|pids| pids.current)
-        .unwrap_or(0);
-
-    Ok(ContainerStats {
-        id: id.to_string(),
-        cpu_usage_percent,
-        memory_usage_bytes,
-        memory_usage_percent,
-        network_input_bytes,
-        network_output_bytes,
-        block_input_bytes,
-        block_output_bytes,
-        process_count,
-    })
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: &str) -> CreateContainerRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn problems(json: &str) -> Vec<String> {
+        let e = prepare(&request(json)).unwrap_err();
+        e.downcast::<InvalidContainer>().unwrap().0
+    }
+
+    #[test]
+    fn test_prepare_maps_host_config() {
+        let prepared = prepare(&request(
+            r#"{
+                "image": "nginx:1.27", "name": "web", "command": ["nginx", "-g", "daemon off;"],
+                "ports": [{"container_port": 80, "host_port": 8080}, {"container_port": 53, "protocol": "udp"}],
+                "volumes": [{"source": "data", "target": "/data"}, {"type": "bind", "source": "/etc/web", "target": "/etc/nginx", "read_only": true}],
+                "restart": {"policy": "on-failure", "max_retries": 3},
+                "resources": {"cpus": 1.5, "memory": "512m", "memory_swap": "-1"},
+                "cap_add": ["NET_ADMIN"], "devices": [{"path_on_host": "/dev/fuse"}],
+                "networks": [{"name": "front", "aliases": ["www"]}, {"name": "back", "ipv4_address": "10.0.0.5"}],
+                "healthcheck": {"test": ["CMD-SHELL", "curl -f localhost"], "interval": "30s", "retries": 3},
+                "log": {"driver": "local", "options": {"max-size": "10m"}},
+                "ulimits": [{"name": "nofile", "soft": 1024, "hard": 2048}],
+                "dns": {"servers": ["1.1.1.1"]}, "extra_hosts": {"db": "host-gateway"}
+            }"#,
+        ))
+        .unwrap();
+        assert_eq!(prepared.name.as_deref(), Some("web"));
+
+        let host = prepared.body.host_config.unwrap();
+        assert_eq!(host.nano_cpus, Some(1_500_000_000));
+        assert_eq!(host.memory, Some(512 << 20));
+        assert_eq!(host.memory_swap, Some(-1));
+        assert_eq!(host.restart_policy.unwrap().maximum_retry_count, Some(3));
+        let bindings = host.port_bindings.unwrap();
+        assert_eq!(bindings["80/tcp"].as_ref().unwrap()[0].host_port.as_deref(), Some("8080"));
+        assert_eq!(bindings["53/udp"].as_ref().unwrap()[0].host_port, None);
+        let mounts = host.mounts.unwrap();
+        assert_eq!(mounts[0].typ, Some(MountTypeEnum::VOLUME));
+        assert_eq!(mounts[1].read_only, Some(true));
+        assert_eq!(host.devices.unwrap()[0].path_in_container.as_deref(), Some("/dev/fuse"));
+        assert_eq!(host.extra_hosts.unwrap(), vec!["db:host-gateway"]);
+        assert_eq!(host.network_mode.as_deref(), Some("front"));
+
+        let endpoints = prepared.body.networking_config.unwrap().endpoints_config.unwrap();
+        assert_eq!(endpoints["front"].aliases, Some(vec!["www".to_string()]));
+        assert_eq!(prepared.networks.len(), 1);
+        let (network, endpoint) = &prepared.networks[0];
+        assert_eq!(network, "back");
+        assert_eq!(endpoint.ipam_config.as_ref().unwrap().ipv4_address.as_deref(), Some("10.0.0.5"));
+
+        let healthcheck = prepared.body.healthcheck.unwrap();
+        assert_eq!(healthcheck.interval, Some(30_000_000_000));
+    }
+
+    #[test]
+    fn test_prepare_reports_every_problem() {
+        let problems = problems(
+            r#"{
+                "image": " ", "name": "-web", "working_dir": "app",
+                "ports": [{"container_port": 80, "protocol": "http"}],
+                "volumes": [{"type": "bind", "source": "data", "target": "/data"}],
+                "restart": {"policy": "always", "max_retries": 3},
+                "resources": {"memory": "1k", "memory_swap": "lots"},
+                "cap_add": ["net admin"],
+                "networks": [{"name": "front", "ipv4_address": "10.0.0"}],
+                "healthcheck": {"test": ["curl", "-f", "localhost"]},
+                "ulimits": [{"name": "nofile", "soft": 4096, "hard": 1024}],
+                "extra_hosts": {"db": "nowhere"}
+            }"#,
+        );
+        assert_eq!(problems.len(), 13, "{:?}", problems);
+        assert!(problems.contains(&"image is required".to_string()));
+    }
+
+    #[test]
+    fn test_prepare_rejects_networks_with_host_mode() {
+        let problems = problems(
+            r#"{"image": "nginx", "network_mode": "host", "networks": [{"name": "front"}], "ports": [{"container_port": 80}]}"#,
+        );
+        assert_eq!(problems.len(), 2);
+    }
+}
@@ -0,0 +1,297 @@
+//! Images in the local store: listing, inspecting, pulling, removing and
+//! pruning them.
+
+use anyhow::{anyhow, Context, Result};
+use bollard::auth::DockerCredentials;
+use bollard::errors::Error as DockerError;
+use bollard::models::{ImageDeleteResponseItem, ImageInspect};
+use bollard::query_parameters::{
+    CreateImageOptions, ListContainersOptions, ListImagesOptions, PruneImagesOptions, RemoveImageOptions,
+    TagImageOptions,
+};
+use bollard::Docker;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+
+use crate::models::image::{ImageDetails, ImageInfo, ImageLayer, PruneResult, PullProgress, RemovedImages};
+
+/// List tagged and dangling images, leaving out intermediate build layers.
+pub async fn list(docker: &Docker) -> Result<Vec<ImageInfo>> {
+    let images = docker
+        .list_images(Some(ListImagesOptions::default()))
+        .await
+        .context("Failed to list images")?;
+    let mut users = container_names(docker).await?;
+
+    let mut infos: Vec<ImageInfo> = images
+        .into_iter()
+        .map(|image| ImageInfo {
+            dangling: is_dangling(&image.repo_tags),
+            containers: users.remove(&image.id).unwrap_or_default(),
+            id: image.id,
+            repo_tags: image.repo_tags.into_iter().filter(|tag| tag != NO_TAG).collect(),
+            repo_digests: image.repo_digests.into_iter().filter(|digest| digest != NO_DIGEST).collect(),
+            size: image.size.max(0) as u64,
+            created: image.created,
+        })
+        .collect();
+    infos.sort_by_key(|image| std::cmp::Reverse(image.created));
+    Ok(infos)
+}
+
+/// Get an image by ID or reference, with its configuration and layers.
+pub async fn get(docker: &Docker, name: &str) -> Result<Option<ImageDetails>> {
+    let Some(image) = inspect(docker, name).await? else {
+        return Ok(None);
+    };
+    let history = docker
+        .image_history(name)
+        .await
+        .with_context(|| format!("Failed to read history of image {}", name))?;
+    let info = image_info(docker, &image).await?;
+    let config = image.config.unwrap_or_default();
+    let keys = |map: Option<HashMap<String, HashMap<(), ()>>>| {
+        let mut keys: Vec<String> = map.unwrap_or_default().into_keys().collect();
+        keys.sort();
+        keys
+    };
+
+    Ok(Some(ImageDetails {
+        info,
+        architecture: image.architecture,
+        os: image.os,
+        author: image.author.filter(|author| !author.is_empty()),
+        cmd: config.cmd.unwrap_or_default(),
+        entrypoint: config.entrypoint.unwrap_or_default(),
+        env: config.env.unwrap_or_default(),
+        exposed_ports: keys(config.exposed_ports),
+        volumes: keys(config.volumes),
+        working_dir: config.working_dir.filter(|dir| !dir.is_empty()),
+        user: config.user.filter(|user| !user.is_empty()),
+        labels: config.labels.unwrap_or_default(),
+        history: history
+            .into_iter()
+            .map(|layer| ImageLayer {
+                created: layer.created,
+                created_by: layer.created_by,
+                size: layer.size.max(0) as u64,
+                comment: layer.comment,
+            })
+            .collect(),
+    }))
+}
+
+/// Get the list entry of an image by ID or reference.
+pub async fn get_info(docker: &Docker, name: &str) -> Result<Option<ImageInfo>> {
+    match inspect(docker, name).await? {
+        Some(image) => Ok(Some(image_info(docker, &image).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Pull an image, yielding the progress the daemon reports. The stream ends
+/// with an error if the pull fails.
+pub fn pull(
+    docker: &Docker,
+    image: &str,
+    credentials: Option<DockerCredentials>,
+) -> impl Stream<Item = Result<PullProgress>> {
+    let image = reference(image);
+    let options = CreateImageOptions {
+        from_image: Some(image.clone()),
+        ..Default::default()
+    };
+
+    docker.create_image(Some(options), None, credentials).map(move |info| {
+        let info = info.with_context(|| format!("Failed to pull image {}", image))?;
+        if let Some(error) = info.error {
+            return Err(anyhow!(DockerError::DockerStreamError { error }))
+                .with_context(|| format!("Failed to pull image {}", image));
+        }
+        let detail = info.progress_detail.unwrap_or_default();
+        Ok(PullProgress {
+            // Messages about the whole image carry its tag as their ID
+            layer: info.id.filter(|id| id.len() == 12 && id.chars().all(|c| c.is_ascii_hexdigit())),
+            status: info.status.unwrap_or_default(),
+            current: detail.current.map(|current| current.max(0) as u64),
+            total: detail.total.filter(|total| *total > 0).map(|total| total as u64),
+        })
+    })
+}
+
+/// Remove an image, or only one of its tags when it has several. Returns
+/// `None` when there is no such image.
+pub async fn remove(docker: &Docker, name: &str, force: bool) -> Result<Option<RemovedImages>> {
+    let options = RemoveImageOptions {
+        force,
+        noprune: false,
+    };
+    match docker.remove_image(name, Some(options), None).await {
+        Ok(items) => Ok(Some(removed(items))),
+        Err(DockerError::DockerResponseServerError { status_code: 404, .. }) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to remove image {}", name)),
+    }
+}
+
+/// Remove dangling images, or with `all` every image that no container
+/// uses.
+pub async fn prune(docker: &Docker, all: bool) -> Result<PruneResult> {
+    let options = PruneImagesOptions {
+        filters: Some(HashMap::from([("dangling".to_string(), vec![(!all).to_string()])])),
+    };
+    let response = docker.prune_images(Some(options)).await.context("Failed to prune images")?;
+    Ok(PruneResult {
+        removed: removed(response.images_deleted.unwrap_or_default()),
+        space_reclaimed: response.space_reclaimed.unwrap_or(0).max(0) as u64,
+    })
+}
+
+/// Status code of the daemon's response, if an error came from it.
+pub fn daemon_status(error: &anyhow::Error) -> Option<u16> {
+    match error.downcast_ref::<DockerError>()? {
+        DockerError::DockerResponseServerError { status_code, .. } => Some(*status_code),
+        _ => None,
+    }
+}
+
+/// Whether an error was reported by the registry in the middle of a pull.
+pub fn is_pull_error(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<DockerError>(), Some(DockerError::DockerStreamError { .. }))
+}
+
+/// Tag shown by older daemons for untagged images.
+const NO_TAG: &str = "<none>:<none>";
+const NO_DIGEST: &str = "<none>@<none>";
+
+fn is_dangling(tags: &[String]) -> bool {
+    tags.iter().all(|tag| tag == NO_TAG)
+}
+
+/// The reference to pull, with `latest` added when it has no tag or digest,
+/// as `docker pull` does. Without one, the daemon would pull every tag.
+pub fn reference(image: &str) -> String {
+    let name = image.rsplit('/').next().unwrap_or(image);
+    if name.contains(':') || name.contains('@') {
+        image.to_string()
+    } else {
+        format!("{}:latest", image)
+    }
+}
+
+/// Repository and tag of a reference, with `latest` as the default tag.
+pub fn split_tag(reference: &str) -> (&str, &str) {
+    match reference.rsplit_once(':') {
+        Some((repo, tag)) if !tag.contains('/') => (repo, tag),
+        _ => (reference, "latest"),
+    }
+}
+
+/// Repository of a reference as repo digests name it, without Docker Hub's
+/// host or `library/` namespace.
+pub fn repository(reference: &str) -> &str {
+    let repo = split_tag(reference).0;
+    let repo = ["docker.io/", "index.docker.io/"]
+        .iter()
+        .find_map(|host| repo.strip_prefix(host))
+        .unwrap_or(repo);
+    repo.strip_prefix("library/").unwrap_or(repo)
+}
+
+/// Give an image another tag, such as `app:1.2`.
+pub async fn tag(docker: &Docker, image: &str, reference: &str) -> Result<()> {
+    let (repo, tag) = split_tag(reference);
+    let options = TagImageOptions {
+        repo: Some(repo.to_string()),
+        tag: Some(tag.to_string()),
+    };
+    docker
+        .tag_image(image, Some(options))
+        .await
+        .with_context(|| format!("Failed to tag image as {}:{}", repo, tag))
+}
+
+async fn inspect(docker: &Docker, name: &str) -> Result<Option<ImageInspect>> {
+    match docker.inspect_image(name).await {
+        Ok(image) => Ok(Some(image)),
+        Err(DockerError::DockerResponseServerError { status_code: 404, .. }) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to inspect image {}", name)),
+    }
+}
+
+async fn image_info(docker: &Docker, image: &ImageInspect) -> Result<ImageInfo> {
+    let id = image.id.clone().unwrap_or_default();
+    let tags = image.repo_tags.clone().unwrap_or_default();
+    Ok(ImageInfo {
+        containers: container_names(docker).await?.remove(&id).unwrap_or_default(),
+        id,
+        dangling: is_dangling(&tags),
+        repo_tags: tags.into_iter().filter(|tag| tag != NO_TAG).collect(),
+        repo_digests: image.repo_digests.clone().unwrap_or_default(),
+        size: image.size.unwrap_or(0).max(0) as u64,
+        created: image.created.map(|created| created.timestamp()).unwrap_or(0),
+    })
+}
+
+/// Names of all containers, by the ID of their image.
+async fn container_names(docker: &Docker) -> Result<HashMap<String, Vec<String>>> {
+    let options = ListContainersOptions {
+        all: true,
+        ..Default::default()
+    };
+    let containers = docker
+        .list_containers(Some(options))
+        .await
+        .context("Failed to list containers")?;
+
+    let mut names: HashMap<String, Vec<String>> = HashMap::new();
+    for container in containers {
+        let (Some(image), Some(name)) = (container.image_id, container.names.iter().flatten().next()) else {
+            continue;
+        };
+        names.entry(image).or_default().push(name.trim_start_matches('/').to_string());
+    }
+    for list in names.values_mut() {
+        list.sort();
+    }
+    Ok(names)
+}
+
+fn removed(items: Vec<ImageDeleteResponseItem>) -> RemovedImages {
+    let mut removed = RemovedImages::default();
+    for item in items {
+        removed.untagged.extend(item.untagged);
+        removed.deleted.extend(item.deleted);
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_defaults_to_latest() {
+        assert_eq!(reference("nginx"), "nginx:latest");
+        assert_eq!(reference("nginx:1.27"), "nginx:1.27");
+        assert_eq!(reference("localhost:5000/app"), "localhost:5000/app:latest");
+        assert_eq!(reference("localhost:5000/app:v2"), "localhost:5000/app:v2");
+        assert_eq!(reference("ghcr.io/org/app@sha256:abc"), "ghcr.io/org/app@sha256:abc");
+    }
+
+    #[test]
+    fn test_split_tag() {
+        assert_eq!(split_tag("app:1.2"), ("app", "1.2"));
+        assert_eq!(split_tag("app"), ("app", "latest"));
+        assert_eq!(split_tag("localhost:5000/app"), ("localhost:5000/app", "latest"));
+        assert_eq!(split_tag("localhost:5000/app:v2"), ("localhost:5000/app", "v2"));
+    }
+
+    #[test]
+    fn test_repository_matches_repo_digests() {
+        assert_eq!(repository("nginx:1.27"), "nginx");
+        assert_eq!(repository("docker.io/library/nginx"), "nginx");
+        assert_eq!(repository("docker.io/grafana/grafana:11.0.0"), "grafana/grafana");
+        assert_eq!(repository("ghcr.io/library/app:v1"), "ghcr.io/library/app");
+        assert_eq!(repository("localhost:5000/app"), "localhost:5000/app");
+    }
+}
@@ -0,0 +1,587 @@
+//! Log snapshots and live log streams of containers and stacks.
+//!
+//! Docker multiplexes stdout and stderr over one connection. Bollard splits
+//! the frames apart again, but a frame may hold part of a line or several
+//! lines, so lines are reassembled here per stream. Each container is read by
+//! its own task, and the lines of all of them are merged into one channel,
+//! tagged with the container's prefix and color. Values from the secrets
+//! store are masked before lines are filtered, so a filter cannot probe them.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bollard::container::LogOutput;
+use bollard::query_parameters::{EventsOptions, InspectContainerOptions, LogsOptions};
+use bollard::Docker;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use regex_automata::meta::Regex;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use super::compose::engine::{self, container_number, label, SERVICE_LABEL};
+use super::compose::spec::parse_duration;
+use crate::models::logs::{LogColor, LogLine, LogStream, LogsQuery};
+use crate::secrets;
+
+/// Lines shown from the end of each log unless asked otherwise.
+const DEFAULT_TAIL: usize = 100;
+
+/// Lines buffered between the container readers and a slow client.
+const CHANNEL_SIZE: usize = 256;
+
+/// What to read logs from.
+#[derive(Debug, Clone)]
+pub enum LogTarget {
+    /// A single container by ID or name.
+    Container(String),
+    /// Every container of a compose project. Services are colored by their
+    /// position in `services`.
+    Project { name: String, services: Vec<String> },
+}
+
+/// Parsed [`LogsQuery`].
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    /// `None` for the whole log.
+    pub tail: Option<usize>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub filter: Option<Regex>,
+    /// `None` for every service.
+    pub services: Option<Vec<String>>,
+    /// Values masked in every line, before it is filtered.
+    pub redact: Vec<String>,
+}
+
+impl LogOptions {
+    pub fn from_query(query: &LogsQuery) -> Result<Self> {
+        let now = Utc::now();
+        let tail = match query.tail.as_deref() {
+            None => Some(DEFAULT_TAIL),
+            Some("all") => None,
+            Some(tail) => Some(tail.parse().map_err(|_| anyhow!("Invalid tail {:?}", tail))?),
+        };
+        let filter = query
+            .filter
+            .as_deref()
+            .map(|filter| Regex::new(filter).with_context(|| format!("Invalid filter {:?}", filter)))
+            .transpose()?;
+        let services = query.services.as_deref().map(|services| {
+            services
+                .split(',')
+                .map(str::trim)
+                .filter(|service| !service.is_empty())
+                .map(str::to_string)
+                .collect()
+        });
+        Ok(Self {
+            tail,
+            since: query.since.as_deref().map(|since| parse_time(since, now)).transpose()?,
+            until: query.until.as_deref().map(|until| parse_time(until, now)).transpose()?,
+            filter,
+            services,
+            redact: Vec::new(),
+        })
+    }
+
+    fn matches(&self, message: &str) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter.is_match(message))
+    }
+}
+
+/// Parse a UNIX timestamp, an RFC 3339 time or a duration before `now`,
+/// the way `docker logs --since` does.
+fn parse_time(value: &str, now: DateTime<Utc>) -> Result<i64> {
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Ok(seconds);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp());
+    }
+    match parse_duration(value) {
+        Ok(ago) => Ok(now.timestamp() - ago.as_secs() as i64),
+        Err(_) => bail!("Invalid time {:?}", value),
+    }
+}
+
+/// A container whose log is read, and how its lines are labelled.
+#[derive(Debug, Clone)]
+struct LogSource {
+    id: String,
+    name: String,
+    service: Option<String>,
+    prefix: String,
+    color: LogColor,
+    width: usize,
+}
+
+impl LogTarget {
+    /// The containers to read, or `None` when a single container does not exist.
+    async fn sources(&self, docker: &Docker, options: &LogOptions) -> Result<Option<Vec<LogSource>>> {
+        let mut sources = Vec::new();
+        match self {
+            LogTarget::Container(id) => match self.source(docker, id, options).await? {
+                Some(source) => sources.push(source),
+                None => return Ok(None),
+            },
+            LogTarget::Project { name, .. } => {
+                for container in engine::containers(docker, name).await? {
+                    let service = label(&container, SERVICE_LABEL).unwrap_or_default();
+                    let Some(id) = container.id.as_deref() else {
+                        continue;
+                    };
+                    if !wanted(options, service) {
+                        continue;
+                    }
+                    let name = container
+                        .names
+                        .iter()
+                        .flatten()
+                        .next()
+                        .map(|name| name.trim_start_matches('/').to_string())
+                        .unwrap_or_else(|| id.to_string());
+                    let prefix = format!("{}-{}", service, container_number(&container));
+                    sources.push(self.labelled(id, name, Some(service.to_string()), prefix));
+                }
+            }
+        }
+
+        let width = sources.iter().map(|source| source.prefix.len()).max().unwrap_or(0);
+        for source in &mut sources {
+            source.width = width;
+        }
+        Ok(Some(sources))
+    }
+
+    /// A container of the target found by ID, or `None` when it is gone or
+    /// not wanted.
+    async fn source(&self, docker: &Docker, id: &str, options: &LogOptions) -> Result<Option<LogSource>> {
+        let info = match docker.inspect_container(id, None::<InspectContainerOptions>).await {
+            Ok(info) => info,
+            Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to inspect container {}", id)),
+        };
+        let id = info.id.unwrap_or_else(|| id.to_string());
+        let name = info.name.map(|name| name.trim_start_matches('/').to_string()).unwrap_or_else(|| id.clone());
+        let labels = info.config.and_then(|config| config.labels).unwrap_or_default();
+
+        match self {
+            LogTarget::Container(_) => Ok(Some(self.labelled(&id, name.clone(), None, name))),
+            LogTarget::Project { .. } => {
+                let service = labels.get(SERVICE_LABEL).cloned().unwrap_or_default();
+                if !wanted(options, &service) {
+                    return Ok(None);
+                }
+                let number = labels.get(engine::NUMBER_LABEL).map(String::as_str).unwrap_or("0");
+                let prefix = format!("{}-{}", service, number);
+                Ok(Some(self.labelled(&id, name, Some(service), prefix)))
+            }
+        }
+    }
+
+    fn labelled(&self, id: &str, name: String, service: Option<String>, prefix: String) -> LogSource {
+        LogSource {
+            id: id.to_string(),
+            name,
+            color: self.color(service.as_deref()),
+            width: prefix.len(),
+            service,
+            prefix,
+        }
+    }
+
+    fn color(&self, service: Option<&str>) -> LogColor {
+        let index = match (self, service) {
+            (LogTarget::Project { services, .. }, Some(service)) => {
+                services.iter().position(|name| name == service).unwrap_or(services.len())
+            }
+            _ => 0,
+        };
+        LogColor::PALETTE[index % LogColor::PALETTE.len()]
+    }
+
+    /// Docker event filter for containers of the target that start.
+    fn start_filters(&self) -> HashMap<String, Vec<String>> {
+        let mut filters = HashMap::from([
+            ("type".to_string(), vec!["container".to_string()]),
+            ("event".to_string(), vec!["start".to_string()]),
+        ]);
+        match self {
+            LogTarget::Container(id) => filters.insert("container".to_string(), vec![id.clone()]),
+            LogTarget::Project { name, .. } => {
+                filters.insert("label".to_string(), vec![format!("{}={}", engine::PROJECT_LABEL, name)])
+            }
+        };
+        filters
+    }
+}
+
+fn wanted(options: &LogOptions, service: &str) -> bool {
+    options.services.as_ref().is_none_or(|services| services.iter().any(|s| s == service))
+}
+
+/// The lines logged so far, oldest first across containers. `None` when a
+/// single container does not exist.
+pub async fn snapshot(docker: &Docker, target: &LogTarget, options: LogOptions) -> Result<Option<Vec<LogLine>>> {
+    let Some(sources) = target.sources(docker, &options).await? else {
+        return Ok(None);
+    };
+
+    let options = Arc::new(options);
+    let (tx, mut rx) = mpsc::channel(CHANNEL_SIZE);
+    for source in sources {
+        let read = Read {
+            follow: false,
+            since: options.since,
+            tail: options.tail,
+        };
+        tokio::spawn(forward(docker.clone(), source, options.clone(), read, tx.clone(), None));
+    }
+    drop(tx);
+
+    let mut lines = Vec::new();
+    while let Some(line) = rx.recv().await {
+        lines.push(line?);
+    }
+    lines.sort_by_key(|line| line.timestamp);
+    Ok(Some(lines))
+}
+
+/// Follow the logs as they are written, including those of containers that
+/// start later, until the returned stream is dropped. `None` when a single
+/// container does not exist.
+pub async fn follow(
+    docker: &Docker,
+    target: LogTarget,
+    options: LogOptions,
+) -> Result<Option<impl Stream<Item = Result<LogLine>>>> {
+    let Some(sources) = target.sources(docker, &options).await? else {
+        return Ok(None);
+    };
+
+    let options = Arc::new(options);
+    let following = Arc::new(Mutex::new(HashSet::new()));
+    let (tx, mut rx) = mpsc::channel(CHANNEL_SIZE);
+    for source in sources {
+        following.lock().unwrap().insert(source.id.clone());
+        let read = Read {
+            follow: true,
+            since: options.since,
+            tail: options.tail,
+        };
+        tokio::spawn(forward(docker.clone(), source, options.clone(), read, tx.clone(), Some(following.clone())));
+    }
+    tokio::spawn(attach_started(docker.clone(), target, options, tx, following));
+
+    Ok(Some(futures::stream::poll_fn(move |cx| rx.poll_recv(cx))))
+}
+
+/// Start following containers of the target as they start or restart.
+async fn attach_started(
+    docker: Docker,
+    target: LogTarget,
+    options: Arc<LogOptions>,
+    tx: mpsc::Sender<Result<LogLine>>,
+    following: Arc<Mutex<HashSet<String>>>,
+) {
+    let events_options = EventsOptions {
+        filters: Some(target.start_filters()),
+        ..Default::default()
+    };
+    let mut events = docker.events(Some(events_options));
+    loop {
+        let event = tokio::select! {
+            event = events.next() => event,
+            _ = tx.closed() => return,
+        };
+        let event = match event {
+            Some(Ok(event)) => event,
+            Some(Err(e)) => {
+                let _ = tx.send(Err(anyhow!(e).context("Failed to watch for started containers"))).await;
+                return;
+            }
+            None => return,
+        };
+        let Some(id) = event.actor.and_then(|actor| actor.id) else {
+            continue;
+        };
+        if !following.lock().unwrap().insert(id.clone()) {
+            continue;
+        }
+        let source = match target.source(&docker, &id, &options).await {
+            Ok(Some(source)) => source,
+            Ok(None) => {
+                following.lock().unwrap().remove(&id);
+                continue;
+            }
+            Err(e) => {
+                following.lock().unwrap().remove(&id);
+                debug!("Not following logs of container {}: {:#}", id, e);
+                continue;
+            }
+        };
+        let read = Read {
+            follow: true,
+            since: event.time,
+            tail: None,
+        };
+        tokio::spawn(forward(docker.clone(), source, options.clone(), read, tx.clone(), Some(following.clone())));
+    }
+}
+
+/// Which part of a container's log to read.
+#[derive(Debug, Clone, Copy)]
+struct Read {
+    follow: bool,
+    since: Option<i64>,
+    tail: Option<usize>,
+}
+
+/// Read one container's log into the channel until it ends or the receiver
+/// is dropped.
+async fn forward(
+    docker: Docker,
+    source: LogSource,
+    options: Arc<LogOptions>,
+    read: Read,
+    tx: mpsc::Sender<Result<LogLine>>,
+    following: Option<Arc<Mutex<HashSet<String>>>>,
+) {
+    let request = LogsOptions {
+        follow: read.follow,
+        stdout: true,
+        stderr: true,
+        since: read.since.unwrap_or(0) as i32,
+        until: options.until.unwrap_or(0) as i32,
+        timestamps: true,
+        tail: read.tail.map_or_else(|| "all".to_string(), |tail| tail.to_string()),
+    };
+    let mut frames = docker.logs(&source.id, Some(request));
+    let mut lines = Lines::default();
+
+    loop {
+        let frame = tokio::select! {
+            frame = frames.next() => frame,
+            _ = tx.closed() => break,
+        };
+        let (raw, last) = match frame {
+            Some(Ok(frame)) => (lines.push(frame), false),
+            Some(Err(e)) => {
+                let error = anyhow!(e).context(format!("Failed to read logs of container {}", source.name));
+                let _ = tx.send(Err(error)).await;
+                break;
+            }
+            None => (lines.finish(), true),
+        };
+        let mut sent = true;
+        let raw = raw.into_iter().map(|mut raw| {
+            if let Cow::Owned(message) = secrets::redact(&raw.message, &options.redact) {
+                raw.message = message;
+            }
+            raw
+        });
+        for raw in raw.filter(|raw| options.matches(&raw.message)) {
+            sent = tx.send(Ok(source.line(raw))).await.is_ok();
+            if !sent {
+                break;
+            }
+        }
+        if last || !sent {
+            break;
+        }
+    }
+
+    if let Some(following) = following {
+        following.lock().unwrap().remove(&source.id);
+    }
+}
+
+impl LogSource {
+    fn line(&self, raw: RawLine) -> LogLine {
+        LogLine {
+            container: self.name.clone(),
+            service: self.service.clone(),
+            prefix: self.prefix.clone(),
+            color: self.color,
+            stream: raw.stream,
+            timestamp: raw.timestamp,
+            message: raw.message,
+            width: self.width,
+        }
+    }
+}
+
+/// A complete line of one output stream.
+#[derive(Debug, Clone, PartialEq)]
+struct RawLine {
+    stream: LogStream,
+    timestamp: Option<DateTime<Utc>>,
+    message: String,
+}
+
+/// Reassembles lines from log frames, keeping stdout and stderr apart.
+/// Frames start with the timestamp Docker adds when asked to.
+#[derive(Debug, Default)]
+struct Lines {
+    partial: HashMap<StreamKey, (Option<DateTime<Utc>>, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum StreamKey {
+    Stdout,
+    Stderr,
+}
+
+impl From<StreamKey> for LogStream {
+    fn from(key: StreamKey) -> Self {
+        match key {
+            StreamKey::Stdout => LogStream::Stdout,
+            StreamKey::Stderr => LogStream::Stderr,
+        }
+    }
+}
+
+impl Lines {
+    fn push(&mut self, frame: LogOutput) -> Vec<RawLine> {
+        let (key, message) = match frame {
+            LogOutput::StdOut { message } | LogOutput::Console { message } => (StreamKey::Stdout, message),
+            LogOutput::StdErr { message } => (StreamKey::Stderr, message),
+            LogOutput::StdIn { .. } => return Vec::new(),
+        };
+        let (timestamp, content) = split_timestamp(&message);
+
+        let (started, buffer) = self.partial.entry(key).or_insert((None, Vec::new()));
+        if buffer.is_empty() {
+            *started = timestamp;
+        }
+        buffer.extend_from_slice(content);
+
+        let mut lines = Vec::new();
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let rest = buffer.split_off(end + 1);
+            let line = std::mem::replace(buffer, rest);
+            lines.push(raw_line(key, *started, &line));
+            *started = timestamp;
+        }
+        lines
+    }
+
+    /// Lines left without a final newline when the log ends.
+    fn finish(&mut self) -> Vec<RawLine> {
+        let mut lines: Vec<RawLine> = self
+            .partial
+            .drain()
+            .filter(|(_, (_, buffer))| !buffer.is_empty())
+            .map(|(key, (started, buffer))| raw_line(key, started, &buffer))
+            .collect();
+        lines.sort_by_key(|line| line.timestamp);
+        lines
+    }
+}
+
+fn raw_line(key: StreamKey, timestamp: Option<DateTime<Utc>>, bytes: &[u8]) -> RawLine {
+    let text = String::from_utf8_lossy(bytes);
+    RawLine {
+        stream: key.into(),
+        timestamp,
+        message: text.trim_end_matches(['\n', '\r']).to_string(),
+    }
+}
+
+/// Split the RFC 3339 timestamp Docker puts before a frame's content.
+fn split_timestamp(frame: &[u8]) -> (Option<DateTime<Utc>>, &[u8]) {
+    let Some(space) = frame.iter().position(|&b| b == b' ') else {
+        return (None, frame);
+    };
+    let parsed = std::str::from_utf8(&frame[..space])
+        .ok()
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+    match parsed {
+        Some(time) => (Some(time.with_timezone(&Utc)), &frame[space + 1..]),
+        None => (None, frame),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn stdout(text: &str) -> LogOutput {
+        LogOutput::StdOut {
+            message: Bytes::from(text.to_string()),
+        }
+    }
+
+    fn stderr(text: &str) -> LogOutput {
+        LogOutput::StdErr {
+            message: Bytes::from(text.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_lines_are_reassembled_per_stream() {
+        let mut lines = Lines::default();
+        assert!(lines.push(stdout("2024-05-01T10:00:00.5Z GET /ind")).is_empty());
+        let err = lines.push(stderr("2024-05-01T10:00:01Z warning: slow\r\n"));
+        assert_eq!(err.len(), 1);
+        assert_eq!((err[0].stream, err[0].message.as_str()), (LogStream::Stderr, "warning: slow"));
+
+        let out = lines.push(stdout("2024-05-01T10:00:02Z ex.html\nGET /\n"));
+        let messages: Vec<&str> = out.iter().map(|line| line.message.as_str()).collect();
+        assert_eq!(messages, ["GET /index.html", "GET /"]);
+        assert_eq!(out[0].timestamp.unwrap().to_rfc3339(), "2024-05-01T10:00:00.500+00:00");
+        assert_eq!(out[1].stream, LogStream::Stdout);
+
+        lines.push(stdout("2024-05-01T10:00:03Z no newline"));
+        let rest = lines.finish();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].message, "no newline");
+    }
+
+    #[test]
+    fn test_query_parsing() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(parse_time("1714564800", now).unwrap(), 1714564800);
+        assert_eq!(parse_time("2024-05-01T11:00:00+00:00", now).unwrap(), now.timestamp() - 3600);
+        assert_eq!(parse_time("10m", now).unwrap(), now.timestamp() - 600);
+        assert!(parse_time("yesterday", now).is_err());
+
+        let query = LogsQuery {
+            tail: Some("all".to_string()),
+            filter: Some("(?i)error|panic".to_string()),
+            services: Some("web, db".to_string()),
+            ..Default::default()
+        };
+        let options = LogOptions::from_query(&query).unwrap();
+        assert_eq!(options.tail, None);
+        assert!(options.matches("ERROR: disk full") && !options.matches("all good"));
+        assert!(wanted(&options, "db") && !wanted(&options, "cache"));
+        assert_eq!(LogOptions::from_query(&LogsQuery::default()).unwrap().tail, Some(DEFAULT_TAIL));
+        assert!(LogOptions::from_query(&LogsQuery { filter: Some("(".to_string()), ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_services_keep_their_color() {
+        let target = LogTarget::Project {
+            name: "blog".to_string(),
+            services: vec!["db".to_string(), "web".to_string()],
+        };
+        assert_eq!(target.color(Some("db")), LogColor::Cyan);
+        assert_eq!(target.color(Some("web")), LogColor::Yellow);
+        assert_eq!(target.color(Some("gone")), LogColor::Green);
+
+        let source = target.labelled("abc", "blog-web-1".to_string(), Some("web".to_string()), "web-1".to_string());
+        let line = LogLine {
+            width: 7,
+            ..source.line(RawLine {
+                stream: LogStream::Stdout,
+                timestamp: None,
+                message: "ready".to_string(),
+            })
+        };
+        assert_eq!(line.text(false), "\x1b[33mweb-1   |\x1b[0m ready");
+    }
+}
@@ -2,6 +2,16 @@ use anyhow::{Context, Result};
 use bollard::Docker;
 use tracing::info;
 
+pub mod applications;
+pub mod builds;
+pub mod compose;
+pub mod containers;
+pub mod images;
+pub mod logs;
+pub mod services;
+pub mod templates;
+pub mod updates;
+
 pub async fn connect_docker() -> Result<Docker> {
     info!("Connecting to Docker...");
     
@@ -26,35 +36,6 @@ pub async fn get_container_info(
         .context("Failed to inspect container")
 }
 
-pub async fn get_container_logs(
-    docker: &Docker,
-    container_id: &str,
-    tail: Option<usize>,
-) -> Result<String> {
-    use bollard::query_parameters::LogsOptions;
-    use futures::StreamExt;
-    
-    let mut options = LogsOptions::default();
-    options.stdout = true;
-    options.stderr = true;
-    
-    if let Some(t) = tail {
-        options.tail = t.to_string();
-    }
-    
-    let mut logs_stream = docker.logs(container_id, Some(options));
-    let mut logs = Vec::new();
-    
-    while let Some(log_result) = logs_stream.next().await {
-        match log_result {
-            Ok(log) => logs.push(log.to_string()),
-            Err(e) => return Err(anyhow::anyhow!("Failed to get container logs: {}", e)),
-        }
-    }
-    
-    Ok(logs.join("\n"))
-}
-
 pub async fn list_containers(docker: &Docker) -> Result<Vec<bollard::models::ContainerSummary>> {
     use bollard::query_parameters::ListContainersOptions;
     
@@ -67,16 +48,28 @@ pub async fn list_containers(docker: &Docker) -> Result<Vec<bollard::models::Con
         .context("Failed to list containers")
 }
 
-pub async fn list_images(docker: &Docker) -> Result<Vec<bollard::models::ImageSummary>> {
-    use bollard::query_parameters::ListImagesOptions;
-    
-    let mut options = ListImagesOptions::default();
-    options.all = true;
-    
-    docker
-        .list_images(Some(options))
+/// Find a container by ID, ID prefix or name.
+pub async fn find_container(docker: &Docker, id: &str) -> Result<Option<bollard::models::ContainerSummary>> {
+    use bollard::query_parameters::ListContainersOptions;
+
+    let info = match get_container_info(docker, id).await {
+        Ok(info) => info,
+        Err(e) if images::daemon_status(&e) == Some(404) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let Some(id) = info.id else {
+        return Ok(None);
+    };
+    let options = ListContainersOptions {
+        all: true,
+        filters: Some(std::collections::HashMap::from([("id".to_string(), vec![id.clone()])])),
+        ..Default::default()
+    };
+    let containers = docker
+        .list_containers(Some(options))
         .await
-        .context("Failed to list images")
+        .context("Failed to list containers")?;
+    Ok(containers.into_iter().find(|container| container.id.as_deref() == Some(id.as_str())))
 }
 
 // Simplified implementations that avoid the type issues
@@ -3,15 +3,17 @@
 //! This module provides functionality for managing services for domain-based routing.
 
 use anyhow::{Result, Context};
-use sqlx::{Pool, Sqlite, Row};
+use sqlx::{sqlite::SqliteRow, Pool, Sqlite, Row};
 use uuid::Uuid;
 use std::collections::HashMap;
 use chrono::Utc;
 use serde_json;
 
+use crate::proxy::middleware;
+use crate::proxy::routing::normalize_prefix;
 use crate::models::service::{
     Service, ServiceType, SSLConfig,
-    CreateServiceRequest, UpdateServiceRequest, ServiceResponse
+    CreateServiceRequest, UpdateServiceRequest
 };
 
 /// Create a new service
@@ -37,15 +39,23 @@ pub async fn create_service(
         auto_generate: false,
     });
     
+    let load_balancing = request.load_balancing.unwrap_or_default();
+    let path_prefix = normalize_prefix(request.path_prefix.as_deref().unwrap_or("/"));
+    let strip_prefix = request.strip_prefix.unwrap_or(false);
+    let priority = request.priority.unwrap_or(0);
+    let mut route_middleware = request.middleware.unwrap_or_default();
+    middleware::hash_passwords(&mut route_middleware)?;
+    
     // Insert into database
     sqlx::query(
         r#"
         INSERT INTO services (
             id, name, domain, service_type, target, port,
+            path_prefix, strip_prefix, rewrite_prefix, priority,
             ssl_enabled, ssl_cert_path, ssl_key_path, ssl_auto_generate,
-            headers, enabled, created_at, updated_at
+            headers, lb_strategy, health_check, middleware, enabled, created_at, updated_at
         )
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         "#
     )
     .bind(id.to_string())
@@ -54,11 +64,18 @@ pub async fn create_service(
     .bind(serde_json::to_string(&request.service_type)?)
     .bind(&request.target)
     .bind(request.port as i64)
+    .bind(&path_prefix)
+    .bind(strip_prefix)
+    .bind(request.rewrite_prefix.as_deref())
+    .bind(priority)
     .bind(ssl.enabled)
     .bind(ssl.cert_path.as_deref())
     .bind(ssl.key_path.as_deref())
     .bind(ssl.auto_generate)
     .bind(headers_json)
+    .bind(load_balancing)
+    .bind(request.health_check.as_ref().map(serde_json::to_string).transpose()?)
+    .bind(serde_json::to_string(&route_middleware)?)
     .bind(true)
     .bind(now)
     .bind(now)
@@ -74,9 +91,17 @@ pub async fn create_service(
         service_type: request.service_type,
         target: request.target,
         port: request.port,
+        path_prefix,
+        strip_prefix,
+        rewrite_prefix: request.rewrite_prefix,
+        priority,
         ssl,
         headers: request.headers.unwrap_or_default(),
+        load_balancing,
+        health_check: request.health_check,
+        middleware: route_middleware,
         enabled: true,
+        discovered: false,
         created_at: now,
         updated_at: now,
     };
@@ -93,8 +118,9 @@ pub async fn get_service(
         r#"
         SELECT
             id, name, domain, service_type, target, port,
+            path_prefix, strip_prefix, rewrite_prefix, priority,
             ssl_enabled, ssl_cert_path, ssl_key_path, ssl_auto_generate,
-            headers, enabled, created_at, updated_at
+            headers, lb_strategy, health_check, middleware, enabled, created_at, updated_at
         FROM services
         WHERE id = ?
         "#
@@ -105,53 +131,7 @@ pub async fn get_service(
     .context("Failed to fetch service from database")?;
     
     match record {
-        Some(row) => {
-            let id: String = row.try_get("id")?;
-            let name: String = row.try_get("name")?;
-            let domain: String = row.try_get("domain")?;
-            let service_type_str: String = row.try_get("service_type")?;
-            let target: String = row.try_get("target")?;
-            let port: i64 = row.try_get("port")?;
-            let ssl_enabled: bool = row.try_get("ssl_enabled")?;
-            let ssl_cert_path: Option<String> = row.try_get("ssl_cert_path")?;
-            let ssl_key_path: Option<String> = row.try_get("ssl_key_path")?;
-            let ssl_auto_generate: bool = row.try_get("ssl_auto_generate")?;
-            let headers_json: Option<String> = row.try_get("headers")?;
-            let enabled: bool = row.try_get("enabled")?;
-            let created_at: chrono::DateTime<chrono::Utc> = row.try_get("created_at")?;
-            let updated_at: chrono::DateTime<chrono::Utc> = row.try_get("updated_at")?;
-            
-            let service_type: ServiceType = serde_json::from_str(&service_type_str)
-                .context("Failed to deserialize service type")?;
-            
-            let headers: HashMap<String, String> = if let Some(json) = headers_json {
-                serde_json::from_str(&json)
-                    .context("Failed to deserialize headers")?
-            } else {
-                HashMap::new()
-            };
-            
-            let ssl = SSLConfig {
-                enabled: ssl_enabled,
-                cert_path: ssl_cert_path,
-                key_path: ssl_key_path,
-                auto_generate: ssl_auto_generate,
-            };
-            
-            Ok(Some(Service {
-                id: Uuid::parse_str(&id)?,
-                name,
-                domain,
-                service_type,
-                target,
-                port: port as u16,
-                ssl,
-                headers,
-                enabled,
-                created_at,
-                updated_at,
-            }))
-        },
+        Some(row) => Ok(Some(service_from_row(&row)?)),
         None => Ok(None),
     }
 }
@@ -164,8 +144,9 @@ pub async fn list_services(
         r#"
         SELECT
             id, name, domain, service_type, target, port,
+            path_prefix, strip_prefix, rewrite_prefix, priority,
             ssl_enabled, ssl_cert_path, ssl_key_path, ssl_auto_generate,
-            headers, enabled, created_at, updated_at
+            headers, lb_strategy, health_check, middleware, enabled, created_at, updated_at
         FROM services
         ORDER BY name
         "#
@@ -177,51 +158,7 @@ pub async fn list_services(
     let mut services = Vec::with_capacity(rows.len());
     
     for row in rows {
-        let id: String = row.try_get("id")?;
-        let name: String = row.try_get("name")?;
-        let domain: String = row.try_get("domain")?;
-        let service_type_str: String = row.try_get("service_type")?;
-        let target: String = row.try_get("target")?;
-        let port: i64 = row.try_get("port")?;
-        let ssl_enabled: bool = row.try_get("ssl_enabled")?;
-        let ssl_cert_path: Option<String> = row.try_get("ssl_cert_path")?;
-        let ssl_key_path: Option<String> = row.try_get("ssl_key_path")?;
-        let ssl_auto_generate: bool = row.try_get("ssl_auto_generate")?;
-        let headers_json: Option<String> = row.try_get("headers")?;
-        let enabled: bool = row.try_get("enabled")?;
-        let created_at: chrono::DateTime<chrono::Utc> = row.try_get("created_at")?;
-        let updated_at: chrono::DateTime<chrono::Utc> = row.try_get("updated_at")?;
-        
-        let service_type: ServiceType = serde_json::from_str(&service_type_str)
-            .context("Failed to deserialize service type")?;
-        
-        let headers: HashMap<String, String> = if let Some(json) = headers_json {
-            serde_json::from_str(&json)
-                .context("Failed to deserialize headers")?
-        } else {
-            HashMap::new()
-        };
-        
-        let ssl = SSLConfig {
-            enabled: ssl_enabled,
-            cert_path: ssl_cert_path,
-            key_path: ssl_key_path,
-            auto_generate: ssl_auto_generate,
-        };
-        
-        services.push(Service {
-            id: Uuid::parse_str(&id)?,
-            name,
-            domain,
-            service_type,
-            target,
-            port: port as u16,
-            ssl,
-            headers,
-            enabled,
-            created_at,
-            updated_at,
-        });
+        services.push(service_from_row(&row)?);
     }
     
     Ok(services)
@@ -239,72 +176,92 @@ pub async fn update_service(
         return Ok(None);
     }
     
-    let existing = existing.unwrap();
-    let now = Utc::now();
-    
-    // Build the update query dynamically based on what fields are provided
-    let mut query = String::from("UPDATE services SET updated_at = ? ");
-    let mut params: Vec<String> = vec![now.to_string()];
-    
-    if let Some(name) = &request.name {
-        query.push_str(", name = ? ");
-        params.push(name.clone());
+    let mut service = existing.unwrap();
+
+    // Merge the provided fields into the stored service
+    if let Some(name) = request.name {
+        service.name = name;
     }
-    
-    if let Some(domain) = &request.domain {
-        query.push_str(", domain = ? ");
-        params.push(domain.clone());
+    if let Some(domain) = request.domain {
+        service.domain = domain;
     }
-    
-    if let Some(service_type) = &request.service_type {
-        query.push_str(", service_type = ? ");
-        params.push(serde_json::to_string(service_type)?);
+    if let Some(service_type) = request.service_type {
+        service.service_type = service_type;
     }
-    
-    if let Some(target) = &request.target {
-        query.push_str(", target = ? ");
-        params.push(target.clone());
+    if let Some(target) = request.target {
+        service.target = target;
     }
-    
     if let Some(port) = request.port {
-        query.push_str(", port = ? ");
-        params.push(port.to_string());
+        service.port = port;
     }
-    
-    if let Some(ssl) = &request.ssl {
-        query.push_str(", ssl_enabled = ?, ssl_cert_path = ?, ssl_key_path = ?, ssl_auto_generate = ? ");
-        params.push(ssl.enabled.to_string());
-        params.push(ssl.cert_path.clone().unwrap_or_default());
-        params.push(ssl.key_path.clone().unwrap_or_default());
-        params.push(ssl.auto_generate.to_string());
+    if let Some(path_prefix) = request.path_prefix {
+        service.path_prefix = normalize_prefix(&path_prefix);
     }
-    
-    if let Some(headers) = &request.headers {
-        query.push_str(", headers = ? ");
-        params.push(serde_json::to_string(headers)?);
+    if let Some(strip_prefix) = request.strip_prefix {
+        service.strip_prefix = strip_prefix;
     }
-    
-    if let Some(enabled) = request.enabled {
-        query.push_str(", enabled = ? ");
-        params.push(enabled.to_string());
+    if let Some(rewrite_prefix) = request.rewrite_prefix {
+        service.rewrite_prefix = Some(rewrite_prefix).filter(|prefix| !prefix.is_empty());
     }
-    
-    query.push_str("WHERE id = ?");
-    params.push(id.to_string());
-    
-    // Execute the update query
-    let mut db_query = sqlx::query(&query);
-    for param in params {
-        db_query = db_query.bind(param);
+    if let Some(priority) = request.priority {
+        service.priority = priority;
     }
-    
-    db_query
-        .execute(db)
-        .await
-        .context("Failed to update service in database")?;
-    
-    // Return the updated service
-    get_service(db, id).await
+    if let Some(ssl) = request.ssl {
+        service.ssl = ssl;
+    }
+    if let Some(headers) = request.headers {
+        service.headers = headers;
+    }
+    if let Some(load_balancing) = request.load_balancing {
+        service.load_balancing = load_balancing;
+    }
+    if let Some(health_check) = request.health_check {
+        service.health_check = Some(health_check);
+    }
+    if let Some(mut route_middleware) = request.middleware {
+        middleware::hash_passwords(&mut route_middleware)?;
+        service.middleware = route_middleware;
+    }
+    if let Some(enabled) = request.enabled {
+        service.enabled = enabled;
+    }
+    service.updated_at = Utc::now();
+
+    sqlx::query(
+        r#"
+        UPDATE services SET
+            name = ?, domain = ?, service_type = ?, target = ?, port = ?,
+            path_prefix = ?, strip_prefix = ?, rewrite_prefix = ?, priority = ?,
+            ssl_enabled = ?, ssl_cert_path = ?, ssl_key_path = ?, ssl_auto_generate = ?,
+            headers = ?, lb_strategy = ?, health_check = ?, middleware = ?, enabled = ?, updated_at = ?
+        WHERE id = ?
+        "#
+    )
+    .bind(&service.name)
+    .bind(&service.domain)
+    .bind(serde_json::to_string(&service.service_type)?)
+    .bind(&service.target)
+    .bind(service.port as i64)
+    .bind(&service.path_prefix)
+    .bind(service.strip_prefix)
+    .bind(service.rewrite_prefix.as_deref())
+    .bind(service.priority)
+    .bind(service.ssl.enabled)
+    .bind(service.ssl.cert_path.as_deref())
+    .bind(service.ssl.key_path.as_deref())
+    .bind(service.ssl.auto_generate)
+    .bind(serde_json::to_string(&service.headers)?)
+    .bind(service.load_balancing)
+    .bind(service.health_check.as_ref().map(serde_json::to_string).transpose()?)
+    .bind(serde_json::to_string(&service.middleware)?)
+    .bind(service.enabled)
+    .bind(service.updated_at)
+    .bind(id.to_string())
+    .execute(db)
+    .await
+    .context("Failed to update service in database")?;
+
+    Ok(Some(service))
 }
 
 /// Delete a service
@@ -321,78 +278,6 @@ pub async fn delete_service(
     Ok(result.rows_affected() > 0)
 }
 
-/// Get a service by domain
-pub async fn get_service_by_domain(
-    db: &Pool<Sqlite>,
-    domain: &str
-) -> Result<Option<Service>> {
-    let row = sqlx::query(
-        r#"
-        SELECT
-            id, name, domain, service_type, target, port,
-            ssl_enabled, ssl_cert_path, ssl_key_path, ssl_auto_generate,
-            headers, enabled, created_at, updated_at
-        FROM services
-        WHERE domain = ? AND enabled = TRUE
-        "#
-    )
-    .bind(domain)
-    .fetch_optional(db)
-    .await
-    .context("Failed to fetch service from database")?;
-    
-    match row {
-        Some(row) => {
-            let id: String = row.try_get("id")?;
-            let name: String = row.try_get("name")?;
-            let domain: String = row.try_get("domain")?;
-            let service_type_str: String = row.try_get("service_type")?;
-            let target: String = row.try_get("target")?;
-            let port: i64 = row.try_get("port")?;
-            let ssl_enabled: bool = row.try_get("ssl_enabled")?;
-            let ssl_cert_path: Option<String> = row.try_get("ssl_cert_path")?;
-            let ssl_key_path: Option<String> = row.try_get("ssl_key_path")?;
-            let ssl_auto_generate: bool = row.try_get("ssl_auto_generate")?;
-            let headers_json: Option<String> = row.try_get("headers")?;
-            let enabled: bool = row.try_get("enabled")?;
-            let created_at: chrono::DateTime<chrono::Utc> = row.try_get("created_at")?;
-            let updated_at: chrono::DateTime<chrono::Utc> = row.try_get("updated_at")?;
-            
-            let service_type: ServiceType = serde_json::from_str(&service_type_str)
-                .context("Failed to deserialize service type")?;
-            
-            let headers: HashMap<String, String> = if let Some(json) = headers_json {
-                serde_json::from_str(&json)
-                    .context("Failed to deserialize headers")?
-            } else {
-                HashMap::new()
-            };
-            
-            let ssl = SSLConfig {
-                enabled: ssl_enabled,
-                cert_path: ssl_cert_path,
-                key_path: ssl_key_path,
-                auto_generate: ssl_auto_generate,
-            };
-            
-            Ok(Some(Service {
-                id: Uuid::parse_str(&id)?,
-                name,
-                domain,
-                service_type,
-                target,
-                port: port as u16,
-                ssl,
-                headers,
-                enabled,
-                created_at,
-                updated_at,
-            }))
-        },
-        None => Ok(None),
-    }
-}
-
 /// Enable a service
 pub async fn enable_service(
     db: &Pool<Sqlite>,
@@ -433,4 +318,64 @@ pub async fn disable_service(
     } else {
         Ok(None)
     }
-}
\ No newline at end of file
+}
+
+/// Build a service from a `services` row.
+fn service_from_row(row: &SqliteRow) -> Result<Service> {
+    let id: String = row.try_get("id")?;
+    let service_type_str: String = row.try_get("service_type")?;
+    let port: i64 = row.try_get("port")?;
+    let headers_json: Option<String> = row.try_get("headers")?;
+    let health_check_json: Option<String> = row.try_get("health_check")?;
+    let middleware_json: Option<String> = row.try_get("middleware")?;
+    
+    let service_type: ServiceType = serde_json::from_str(&service_type_str)
+        .context("Failed to deserialize service type")?;
+    
+    let headers: HashMap<String, String> = if let Some(json) = headers_json {
+        serde_json::from_str(&json)
+            .context("Failed to deserialize headers")?
+    } else {
+        HashMap::new()
+    };
+    
+    let health_check = health_check_json
+        .map(|json| serde_json::from_str(&json))
+        .transpose()
+        .context("Failed to deserialize health check")?;
+
+    let middleware = middleware_json
+        .map(|json| serde_json::from_str(&json))
+        .transpose()
+        .context("Failed to deserialize middleware")?
+        .unwrap_or_default();
+
+    let ssl = SSLConfig {
+        enabled: row.try_get("ssl_enabled")?,
+        cert_path: row.try_get("ssl_cert_path")?,
+        key_path: row.try_get("ssl_key_path")?,
+        auto_generate: row.try_get("ssl_auto_generate")?,
+    };
+    
+    Ok(Service {
+        id: Uuid::parse_str(&id)?,
+        name: row.try_get("name")?,
+        domain: row.try_get("domain")?,
+        service_type,
+        target: row.try_get("target")?,
+        port: port as u16,
+        path_prefix: row.try_get("path_prefix")?,
+        strip_prefix: row.try_get("strip_prefix")?,
+        rewrite_prefix: row.try_get("rewrite_prefix")?,
+        priority: row.try_get("priority")?,
+        ssl,
+        headers,
+        load_balancing: row.try_get("lb_strategy")?,
+        health_check,
+        middleware,
+        enabled: row.try_get("enabled")?,
+        discovered: false,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
@@ -1,41 +1,32 @@
 use anyhow::Result;
+use bollard::auth::DockerCredentials;
+use bollard::models::{ContainerCreateBody, HostConfig, PortBinding};
+use bollard::query_parameters::{CreateContainerOptions, StartContainerOptions};
 use bollard::Docker;
-use bollard::container::{
-    Config, CreateContainerOptions, StartContainerOptions,
-};
-use bollard::models::{HostConfig, PortBinding};
+use sqlx::{Pool, Sqlite};
+use futures::StreamExt;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{LazyLock, Mutex, MutexGuard};
 use uuid::Uuid;
 use chrono::Utc;
+use tracing::info;
+
+use super::images;
+use crate::auth::Claims;
+use crate::registries;
+use crate::secrets::{self, MissingSecret};
 
-use crate::models::{
-    ContainerTemplate, CreateTemplateRequest, UpdateTemplateRequest,
-    DeployTemplateRequest
+use crate::models::template::{
+    ContainerTemplate, CreateTemplateRequest, UpdateTemplateRequest, DeployTemplateRequest,
+    PortMapping, VolumeMapping, ResourceLimits,
 };
-use crate::models::template::{PortMapping, VolumeMapping, ResourceLimits};
 
 /// In-memory storage for templates (in a real application, this would be a database)
-static mut TEMPLATES: Option<HashMap<Uuid, ContainerTemplate>> = None;
+static TEMPLATES: LazyLock<Mutex<HashMap<Uuid, ContainerTemplate>>> = LazyLock::new(Default::default);
 
-/// Initialize the templates storage
-fn init_templates() -> &'static mut HashMap<Uuid, ContainerTemplate> {
-    unsafe {
-        if TEMPLATES.is_none() {
-            TEMPLATES = Some(HashMap::new());
-        }
-        TEMPLATES.as_mut().unwrap()
-    }
-}
-
-/// Get a reference to the templates storage
-fn get_templates() -> &'static mut HashMap<Uuid, ContainerTemplate> {
-    unsafe {
-        if TEMPLATES.is_none() {
-            init_templates();
-        }
-        TEMPLATES.as_mut().unwrap()
-    }
+/// Lock the templates storage
+fn get_templates() -> MutexGuard<'static, HashMap<Uuid, ContainerTemplate>> {
+    TEMPLATES.lock().unwrap()
 }
 
 /// List all available container templates
@@ -51,9 +42,18 @@ pub async fn get_template(id: &Uuid) -> Result<Option<ContainerTemplate>> {
     Ok(templates.get(id).cloned())
 }
 
+/// Check the names of the secrets a template's environment refers to
+fn check_secret_env(secret_env: &HashMap<String, String>) -> Result<()> {
+    for name in secret_env.values() {
+        secrets::check_name(name)?;
+    }
+    Ok(())
+}
+
 /// Create a new container template
 pub async fn create_template(request: CreateTemplateRequest) -> Result<ContainerTemplate> {
-    let templates = get_templates();
+    check_secret_env(&request.secret_env)?;
+    let mut templates = get_templates();
     
     let id = Uuid::new_v4();
     let now = Utc::now();
@@ -67,6 +67,7 @@ pub async fn create_template(request: CreateTemplateRequest) -> Result<Container
         tag: request.tag,
         command: request.command,
         env: request.env,
+        secret_env: request.secret_env,
         ports: request.ports,
         volumes: request.volumes,
         network_mode: request.network_mode,
@@ -86,7 +87,10 @@ pub async fn create_template(request: CreateTemplateRequest) -> Result<Container
 
 /// Update an existing template
 pub async fn update_template(id: &Uuid, request: UpdateTemplateRequest) -> Result<Option<ContainerTemplate>> {
-    let templates = get_templates();
+    if let Some(secret_env) = &request.secret_env {
+        check_secret_env(secret_env)?;
+    }
+    let mut templates = get_templates();
     
     if let Some(template) = templates.get_mut(id) {
         if let Some(name) = request.name {
@@ -117,6 +121,10 @@ pub async fn update_template(id: &Uuid, request: UpdateTemplateRequest) -> Resul
             template.env = env;
         }
         
+        if let Some(secret_env) = request.secret_env {
+            template.secret_env = secret_env;
+        }
+        
         if let Some(ports) = request.ports {
             template.ports = ports;
         }
@@ -152,29 +160,62 @@ pub async fn update_template(id: &Uuid, request: UpdateTemplateRequest) -> Resul
 
 /// Delete a template
 pub async fn delete_template(id: &Uuid) -> Result<bool> {
-    let templates = get_templates();
+    let mut templates = get_templates();
     Ok(templates.remove(id).is_some())
 }
 
-/// Deploy a container from a template
+/// The image reference a template deploys.
+pub fn template_image(template: &ContainerTemplate) -> String {
+    format!("{}:{}", template.image, template.tag)
+}
+
+/// A template's environment with the deploy's overrides, and the secrets it
+/// refers to decrypted.
+async fn template_env(
+    db: &Pool<Sqlite>,
+    template: &ContainerTemplate,
+    env_override: Option<HashMap<String, String>>,
+    secret_env_override: Option<HashMap<String, String>>,
+) -> Result<HashMap<String, String>> {
+    let mut env = template.env.clone();
+    env.extend(env_override.unwrap_or_default());
+    let mut secret_env = template.secret_env.clone();
+    secret_env.extend(secret_env_override.unwrap_or_default());
+    for (key, name) in secret_env {
+        let revealed = secrets::reveal(db, &name)
+            .await?
+            .ok_or_else(|| MissingSecret(name.clone()))?;
+        env.insert(key, revealed.value);
+    }
+    Ok(env)
+}
+
+/// Credentials of the registry `caller`'s pulls of a template's image use.
+pub async fn pull_credentials(
+    db: &Pool<Sqlite>,
+    template: &ContainerTemplate,
+    caller: Option<&Claims>,
+) -> Result<Option<DockerCredentials>> {
+    let login = registries::credentials_for(db, &template_image(template), caller).await?;
+    Ok(login.map(|login| login.docker_credentials()))
+}
+
+/// Deploy a container from a template, pulling its image with `caller`'s
+/// registry credentials if it is missing. Secrets are decrypted into the
+/// container's environment only here. Returns `None` when there is no such
+/// template.
 pub async fn deploy_from_template(
-    docker: Arc<Docker>,
+    docker: &Docker,
+    db: &Pool<Sqlite>,
     request: DeployTemplateRequest,
-) -> Result<String> {
-    let templates = get_templates();
-    
-    let template = match templates.get(&request.template_id) {
-        Some(t) => t,
-        None => return Err(anyhow::anyhow!("Template not found")),
+    caller: Option<&Claims>,
+) -> Result<Option<String>> {
+    let Some(template) = get_templates().get(&request.template_id).cloned() else {
+        return Ok(None);
     };
     
     // Prepare environment variables
-    let mut env = template.env.clone();
-    if let Some(env_override) = request.env_override {
-        for (key, value) in env_override {
-            env.insert(key, value);
-        }
-    }
+    let env = template_env(db, &template, request.env_override, request.secret_env_override).await?;
     let env_vec: Vec<String> = env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
     
     // Prepare port bindings
@@ -184,10 +225,7 @@ pub async fn deploy_from_template(
         let container_port = format!("{}/{}", port.container_port, port.protocol);
         let host_binding = vec![PortBinding {
             host_ip: Some(String::from("0.0.0.0")),
-            host_port: match port.host_port {
-                Some(p) => Some(p.to_string()),
-                None => None,
-            },
+            host_port: port.host_port.map(|p| p.to_string()),
         }];
         port_bindings.insert(container_port, host_binding);
     }
@@ -244,9 +282,20 @@ pub async fn deploy_from_template(
         }
     }
     
+    // Pull the image if it is not in the local store yet
+    let image = template_image(&template);
+    if docker.inspect_image(&image).await.is_err() {
+        info!("Pulling image {}", image);
+        let credentials = pull_credentials(db, &template, caller).await?;
+        let mut progress = Box::pin(images::pull(docker, &image, credentials));
+        while let Some(step) = progress.next().await {
+            step?;
+        }
+    }
+    
     // Create container config
-    let config = Config {
-        image: Some(format!("{}:{}", template.image, template.tag)),
+    let config = ContainerCreateBody {
+        image: Some(image),
         cmd: template.command.as_ref().map(|cmd| cmd.split_whitespace().map(String::from).collect()),
         env: Some(env_vec),
         host_config: Some(host_config),
@@ -256,21 +305,21 @@ pub async fn deploy_from_template(
     
     // Create the container
     let options = CreateContainerOptions {
-        name: request.name,
+        name: Some(request.name),
         ..Default::default()
     };
     
     let response = docker.create_container(Some(options), config).await?;
     
     // Start the container
-    docker.start_container(&response.id, None::<StartContainerOptions<String>>).await?;
+    docker.start_container(&response.id, None::<StartContainerOptions>).await?;
     
-    Ok(response.id)
+    Ok(Some(response.id))
 }
 
 /// Initialize default templates
 pub async fn init_default_templates() -> Result<()> {
-    let templates = get_templates();
+    let mut templates = get_templates();
     
     // Only initialize if empty
     if !templates.is_empty() {
@@ -290,6 +339,7 @@ pub async fn init_default_templates() -> Result<()> {
         tag: "latest".to_string(),
         command: None,
         env: HashMap::new(),
+        secret_env: HashMap::new(),
         ports: vec![
             PortMapping {
                 host_port: Some(8080),
@@ -341,6 +391,7 @@ pub async fn init_default_templates() -> Result<()> {
             env.insert("POSTGRES_DB".to_string(), "postgres".to_string());
             env
         },
+        secret_env: HashMap::new(),
         ports: vec![
             PortMapping {
                 host_port: Some(5432),
@@ -385,6 +436,7 @@ pub async fn init_default_templates() -> Result<()> {
         tag: "alpine".to_string(),
         command: None,
         env: HashMap::new(),
+        secret_env: HashMap::new(),
         ports: vec![
             PortMapping {
                 host_port: Some(6379),
@@ -415,4 +467,96 @@ pub async fn init_default_templates() -> Result<()> {
     templates.insert(redis_id, redis_template);
     
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::registry::{CreateRegistryRequest, RegistryScope};
+    use crate::models::secret::CreateSecretRequest;
+
+    fn claims(username: &str) -> Claims {
+        Claims {
+            sub: username.to_string(),
+            username: username.to_string(),
+            role: "operator".to_string(),
+            exp: 0,
+            iat: 0,
+        }
+    }
+
+    fn template(image: &str) -> ContainerTemplate {
+        serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "name": "App",
+            "description": "",
+            "category": "Test",
+            "image": image,
+            "tag": "1.0",
+            "command": null,
+            "env": { "TZ": "UTC", "DB_PASSWORD": "changeme" },
+            "secret_env": { "DB_PASSWORD": "db_password" },
+            "ports": [],
+            "volumes": [],
+            "network_mode": null,
+            "restart_policy": null,
+            "resources": null,
+            "labels": {},
+            "version": "1.0",
+            "created_at": Utc::now(),
+            "updated_at": Utc::now(),
+            "created_by": null,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pull_credentials_are_the_callers() {
+        let db = crate::db::test_pool().await;
+        let (alice, bob) = (claims("alice"), claims("bob"));
+        let request = CreateRegistryRequest {
+            name: "Team".to_string(),
+            url: "https://registry.example.com".to_string(),
+            username: Some("alice".to_string()),
+            password: Some("hunter22".to_string()),
+            scope: RegistryScope::User,
+            owner: None,
+        };
+        registries::create(&db, request, Some(&alice)).await.unwrap();
+
+        let private = template("registry.example.com/team/app");
+        let credentials = pull_credentials(&db, &private, Some(&alice)).await.unwrap().unwrap();
+        assert_eq!(credentials.username.as_deref(), Some("alice"));
+        assert_eq!(credentials.password.as_deref(), Some("hunter22"));
+        assert_eq!(credentials.serveraddress.as_deref(), Some("registry.example.com"));
+        assert!(pull_credentials(&db, &private, Some(&bob)).await.unwrap().is_none());
+        assert!(pull_credentials(&db, &private, None).await.unwrap().is_none());
+        assert!(pull_credentials(&db, &template("nginx"), Some(&alice)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_template_env_reveals_secrets_by_name() {
+        let db = crate::db::test_pool().await;
+        let request = CreateSecretRequest {
+            name: "db_password".to_string(),
+            value: "hunter22-long".to_string(),
+            description: None,
+        };
+        secrets::create(&db, request).await.unwrap();
+        let template = template("postgres");
+
+        let env = template_env(&db, &template, None, None).await.unwrap();
+        assert_eq!(env["TZ"], "UTC");
+        assert_eq!(env["DB_PASSWORD"], "hunter22-long");
+
+        // Overrides add variables, and secrets still win over literal values
+        let env_override = HashMap::from([("DB_PASSWORD".to_string(), "plain".to_string())]);
+        let secret_env_override = HashMap::from([("API_TOKEN".to_string(), "db_password".to_string())]);
+        let env = template_env(&db, &template, Some(env_override), Some(secret_env_override)).await.unwrap();
+        assert_eq!(env["DB_PASSWORD"], "hunter22-long");
+        assert_eq!(env["API_TOKEN"], "hunter22-long");
+
+        let missing = HashMap::from([("API_TOKEN".to_string(), "nope".to_string())]);
+        let e = template_env(&db, &template, None, Some(missing)).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<MissingSecret>().unwrap().0, "nope");
+    }
+}
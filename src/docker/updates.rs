@@ -0,0 +1,964 @@
+//! Image update checks for running containers.
+//!
+//! Every `UPDATE_CHECK_INTERVAL`, the tag each running container was created
+//! from is looked up in its registry with the shared registry credentials,
+//! and the digest found is compared with the repo digest of the image the
+//! container runs. Results are kept in the `image_updates` table by
+//! container name, so they outlive recreated containers. Images that were
+//! built locally have no repo digest and are not checked.
+//!
+//! The `rustainer.update` label (`off`, `notify` or `auto`) decides what
+//! happens to a container, falling back to its stack's policy and then to
+//! `notify`. Notified updates are logged and posted to `UPDATE_NOTIFY_URL`
+//! once per digest. Automatic updates tag the current image as
+//! `<tag>-rollback`, pull the new one and recreate the container with the
+//! same settings. Stack containers are recreated by redeploying their stack
+//! instead. A digest that fails to update, or is rolled back, is skipped
+//! until the tag moves on.
+
+use anyhow::{anyhow, Context, Result};
+use axum::body::Body;
+use axum::http::{header, Request};
+use bollard::models::{
+    ContainerConfig, ContainerCreateBody, ContainerInspectResponse, ContainerSummary, EndpointSettings, ImageConfig,
+    Mount, MountPointTypeEnum, MountTypeEnum, NetworkConnectRequest, NetworkingConfig,
+};
+use bollard::query_parameters::{
+    CreateContainerOptions, InspectContainerOptions, ListContainersOptions, RemoveContainerOptions,
+    RenameContainerOptions, StartContainerOptions, StopContainerOptions,
+};
+use bollard::Docker;
+use chrono::Utc;
+use futures::StreamExt;
+use serde::Serialize;
+use sqlx::{sqlite::SqliteRow, Pool, Row, Sqlite};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use super::compose::{self, engine};
+use super::images;
+use crate::models::update::{ImageUpdate, UpdatePolicy};
+use crate::proxy::AppState;
+use crate::registries;
+
+/// Container label that sets the update policy, overriding the stack's.
+pub const POLICY_LABEL: &str = "rustainer.update";
+
+/// Appended to the tag of the image kept for a rollback.
+const ROLLBACK_SUFFIX: &str = "-rollback";
+
+/// How long a recreated container must keep running, when it has no
+/// healthcheck, before the old one is removed.
+const MONITOR: Duration = Duration::from_secs(10);
+
+const DEFAULT_STOP_TIMEOUT: i32 = 10;
+
+/// Keeps checks and rollbacks from running at the same time.
+static CHECKS: Mutex<()> = Mutex::const_new(());
+
+/// Returned when rolling back a container that has no image kept from
+/// before an automatic update.
+#[derive(Debug)]
+pub struct NoRollback(pub String);
+
+impl std::fmt::Display for NoRollback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "container {} has not been updated automatically", self.0)
+    }
+}
+
+impl std::error::Error for NoRollback {}
+
+/// Something that happened to a container's image, as notified.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Event {
+    UpdateAvailable,
+    Updated,
+    UpdateFailed,
+    RolledBack,
+}
+
+#[derive(Serialize)]
+struct Notification<'a> {
+    event: Event,
+    #[serde(flatten)]
+    update: &'a ImageUpdate,
+}
+
+/// A running container whose image can be checked.
+#[derive(Debug)]
+struct Candidate {
+    id: String,
+    name: String,
+    /// Reference the container was created from, with its tag.
+    image: String,
+    image_id: String,
+    project: Option<String>,
+    /// Stack that manages the container, if any.
+    stack_id: Option<String>,
+    policy: UpdatePolicy,
+}
+
+/// A row of the `image_updates` table.
+struct Record {
+    update: ImageUpdate,
+    notified_digest: Option<String>,
+    skipped_digest: Option<String>,
+}
+
+/// Check every running container periodically, unless checks are disabled.
+pub fn spawn_checker(state: Arc<AppState>) {
+    let interval = state.updates.check_interval;
+    if interval == 0 {
+        info!("Image update checks are disabled");
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(60)));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = check(&state).await {
+                warn!("Image update check failed: {:#}", e);
+            }
+        }
+    });
+}
+
+/// Check the images of all running containers, then notify or apply the
+/// updates found as their policies say.
+pub async fn check(state: &AppState) -> Result<Vec<ImageUpdate>> {
+    let _check = CHECKS.lock().await;
+    let (db, docker) = (&state.db, &state.docker);
+    let candidates = candidates(db, docker).await?;
+    let previous = records(db).await?;
+
+    let now = Utc::now();
+    let mut latest: HashMap<String, Result<String, String>> = HashMap::new();
+    for candidate in &candidates {
+        let kept = previous.get(&candidate.name).map(|record| &record.update);
+        let mut update = ImageUpdate {
+            container: candidate.name.clone(),
+            container_id: candidate.id.clone(),
+            image: candidate.image.clone(),
+            project: candidate.project.clone(),
+            policy: candidate.policy,
+            current_digest: None,
+            latest_digest: None,
+            available: false,
+            error: None,
+            checked_at: now,
+            rollback_image: kept.and_then(|update| update.rollback_image.clone()),
+            updated_at: kept.and_then(|update| update.updated_at),
+        };
+        if candidate.policy != UpdatePolicy::Off {
+            match current_digest(docker, &candidate.image_id, &candidate.image).await {
+                Ok(digest) => update.current_digest = digest,
+                Err(e) => update.error = Some(format!("{:#}", e)),
+            }
+        }
+        // Without a repo digest there is nothing to compare with
+        if update.current_digest.is_some() {
+            if !latest.contains_key(&candidate.image) {
+                let digest = latest_digest(db, docker, &candidate.image).await.map_err(|e| format!("{:#}", e));
+                latest.insert(candidate.image.clone(), digest);
+            }
+            match &latest[&candidate.image] {
+                Ok(digest) => update.latest_digest = Some(digest.clone()),
+                Err(e) => update.error = Some(e.clone()),
+            }
+        }
+        update.available = matches!(
+            (&update.current_digest, &update.latest_digest),
+            (Some(current), Some(latest)) if current != latest
+        );
+        save(db, &update).await?;
+    }
+    sqlx::query("DELETE FROM image_updates WHERE checked_at < ?")
+        .bind(now)
+        .execute(db)
+        .await
+        .context("Failed to remove old image update checks")?;
+
+    let records = records(db).await?;
+    let mut stacks: BTreeMap<&str, Vec<&Candidate>> = BTreeMap::new();
+    for candidate in &candidates {
+        let Some(record) = records.get(&candidate.name).filter(|record| record.update.available) else {
+            continue;
+        };
+        let latest = record.update.latest_digest.as_deref();
+        match record.update.policy {
+            UpdatePolicy::Off => {}
+            UpdatePolicy::Notify => {
+                if record.notified_digest.as_deref() != latest {
+                    notify(state, Event::UpdateAvailable, &record.update).await;
+                    sqlx::query("UPDATE image_updates SET notified_digest = latest_digest WHERE container = ?")
+                        .bind(&candidate.name)
+                        .execute(db)
+                        .await
+                        .context("Failed to save image update check")?;
+                }
+            }
+            UpdatePolicy::Auto if record.skipped_digest.as_deref() == latest => {}
+            UpdatePolicy::Auto => match &candidate.stack_id {
+                Some(stack_id) => stacks.entry(stack_id).or_default().push(candidate),
+                None => {
+                    let result = update_container(db, docker, candidate).await;
+                    finish(state, &[candidate], result).await?;
+                }
+            },
+        }
+    }
+    for (stack_id, members) in stacks {
+        let result = update_stack(db, docker, stack_id, &members).await;
+        finish(state, &members, result).await?;
+    }
+
+    list(db).await
+}
+
+/// Put the image a container ran before its last automatic update back
+/// under its tag, and recreate the container, or redeploy its stack, with
+/// it. The newer digest is skipped until the tag moves on again.
+pub async fn rollback(state: &AppState, container: &str) -> Result<Option<ImageUpdate>> {
+    let _check = CHECKS.lock().await;
+    let (db, docker) = (&state.db, &state.docker);
+    let Some(update) = get(db, container).await? else {
+        return Ok(None);
+    };
+    let Some(rollback_image) = &update.rollback_image else {
+        return Err(NoRollback(container.to_string()).into());
+    };
+
+    info!("Rolling back container {} to {}", container, rollback_image);
+    images::tag(docker, rollback_image, &update.image).await?;
+    let stacks = compose::update_policies(db).await?;
+    match update.project.as_ref().and_then(|project| stacks.get(project)) {
+        Some((stack_id, _)) => {
+            compose::up_stack(db, docker, stack_id, None).await?;
+        }
+        None => {
+            recreate(docker, container).await?;
+        }
+    }
+
+    let current = current_digest(docker, &update.image, &update.image).await?;
+    sqlx::query(
+        "UPDATE image_updates SET current_digest = ?, available = (latest_digest IS NOT NULL AND latest_digest IS NOT ?), \
+         rollback_image = NULL, updated_at = NULL WHERE container = ?",
+    )
+    .bind(&current)
+    .bind(&current)
+    .bind(container)
+    .execute(db)
+    .await
+    .context("Failed to save image update check")?;
+    sqlx::query("UPDATE image_updates SET skipped_digest = latest_digest WHERE image = ?")
+        .bind(&update.image)
+        .execute(db)
+        .await
+        .context("Failed to save image update check")?;
+
+    let update = get(db, container).await?;
+    if let Some(update) = &update {
+        notify(state, Event::RolledBack, update).await;
+    }
+    Ok(update)
+}
+
+/// The last check of every container, by container name.
+pub async fn list(db: &Pool<Sqlite>) -> Result<Vec<ImageUpdate>> {
+    Ok(records(db).await?.into_values().map(|record| record.update).collect())
+}
+
+/// The last check of each container, by container name.
+pub async fn by_container(db: &Pool<Sqlite>) -> Result<HashMap<String, ImageUpdate>> {
+    Ok(records(db)
+        .await?
+        .into_iter()
+        .map(|(name, record)| (name, record.update))
+        .collect())
+}
+
+/// The last check of a container, by name.
+pub async fn get(db: &Pool<Sqlite>, container: &str) -> Result<Option<ImageUpdate>> {
+    let row = sqlx::query("SELECT * FROM image_updates WHERE container = ?")
+        .bind(container)
+        .fetch_optional(db)
+        .await
+        .context("Failed to fetch image update check from database")?;
+    Ok(row.as_ref().map(record_from_row).transpose()?.map(|record| record.update))
+}
+
+/// Whether the last check found an update for a listed container.
+pub fn available(updates: &HashMap<String, ImageUpdate>, container: &ContainerSummary) -> bool {
+    container
+        .names
+        .iter()
+        .flatten()
+        .next()
+        .and_then(|name| updates.get(name.trim_start_matches('/')))
+        .is_some_and(|update| update.available)
+}
+
+/// Running containers created from a tag, with the policy that applies to
+/// each. Containers created from an image ID or a digest are left out, as
+/// they cannot move to another image.
+async fn candidates(db: &Pool<Sqlite>, docker: &Docker) -> Result<Vec<Candidate>> {
+    let stacks = compose::update_policies(db).await?;
+    let containers = docker
+        .list_containers(Some(ListContainersOptions::default()))
+        .await
+        .context("Failed to list containers")?;
+
+    let mut candidates = Vec::new();
+    for summary in containers {
+        let (Some(id), Some(image_id)) = (summary.id.clone(), summary.image_id.clone()) else {
+            continue;
+        };
+        let name = match summary.names.iter().flatten().next() {
+            Some(name) => name.trim_start_matches('/').to_string(),
+            None => id.clone(),
+        };
+        // The listed image turns into an ID once its tag moves on
+        let info = match docker.inspect_container(&id, None::<InspectContainerOptions>).await {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Failed to inspect container {}: {}", name, e);
+                continue;
+            }
+        };
+        let image = info.config.and_then(|config| config.image).unwrap_or_default();
+        let by_id = image_id.trim_start_matches("sha256:").starts_with(image.trim_start_matches("sha256:"));
+        if image.is_empty() || image.contains('@') || by_id {
+            continue;
+        }
+
+        let labels = summary.labels.unwrap_or_default();
+        let project = labels.get(engine::PROJECT_LABEL).cloned();
+        let stack = project.as_ref().and_then(|project| stacks.get(project));
+        let fallback = stack.map(|(_, policy)| *policy).unwrap_or_default();
+        let policy = match labels.get(POLICY_LABEL) {
+            Some(value) => UpdatePolicy::parse(value).unwrap_or_else(|| {
+                warn!("Invalid {} label {:?} on container {}", POLICY_LABEL, value, name);
+                fallback
+            }),
+            None => fallback,
+        };
+        candidates.push(Candidate {
+            id,
+            name,
+            image: images::reference(&image),
+            image_id,
+            project,
+            stack_id: stack.map(|(id, _)| id.clone()),
+            policy,
+        });
+    }
+    Ok(candidates)
+}
+
+/// Digest of a local image in the repository of `reference`, if it was
+/// pulled from or pushed to one.
+async fn current_digest(docker: &Docker, image: &str, reference: &str) -> Result<Option<String>> {
+    let inspect = docker
+        .inspect_image(image)
+        .await
+        .with_context(|| format!("Failed to inspect image {}", image))?;
+    let repository = images::repository(reference);
+    Ok(inspect.repo_digests.unwrap_or_default().iter().find_map(|repo_digest| {
+        let (repo, digest) = repo_digest.split_once('@')?;
+        (images::repository(repo) == repository).then(|| digest.to_string())
+    }))
+}
+
+/// Digest that a tag points to in its registry.
+async fn latest_digest(db: &Pool<Sqlite>, docker: &Docker, image: &str) -> Result<String> {
+    let credentials = registries::credentials_for(db, image, None)
+        .await?
+        .map(|login| login.docker_credentials());
+    let inspect = docker
+        .inspect_registry_image(image, credentials)
+        .await
+        .with_context(|| format!("Failed to look up image {} in its registry", image))?;
+    inspect
+        .descriptor
+        .digest
+        .ok_or_else(|| anyhow!("Registry returned no digest for image {}", image))
+}
+
+/// Keep a standalone container's image for a rollback, pull the new one
+/// and recreate the container with it.
+async fn update_container(db: &Pool<Sqlite>, docker: &Docker, candidate: &Candidate) -> Result<()> {
+    keep_for_rollback(docker, &candidate.image_id, &candidate.image).await?;
+    pull(db, docker, &candidate.image).await?;
+    recreate(docker, &candidate.name).await?;
+    Ok(())
+}
+
+/// Keep the images of a stack's containers for a rollback, pull the new
+/// ones and redeploy the stack, which replaces every container whose image
+/// changed. Services that share an image are updated together.
+async fn update_stack(db: &Pool<Sqlite>, docker: &Docker, stack_id: &str, members: &[&Candidate]) -> Result<()> {
+    let mut pulled = HashSet::new();
+    for member in members {
+        if pulled.insert(&member.image) {
+            keep_for_rollback(docker, &member.image_id, &member.image).await?;
+            pull(db, docker, &member.image).await?;
+        }
+    }
+    compose::up_stack(db, docker, stack_id, None)
+        .await?
+        .ok_or_else(|| anyhow!("Stack {} no longer exists", stack_id))?;
+    Ok(())
+}
+
+/// Record and notify the outcome of an automatic update.
+async fn finish(state: &AppState, members: &[&Candidate], result: Result<()>) -> Result<()> {
+    let db = &state.db;
+    let event = match &result {
+        Ok(_) => Event::Updated,
+        Err(_) => Event::UpdateFailed,
+    };
+    for member in members {
+        match &result {
+            Ok(()) => {
+                sqlx::query(
+                    "UPDATE image_updates SET current_digest = latest_digest, available = 0, error = NULL, \
+                     rollback_image = ?, updated_at = ? WHERE container = ?",
+                )
+                .bind(rollback_name(&member.image))
+                .bind(Utc::now())
+                .bind(&member.name)
+                .execute(db)
+                .await
+                .context("Failed to save image update check")?;
+            }
+            Err(e) => {
+                warn!("Failed to update container {}: {:#}", member.name, e);
+                sqlx::query("UPDATE image_updates SET skipped_digest = latest_digest, error = ? WHERE container = ?")
+                    .bind(format!("{:#}", e))
+                    .bind(&member.name)
+                    .execute(db)
+                    .await
+                    .context("Failed to save image update check")?;
+            }
+        }
+        if let Some(update) = get(db, &member.name).await? {
+            notify(state, event, &update).await;
+        }
+    }
+    Ok(())
+}
+
+/// Tag a container's current image as `<tag>-rollback`.
+async fn keep_for_rollback(docker: &Docker, image_id: &str, image: &str) -> Result<()> {
+    images::tag(docker, image_id, &rollback_name(image)).await
+}
+
+fn rollback_name(image: &str) -> String {
+    let (repo, tag) = images::split_tag(image);
+    format!("{}:{}{}", repo, tag, ROLLBACK_SUFFIX)
+}
+
+async fn pull(db: &Pool<Sqlite>, docker: &Docker, image: &str) -> Result<()> {
+    info!("Pulling image {}", image);
+    let credentials = registries::credentials_for(db, image, None)
+        .await?
+        .map(|login| login.docker_credentials());
+    let mut progress = Box::pin(images::pull(docker, image, credentials));
+    while let Some(step) = progress.next().await {
+        step?;
+    }
+    Ok(())
+}
+
+/// Replace a container with one created from the same settings, so that it
+/// runs the image its tag points to now. The old container is kept, stopped,
+/// until the new one is up, and brought back if it does not come up.
+pub async fn recreate(docker: &Docker, name: &str) -> Result<String> {
+    let old = docker
+        .inspect_container(name, None::<InspectContainerOptions>)
+        .await
+        .with_context(|| format!("Failed to inspect container {}", name))?;
+    let old_id = old.id.clone().ok_or_else(|| anyhow!("Container {} has no ID", name))?;
+    let name = old.name.as_deref().unwrap_or(name).trim_start_matches('/').to_string();
+    let running = old.state.as_ref().and_then(|state| state.running).unwrap_or(false);
+    let image_config = match &old.image {
+        Some(image) => docker.inspect_image(image).await.ok().and_then(|image| image.config),
+        None => None,
+    };
+    let (body, networks) = create_body(&old, image_config.as_ref());
+    let timeout = old
+        .config
+        .as_ref()
+        .and_then(|config| config.stop_timeout)
+        .map_or(DEFAULT_STOP_TIMEOUT, |timeout| timeout as i32);
+
+    info!("Recreating container {}", name);
+    if running {
+        docker
+            .stop_container(&old_id, Some(StopContainerOptions { t: Some(timeout), ..Default::default() }))
+            .await
+            .with_context(|| format!("Failed to stop container {}", name))?;
+    }
+    let short: String = old_id.chars().take(12).collect();
+    if let Err(e) = rename(docker, &old_id, &format!("{}-{}", name, short)).await {
+        if running {
+            start(docker, &old_id, &name).await?;
+        }
+        return Err(e);
+    }
+
+    let mut new_id = None;
+    match replace(docker, &name, body, networks, running, &mut new_id).await {
+        Ok(()) => {
+            docker
+                .remove_container(&old_id, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+                .await
+                .with_context(|| format!("Failed to remove the old container {}", name))?;
+            Ok(new_id.unwrap_or_default())
+        }
+        Err(e) => {
+            warn!("Bringing back the old container {}", name);
+            if let Some(id) = new_id {
+                docker
+                    .remove_container(&id, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+                    .await
+                    .with_context(|| format!("Failed to remove the new container {}", name))?;
+            }
+            rename(docker, &old_id, &name).await?;
+            if running {
+                start(docker, &old_id, &name).await?;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Create, connect and start the container replacing an old one.
+async fn replace(
+    docker: &Docker,
+    name: &str,
+    body: ContainerCreateBody,
+    networks: Vec<(String, EndpointSettings)>,
+    running: bool,
+    new_id: &mut Option<String>,
+) -> Result<()> {
+    let options = CreateContainerOptions {
+        name: Some(name.to_string()),
+        ..Default::default()
+    };
+    let id = docker
+        .create_container(Some(options), body)
+        .await
+        .with_context(|| format!("Failed to create container {}", name))?
+        .id;
+    *new_id = Some(id.clone());
+
+    for (network, endpoint) in networks {
+        let request = NetworkConnectRequest {
+            container: Some(id.clone()),
+            endpoint_config: Some(endpoint),
+        };
+        docker
+            .connect_network(&network, request)
+            .await
+            .with_context(|| format!("Failed to connect {} to network {}", name, network))?;
+    }
+    if running {
+        start(docker, &id, name).await?;
+        engine::wait_ready(docker, &id, name, MONITOR).await?;
+    }
+    Ok(())
+}
+
+async fn start(docker: &Docker, id: &str, name: &str) -> Result<()> {
+    docker
+        .start_container(id, None::<StartContainerOptions>)
+        .await
+        .with_context(|| format!("Failed to start container {}", name))
+}
+
+async fn rename(docker: &Docker, id: &str, name: &str) -> Result<()> {
+    docker
+        .rename_container(id, RenameContainerOptions { name: name.to_string() })
+        .await
+        .with_context(|| format!("Failed to rename container to {}", name))
+}
+
+/// Settings to create a container like an inspected one, along with the
+/// networks to connect it to after the first. Settings the container only
+/// has from its image are left to the image.
+fn create_body(
+    old: &ContainerInspectResponse,
+    image: Option<&ImageConfig>,
+) -> (ContainerCreateBody, Vec<(String, EndpointSettings)>) {
+    let mut config = old.config.clone().unwrap_or_default();
+    if let Some(image) = image {
+        strip_image_defaults(&mut config, image);
+    }
+    let mut host_config = old.host_config.clone().unwrap_or_default();
+    let short_id: String = old.id.as_deref().unwrap_or_default().chars().take(12).collect();
+
+    // Anonymous volumes are only listed as mounts; mount them by name so the
+    // new container keeps their data
+    let mut taken: HashSet<String> = host_config
+        .binds
+        .iter()
+        .flatten()
+        .filter_map(|bind| bind.split(':').nth(1))
+        .map(str::to_string)
+        .collect();
+    taken.extend(host_config.mounts.iter().flatten().filter_map(|mount| mount.target.clone()));
+    for mount in old.mounts.iter().flatten() {
+        let (Some(volume), Some(target)) = (&mount.name, &mount.destination) else {
+            continue;
+        };
+        if mount.typ != Some(MountPointTypeEnum::VOLUME) || taken.contains(target) {
+            continue;
+        }
+        host_config.mounts.get_or_insert_with(Vec::new).push(Mount {
+            target: Some(target.clone()),
+            source: Some(volume.clone()),
+            typ: Some(MountTypeEnum::VOLUME),
+            read_only: mount.rw.map(|rw| !rw),
+            ..Default::default()
+        });
+        if let Some(volumes) = &mut config.volumes {
+            volumes.remove(target);
+        }
+    }
+
+    let mode = host_config.network_mode.clone().unwrap_or_default();
+    let shared = matches!(mode.as_str(), "host" | "none") || mode.starts_with("container:");
+    let mut endpoints: Vec<(String, EndpointSettings)> = old
+        .network_settings
+        .iter()
+        .flat_map(|settings| settings.networks.iter().flatten())
+        .filter(|_| !shared)
+        .map(|(network, endpoint)| {
+            let settings = EndpointSettings {
+                ipam_config: endpoint.ipam_config.clone(),
+                links: endpoint.links.clone(),
+                aliases: endpoint
+                    .aliases
+                    .clone()
+                    .map(|aliases| aliases.into_iter().filter(|alias| *alias != short_id).collect()),
+                driver_opts: endpoint.driver_opts.clone(),
+                gw_priority: endpoint.gw_priority,
+                ..Default::default()
+            };
+            (network.clone(), settings)
+        })
+        .collect();
+    endpoints.sort_by(|a, b| a.0.cmp(&b.0));
+    let primary = match mode.as_str() {
+        "" | "default" => "bridge",
+        mode => mode,
+    };
+    let networking_config = endpoints
+        .iter()
+        .position(|(network, _)| network == primary)
+        .map(|index| endpoints.remove(index))
+        .map(|(network, endpoint)| NetworkingConfig {
+            endpoints_config: Some(HashMap::from([(network, endpoint)])),
+        });
+
+    let body = ContainerCreateBody {
+        // The daemon names the host after the container unless given a name
+        hostname: config.hostname.filter(|hostname| *hostname != short_id),
+        domainname: config.domainname,
+        user: config.user,
+        attach_stdin: config.attach_stdin,
+        attach_stdout: config.attach_stdout,
+        attach_stderr: config.attach_stderr,
+        exposed_ports: config.exposed_ports,
+        tty: config.tty,
+        open_stdin: config.open_stdin,
+        stdin_once: config.stdin_once,
+        env: config.env,
+        cmd: config.cmd,
+        healthcheck: config.healthcheck,
+        args_escaped: config.args_escaped,
+        image: config.image,
+        volumes: config.volumes,
+        working_dir: config.working_dir,
+        entrypoint: config.entrypoint,
+        network_disabled: config.network_disabled,
+        mac_address: config.mac_address,
+        on_build: config.on_build,
+        labels: config.labels,
+        stop_signal: config.stop_signal,
+        stop_timeout: config.stop_timeout,
+        shell: config.shell,
+        host_config: Some(host_config),
+        networking_config,
+    };
+    (body, endpoints)
+}
+
+/// Leave out the settings a container only has from its image, so that the
+/// new image's defaults apply rather than the old one's.
+fn strip_image_defaults(config: &mut ContainerConfig, image: &ImageConfig) {
+    if let (Some(env), Some(defaults)) = (&mut config.env, &image.env) {
+        env.retain(|var| !defaults.contains(var));
+    }
+    if let (Some(labels), Some(defaults)) = (&mut config.labels, &image.labels) {
+        labels.retain(|key, value| defaults.get(key) != Some(value));
+    }
+    if let (Some(ports), Some(defaults)) = (&mut config.exposed_ports, &image.exposed_ports) {
+        ports.retain(|port, _| !defaults.contains_key(port));
+    }
+    if let (Some(volumes), Some(defaults)) = (&mut config.volumes, &image.volumes) {
+        volumes.retain(|path, _| !defaults.contains_key(path));
+    }
+    if config.cmd == image.cmd {
+        config.cmd = None;
+    }
+    if config.entrypoint == image.entrypoint {
+        config.entrypoint = None;
+    }
+    if config.working_dir == image.working_dir {
+        config.working_dir = None;
+    }
+    if config.user == image.user {
+        config.user = None;
+    }
+    if config.healthcheck == image.healthcheck {
+        config.healthcheck = None;
+    }
+    if config.stop_signal == image.stop_signal {
+        config.stop_signal = None;
+    }
+    if config.shell == image.shell {
+        config.shell = None;
+    }
+}
+
+/// Log an event and post it to the notification webhook, if one is set.
+async fn notify(state: &AppState, event: Event, update: &ImageUpdate) {
+    match event {
+        Event::UpdateAvailable => info!(
+            "Image {} of container {} has an update: {}",
+            update.image,
+            update.container,
+            update.latest_digest.as_deref().unwrap_or_default()
+        ),
+        Event::Updated => info!("Updated container {} to the latest {}", update.container, update.image),
+        Event::UpdateFailed => warn!("Automatic update of container {} failed", update.container),
+        Event::RolledBack => info!("Rolled back container {} to its previous image", update.container),
+    }
+    let Some(url) = &state.updates.notify_url else {
+        return;
+    };
+    let body = match serde_json::to_vec(&Notification { event, update }) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to encode update notification: {}", e);
+            return;
+        }
+    };
+    let request = match Request::post(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::USER_AGENT, "rustainer-updates")
+        .body(Body::from(body))
+    {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Invalid update notification URL {}: {}", url, e);
+            return;
+        }
+    };
+    match tokio::time::timeout(Duration::from_secs(10), state.client.request(request)).await {
+        Ok(Ok(response)) if response.status().is_success() => {}
+        Ok(Ok(response)) => warn!("Update notification to {} returned {}", url, response.status()),
+        Ok(Err(e)) => warn!("Failed to send update notification to {}: {}", url, e),
+        Err(_) => warn!("Update notification to {} timed out", url),
+    }
+}
+
+async fn save(db: &Pool<Sqlite>, update: &ImageUpdate) -> Result<()> {
+    // What was kept for a rollback no longer applies once the name is
+    // reused for another image
+    sqlx::query(
+        r#"
+        INSERT INTO image_updates (
+            container, container_id, image, project, policy, current_digest, latest_digest, available, error,
+            checked_at
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (container) DO UPDATE SET
+            rollback_image = CASE WHEN image = excluded.image THEN rollback_image END,
+            updated_at = CASE WHEN image = excluded.image THEN updated_at END,
+            container_id = excluded.container_id,
+            image = excluded.image,
+            project = excluded.project,
+            policy = excluded.policy,
+            current_digest = excluded.current_digest,
+            latest_digest = excluded.latest_digest,
+            available = excluded.available,
+            error = excluded.error,
+            checked_at = excluded.checked_at
+        "#,
+    )
+    .bind(&update.container)
+    .bind(&update.container_id)
+    .bind(&update.image)
+    .bind(&update.project)
+    .bind(update.policy.as_str())
+    .bind(&update.current_digest)
+    .bind(&update.latest_digest)
+    .bind(update.available)
+    .bind(&update.error)
+    .bind(update.checked_at)
+    .execute(db)
+    .await
+    .context("Failed to save image update check")?;
+    Ok(())
+}
+
+async fn records(db: &Pool<Sqlite>) -> Result<BTreeMap<String, Record>> {
+    let rows = sqlx::query("SELECT * FROM image_updates ORDER BY container")
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch image update checks from database")?;
+    rows.iter()
+        .map(|row| record_from_row(row).map(|record| (record.update.container.clone(), record)))
+        .collect()
+}
+
+fn record_from_row(row: &SqliteRow) -> Result<Record> {
+    let policy: String = row.try_get("policy")?;
+    Ok(Record {
+        update: ImageUpdate {
+            container: row.try_get("container")?,
+            container_id: row.try_get("container_id")?,
+            image: row.try_get("image")?,
+            project: row.try_get("project")?,
+            policy: UpdatePolicy::parse(&policy).unwrap_or_default(),
+            current_digest: row.try_get("current_digest")?,
+            latest_digest: row.try_get("latest_digest")?,
+            available: row.try_get("available")?,
+            error: row.try_get("error")?,
+            checked_at: row.try_get("checked_at")?,
+            rollback_image: row.try_get("rollback_image")?,
+            updated_at: row.try_get("updated_at")?,
+        },
+        notified_digest: row.try_get("notified_digest")?,
+        skipped_digest: row.try_get("skipped_digest")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::{HostConfig, MountPoint, NetworkSettings};
+
+    fn keys(keys: &[&str]) -> Option<HashMap<String, HashMap<(), ()>>> {
+        Some(keys.iter().map(|key| (key.to_string(), HashMap::new())).collect())
+    }
+
+    #[test]
+    fn test_strip_image_defaults() {
+        let image = ImageConfig {
+            env: Some(vec!["PATH=/usr/bin".to_string(), "VERSION=1.0".to_string()]),
+            cmd: Some(vec!["nginx".to_string()]),
+            exposed_ports: keys(&["80/tcp"]),
+            labels: Some(HashMap::from([("maintainer".to_string(), "nginx".to_string())])),
+            ..Default::default()
+        };
+        let mut config = ContainerConfig {
+            env: Some(vec!["PATH=/usr/bin".to_string(), "VERSION=1.0".to_string(), "MODE=prod".to_string()]),
+            cmd: Some(vec!["nginx".to_string()]),
+            entrypoint: Some(vec!["/entrypoint.sh".to_string()]),
+            exposed_ports: keys(&["80/tcp", "8080/tcp"]),
+            labels: Some(HashMap::from([
+                ("maintainer".to_string(), "nginx".to_string()),
+                ("rustainer.update".to_string(), "auto".to_string()),
+            ])),
+            ..Default::default()
+        };
+        strip_image_defaults(&mut config, &image);
+        assert_eq!(config.env, Some(vec!["MODE=prod".to_string()]));
+        assert_eq!(config.cmd, None);
+        assert_eq!(config.entrypoint, Some(vec!["/entrypoint.sh".to_string()]));
+        assert_eq!(config.exposed_ports, keys(&["8080/tcp"]));
+        assert_eq!(
+            config.labels,
+            Some(HashMap::from([("rustainer.update".to_string(), "auto".to_string())]))
+        );
+    }
+
+    #[test]
+    fn test_create_body_keeps_volumes_and_networks() {
+        let old = ContainerInspectResponse {
+            id: Some("0123456789abcdef".to_string()),
+            config: Some(ContainerConfig {
+                hostname: Some("0123456789ab".to_string()),
+                image: Some("nginx:1.27".to_string()),
+                volumes: keys(&["/cache", "/data"]),
+                ..Default::default()
+            }),
+            host_config: Some(HostConfig {
+                binds: Some(vec!["site:/data:ro".to_string()]),
+                network_mode: Some("web".to_string()),
+                ..Default::default()
+            }),
+            mounts: Some(vec![
+                MountPoint {
+                    typ: Some(MountPointTypeEnum::VOLUME),
+                    name: Some("site".to_string()),
+                    destination: Some("/data".to_string()),
+                    ..Default::default()
+                },
+                MountPoint {
+                    typ: Some(MountPointTypeEnum::VOLUME),
+                    name: Some("f00d".to_string()),
+                    destination: Some("/cache".to_string()),
+                    rw: Some(true),
+                    ..Default::default()
+                },
+            ]),
+            network_settings: Some(NetworkSettings {
+                networks: Some(HashMap::from([
+                    (
+                        "web".to_string(),
+                        EndpointSettings {
+                            aliases: Some(vec!["0123456789ab".to_string(), "site".to_string()]),
+                            ip_address: Some("172.18.0.2".to_string()),
+                            ..Default::default()
+                        },
+                    ),
+                    ("backend".to_string(), EndpointSettings::default()),
+                ])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let (body, networks) = create_body(&old, None);
+        assert_eq!(body.hostname, None);
+        assert_eq!(body.image.as_deref(), Some("nginx:1.27"));
+        assert_eq!(body.volumes, keys(&["/data"]));
+        let mounts = body.host_config.unwrap().mounts.unwrap();
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].source.as_deref(), Some("f00d"));
+        assert_eq!(mounts[0].target.as_deref(), Some("/cache"));
+        assert_eq!(mounts[0].read_only, Some(false));
+
+        let endpoints = body.networking_config.unwrap().endpoints_config.unwrap();
+        let web = &endpoints["web"];
+        assert_eq!(web.aliases, Some(vec!["site".to_string()]));
+        assert_eq!(web.ip_address, None);
+        assert_eq!(networks.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["backend"]);
+    }
+
+    #[test]
+    fn test_rollback_name() {
+        assert_eq!(rollback_name("nginx:1.27"), "nginx:1.27-rollback");
+        assert_eq!(rollback_name("localhost:5000/app:latest"), "localhost:5000/app:latest-rollback");
+    }
+}
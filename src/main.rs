@@ -2,6 +2,7 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{get, post, put, delete},
     Router,
     response::{IntoResponse, Html, Redirect},
@@ -11,11 +12,14 @@ use tower_http::services::ServeDir;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod api;
+mod auth;
 mod config;
 mod db;
 mod docker;
 mod models;
 mod proxy;
+mod registries;
+mod secrets;
 
 use crate::proxy::AppState;
 
@@ -40,6 +44,12 @@ async fn main() {
         }
     };
 
+    // Load the key secrets are encrypted with
+    if let Err(e) = secrets::init(&config.secrets) {
+        tracing::error!("Failed to load secrets key: {:#}", e);
+        std::process::exit(1);
+    }
+
     // Create a database connection pool
     let db = match db::init_db_pool(&config.database.url).await {
         Ok(pool) => pool,
@@ -64,14 +74,68 @@ async fn main() {
         }
     };
     
+    // Load certificates for the HTTPS listener
+    let certs = Arc::new(proxy::tls::CertStore::new(proxy::tls::CERTS_DIR));
+    if let Err(e) = certs.load_dir() {
+        tracing::error!("Failed to load certificates: {}", e);
+    }
+
+    // Start the access log writer
+    let access_log = match proxy::access_log::AccessLog::start(&config.access_log) {
+        Ok(access_log) => access_log,
+        Err(e) => {
+            tracing::error!("Failed to start access log: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Create the app state
     let app_state = Arc::new(AppState {
         db: db.clone(),
         docker,
+        client: proxy::build_client(),
+        balancers: Default::default(),
+        routes: Default::default(),
+        discovered: Default::default(),
+        certs: certs.clone(),
+        acme_challenges: Default::default(),
+        https_port: config.proxy.https_port,
+        jwt: Arc::new(config.create_jwt_config()),
+        rate_limits: Default::default(),
+        metrics: Default::default(),
+        access_log,
+        updates: config.updates.clone(),
     });
+    proxy::routing::reload(&app_state).await;
+
+    // Drain proxied traffic from stack containers before they are stopped
+    docker::compose::engine::set_drain(app_state.clone());
+
+    // Encrypt stored git credentials, and bring back the secret files of
+    // running stacks after a host restart emptied the runtime directory
+    if let Err(e) = docker::compose::git::seal_credentials(&db).await {
+        tracing::error!("Failed to encrypt git credentials: {:#}", e);
+    }
+    if let Err(e) = docker::compose::restore_secret_files(&db, &app_state.docker).await {
+        tracing::error!("Failed to restore stack secret files: {:#}", e);
+    }
+
+    if let Err(e) = docker::templates::init_default_templates().await {
+        tracing::error!("Failed to add default container templates: {:#}", e);
+    }
+
+    // Builds cannot outlive the process that ran them
+    if let Err(e) = docker::builds::fail_interrupted(&db).await {
+        tracing::error!("Failed to update interrupted image builds: {:#}", e);
+    }
 
     // Create API routes
     let api_routes = Router::new()
+        // Auth routes
+        .route("/auth/login", post(auth::handlers::login))
+        .route("/auth/logout", post(auth::handlers::logout))
+        .route("/auth/redirect", post(auth::handlers::login_redirect))
+        .route("/auth/me", get(auth::handlers::get_current_user))
         // Container routes
         .route("/containers", get(api::list_containers))
         .route("/containers", post(api::create_container))
@@ -80,10 +144,93 @@ async fn main() {
         .route("/containers/:id/start", post(api::start_container))
         .route("/containers/:id/stop", post(api::stop_container))
         .route("/containers/:id/restart", post(api::restart_container))
+        .route("/containers/:id/logs", get(api::get_container_logs))
+        .route("/containers/:id/logs/stream", get(api::stream_container_logs))
+        .route("/containers/:id/logs/ws", get(api::container_logs_socket))
         // Image routes
         .route("/images", get(api::list_images))
         .route("/images/pull", post(api::pull_image))
-        .route("/images/:id", delete(api::delete_image));
+        .route("/images/pull/ws", get(api::pull_image_socket))
+        .route("/images/prune", post(api::prune_images))
+        .route("/images/build", post(api::build_image))
+        .route(
+            "/images/build/upload",
+            post(api::upload_build_context).layer(DefaultBodyLimit::max(api::images::BUILD_CONTEXT_LIMIT)),
+        )
+        .route("/images/build/ws", get(api::build_image_socket))
+        .route("/images/builds", get(api::list_builds))
+        .route("/images/builds/:id", get(api::get_build))
+        .route("/images/:id/builds", get(api::list_image_builds))
+        .route("/images/:id", get(api::get_image))
+        .route("/images/:id", delete(api::delete_image))
+        // Service routes
+        .route("/services", get(api::services::list_services))
+        .route("/services", post(api::services::create_service))
+        .route("/services/:id", get(api::services::get_service))
+        .route("/services/:id", put(api::services::update_service))
+        .route("/services/:id", delete(api::services::delete_service))
+        .route("/services/:id/enable", post(api::services::enable_service))
+        .route("/services/:id/disable", post(api::services::disable_service))
+        // Compose stack routes
+        .route("/compose", get(api::compose::list_compose_stacks))
+        .route("/compose", post(api::compose::create_compose_stack))
+        .route("/compose/validate", post(api::compose::validate_compose_file))
+        .route("/compose/git", post(api::compose::create_git_compose_stack))
+        .route("/compose/:id", get(api::compose::get_compose_stack))
+        .route("/compose/:id", put(api::compose::update_compose_stack))
+        .route("/compose/:id", delete(api::compose::delete_compose_stack))
+        .route("/compose/:id/up", post(api::compose::up_compose_stack))
+        .route("/compose/:id/plan", post(api::compose::plan_compose_stack))
+        .route("/compose/:id/adopt", post(api::compose::adopt_compose_stack))
+        .route("/compose/:id/down", post(api::compose::down_compose_stack))
+        .route("/compose/:id/restart", post(api::compose::restart_compose_stack))
+        .route("/compose/:id/scale", post(api::compose::scale_compose_stack))
+        .route("/compose/:id/logs", get(api::compose::get_compose_stack_logs))
+        .route("/compose/:id/logs/stream", get(api::compose::stream_compose_stack_logs))
+        .route("/compose/:id/logs/ws", get(api::compose::compose_stack_logs_socket))
+        .route("/compose/:id/config", get(api::compose::get_compose_stack_config))
+        .route("/compose/:id/revisions", get(api::compose::list_stack_revisions))
+        .route("/compose/:id/revisions/:number", get(api::compose::get_stack_revision))
+        .route("/compose/:id/revisions/:number/rollback", post(api::compose::rollback_compose_stack))
+        .route("/compose/:id/diff", get(api::compose::diff_stack_revisions))
+        .route("/compose/:id/git", put(api::compose::update_compose_stack_source))
+        .route("/compose/:id/sync", post(api::compose::sync_compose_stack))
+        .route("/compose/:id/webhook", post(api::compose::compose_stack_webhook))
+        .route("/compose/:id/updates", put(api::compose::set_compose_stack_update_policy))
+        // Template routes
+        .route("/templates", get(api::templates::list_templates))
+        .route("/templates", post(api::templates::create_template))
+        .route("/templates/deploy", post(api::templates::deploy_template))
+        .route("/templates/:id", get(api::templates::get_template))
+        .route("/templates/:id", put(api::templates::update_template))
+        .route("/templates/:id", delete(api::templates::delete_template))
+        // Image update routes
+        .route("/updates", get(api::updates::list_updates))
+        .route("/updates/check", post(api::updates::check_updates))
+        .route("/updates/:container/rollback", post(api::updates::rollback_update))
+        // Secret routes
+        .route("/secrets", get(api::secrets::list_secrets))
+        .route("/secrets", post(api::secrets::create_secret))
+        .route("/secrets/:name", get(api::secrets::get_secret))
+        .route("/secrets/:name", put(api::secrets::update_secret))
+        .route("/secrets/:name", delete(api::secrets::delete_secret))
+        .route("/registries", get(api::registries::list_registries))
+        .route("/registries", post(api::registries::create_registry))
+        .route("/registries/:id", get(api::registries::get_registry))
+        .route("/registries/:id", put(api::registries::update_registry))
+        .route("/registries/:id", delete(api::registries::delete_registry))
+        .route("/registries/:id/repositories", get(api::registries::list_repositories))
+        .route("/registries/:id/tags/*repository", get(api::registries::list_tags))
+        // Metrics routes
+        .route("/metrics", get(api::metrics::route_metrics))
+        // Application routes
+        .route("/applications", get(api::applications::list_applications))
+        .route("/applications", post(api::applications::create_application))
+        .route("/applications/:id", get(api::applications::get_application))
+        .route("/applications/:id", put(api::applications::update_application))
+        .route("/applications/:id", delete(api::applications::delete_application))
+        .route("/applications/:id/enable", post(api::applications::enable_application))
+        .route("/applications/:id/disable", post(api::applications::disable_application));
 
     // Create basic routes
     let app = Router::new()
@@ -96,13 +243,79 @@ async fn main() {
         .route("/containers/create", get(container_create_handler))
         .route("/images", get(images_handler))
         .route("/images/create", get(image_create_handler))
+        .route("/images/build", get(image_build_handler))
         .route("/settings", get(settings_handler))
         .route("/health", get(health_handler))
+        .route("/metrics", get(api::metrics::prometheus_metrics))
         // Nest API routes
         .nest("/api", api_routes)
         // Serve static files
         .nest_service("/static", ServeDir::new("src/static"))
-        .with_state(app_state);
+        .with_state(app_state.clone());
+
+    // Create the domain proxy, which routes every request by its Host header
+    let proxy_app = Router::new()
+        .fallback(proxy::handle_proxy_request)
+        .with_state(app_state.clone());
+
+    // Plain HTTP also answers ACME challenges and redirects SSL domains
+    let http_proxy_app = proxy_app.clone().layer(axum::middleware::from_fn_with_state(
+        app_state.clone(),
+        proxy::tls::redirect_to_https,
+    ));
+
+    // Start the proxy server
+    let proxy_addr = format!("{}:{}", config.proxy.host, config.proxy.port);
+    let proxy_listener = match tokio::net::TcpListener::bind(&proxy_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to bind proxy listener on {}: {}", proxy_addr, e);
+            std::process::exit(1);
+        }
+    };
+    tracing::info!("Proxy listening on {}", proxy_addr);
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(
+            proxy_listener,
+            http_proxy_app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await {
+            tracing::error!("Proxy server error: {}", e);
+        }
+    });
+
+    // Start the HTTPS proxy server, picking certificates by SNI
+    let tls_config = match proxy::tls::server_config(certs) {
+        Ok(tls_config) => tls_config,
+        Err(e) => {
+            tracing::error!("Failed to configure TLS: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let https_addr = format!("{}:{}", config.proxy.host, config.proxy.https_port);
+    let https_listener = match tokio::net::TcpListener::bind(&https_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to bind HTTPS proxy listener on {}: {}", https_addr, e);
+            std::process::exit(1);
+        }
+    };
+    tracing::info!("HTTPS proxy listening on {}", https_addr);
+    tokio::spawn(proxy::tls::serve_https(https_listener, tls_config, proxy_app));
+
+    // Keep load balancer backends in sync with running containers
+    proxy::balancer::spawn_watcher(app_state.clone());
+    proxy::health::spawn_checker(app_state.clone());
+
+    // Redeploy stacks from git when their branches move
+    docker::compose::git::spawn_poller(app_state.clone());
+
+    // Look for newer images of running containers' tags
+    docker::updates::spawn_checker(app_state.clone());
+
+    // Issue and renew certificates in the background
+    proxy::acme::spawn_renewal(app_state, config.acme.clone());
 
     // Start the server
     let addr = SocketAddr::from(([127, 0, 0, 1], config.server.port));
@@ -159,6 +372,10 @@ async fn image_create_handler() -> impl IntoResponse {
     Html(include_str!("static/image_create.html"))
 }
 
+async fn image_build_handler() -> impl IntoResponse {
+    Html(include_str!("static/image_build.html"))
+}
+
 async fn settings_handler() -> impl IntoResponse {
     Html(include_str!("static/settings.html"))
 }
\ No newline at end of file
@@ -1,16 +1,28 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use sqlx::FromRow;
 use uuid::Uuid;
 
+use super::service::{HealthCheck, LoadBalancing};
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Application {
     pub id: String,
     pub name: String,
     pub domain: String,
     pub container_id: Option<String>,
+    pub target_label: Option<String>, // route to every container with this label (key or key=value)
     pub container_port: i64,
+    pub path_prefix: String, // only requests under this path are routed here
+    pub strip_prefix: bool,
+    pub rewrite_prefix: Option<String>, // replaces path_prefix before forwarding
+    pub priority: i32, // higher wins when several routes match
+    pub lb_strategy: LoadBalancing,
+    pub health_check: Option<Json<HealthCheck>>,
     pub enabled: bool,
+    pub ssl_enabled: bool,
+    pub ssl_auto_generate: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -23,8 +35,17 @@ impl Application {
             name,
             domain,
             container_id,
+            target_label: None,
             container_port,
+            path_prefix: "/".to_string(),
+            strip_prefix: false,
+            rewrite_prefix: None,
+            priority: 0,
+            lb_strategy: LoadBalancing::default(),
+            health_check: None,
             enabled: true,
+            ssl_enabled: false,
+            ssl_auto_generate: false,
             created_at: now,
             updated_at: now,
         }
@@ -48,4 +69,39 @@ impl Application {
         self.container_id = container_id;
         self.updated_at = Utc::now();
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApplicationRequest {
+    pub name: String,
+    pub domain: String,
+    pub container_id: Option<String>,
+    pub target_label: Option<String>,
+    pub container_port: u16,
+    pub path_prefix: Option<String>,
+    pub strip_prefix: Option<bool>,
+    pub rewrite_prefix: Option<String>,
+    pub priority: Option<i32>,
+    pub lb_strategy: Option<LoadBalancing>,
+    pub health_check: Option<HealthCheck>,
+    pub ssl_enabled: Option<bool>,
+    pub ssl_auto_generate: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateApplicationRequest {
+    pub name: Option<String>,
+    pub domain: Option<String>,
+    pub container_id: Option<String>,
+    pub target_label: Option<String>,
+    pub container_port: Option<u16>,
+    pub path_prefix: Option<String>,
+    pub strip_prefix: Option<bool>,
+    pub rewrite_prefix: Option<String>,
+    pub priority: Option<i32>,
+    pub lb_strategy: Option<LoadBalancing>,
+    pub health_check: Option<HealthCheck>,
+    pub enabled: Option<bool>,
+    pub ssl_enabled: Option<bool>,
+    pub ssl_auto_generate: Option<bool>,
+}
@@ -4,6 +4,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use super::update::UpdatePolicy;
+
 /// Represents a Docker Compose stack.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ComposeStack {
@@ -11,6 +13,8 @@ pub struct ComposeStack {
     pub id: String,
     /// Name of the stack.
     pub name: String,
+    /// Compose project name that the stack's resources are labelled with.
+    pub project: String,
     /// Path to the compose file.
     pub file_path: String,
     /// Status of the stack (up, down, partial).
@@ -23,8 +27,24 @@ pub struct ComposeStack {
     pub services: Vec<ComposeService>,
     /// Environment variables for the stack.
     pub environment: Option<HashMap<String, String>>,
+    /// Env files stored next to the compose file.
+    pub env_files: Vec<String>,
     /// Version of the compose file.
     pub version: Option<String>,
+    /// Repository the compose file is deployed from, if any.
+    pub git: Option<GitSource>,
+    /// Whether the project was started outside Rustainer, for example with
+    /// `docker compose up`. External stacks are read-only until adopted, and
+    /// their ID is the project name.
+    #[serde(default)]
+    pub external: bool,
+    /// What happens when a service's image has an update, unless the
+    /// container's `rustainer.update` label says otherwise.
+    #[serde(default)]
+    pub update_policy: UpdatePolicy,
+    /// Whether the registry has a newer image for any running service.
+    #[serde(default)]
+    pub update_available: bool,
 }
 
 /// Status of a Docker Compose stack.
@@ -62,6 +82,10 @@ pub struct ComposeService {
     pub status: String,
     /// Container ID if the service is running.
     pub container_id: Option<String>,
+    /// Number of containers the compose file asks for.
+    pub replicas: u32,
+    /// Number of containers that are running.
+    pub running: u32,
     /// Ports exposed by the service.
     pub ports: Option<Vec<String>>,
     /// Volumes used by the service.
@@ -72,6 +96,9 @@ pub struct ComposeService {
     pub environment: Option<HashMap<String, String>>,
     /// Dependencies of the service.
     pub depends_on: Option<Vec<String>>,
+    /// Whether the registry has a newer image for the service's containers.
+    #[serde(default)]
+    pub update_available: bool,
 }
 
 /// Request to create a new Docker Compose stack.
@@ -82,9 +109,28 @@ pub struct CreateStackRequest {
     /// Content of the compose file.
     pub compose_content: String,
     /// Whether to start the stack after creation.
+    #[serde(default)]
     pub start: bool,
     /// Environment variables for the stack.
     pub environment: Option<HashMap<String, String>>,
+    /// Env files to store next to the compose file, keyed by relative path.
+    /// `.env` provides variables for interpolation.
+    pub env_files: Option<HashMap<String, String>>,
+    /// Description of the change, kept in the stack's revision history.
+    pub message: Option<String>,
+    /// What happens when a service's image has an update.
+    #[serde(default)]
+    pub update_policy: UpdatePolicy,
+}
+
+/// Request to adopt a project started outside Rustainer as a stack.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AdoptStackRequest {
+    /// Name of the stack, which must give the same project name. Defaults to
+    /// the project name.
+    pub name: Option<String>,
+    /// Description kept in the stack's revision history.
+    pub message: Option<String>,
 }
 
 /// Request to update an existing Docker Compose stack.
@@ -92,19 +138,16 @@ pub struct CreateStackRequest {
 pub struct UpdateStackRequest {
     /// New content of the compose file.
     pub compose_content: String,
-    /// Whether to restart the stack after update.
+    /// Whether to apply the new file to running containers after the update.
+    #[serde(default)]
     pub restart: bool,
     /// Environment variables for the stack.
     pub environment: Option<HashMap<String, String>>,
-}
-
-/// Response for stack operations.
-#[derive(Debug, Serialize)]
-pub struct StackResponse {
-    /// The stack that was operated on.
-    pub stack: ComposeStack,
-    /// Any messages related to the operation.
-    pub messages: Vec<String>,
+    /// Env files to store next to the compose file, keyed by relative path.
+    /// `.env` provides variables for interpolation.
+    pub env_files: Option<HashMap<String, String>>,
+    /// Description of the change, kept in the stack's revision history.
+    pub message: Option<String>,
 }
 
 /// Request to scale services in a stack.
@@ -112,4 +155,219 @@ pub struct StackResponse {
 pub struct ScaleStackRequest {
     /// Map of service names to desired replica counts.
     pub services: HashMap<String, u32>,
-}
\ No newline at end of file
+}
+
+/// Request for a dry run of an update. Fields that are left out are taken
+/// from the stored stack.
+#[derive(Debug, Default, Deserialize)]
+pub struct PlanStackRequest {
+    /// New content of the compose file.
+    pub compose_content: Option<String>,
+    /// Environment variables for the stack.
+    pub environment: Option<HashMap<String, String>>,
+    /// Env files to store next to the compose file, keyed by relative path.
+    pub env_files: Option<HashMap<String, String>>,
+}
+
+/// What `up` would change in a stack.
+#[derive(Debug, Clone, Serialize)]
+pub struct StackPlan {
+    /// Changes to each service, including services that would be removed.
+    pub services: Vec<ServicePlan>,
+    /// Changes to the stack's networks.
+    pub networks: Vec<ResourcePlan>,
+    /// Changes to the stack's named volumes.
+    pub volumes: Vec<ResourcePlan>,
+}
+
+/// What `up` would do to a service's containers.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServicePlan {
+    /// Name of the service.
+    pub name: String,
+    /// The change that would be made.
+    pub action: PlanAction,
+    /// Why, such as the settings that changed.
+    pub reasons: Vec<String>,
+    /// Number of containers the compose file asks for.
+    pub replicas: u32,
+    /// Number of containers that exist now.
+    pub containers: u32,
+}
+
+/// Change to a service's containers.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum PlanAction {
+    /// Containers would be created for a service that has none.
+    Create,
+    /// Containers would be replaced because their configuration changed.
+    Recreate,
+    /// Containers would be added or removed to match the replica count.
+    Scale,
+    /// Stopped containers would be started.
+    Start,
+    /// Containers would be removed because their service is gone.
+    Remove,
+    /// Nothing would change.
+    Unchanged,
+}
+
+/// What `up` would do to a network or volume.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourcePlan {
+    /// Docker name of the network or volume.
+    pub name: String,
+    /// The change that would be made.
+    pub action: ResourceAction,
+    /// Why, such as the settings that differ.
+    pub reasons: Vec<String>,
+}
+
+/// Change to a network or volume.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum ResourceAction {
+    /// It would be created.
+    Create,
+    /// It exists as the compose file describes.
+    Unchanged,
+    /// It exists with other settings. `up` keeps it as it is, so it has to
+    /// be removed with `down` for the new settings to apply.
+    Outdated,
+    /// It belongs to the stack but the compose file no longer uses it. `up`
+    /// keeps it.
+    Unused,
+    /// It is external and does not exist, so `up` would fail.
+    Missing,
+}
+
+/// Repository that a git-backed stack is deployed from. Credentials are
+/// stored but never returned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitSource {
+    /// URL of the repository.
+    pub repository: String,
+    /// Branch that is deployed.
+    pub branch: String,
+    /// Path of the compose file inside the repository.
+    pub path: String,
+    /// Seconds between checks for new commits, or 0 to only deploy on
+    /// webhooks and manual syncs.
+    pub poll_interval: u64,
+    /// Whether a username and password or an SSH key are stored.
+    pub credentials: bool,
+    /// Whether webhooks are accepted, which needs a secret to sign them.
+    pub webhook: bool,
+    /// Commit that was last deployed.
+    pub commit: Option<String>,
+    /// When the repository was last checked.
+    pub checked_at: Option<DateTime<Utc>>,
+    /// Why the last check failed, if it did.
+    pub error: Option<String>,
+}
+
+/// Request to set the repository of a git-backed stack. When updating,
+/// credentials and the webhook secret that are left out are kept, and empty
+/// ones are removed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitSourceRequest {
+    /// URL of the repository, such as `https://`, `ssh://` or `file://`.
+    pub repository: String,
+    /// Branch to deploy.
+    #[serde(default = "default_branch")]
+    pub branch: String,
+    /// Path of the compose file inside the repository.
+    #[serde(default = "default_compose_path")]
+    pub path: String,
+    /// Username for HTTPS repositories.
+    pub username: Option<String>,
+    /// Password or access token for HTTPS repositories.
+    pub password: Option<String>,
+    /// Private key for SSH repositories.
+    pub ssh_key: Option<String>,
+    /// Seconds between checks for new commits, or 0 to only deploy on
+    /// webhooks and manual syncs.
+    #[serde(default)]
+    pub poll_interval: u64,
+    /// Secret that webhooks are signed with.
+    pub webhook_secret: Option<String>,
+}
+
+fn default_branch() -> String {
+    "main".to_string()
+}
+
+fn default_compose_path() -> String {
+    "compose.yml".to_string()
+}
+
+/// Request to create a stack deployed from a git repository.
+#[derive(Debug, Deserialize)]
+pub struct CreateGitStackRequest {
+    /// Name of the stack.
+    pub name: String,
+    /// Repository to deploy from.
+    pub source: GitSourceRequest,
+    /// Environment variables for the stack.
+    pub environment: Option<HashMap<String, String>>,
+    /// Whether to start the stack after creation.
+    #[serde(default)]
+    pub start: bool,
+    /// What happens when a service's image has an update.
+    #[serde(default)]
+    pub update_policy: UpdatePolicy,
+}
+
+/// Outcome of checking a git-backed stack for new commits.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncResult {
+    /// Commit at the head of the branch.
+    pub commit: String,
+    /// Services whose configuration changed with the commit.
+    pub changed_services: Vec<String>,
+    /// Whether the commit was deployed.
+    pub deployed: bool,
+}
+/// A saved version of a stack's compose file, environment and env files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StackRevision {
+    /// Revision number, counting up from 1 for each stack.
+    pub number: i64,
+    /// Username of whoever saved the revision.
+    pub author: Option<String>,
+    /// Description of the change.
+    pub message: Option<String>,
+    /// When the revision was saved.
+    pub created_at: DateTime<Utc>,
+    /// Content of the compose file.
+    pub compose_content: String,
+    /// Environment variables for the stack.
+    pub environment: HashMap<String, String>,
+    /// Env files stored next to the compose file, keyed by relative path.
+    pub env_files: HashMap<String, String>,
+}
+
+/// Differences between two revisions of a stack.
+#[derive(Debug, Clone, Serialize)]
+pub struct RevisionDiff {
+    /// Revision compared from.
+    pub from: i64,
+    /// Revision compared to.
+    pub to: i64,
+    /// Unified diff of the compose file, empty when it is unchanged.
+    pub compose: String,
+    /// Environment variables that were added, removed or changed.
+    pub environment: Vec<EnvChange>,
+    /// Unified diffs of env files that changed, keyed by path.
+    pub env_files: HashMap<String, String>,
+}
+
+/// A changed environment variable. `None` means the variable is not set.
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvChange {
+    /// Name of the variable.
+    pub key: String,
+    /// Value in the older revision.
+    pub from: Option<String>,
+    /// Value in the newer revision.
+    pub to: Option<String>,
+}
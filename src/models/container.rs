@@ -1,80 +1,229 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+//! Creating standalone containers with the options of `docker run`.
+
+use serde::Deserialize;
 use std::collections::HashMap;
 
-/// Represents a Docker container
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Container {
-    /// Container ID
-    pub id: String,
-    /// Container names (including leading slash)
-    pub names: Vec<String>,
-    /// Container image
+/// Request to create a container. Everything but the image is optional and
+/// defaults to what the daemon or the image sets.
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateContainerRequest {
+    /// Image to run, pulled first if it is not in the local store.
     pub image: String,
-    /// Container status (e.g., "running", "exited")
-    pub status: String,
-    /// Container state (e.g., "running", "exited")
-    pub state: String,
-    /// Container creation time
-    pub created: DateTime<Utc>,
-    /// Container ports
-    pub ports: Vec<PortMapping>,
+    /// Container name. The daemon picks one when it is left out.
+    pub name: Option<String>,
+    /// Arguments replacing the image's `CMD`.
+    pub command: Option<Vec<String>>,
+    /// Executable and arguments replacing the image's `ENTRYPOINT`. An empty
+    /// list clears it.
+    pub entrypoint: Option<Vec<String>>,
+    /// Absolute path the command runs in.
+    pub working_dir: Option<String>,
+    /// User and optional group to run as, by name or ID, such as `1000:1000`.
+    pub user: Option<String>,
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub ports: Vec<PortRequest>,
+    #[serde(default)]
+    pub volumes: Vec<VolumeRequest>,
+    #[serde(default)]
+    pub restart: RestartRequest,
+    #[serde(default)]
+    pub resources: ResourcesRequest,
+    /// Capabilities to add, such as `NET_ADMIN`, or `ALL`.
+    #[serde(default)]
+    pub cap_add: Vec<String>,
+    /// Capabilities to drop.
+    #[serde(default)]
+    pub cap_drop: Vec<String>,
+    #[serde(default)]
+    pub privileged: bool,
+    /// Mount the root filesystem read-only.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Run an init process that forwards signals and reaps zombies.
+    pub init: Option<bool>,
+    #[serde(default)]
+    pub devices: Vec<DeviceRequest>,
+    /// `bridge`, `host`, `none` or `container:<name>`. Ignored when
+    /// `networks` are given.
+    pub network_mode: Option<String>,
+    /// Networks to join. The container joins the default bridge network
+    /// when there are none.
+    #[serde(default)]
+    pub networks: Vec<NetworkRequest>,
+    pub healthcheck: Option<HealthcheckRequest>,
+    pub log: Option<LogRequest>,
+    /// tmpfs mounts, by path, with their mount options such as `size=64m`.
+    #[serde(default)]
+    pub tmpfs: HashMap<String, String>,
+    #[serde(default)]
+    pub ulimits: Vec<UlimitRequest>,
+    /// Kernel parameters, such as `net.core.somaxconn`.
+    #[serde(default)]
+    pub sysctls: HashMap<String, String>,
+    #[serde(default)]
+    pub dns: DnsRequest,
+    /// Extra `/etc/hosts` entries, from host name to IP address or
+    /// `host-gateway`.
+    #[serde(default)]
+    pub extra_hosts: HashMap<String, String>,
+    /// Start the container once it is created.
+    #[serde(default = "default_true")]
+    pub start: bool,
+}
+
+fn default_true() -> bool {
+    true
 }
 
-/// Represents a port mapping for a container
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct PortMapping {
-    /// Container port
+/// A container port published on the host.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PortRequest {
     pub container_port: u16,
-    /// Host port
+    /// Host port. The daemon picks a free one when it is left out.
     pub host_port: Option<u16>,
-    /// Protocol (e.g., "tcp", "udp")
+    /// Host address to listen on. Defaults to every address.
+    pub host_ip: Option<String>,
+    /// `tcp`, `udp` or `sctp`.
+    #[serde(default = "default_protocol")]
     pub protocol: String,
 }
 
-/// Request to create a new container
-#[derive(Debug, Deserialize)]
-pub struct CreateContainerRequest {
-    /// Image name
-    pub image: String,
-    /// Container name
+fn default_protocol() -> String {
+    "tcp".to_string()
+}
+
+/// A volume, bind mount or tmpfs mounted in the container.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VolumeRequest {
+    #[serde(rename = "type", default)]
+    pub kind: VolumeKind,
+    /// Volume name or absolute host path. Left out for anonymous volumes
+    /// and tmpfs.
+    pub source: Option<String>,
+    /// Absolute path in the container.
+    pub target: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeKind {
+    #[default]
+    Volume,
+    Bind,
+    Tmpfs,
+}
+
+/// When the daemon restarts the container.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RestartRequest {
+    #[serde(default)]
+    pub policy: RestartPolicy,
+    /// Restarts to try before giving up, only for `on-failure`.
+    pub max_retries: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    No,
+    Always,
+    UnlessStopped,
+    OnFailure,
+}
+
+/// CPU, memory and process limits. Sizes are strings such as `512m`, or a
+/// number of bytes.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ResourcesRequest {
+    /// Number of CPUs the container may use, such as `1.5`.
+    pub cpus: Option<f64>,
+    /// Relative CPU weight against other containers. The daemon's default
+    /// is 1024.
+    pub cpu_shares: Option<i64>,
+    /// CPUs the container may run on, such as `0-3` or `0,2`.
+    pub cpuset_cpus: Option<String>,
+    pub memory: Option<String>,
+    /// Soft limit the container is held to when the host runs low.
+    pub memory_reservation: Option<String>,
+    /// Memory plus swap, or `-1` for unlimited swap. Needs `memory`.
+    pub memory_swap: Option<String>,
+    pub pids_limit: Option<i64>,
+}
+
+/// A host device made available in the container.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceRequest {
+    pub path_on_host: String,
+    /// Defaults to the host path.
+    pub path_in_container: Option<String>,
+    /// Any of `r`, `w` and `m`. Defaults to `rwm`.
+    pub permissions: Option<String>,
+}
+
+/// A network the container joins.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NetworkRequest {
     pub name: String,
-    /// Port mappings (host_port:container_port)
-    pub ports: Option<Vec<String>>,
-    /// Environment variables
-    pub env: Option<Vec<String>>,
-    /// Volume mappings (host_path:container_path)
-    pub volumes: Option<Vec<String>>,
+    /// Extra names other containers on the network can reach it by.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Static address, from the network's subnet.
+    pub ipv4_address: Option<String>,
+    pub ipv6_address: Option<String>,
 }
 
-/// Container resource statistics
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ContainerStats {
-    /// Container ID
-    pub id: String,
-    /// CPU usage percentage
-    pub cpu_usage_percent: f64,
-    /// Memory usage in bytes
-    pub memory_usage_bytes: u64,
-    /// Memory usage percentage
-    pub memory_usage_percent: f64,
-    /// Network input in bytes
-    pub network_input_bytes: u64,
-    /// Network output in bytes
-    pub network_output_bytes: u64,
-    /// Block input in bytes
-    pub block_input_bytes: u64,
-    /// Block output in bytes
-    pub block_output_bytes: u64,
-    /// Process count
-    pub process_count: u64,
+/// How the daemon checks that the container is healthy. Durations are
+/// strings such as `30s` or `1m30s`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HealthcheckRequest {
+    /// `["CMD", executable, args...]` or `["CMD-SHELL", command]`. Left out
+    /// to keep the image's test.
+    #[serde(default)]
+    pub test: Vec<String>,
+    pub interval: Option<String>,
+    pub timeout: Option<String>,
+    pub retries: Option<i64>,
+    pub start_period: Option<String>,
+    /// Turn off the image's healthcheck.
+    #[serde(default)]
+    pub disable: bool,
 }
 
-/// Container logs
-#[derive(Debug, Serialize)]
-pub struct ContainerLogs {
-    /// Container ID
-    pub id: String,
-    /// Log lines
-    pub logs: Vec<String>,
-}
\ No newline at end of file
+/// Where the container's output is logged.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogRequest {
+    /// Log driver, such as `json-file`, `local` or `syslog`.
+    pub driver: String,
+    #[serde(default)]
+    pub options: HashMap<String, String>,
+}
+
+/// A resource limit, such as `nofile`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UlimitRequest {
+    pub name: String,
+    pub soft: i64,
+    pub hard: i64,
+}
+
+/// Name resolution in the container.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DnsRequest {
+    /// Name server addresses.
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// Search domains.
+    #[serde(default)]
+    pub search: Vec<String>,
+    /// Resolver options, such as `ndots:2`.
+    #[serde(default)]
+    pub options: Vec<String>,
+}
@@ -0,0 +1,283 @@
+//! Docker images, and pulling, building and pruning them.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// An image in the local store.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageInfo {
+    /// Image ID, as `sha256:...`.
+    pub id: String,
+    /// Tags such as `nginx:1.27`. Empty for untagged images.
+    pub repo_tags: Vec<String>,
+    /// Digests of the image in the registries it was pulled from.
+    pub repo_digests: Vec<String>,
+    /// Size on disk in bytes, including layers shared with other images.
+    pub size: u64,
+    /// When the image was built, as a UNIX timestamp.
+    pub created: i64,
+    /// Whether the image has no tags left, usually because a newer image
+    /// took its tag.
+    pub dangling: bool,
+    /// Names of the containers created from the image, running or not.
+    pub containers: Vec<String>,
+}
+
+/// An image with its configuration and layers.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageDetails {
+    #[serde(flatten)]
+    pub info: ImageInfo,
+    pub architecture: Option<String>,
+    pub os: Option<String>,
+    pub author: Option<String>,
+    /// Default command run by containers of the image.
+    pub cmd: Vec<String>,
+    pub entrypoint: Vec<String>,
+    /// Default environment, as `KEY=value`.
+    pub env: Vec<String>,
+    /// Ports the image exposes, such as `80/tcp`.
+    pub exposed_ports: Vec<String>,
+    /// Paths the image declares as volumes.
+    pub volumes: Vec<String>,
+    pub working_dir: Option<String>,
+    pub user: Option<String>,
+    pub labels: HashMap<String, String>,
+    /// Layers, newest first, as `docker history` lists them.
+    pub history: Vec<ImageLayer>,
+}
+
+/// A layer of an image and the instruction that created it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageLayer {
+    pub created: i64,
+    pub created_by: String,
+    pub size: u64,
+    pub comment: String,
+}
+
+/// Request to pull an image.
+#[derive(Debug, Deserialize)]
+pub struct PullImageRequest {
+    /// Image reference, such as `nginx:1.27` or `ghcr.io/org/app@sha256:...`.
+    /// Without a tag or digest, `latest` is pulled.
+    #[serde(alias = "imageTag")]
+    pub image_tag: String,
+    pub auth: Option<ImageAuth>,
+}
+
+/// Registry credentials for one pull, used instead of the stored ones. They
+/// are passed to the Docker daemon and not stored.
+#[derive(Deserialize)]
+pub struct ImageAuth {
+    pub username: String,
+    pub password: String,
+    /// Registry to log in to. Defaults to the registry of the image.
+    pub server_address: Option<String>,
+}
+
+impl std::fmt::Debug for ImageAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImageAuth")
+            .field("username", &self.username)
+            .field("server_address", &self.server_address)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Progress of one step of a pull, usually the download or extraction of a
+/// layer.
+#[derive(Debug, Clone, Serialize)]
+pub struct PullProgress {
+    /// Layer ID, or `None` for messages about the whole image.
+    pub layer: Option<String>,
+    /// Status such as `Downloading`, `Extracting` or `Pull complete`.
+    pub status: String,
+    /// Bytes done so far, while downloading or extracting.
+    pub current: Option<u64>,
+    /// Bytes in total, while downloading or extracting.
+    pub total: Option<u64>,
+}
+
+/// A message sent while pulling over a WebSocket.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PullEvent {
+    Progress(PullProgress),
+    /// The pull finished, with the image now in the store.
+    Done { image: ImageInfo },
+    /// The pull failed.
+    Error { message: String },
+}
+
+/// Query parameters for removing an image.
+#[derive(Debug, Default, Deserialize)]
+pub struct RemoveImageQuery {
+    /// Remove the image even if containers use it or it has several tags.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Tags and images that a removal or prune took away.
+#[derive(Debug, Default, Serialize)]
+pub struct RemovedImages {
+    pub untagged: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+/// Query parameters for pruning images.
+#[derive(Debug, Default, Deserialize)]
+pub struct PruneImagesQuery {
+    /// Remove every image no container uses, not only dangling ones.
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// Result of pruning images.
+#[derive(Debug, Serialize)]
+pub struct PruneResult {
+    #[serde(flatten)]
+    pub removed: RemovedImages,
+    /// Bytes freed on disk.
+    pub space_reclaimed: u64,
+}
+
+/// Request to build an image.
+#[derive(Debug, Default, Deserialize)]
+pub struct BuildImageRequest {
+    /// Tags to give the image, such as `app:1.2`. Without a tag, the image
+    /// is only known by its ID.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub source: BuildSource,
+    /// Path of the Dockerfile in the context. Defaults to `Dockerfile`.
+    pub dockerfile: Option<String>,
+    /// Values for `ARG` instructions.
+    #[serde(default)]
+    pub build_args: HashMap<String, String>,
+    /// Stage of a multi-stage Dockerfile to build.
+    pub target: Option<String>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub builder: Builder,
+    /// Run every step again instead of using the build cache.
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Pull base images even if they are in the local store.
+    #[serde(default)]
+    pub pull: bool,
+    /// Platform to build for, such as `linux/arm64`.
+    pub platform: Option<String>,
+}
+
+/// Where the build context comes from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BuildSource {
+    /// A tar archive sent with the request, optionally compressed.
+    #[default]
+    Upload,
+    /// A Dockerfile sent inline, built with an otherwise empty context.
+    Dockerfile { content: String },
+    /// A directory on the Rustainer host. `.dockerignore` is honoured.
+    Directory { path: String },
+    /// A git repository the daemon clones, at a branch, tag or commit, and
+    /// optionally from a subdirectory.
+    Git {
+        url: String,
+        #[serde(rename = "ref")]
+        reference: Option<String>,
+        subdirectory: Option<String>,
+    },
+}
+
+/// Builder the daemon runs the Dockerfile with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Builder {
+    /// The daemon's original builder.
+    #[default]
+    Classic,
+    /// BuildKit, which runs independent stages in parallel.
+    Buildkit,
+}
+
+impl Builder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Builder::Classic => "classic",
+            Builder::Buildkit => "buildkit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl BuildStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildStatus::Running => "running",
+            BuildStatus::Succeeded => "succeeded",
+            BuildStatus::Failed => "failed",
+        }
+    }
+}
+
+/// A build in the build history.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageBuild {
+    pub id: String,
+    pub tags: Vec<String>,
+    /// Where the context came from. Credentials in git URLs are left out.
+    pub source: BuildSource,
+    pub dockerfile: Option<String>,
+    pub target: Option<String>,
+    /// Build arguments, with the values of stored secrets masked.
+    pub build_args: HashMap<String, String>,
+    pub labels: HashMap<String, String>,
+    pub builder: Builder,
+    pub status: BuildStatus,
+    /// ID of the built image, once the build succeeded.
+    pub image_id: Option<String>,
+    pub error: Option<String>,
+    pub author: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Build output. Left out of lists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<String>,
+}
+
+/// Query parameters for listing builds.
+#[derive(Debug, Default, Deserialize)]
+pub struct BuildHistoryQuery {
+    /// Most builds to return, newest first. Defaults to 50.
+    pub limit: Option<u32>,
+}
+
+/// A message sent while building over a WebSocket.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum BuildEvent {
+    /// The build was recorded and started.
+    Started { build: Box<ImageBuild> },
+    /// A line of build output.
+    Output { text: String },
+    /// The build succeeded.
+    Done { build: Box<ImageBuild> },
+    /// The build failed, or could not be started, in which case there is no
+    /// build.
+    Error {
+        message: String,
+        build: Option<Box<ImageBuild>>,
+    },
+}
@@ -0,0 +1,126 @@
+//! Log lines of containers and stacks.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Query parameters for log snapshots and streams.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogsQuery {
+    /// Lines to show from the end of each container's log, or `all`. Defaults to 100.
+    pub tail: Option<String>,
+    /// Only lines from this time on: a UNIX timestamp, an RFC 3339 time, or
+    /// a duration such as `10m` before now.
+    pub since: Option<String>,
+    /// Only lines before this time, in the same formats as `since`.
+    pub until: Option<String>,
+    /// Only lines matching this regular expression.
+    pub filter: Option<String>,
+    /// Comma-separated services to show, for stacks.
+    pub services: Option<String>,
+    /// Start text lines with their timestamp.
+    #[serde(default)]
+    pub timestamps: bool,
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+/// How streamed lines are sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One [`LogLine`] object per message.
+    #[default]
+    Json,
+    /// Colored `prefix | message` lines, as `docker compose logs` prints them.
+    Text,
+}
+
+/// The output stream a line was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// Color of a container's prefix, cycling like `docker compose` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogColor {
+    Cyan,
+    Yellow,
+    Green,
+    Magenta,
+    Blue,
+    BrightCyan,
+    BrightYellow,
+    BrightGreen,
+    BrightMagenta,
+    BrightBlue,
+}
+
+impl LogColor {
+    pub const PALETTE: [LogColor; 10] = [
+        LogColor::Cyan,
+        LogColor::Yellow,
+        LogColor::Green,
+        LogColor::Magenta,
+        LogColor::Blue,
+        LogColor::BrightCyan,
+        LogColor::BrightYellow,
+        LogColor::BrightGreen,
+        LogColor::BrightMagenta,
+        LogColor::BrightBlue,
+    ];
+
+    fn ansi_code(self) -> u8 {
+        match self {
+            LogColor::Cyan => 36,
+            LogColor::Yellow => 33,
+            LogColor::Green => 32,
+            LogColor::Magenta => 35,
+            LogColor::Blue => 34,
+            LogColor::BrightCyan => 96,
+            LogColor::BrightYellow => 93,
+            LogColor::BrightGreen => 92,
+            LogColor::BrightMagenta => 95,
+            LogColor::BrightBlue => 94,
+        }
+    }
+}
+
+/// One line of a container's log.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    /// Container name.
+    pub container: String,
+    /// Compose service, for stack containers.
+    pub service: Option<String>,
+    /// Short name shown before the message, such as `web-1`.
+    pub prefix: String,
+    pub color: LogColor,
+    pub stream: LogStream,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub message: String,
+    /// Width prefixes are padded to, so messages line up.
+    #[serde(skip)]
+    pub width: usize,
+}
+
+impl LogLine {
+    /// The line as `docker compose logs` prints it, with ANSI colors.
+    pub fn text(&self, timestamps: bool) -> String {
+        let timestamp = match (timestamps, self.timestamp) {
+            (true, Some(time)) => format!("{} ", time.to_rfc3339_opts(SecondsFormat::Nanos, true)),
+            _ => String::new(),
+        };
+        format!(
+            "\x1b[{}m{:width$} |\x1b[0m {}{}",
+            self.color.ansi_code(),
+            self.prefix,
+            timestamp,
+            self.message,
+            width = self.width
+        )
+    }
+}
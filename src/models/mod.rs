@@ -1,5 +1,14 @@
 pub mod user;
 pub mod application;
+pub mod compose;
+pub mod container;
+pub mod image;
+pub mod logs;
+pub mod registry;
+pub mod secret;
+pub mod service;
+pub mod template;
+pub mod update;
 
 pub use user::User;
 pub use application::Application;
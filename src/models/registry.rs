@@ -0,0 +1,98 @@
+//! Container registries with stored credentials.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Who a registry's credentials are used for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistryScope {
+    /// Everyone, and stack deployments.
+    #[default]
+    Shared,
+    /// The user named by `owner`.
+    User,
+    /// Users whose role is `owner`.
+    Role,
+}
+
+impl RegistryScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RegistryScope::Shared => "shared",
+            RegistryScope::User => "user",
+            RegistryScope::Role => "role",
+        }
+    }
+}
+
+/// A stored registry. Its password is never returned.
+#[derive(Debug, Clone, Serialize)]
+pub struct Registry {
+    pub id: String,
+    pub name: String,
+    /// Base URL of the registry, such as `https://ghcr.io`.
+    pub url: String,
+    /// Host that image references name the registry by, such as `ghcr.io`,
+    /// or `docker.io` for Docker Hub.
+    pub host: String,
+    pub username: Option<String>,
+    /// Whether a password or token is stored.
+    pub has_password: bool,
+    pub scope: RegistryScope,
+    /// Username or role the registry belongs to, for `user` and `role`
+    /// scopes.
+    pub owner: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request to store a registry. Not `Debug`, so the password cannot end up
+/// in a log by accident.
+#[derive(Clone, Deserialize)]
+pub struct CreateRegistryRequest {
+    pub name: String,
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub scope: RegistryScope,
+    /// Defaults to the logged-in user for the `user` scope.
+    pub owner: Option<String>,
+}
+
+/// Request to change a registry. Fields that are left out are kept, and an
+/// empty username or password removes it.
+#[derive(Clone, Default, Deserialize)]
+pub struct UpdateRegistryRequest {
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub scope: Option<RegistryScope>,
+    pub owner: Option<String>,
+}
+
+/// Query parameters for paging through a registry's repositories.
+#[derive(Debug, Default, Deserialize)]
+pub struct CatalogQuery {
+    /// Repositories per page. Defaults to 100.
+    pub n: Option<u32>,
+    /// Last repository of the previous page.
+    pub last: Option<String>,
+}
+
+/// A page of a registry's repositories.
+#[derive(Debug, Clone, Serialize)]
+pub struct RepositoryList {
+    pub repositories: Vec<String>,
+    /// Value of `last` for the next page, if there is one.
+    pub next: Option<String>,
+}
+
+/// Tags of a repository.
+#[derive(Debug, Clone, Serialize)]
+pub struct TagList {
+    pub name: String,
+    pub tags: Vec<String>,
+}
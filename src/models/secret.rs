@@ -0,0 +1,33 @@
+//! Secrets kept encrypted in the secrets store.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A stored secret. Its value is never returned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretInfo {
+    /// Name stacks refer to the secret by.
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Stacks whose compose file uses the secret.
+    pub used_by: Vec<String>,
+}
+
+/// Request to store a new secret. Not `Debug`, so the value cannot end up in
+/// a log by accident.
+#[derive(Clone, Deserialize)]
+pub struct CreateSecretRequest {
+    pub name: String,
+    pub value: String,
+    pub description: Option<String>,
+}
+
+/// Request to change a secret. Fields that are left out are kept, and an
+/// empty description removes it.
+#[derive(Clone, Deserialize)]
+pub struct UpdateSecretRequest {
+    pub value: Option<String>,
+    pub description: Option<String>,
+}
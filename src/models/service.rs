@@ -37,6 +37,115 @@ impl PartialEq<&str> for ServiceType {
     }
 }
 
+/// Strategy for spreading requests over the containers behind a route.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum LoadBalancing {
+    /// Take turns across all backends.
+    #[default]
+    RoundRobin,
+    /// Pick the backend with the fewest requests in flight.
+    LeastConnections,
+    /// Pin each client to one backend with a cookie.
+    StickyCookie,
+}
+
+impl fmt::Display for LoadBalancing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadBalancing::RoundRobin => write!(f, "round_robin"),
+            LoadBalancing::LeastConnections => write!(f, "least_connections"),
+            LoadBalancing::StickyCookie => write!(f, "sticky_cookie"),
+        }
+    }
+}
+
+/// Active HTTP health check for the containers behind a route.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthCheck {
+    /// Path requested on each container.
+    pub path: String,
+    /// Seconds between checks.
+    pub interval_secs: u64,
+    /// Seconds to wait for a response before counting a failure.
+    pub timeout_secs: u64,
+    /// Consecutive successes before an ejected container gets traffic again.
+    pub healthy_threshold: u32,
+    /// Consecutive failures before a container is ejected.
+    pub unhealthy_threshold: u32,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            path: "/".to_string(),
+            interval_secs: 10,
+            timeout_secs: 2,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+/// Headers to set and remove on requests or responses.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderRules {
+    /// Headers to add, replacing any existing value.
+    pub set: HashMap<String, String>,
+    /// Headers to remove.
+    pub remove: Vec<String>,
+}
+
+/// Authentication required before requests reach a service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RouteAuth {
+    /// HTTP basic auth. Passwords are stored as Argon2 hashes; plain
+    /// passwords are hashed when the service is saved.
+    Basic {
+        #[serde(default)]
+        realm: Option<String>,
+        users: HashMap<String, String>,
+    },
+    /// Require a valid Rustainer login token, from the `auth_token` cookie or
+    /// a bearer token.
+    Jwt {
+        /// Roles allowed through; empty allows any logged in user.
+        #[serde(default)]
+        roles: Vec<String>,
+        /// Where to send browsers without a token (`?rd=` gets the original URL).
+        #[serde(default)]
+        login_url: Option<String>,
+    },
+}
+
+/// Token bucket rate limit applied per client IP.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Tokens added per second.
+    pub requests_per_second: f64,
+    /// Bucket size, i.e. the largest burst allowed.
+    pub burst: u32,
+}
+
+/// Per-service middleware, run in order: IP filter, rate limit, auth,
+/// request headers, then response headers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Middleware {
+    /// Only clients in these CIDRs are let through (empty allows all).
+    pub allow: Vec<String>,
+    /// Clients in these CIDRs are refused.
+    pub deny: Vec<String>,
+    pub rate_limit: Option<RateLimit>,
+    pub auth: Option<RouteAuth>,
+    pub request_headers: HeaderRules,
+    pub response_headers: HeaderRules,
+}
+
 /// SSL/TLS configuration for a service.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SSLConfig {
@@ -61,16 +170,35 @@ pub struct Service {
     pub domain: String,
     /// Type of service.
     pub service_type: ServiceType,
-    /// Target for the service (container ID, path, or URL).
+    /// Target for the service (container ID, `label:key=value`, directory,
+    /// `volume:name/dir`, or URL).
     pub target: String,
     /// Port to expose the service on.
     pub port: u16,
+    /// Path prefix routed to this service (`/` for everything).
+    pub path_prefix: String,
+    /// Remove the path prefix before forwarding.
+    pub strip_prefix: bool,
+    /// Replace the path prefix with this before forwarding.
+    pub rewrite_prefix: Option<String>,
+    /// Higher priority routes are matched first.
+    pub priority: i32,
     /// SSL/TLS configuration.
     pub ssl: SSLConfig,
     /// Additional headers to add to requests.
     pub headers: HashMap<String, String>,
+    /// How requests are spread over the target containers.
+    pub load_balancing: LoadBalancing,
+    /// Active health check for the target containers.
+    pub health_check: Option<HealthCheck>,
+    /// Middleware run on requests to the service.
+    pub middleware: Middleware,
     /// Whether the service is enabled.
     pub enabled: bool,
+    /// Whether the service comes from container labels rather than the
+    /// database.
+    #[serde(default)]
+    pub discovered: bool,
     /// When the service was created.
     pub created_at: chrono::DateTime<chrono::Utc>,
     /// When the service was last updated.
@@ -94,6 +222,10 @@ impl Service {
             service_type,
             target,
             port,
+            path_prefix: "/".to_string(),
+            strip_prefix: false,
+            rewrite_prefix: None,
+            priority: 0,
             ssl: SSLConfig {
                 enabled: false,
                 cert_path: None,
@@ -101,16 +233,15 @@ impl Service {
                 auto_generate: false,
             },
             headers: HashMap::new(),
+            load_balancing: LoadBalancing::default(),
+            health_check: None,
+            middleware: Middleware::default(),
             enabled: true,
+            discovered: false,
             created_at: now,
             updated_at: now,
         }
     }
-
-    /// Check if the service matches a given domain.
-    pub fn matches_domain(&self, domain: &str) -> bool {
-        self.domain == domain
-    }
 }
 
 /// Service creation request.
@@ -126,10 +257,24 @@ pub struct CreateServiceRequest {
     pub target: String,
     /// Port to expose the service on.
     pub port: u16,
+    /// Path prefix routed to this service.
+    pub path_prefix: Option<String>,
+    /// Remove the path prefix before forwarding.
+    pub strip_prefix: Option<bool>,
+    /// Replace the path prefix with this before forwarding.
+    pub rewrite_prefix: Option<String>,
+    /// Higher priority routes are matched first.
+    pub priority: Option<i32>,
     /// SSL/TLS configuration.
     pub ssl: Option<SSLConfig>,
     /// Additional headers to add to requests.
     pub headers: Option<HashMap<String, String>>,
+    /// How requests are spread over the target containers.
+    pub load_balancing: Option<LoadBalancing>,
+    /// Active health check for the target containers.
+    pub health_check: Option<HealthCheck>,
+    /// Middleware run on requests to the service.
+    pub middleware: Option<Middleware>,
 }
 
 /// Service update request.
@@ -145,10 +290,24 @@ pub struct UpdateServiceRequest {
     pub target: Option<String>,
     /// Port to expose the service on.
     pub port: Option<u16>,
+    /// Path prefix routed to this service.
+    pub path_prefix: Option<String>,
+    /// Remove the path prefix before forwarding.
+    pub strip_prefix: Option<bool>,
+    /// Replace the path prefix with this before forwarding.
+    pub rewrite_prefix: Option<String>,
+    /// Higher priority routes are matched first.
+    pub priority: Option<i32>,
     /// SSL/TLS configuration.
     pub ssl: Option<SSLConfig>,
     /// Additional headers to add to requests.
     pub headers: Option<HashMap<String, String>>,
+    /// How requests are spread over the target containers.
+    pub load_balancing: Option<LoadBalancing>,
+    /// Active health check for the target containers.
+    pub health_check: Option<HealthCheck>,
+    /// Middleware run on requests to the service.
+    pub middleware: Option<Middleware>,
     /// Whether the service is enabled.
     pub enabled: Option<bool>,
 }
@@ -168,12 +327,28 @@ pub struct ServiceResponse {
     pub target: String,
     /// Port to expose the service on.
     pub port: u16,
+    /// Path prefix routed to this service (`/` for everything).
+    pub path_prefix: String,
+    /// Remove the path prefix before forwarding.
+    pub strip_prefix: bool,
+    /// Replace the path prefix with this before forwarding.
+    pub rewrite_prefix: Option<String>,
+    /// Higher priority routes are matched first.
+    pub priority: i32,
     /// SSL/TLS configuration.
     pub ssl: SSLConfig,
     /// Additional headers to add to requests.
     pub headers: HashMap<String, String>,
+    /// How requests are spread over the target containers.
+    pub load_balancing: LoadBalancing,
+    /// Active health check for the target containers.
+    pub health_check: Option<HealthCheck>,
+    /// Middleware run on requests to the service.
+    pub middleware: Middleware,
     /// Whether the service is enabled.
     pub enabled: bool,
+    /// Whether the service was discovered from container labels.
+    pub discovered: bool,
     /// When the service was created.
     pub created_at: chrono::DateTime<chrono::Utc>,
     /// When the service was last updated.
@@ -189,9 +364,17 @@ impl From<Service> for ServiceResponse {
             service_type: service.service_type,
             target: service.target,
             port: service.port,
+            path_prefix: service.path_prefix,
+            strip_prefix: service.strip_prefix,
+            rewrite_prefix: service.rewrite_prefix,
+            priority: service.priority,
             ssl: service.ssl,
             headers: service.headers,
+            load_balancing: service.load_balancing,
+            health_check: service.health_check,
+            middleware: service.middleware,
             enabled: service.enabled,
+            discovered: service.discovered,
             created_at: service.created_at,
             updated_at: service.updated_at,
         }
@@ -30,6 +30,11 @@ pub struct ContainerTemplate {
     /// Environment variables for the container
     pub env: HashMap<String, String>,
     
+    /// Environment variables set from the secrets store at deploy time, by
+    /// secret name
+    #[serde(default)]
+    pub secret_env: HashMap<String, String>,
+    
     /// Port mappings (host:container)
     pub ports: Vec<PortMapping>,
     
@@ -124,6 +129,10 @@ pub struct CreateTemplateRequest {
     /// Environment variables
     pub env: HashMap<String, String>,
     
+    /// Environment variables from the secrets store, by secret name
+    #[serde(default)]
+    pub secret_env: HashMap<String, String>,
+    
     /// Port mappings
     pub ports: Vec<PortMapping>,
     
@@ -167,6 +176,9 @@ pub struct UpdateTemplateRequest {
     /// Environment variables
     pub env: Option<HashMap<String, String>>,
     
+    /// Environment variables from the secrets store, by secret name
+    pub secret_env: Option<HashMap<String, String>>,
+    
     /// Port mappings
     pub ports: Option<Vec<PortMapping>>,
     
@@ -198,6 +210,9 @@ pub struct DeployTemplateRequest {
     /// Environment variable overrides
     pub env_override: Option<HashMap<String, String>>,
     
+    /// Overrides of the variables set from the secrets store, by secret name
+    pub secret_env_override: Option<HashMap<String, String>>,
+    
     /// Port mapping overrides
     pub port_override: Option<Vec<PortMapping>>,
     
@@ -0,0 +1,70 @@
+//! Image update checks and automatic updates of running containers.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// What happens when the registry has a newer image for a container's tag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdatePolicy {
+    /// The container is not checked.
+    Off,
+    /// The update is flagged and notified once per new image.
+    #[default]
+    Notify,
+    /// The new image is pulled and the container recreated with it.
+    Auto,
+}
+
+impl UpdatePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdatePolicy::Off => "off",
+            UpdatePolicy::Notify => "notify",
+            UpdatePolicy::Auto => "auto",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "false" | "none" => Some(UpdatePolicy::Off),
+            "notify" => Some(UpdatePolicy::Notify),
+            "auto" | "true" => Some(UpdatePolicy::Auto),
+            _ => None,
+        }
+    }
+}
+
+/// Result of the last update check of a container, kept by container name
+/// so that it survives the container being recreated.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageUpdate {
+    pub container: String,
+    pub container_id: String,
+    /// Image reference the container was created from, such as `nginx:1.27`.
+    pub image: String,
+    /// Compose project the container belongs to, if any.
+    pub project: Option<String>,
+    /// Policy in effect, from the container's label or its stack.
+    pub policy: UpdatePolicy,
+    /// Registry digest of the image the container runs, unknown for images
+    /// that were built locally or loaded without a registry.
+    pub current_digest: Option<String>,
+    /// Digest the tag points to in the registry.
+    pub latest_digest: Option<String>,
+    /// Whether the registry has a different image for the tag.
+    pub available: bool,
+    /// Why the last check failed, if it did.
+    pub error: Option<String>,
+    pub checked_at: DateTime<Utc>,
+    /// Tag of the image the container ran before its last automatic update.
+    pub rollback_image: Option<String>,
+    /// When the container was last updated automatically.
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Request to set a stack's update policy.
+#[derive(Debug, Deserialize)]
+pub struct UpdatePolicyRequest {
+    pub policy: UpdatePolicy,
+}
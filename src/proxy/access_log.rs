@@ -0,0 +1,287 @@
+//! Structured access log for proxied requests.
+//!
+//! Entries are handed to a background writer so requests never wait on disk.
+//! The log is written as JSON lines or in Combined Log Format, and can be
+//! rotated by size or by an external tool that sends `SIGHUP` after moving
+//! the file away.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::config::AccessLogConfig;
+
+/// Entries buffered for the writer before new ones are dropped.
+const QUEUE_SIZE: usize = 8192;
+
+/// How each entry is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Combined,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "combined" | "clf" => Ok(Format::Combined),
+            other => Err(anyhow!("Unknown access log format {}", other)),
+        }
+    }
+}
+
+/// One finished request.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub client: Option<IpAddr>,
+    pub host: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub protocol: String,
+    pub status: u16,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub duration_ms: f64,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub user: Option<String>,
+    pub route: Option<String>,
+}
+
+impl Entry {
+    /// Set how long the request took.
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration_ms = duration.as_secs_f64() * 1000.0;
+    }
+
+    fn format(&self, format: Format) -> String {
+        match format {
+            Format::Json => serde_json::to_string(self).unwrap_or_default(),
+            Format::Combined => self.combined(),
+        }
+    }
+
+    /// `host - user [time] "request" status bytes "referer" "user agent"`
+    fn combined(&self) -> String {
+        let dash = |value: &Option<String>| value.as_deref().map_or("-".to_string(), escape);
+        format!(
+            "{} - {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"",
+            self.client.map_or("-".to_string(), |ip| ip.to_string()),
+            dash(&self.user),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            escape(&self.method),
+            escape(&self.path),
+            self.protocol,
+            self.status,
+            if self.bytes_out == 0 { "-".to_string() } else { self.bytes_out.to_string() },
+            dash(&self.referer),
+            dash(&self.user_agent),
+        )
+    }
+}
+
+/// Escape a value for a quoted Combined Log Format field.
+fn escape(value: &str) -> String {
+    value.chars().fold(String::with_capacity(value.len()), |mut out, c| {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+        out
+    })
+}
+
+/// Handle for queueing access log entries.
+#[derive(Debug, Clone, Default)]
+pub struct AccessLog {
+    sender: Option<mpsc::Sender<Entry>>,
+}
+
+impl AccessLog {
+    /// Start the background writer, or return a disabled log when no path is
+    /// configured.
+    pub fn start(config: &AccessLogConfig) -> Result<Self> {
+        let Some(path) = config.path.clone() else {
+            return Ok(Self::default());
+        };
+        let format = Format::parse(&config.format)?;
+        let target = if path == "-" { None } else { Some(PathBuf::from(path)) };
+
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let writer = Writer {
+            target,
+            format,
+            max_size: config.max_size,
+            keep: config.keep.max(1),
+        };
+        tokio::spawn(writer.run(receiver));
+
+        Ok(Self { sender: Some(sender) })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Queue an entry, dropping it if the writer has fallen behind.
+    pub fn record(&self, entry: Entry) {
+        if let Some(sender) = &self.sender {
+            if sender.try_send(entry).is_err() {
+                warn!("Access log queue is full, dropping entry");
+            }
+        }
+    }
+}
+
+struct Writer {
+    /// File to append to, or stdout when `None`.
+    target: Option<PathBuf>,
+    format: Format,
+    max_size: u64,
+    keep: usize,
+}
+
+type Output = Box<dyn AsyncWrite + Send + Unpin>;
+
+impl Writer {
+    async fn run(self, mut receiver: mpsc::Receiver<Entry>) {
+        let (mut output, mut size) = match self.open().await {
+            Ok(opened) => opened,
+            Err(e) => {
+                error!("Failed to open access log: {:#}", e);
+                return;
+            }
+        };
+
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                warn!("Cannot listen for SIGHUP, access log will not be reopened: {}", e);
+                None
+            }
+        };
+
+        loop {
+            tokio::select! {
+                entry = receiver.recv() => {
+                    let Some(entry) = entry else { break };
+                    let mut line = entry.format(self.format);
+                    line.push('\n');
+                    if let Err(e) = output.write_all(line.as_bytes()).await {
+                        error!("Failed to write access log: {}", e);
+                    }
+                    size += line.len() as u64;
+
+                    // Flush once the queue is drained rather than per line
+                    if receiver.is_empty() {
+                        let _ = output.flush().await;
+                    }
+
+                    if self.max_size > 0 && size >= self.max_size {
+                        let _ = output.flush().await;
+                        if let Some(path) = &self.target {
+                            if let Err(e) = rotate(path, self.keep).await {
+                                error!("Failed to rotate access log: {:#}", e);
+                            }
+                        }
+                        match self.open().await {
+                            Ok(opened) => (output, size) = opened,
+                            Err(e) => error!("Failed to reopen access log: {:#}", e),
+                        }
+                    }
+                }
+                Some(()) = async { hangup.as_mut()?.recv().await } => {
+                    let _ = output.flush().await;
+                    match self.open().await {
+                        Ok(opened) => {
+                            info!("Reopened access log");
+                            (output, size) = opened;
+                        }
+                        Err(e) => error!("Failed to reopen access log: {:#}", e),
+                    }
+                }
+            }
+        }
+
+        let _ = output.flush().await;
+    }
+
+    async fn open(&self) -> Result<(Output, u64)> {
+        let Some(path) = &self.target else {
+            return Ok((Box::new(tokio::io::stdout()), 0));
+        };
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(parent).await.ok();
+        }
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let size = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+
+        Ok((Box::new(tokio::io::BufWriter::new(file)), size))
+    }
+}
+
+/// Shift `log.1` to `log.2` and so on, then move the live file to `log.1`.
+async fn rotate(path: &Path, keep: usize) -> Result<()> {
+    let numbered = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+
+    let _ = tokio::fs::remove_file(numbered(keep)).await;
+    for n in (1..keep).rev() {
+        let _ = tokio::fs::rename(numbered(n), numbered(n + 1)).await;
+    }
+    tokio::fs::rename(path, numbered(1))
+        .await
+        .with_context(|| format!("Failed to rotate {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combined_log_format() {
+        let entry = Entry {
+            time: DateTime::parse_from_rfc3339("2024-03-01T12:30:45Z").unwrap().with_timezone(&Utc),
+            client: Some("203.0.113.7".parse().unwrap()),
+            host: Some("app.example.com".to_string()),
+            method: "GET".to_string(),
+            path: "/search?q=\"x\"".to_string(),
+            protocol: "HTTP/1.1".to_string(),
+            status: 200,
+            bytes_in: 0,
+            bytes_out: 512,
+            duration_ms: 3.5,
+            referer: None,
+            user_agent: Some("curl/8.0".to_string()),
+            user: Some("alice".to_string()),
+            route: Some("web".to_string()),
+        };
+
+        assert_eq!(
+            entry.format(Format::Combined),
+            "203.0.113.7 - alice [01/Mar/2024:12:30:45 +0000] \"GET /search?q=\\\"x\\\" HTTP/1.1\" 200 512 \"-\" \"curl/8.0\""
+        );
+        let json: serde_json::Value = serde_json::from_str(&entry.format(Format::Json)).unwrap();
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["route"], "web");
+    }
+}
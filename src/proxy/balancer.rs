@@ -0,0 +1,859 @@
+//! Load balancing across the containers behind a route.
+//!
+//! A route targets either a single container or every running container that
+//! carries a label (for example `com.docker.compose.service=web`, which
+//! covers all replicas created by `scale_stack`). Backend sets are resolved
+//! from Docker and refreshed whenever a container starts, stops or changes
+//! its Docker health status.
+//!
+//! Backends that fail active health checks, report `unhealthy` through their
+//! Docker HEALTHCHECK, or keep failing proxied requests are taken out of
+//! rotation until they recover. Compose stacks drain containers before
+//! stopping them, so their requests in flight can finish.
+
+use axum::http::{header, HeaderMap};
+use bollard::models::{ContainerSummary, ContainerSummaryStateEnum, HealthStatusEnum};
+use bollard::query_parameters::{EventsOptions, InspectContainerOptions, ListContainersOptions};
+use bollard::Docker;
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+use super::{discovery, AppState};
+use crate::docker;
+use crate::docker::compose::engine::Drain;
+use crate::models::service::{HealthCheck, LoadBalancing};
+
+/// Cookie used to pin a client to a backend.
+pub const STICKY_COOKIE: &str = "rustainer_backend";
+
+/// Docker events that change which containers can receive traffic.
+const REFRESH_EVENTS: [&str; 8] = [
+    "start", "restart", "die", "stop", "destroy", "pause", "unpause", "health_status",
+];
+
+/// Consecutive failed proxied requests before a backend is ejected.
+const OUTLIER_FAILURES: u32 = 5;
+
+/// How long a backend stays out of rotation after passive ejection.
+const OUTLIER_EJECTION: Duration = Duration::from_secs(30);
+
+/// How long a container about to be stopped gets to finish its requests.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which containers a route sends traffic to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// A single container by ID, ID prefix or name.
+    Container(String),
+    /// Every running container with a label (`key` or `key=value`).
+    Label(String),
+}
+
+impl Selector {
+    /// Parse a service target: `label:key=value` selects by label, anything
+    /// else is a container ID or name.
+    pub fn from_target(target: &str) -> Self {
+        match target.strip_prefix("label:") {
+            Some(label) => Selector::Label(label.to_string()),
+            None => Selector::Container(target.to_string()),
+        }
+    }
+
+    /// The running containers the selector matches. A single container is
+    /// inspected first, as the `id` list filter does not match names.
+    async fn running(&self, docker: &Docker) -> anyhow::Result<Vec<ContainerSummary>> {
+        match self {
+            Selector::Container(id) => Ok(docker::find_container(docker, id)
+                .await?
+                .into_iter()
+                .filter(|container| container.state == Some(ContainerSummaryStateEnum::RUNNING))
+                .collect()),
+            Selector::Label(label) => {
+                let filters = HashMap::from([
+                    ("status".to_string(), vec!["running".to_string()]),
+                    ("label".to_string(), vec![label.clone()]),
+                ]);
+                let options = ListContainersOptions {
+                    filters: Some(filters),
+                    ..Default::default()
+                };
+                Ok(docker.list_containers(Some(options)).await?)
+            }
+        }
+    }
+}
+
+/// Health reported by a container's own Docker HEALTHCHECK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DockerHealth {
+    /// The image defines no HEALTHCHECK.
+    None,
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+impl From<Option<HealthStatusEnum>> for DockerHealth {
+    fn from(status: Option<HealthStatusEnum>) -> Self {
+        match status {
+            Some(HealthStatusEnum::STARTING) => DockerHealth::Starting,
+            Some(HealthStatusEnum::HEALTHY) => DockerHealth::Healthy,
+            Some(HealthStatusEnum::UNHEALTHY) => DockerHealth::Unhealthy,
+            _ => DockerHealth::None,
+        }
+    }
+}
+
+/// A running container as resolved from Docker.
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub id: String,
+    pub ip: String,
+    pub docker_health: DockerHealth,
+}
+
+/// Health bookkeeping for one backend.
+#[derive(Debug)]
+struct Health {
+    /// Verdict of the active health check; backends start out healthy.
+    healthy: bool,
+    successes: u32,
+    failures: u32,
+    /// Consecutive failed proxied requests.
+    passive_failures: u32,
+    ejected_until: Option<Instant>,
+    docker: DockerHealth,
+    last_error: Option<String>,
+}
+
+/// A container that can receive traffic.
+#[derive(Debug)]
+pub struct Backend {
+    /// Short container ID, also used as the sticky cookie value.
+    pub id: String,
+    /// Container IP address.
+    pub ip: String,
+    /// Requests currently in flight.
+    active: AtomicUsize,
+    /// Set while the container is being stopped.
+    draining: AtomicBool,
+    health: Mutex<Health>,
+}
+
+impl Backend {
+    pub fn new(id: String, ip: String, docker_health: DockerHealth) -> Self {
+        Self {
+            id,
+            ip,
+            active: AtomicUsize::new(0),
+            draining: AtomicBool::new(false),
+            health: Mutex::new(Health {
+                healthy: true,
+                successes: 0,
+                failures: 0,
+                passive_failures: 0,
+                ejected_until: None,
+                docker: docker_health,
+                last_error: None,
+            }),
+        }
+    }
+
+    /// Count a request against this backend until the guard is dropped.
+    pub fn track(self: &Arc<Self>) -> ConnectionGuard {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.clone())
+    }
+
+    /// Number of requests currently in flight.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Whether the container is being stopped.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Whether the backend may receive new requests.
+    pub fn is_available(&self) -> bool {
+        let health = self.health.lock().unwrap();
+        let ejected = health.ejected_until.is_some_and(|until| until > Instant::now());
+        !self.is_draining()
+            && health.healthy
+            && !ejected
+            && !matches!(health.docker, DockerHealth::Starting | DockerHealth::Unhealthy)
+    }
+
+    /// Record the outcome of an active health check.
+    pub fn record_check(&self, check: &HealthCheck, result: Result<(), String>) {
+        let mut health = self.health.lock().unwrap();
+        match result {
+            Ok(()) => {
+                health.failures = 0;
+                health.successes += 1;
+                if !health.healthy && health.successes >= check.healthy_threshold {
+                    info!("Backend {} ({}) passed health checks, restoring", self.id, self.ip);
+                    health.healthy = true;
+                    health.last_error = None;
+                }
+            }
+            Err(e) => {
+                health.successes = 0;
+                health.failures += 1;
+                if health.healthy && health.failures >= check.unhealthy_threshold {
+                    warn!("Backend {} ({}) failed health checks, ejecting: {}", self.id, self.ip, e);
+                    health.healthy = false;
+                }
+                health.last_error = Some(e);
+            }
+        }
+    }
+
+    /// Record the outcome of a proxied request, ejecting the backend for a
+    /// while after too many failures in a row.
+    pub fn record_response(&self, ok: bool) {
+        let mut health = self.health.lock().unwrap();
+        if ok {
+            health.passive_failures = 0;
+            return;
+        }
+
+        health.passive_failures += 1;
+        if health.passive_failures >= OUTLIER_FAILURES {
+            warn!(
+                "Backend {} ({}) failed {} requests in a row, ejecting for {:?}",
+                self.id, self.ip, health.passive_failures, OUTLIER_EJECTION
+            );
+            health.passive_failures = 0;
+            health.ejected_until = Some(Instant::now() + OUTLIER_EJECTION);
+            health.last_error = Some("Too many failed requests".to_string());
+        }
+    }
+
+    /// Current health, for the API.
+    pub fn status(&self) -> BackendStatus {
+        let available = self.is_available();
+        let health = self.health.lock().unwrap();
+        BackendStatus {
+            id: self.id.clone(),
+            ip: self.ip.clone(),
+            available,
+            check_healthy: health.healthy,
+            docker_health: health.docker,
+            ejected: health.ejected_until.is_some_and(|until| until > Instant::now()),
+            draining: self.is_draining(),
+            active_requests: self.active(),
+            last_error: health.last_error.clone(),
+        }
+    }
+}
+
+/// Health of one backend as reported by the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendStatus {
+    pub id: String,
+    pub ip: String,
+    /// Whether the backend currently receives traffic.
+    pub available: bool,
+    /// Verdict of the active health check.
+    pub check_healthy: bool,
+    pub docker_health: DockerHealth,
+    /// Whether the backend is ejected after failing proxied requests.
+    pub ejected: bool,
+    /// Whether the container is being stopped and takes no new requests.
+    pub draining: bool,
+    pub active_requests: usize,
+    pub last_error: Option<String>,
+}
+
+/// Overall health of a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteState {
+    /// Every backend is available.
+    Healthy,
+    /// Some backends are out of rotation.
+    Degraded,
+    /// No backend can take traffic.
+    Down,
+    /// The route has no target or is disabled.
+    Unknown,
+}
+
+/// Health of a route and its backends.
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteHealth {
+    pub state: RouteState,
+    pub backends: Vec<BackendStatus>,
+}
+
+impl RouteHealth {
+    pub fn unknown() -> Self {
+        Self {
+            state: RouteState::Unknown,
+            backends: Vec::new(),
+        }
+    }
+}
+
+/// Marks a request as finished when dropped.
+pub struct ConnectionGuard(Arc<Backend>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// What a route's pool is built from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub selector: Selector,
+    pub port: u16,
+    pub health_check: Option<HealthCheck>,
+}
+
+/// The backends of one route.
+#[derive(Debug)]
+pub struct Pool {
+    config: PoolConfig,
+    backends: RwLock<Vec<Arc<Backend>>>,
+    next: AtomicUsize,
+    last_check: Mutex<Option<Instant>>,
+}
+
+impl Pool {
+    pub fn new(config: PoolConfig) -> Self {
+        Self {
+            config,
+            backends: RwLock::new(Vec::new()),
+            next: AtomicUsize::new(0),
+            last_check: Mutex::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    pub fn backends(&self) -> Vec<Arc<Backend>> {
+        self.backends.read().unwrap().clone()
+    }
+
+    /// Replace the backend set, keeping in-flight counters and health state
+    /// for containers that are still present.
+    pub fn set_backends(&self, resolved: Vec<Resolved>) {
+        let mut backends = self.backends.write().unwrap();
+        let existing: HashMap<String, Arc<Backend>> =
+            backends.drain(..).map(|b| (b.id.clone(), b)).collect();
+
+        *backends = resolved
+            .into_iter()
+            .map(|resolved| match existing.get(&resolved.id) {
+                Some(backend) if backend.ip == resolved.ip => {
+                    backend.health.lock().unwrap().docker = resolved.docker_health;
+                    backend.clone()
+                }
+                _ => Arc::new(Backend::new(resolved.id, resolved.ip, resolved.docker_health)),
+            })
+            .collect();
+    }
+
+    /// Whether the active health check is due, marking it as started if so.
+    pub fn check_due(&self, now: Instant) -> bool {
+        let Some(check) = &self.config.health_check else {
+            return false;
+        };
+
+        let mut last_check = self.last_check.lock().unwrap();
+        let due = last_check
+            .is_none_or(|last| now.duration_since(last) >= Duration::from_secs(check.interval_secs.max(1)));
+        if due {
+            *last_check = Some(now);
+        }
+        due
+    }
+
+    /// Health of the route and each of its backends.
+    pub fn health(&self) -> RouteHealth {
+        let backends: Vec<BackendStatus> =
+            self.backends.read().unwrap().iter().map(|b| b.status()).collect();
+        let available = backends.iter().filter(|b| b.available).count();
+
+        let state = if available == 0 {
+            RouteState::Down
+        } else if available < backends.len() {
+            RouteState::Degraded
+        } else {
+            RouteState::Healthy
+        };
+
+        RouteHealth { state, backends }
+    }
+
+    /// Pick a backend for a request.
+    ///
+    /// Only available backends are considered. Returns the backend and
+    /// whether a sticky cookie has to be set.
+    pub fn pick(&self, strategy: LoadBalancing, headers: &HeaderMap) -> Option<(Arc<Backend>, bool)> {
+        let backends: Vec<Arc<Backend>> = self
+            .backends
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|backend| backend.is_available())
+            .cloned()
+            .collect();
+        if backends.is_empty() {
+            return None;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let round_robin = || backends[start % backends.len()].clone();
+
+        match strategy {
+            LoadBalancing::RoundRobin => Some((round_robin(), false)),
+            LoadBalancing::LeastConnections => {
+                // Scan from the round-robin position so ties are spread out
+                (0..backends.len())
+                    .map(|offset| &backends[(start + offset) % backends.len()])
+                    .min_by_key(|backend| backend.active())
+                    .map(|backend| (backend.clone(), false))
+            }
+            LoadBalancing::StickyCookie => {
+                let pinned = sticky_cookie(headers)
+                    .and_then(|id| backends.iter().find(|backend| backend.id == id));
+                match pinned {
+                    Some(backend) => Some((backend.clone(), false)),
+                    None => Some((round_robin(), true)),
+                }
+            }
+        }
+    }
+}
+
+/// Read the sticky backend ID from the request cookies.
+fn sticky_cookie(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == STICKY_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Backend pools for every route, keyed by route ID.
+#[derive(Debug, Default)]
+pub struct Balancers {
+    pools: RwLock<HashMap<String, Arc<Pool>>>,
+}
+
+impl Balancers {
+    /// Get the pool for a route, resolving its backends on first use or when
+    /// the route's configuration changed.
+    pub async fn pool(&self, docker: &Docker, route: &str, config: PoolConfig) -> Arc<Pool> {
+        if let Some(pool) = self.pools.read().unwrap().get(route) {
+            if pool.config == config {
+                return pool.clone();
+            }
+        }
+
+        let pool = Arc::new(Pool::new(config));
+        refresh_pool(docker, &pool).await;
+        self.pools
+            .write()
+            .unwrap()
+            .insert(route.to_string(), pool.clone());
+        pool
+    }
+
+    /// Forget a route that was removed or disabled.
+    pub fn remove(&self, route: &str) {
+        self.pools.write().unwrap().remove(route);
+    }
+
+    /// Every known pool.
+    pub fn pools(&self) -> Vec<Arc<Pool>> {
+        self.pools.read().unwrap().values().cloned().collect()
+    }
+
+    /// Re-resolve the backends of every known route.
+    pub async fn refresh_all(&self, docker: &Docker) {
+        for pool in self.pools() {
+            refresh_pool(docker, &pool).await;
+        }
+    }
+
+    /// Take a container out of rotation on every route, then wait up to
+    /// `timeout` for its requests in flight to finish.
+    pub async fn drain(&self, container_id: &str, timeout: Duration) {
+        let backends = self.backends_of(container_id);
+        for backend in &backends {
+            backend.draining.store(true, Ordering::Relaxed);
+        }
+
+        let deadline = Instant::now() + timeout;
+        while backends.iter().any(|backend| backend.active() > 0) {
+            if Instant::now() > deadline {
+                warn!("Container {} still has requests in flight after {:?}", container_id, timeout);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Put a drained container back in rotation.
+    pub fn restore(&self, container_id: &str) {
+        for backend in self.backends_of(container_id) {
+            backend.draining.store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn backends_of(&self, container_id: &str) -> Vec<Arc<Backend>> {
+        let id: String = container_id.chars().take(12).collect();
+        self.pools()
+            .iter()
+            .flat_map(|pool| pool.backends())
+            .filter(|backend| backend.id == id)
+            .collect()
+    }
+}
+
+impl Drain for AppState {
+    fn drain<'a>(&'a self, container_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(self.balancers.drain(container_id, DRAIN_TIMEOUT))
+    }
+
+    fn restore(&self, container_id: &str) {
+        self.balancers.restore(container_id);
+    }
+}
+
+/// Resolve the running containers for a pool.
+async fn refresh_pool(docker: &Docker, pool: &Pool) {
+    let containers = match pool.config.selector.running(docker).await {
+        Ok(containers) => containers,
+        Err(e) => {
+            error!("Failed to resolve backends for {:?}: {:#}", pool.config.selector, e);
+            return;
+        }
+    };
+
+    let mut resolved = Vec::with_capacity(containers.len());
+    for container in containers {
+        let (Some(ip), Some(id)) = (container_ip(&container), container.id) else {
+            continue;
+        };
+        resolved.push(Resolved {
+            docker_health: docker_health(docker, &id).await,
+            id: id.chars().take(12).collect(),
+            ip,
+        });
+    }
+    pool.set_backends(resolved);
+}
+
+/// Read a container's HEALTHCHECK status.
+async fn docker_health(docker: &Docker, id: &str) -> DockerHealth {
+    match docker.inspect_container(id, None::<InspectContainerOptions>).await {
+        Ok(info) => info.state.and_then(|state| state.health).and_then(|health| health.status).into(),
+        Err(e) => {
+            debug!("Failed to inspect container {}: {}", id, e);
+            DockerHealth::None
+        }
+    }
+}
+
+/// Pick the address to reach a container on, preferring the bridge network.
+fn container_ip(container: &ContainerSummary) -> Option<String> {
+    let networks = container.network_settings.as_ref()?.networks.as_ref()?;
+
+    let bridge = networks
+        .get("bridge")
+        .and_then(|network| network.ip_address.clone())
+        .filter(|ip| !ip.is_empty());
+
+    bridge.or_else(|| {
+        networks
+            .values()
+            .filter_map(|network| network.ip_address.clone())
+            .find(|ip| !ip.is_empty())
+    })
+}
+
+/// Keep backend sets in sync with container lifecycle events.
+pub fn spawn_watcher(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut filters = HashMap::new();
+        filters.insert("type".to_string(), vec!["container".to_string()]);
+        filters.insert(
+            "event".to_string(),
+            REFRESH_EVENTS.iter().map(|e| e.to_string()).collect(),
+        );
+
+        loop {
+            info!("Watching container events for load balancing");
+            let options = EventsOptions {
+                filters: Some(filters.clone()),
+                ..Default::default()
+            };
+            let mut events = state.docker.events(Some(options));
+            discovery::sync(&state).await;
+
+            while let Some(event) = events.next().await {
+                match event {
+                    Ok(event) => {
+                        debug!("Refreshing backends after container {:?}", event.action);
+                        let health_only = event.action.as_deref().is_some_and(|a| a.starts_with("health_status"));
+                        if !health_only {
+                            discovery::sync(&state).await;
+                        }
+                        state.balancers.refresh_all(&state.docker).await;
+                    }
+                    Err(e) => {
+                        error!("Error receiving Docker event: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            // Catch up on anything missed while disconnected
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            state.balancers.refresh_all(&state.docker).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use axum::response::IntoResponse;
+
+    fn resolved(id: &str, ip: &str) -> Resolved {
+        Resolved {
+            id: id.to_string(),
+            ip: ip.to_string(),
+            docker_health: DockerHealth::None,
+        }
+    }
+
+    fn pool_with(ids: &[&str]) -> Pool {
+        let pool = Pool::new(PoolConfig {
+            selector: Selector::Label("app=web".to_string()),
+            port: 80,
+            health_check: Some(HealthCheck::default()),
+        });
+        pool.set_backends(
+            ids.iter()
+                .enumerate()
+                .map(|(i, id)| resolved(id, &format!("172.17.0.{}", i + 2)))
+                .collect(),
+        );
+        pool
+    }
+
+    #[test]
+    fn test_round_robin_cycles_through_backends() {
+        let pool = pool_with(&["a", "b", "c"]);
+        let picked: Vec<String> = (0..6)
+            .map(|_| pool.pick(LoadBalancing::RoundRobin, &HeaderMap::new()).unwrap().0.id.clone())
+            .collect();
+        assert_eq!(picked, vec!["a", "b", "c", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_least_connections_prefers_idle_backend() {
+        let pool = pool_with(&["a", "b"]);
+        let busy = pool.backends.read().unwrap()[0].clone();
+        let _guards: Vec<_> = (0..3).map(|_| busy.track()).collect();
+
+        for _ in 0..4 {
+            let (backend, _) = pool.pick(LoadBalancing::LeastConnections, &HeaderMap::new()).unwrap();
+            assert_eq!(backend.id, "b");
+        }
+    }
+
+    #[test]
+    fn test_sticky_cookie_pins_backend() {
+        let pool = pool_with(&["a", "b", "c"]);
+
+        let (first, set_cookie) = pool.pick(LoadBalancing::StickyCookie, &HeaderMap::new()).unwrap();
+        assert!(set_cookie);
+
+        let mut headers = HeaderMap::new();
+        let cookie = format!("theme=dark; {}={}", STICKY_COOKIE, first.id);
+        headers.insert(header::COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        for _ in 0..5 {
+            let (backend, set_cookie) = pool.pick(LoadBalancing::StickyCookie, &headers).unwrap();
+            assert_eq!(backend.id, first.id);
+            assert!(!set_cookie);
+        }
+    }
+
+    #[test]
+    fn test_refresh_keeps_in_flight_counts() {
+        let pool = pool_with(&["a", "b"]);
+        let _guard = pool.backends.read().unwrap()[0].track();
+
+        pool.set_backends(vec![resolved("a", "172.17.0.2"), resolved("d", "172.17.0.9")]);
+
+        let backends = pool.backends.read().unwrap();
+        assert_eq!(backends.len(), 2);
+        assert_eq!(backends[0].active(), 1);
+        assert_eq!(backends[1].id, "d");
+    }
+
+    #[test]
+    fn test_failing_health_checks_eject_until_recovered() {
+        let pool = pool_with(&["a", "b"]);
+        let check = HealthCheck::default();
+        let sick = pool.backends()[0].clone();
+
+        for _ in 0..check.unhealthy_threshold {
+            sick.record_check(&check, Err("timed out".to_string()));
+        }
+        assert!(!sick.is_available());
+        assert_eq!(pool.health().state, RouteState::Degraded);
+        for _ in 0..4 {
+            let (backend, _) = pool.pick(LoadBalancing::RoundRobin, &HeaderMap::new()).unwrap();
+            assert_eq!(backend.id, "b");
+        }
+
+        for _ in 0..check.healthy_threshold {
+            sick.record_check(&check, Ok(()));
+        }
+        assert!(sick.is_available());
+        assert_eq!(pool.health().state, RouteState::Healthy);
+    }
+
+    #[test]
+    fn test_repeated_request_failures_eject_backend() {
+        let pool = pool_with(&["a"]);
+        let backend = pool.backends()[0].clone();
+
+        for _ in 0..OUTLIER_FAILURES - 1 {
+            backend.record_response(false);
+        }
+        backend.record_response(true);
+        backend.record_response(false);
+        assert!(backend.is_available());
+
+        for _ in 0..OUTLIER_FAILURES {
+            backend.record_response(false);
+        }
+        assert!(!backend.is_available());
+        assert!(pool.pick(LoadBalancing::RoundRobin, &HeaderMap::new()).is_none());
+        assert_eq!(pool.health().state, RouteState::Down);
+    }
+
+    #[test]
+    fn test_docker_health_gates_traffic() {
+        let pool = pool_with(&[]);
+        pool.set_backends(vec![Resolved {
+            docker_health: DockerHealth::Starting,
+            ..resolved("a", "172.17.0.2")
+        }]);
+        assert!(pool.pick(LoadBalancing::RoundRobin, &HeaderMap::new()).is_none());
+
+        pool.set_backends(vec![Resolved {
+            docker_health: DockerHealth::Healthy,
+            ..resolved("a", "172.17.0.2")
+        }]);
+        assert!(pool.pick(LoadBalancing::RoundRobin, &HeaderMap::new()).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_draining_backend_takes_no_new_requests() {
+        let balancers = Balancers::default();
+        let pool = Arc::new(pool_with(&["a", "b"]));
+        balancers.pools.write().unwrap().insert("route".to_string(), pool.clone());
+
+        let (backend, _) = pool.pick(LoadBalancing::LeastConnections, &HeaderMap::new()).unwrap();
+        let guard = backend.track();
+        let id = backend.id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(guard);
+        });
+        balancers.drain(&id, Duration::from_secs(5)).await;
+        assert_eq!(backend.active(), 0);
+        for _ in 0..4 {
+            let (picked, _) = pool.pick(LoadBalancing::RoundRobin, &HeaderMap::new()).unwrap();
+            assert_ne!(picked.id, id);
+        }
+
+        balancers.restore(&id);
+        assert!(backend.is_available());
+    }
+
+    /// A Docker API that knows one running container, named `web`.
+    async fn stand_in_docker() -> Docker {
+        const ID: &str = "4f2a9c1d8e7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e5d4c3b2a1f";
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new().fallback(|request: axum::http::Request<axum::body::Body>| async move {
+            let path = request.uri().path();
+            let query = request.uri().query().unwrap_or_default();
+            if path.ends_with("/containers/json") {
+                // Listing is only used to find the inspected container's ID
+                if !query.contains(ID) {
+                    return axum::Json(serde_json::json!([])).into_response();
+                }
+                let container = serde_json::json!({
+                    "Id": ID,
+                    "State": "running",
+                    "NetworkSettings": { "Networks": { "bridge": { "IPAddress": "172.17.0.5" } } },
+                });
+                return axum::Json(serde_json::json!([container])).into_response();
+            }
+            // Inspecting accepts the name, the ID or a prefix of it
+            match path.rsplit('/').nth(1) {
+                Some(name) if name == "web" || (!name.is_empty() && ID.starts_with(name)) => {
+                    axum::Json(serde_json::json!({ "Id": ID, "Name": "/web" })).into_response()
+                }
+                _ => {
+                    let body = axum::Json(serde_json::json!({ "message": "No such container" }));
+                    (axum::http::StatusCode::NOT_FOUND, body).into_response()
+                }
+            }
+        });
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Docker::connect_with_http(&format!("http://{}", addr), 4, bollard::API_DEFAULT_VERSION).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_container_selector_resolves_names() {
+        let docker = stand_in_docker().await;
+        let pool = |target: &str| {
+            Pool::new(PoolConfig {
+                selector: Selector::from_target(target),
+                port: 80,
+                health_check: None,
+            })
+        };
+
+        for target in ["web", "4f2a9c1d8e7b"] {
+            let pool = pool(target);
+            refresh_pool(&docker, &pool).await;
+            let backends = pool.backends.read().unwrap();
+            assert_eq!(backends.len(), 1, "{}", target);
+            assert_eq!((backends[0].id.as_str(), backends[0].ip.as_str()), ("4f2a9c1d8e7b", "172.17.0.5"));
+        }
+
+        let gone = pool("db");
+        refresh_pool(&docker, &gone).await;
+        assert!(gone.backends.read().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+//! Label-driven service discovery.
+//!
+//! Running containers can declare a route with labels instead of a stored
+//! service:
+//!
+//! - `rustainer.domain`: host to route (required)
+//! - `rustainer.port`: container port, if it exposes more than one
+//! - `rustainer.path`: path prefix (default `/`)
+//! - `rustainer.strip_prefix`: remove the prefix before forwarding
+//! - `rustainer.priority`: match before lower priority routes
+//!
+//! Discovered services live only in memory. They are rebuilt from the running
+//! containers whenever a container starts, stops or is removed.
+
+use anyhow::{anyhow, bail, Result};
+use bollard::models::ContainerSummary;
+use bollard::query_parameters::ListContainersOptions;
+use chrono::{TimeZone, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::RwLock;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use super::{routing, AppState};
+use crate::models::service::{Service, ServiceType};
+use crate::proxy::routing::normalize_prefix;
+
+pub const DOMAIN_LABEL: &str = "rustainer.domain";
+pub const PORT_LABEL: &str = "rustainer.port";
+pub const PATH_LABEL: &str = "rustainer.path";
+pub const STRIP_PREFIX_LABEL: &str = "rustainer.strip_prefix";
+pub const PRIORITY_LABEL: &str = "rustainer.priority";
+
+/// Services discovered from container labels, keyed by container ID.
+#[derive(Debug, Default)]
+pub struct Discovered {
+    services: RwLock<HashMap<String, Service>>,
+}
+
+impl Discovered {
+    /// Every discovered service, sorted by name.
+    pub fn list(&self) -> Vec<Service> {
+        let mut services: Vec<Service> = self.services.read().unwrap().values().cloned().collect();
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        services
+    }
+
+    /// A discovered service by its ID.
+    pub fn get(&self, id: &Uuid) -> Option<Service> {
+        self.services
+            .read()
+            .unwrap()
+            .values()
+            .find(|service| service.id == *id)
+            .cloned()
+    }
+
+    /// Swap in a new set of services, returning whether anything changed.
+    fn replace(&self, services: HashMap<String, Service>) -> bool {
+        let mut current = self.services.write().unwrap();
+        let fingerprint = |services: &HashMap<String, Service>| {
+            services
+                .iter()
+                .map(|(id, service)| (id.clone(), serde_json::to_string(service).unwrap_or_default()))
+                .collect::<BTreeSet<_>>()
+        };
+
+        if fingerprint(&current) == fingerprint(&services) {
+            return false;
+        }
+        *current = services;
+        true
+    }
+}
+
+/// Build a service from a running container's labels. Returns `Ok(None)` for
+/// containers without a `rustainer.domain` label.
+pub fn service_from_container(container: &ContainerSummary) -> Result<Option<Service>> {
+    let Some(labels) = &container.labels else {
+        return Ok(None);
+    };
+    let Some(domain) = labels.get(DOMAIN_LABEL).map(|d| d.trim()).filter(|d| !d.is_empty()) else {
+        return Ok(None);
+    };
+    let id = container.id.as_deref().ok_or_else(|| anyhow!("Container has no ID"))?;
+
+    let port = match labels.get(PORT_LABEL) {
+        Some(port) => port
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid {} label {:?}", PORT_LABEL, port))?,
+        None => {
+            let ports: BTreeSet<u16> = container
+                .ports
+                .iter()
+                .flatten()
+                .map(|port| port.private_port)
+                .collect();
+            match ports.len() {
+                0 => 80,
+                1 => *ports.first().unwrap(),
+                _ => bail!("Container exposes several ports, set the {} label", PORT_LABEL),
+            }
+        }
+    };
+
+    let name = container
+        .names
+        .iter()
+        .flatten()
+        .next()
+        .map(|name| name.trim_start_matches('/').to_string())
+        .unwrap_or_else(|| id.chars().take(12).collect());
+
+    let mut service = Service::new(name, domain.to_string(), ServiceType::Container, id.to_string(), port);
+    service.id = discovered_id(id)?;
+    service.discovered = true;
+
+    if let Some(path) = labels.get(PATH_LABEL) {
+        service.path_prefix = normalize_prefix(path);
+    }
+    if let Some(strip) = labels.get(STRIP_PREFIX_LABEL) {
+        service.strip_prefix = strip
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid {} label {:?}", STRIP_PREFIX_LABEL, strip))?;
+    }
+    if let Some(priority) = labels.get(PRIORITY_LABEL) {
+        service.priority = priority
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid {} label {:?}", PRIORITY_LABEL, priority))?;
+    }
+
+    // Keep timestamps stable so unchanged containers compare equal
+    if let Some(created) = container.created.and_then(|secs| Utc.timestamp_opt(secs, 0).single()) {
+        service.created_at = created;
+        service.updated_at = created;
+    }
+
+    Ok(Some(service))
+}
+
+/// A stable service ID for a container, taken from its 64 hex digit ID.
+fn discovered_id(container_id: &str) -> Result<Uuid> {
+    container_id
+        .get(..32)
+        .and_then(|hex| Uuid::parse_str(hex).ok())
+        .ok_or_else(|| anyhow!("Unexpected container ID {}", container_id))
+}
+
+/// Rebuild discovered services from the running containers, reloading the
+/// routing table if they changed.
+pub async fn sync(state: &AppState) {
+    let mut filters = HashMap::new();
+    filters.insert("status".to_string(), vec!["running".to_string()]);
+    filters.insert("label".to_string(), vec![DOMAIN_LABEL.to_string()]);
+    let options = ListContainersOptions {
+        filters: Some(filters),
+        ..Default::default()
+    };
+
+    let containers = match state.docker.list_containers(Some(options)).await {
+        Ok(containers) => containers,
+        Err(e) => {
+            error!("Failed to list containers for discovery: {}", e);
+            return;
+        }
+    };
+
+    let mut services = HashMap::new();
+    for container in &containers {
+        match service_from_container(container) {
+            Ok(Some(service)) => {
+                services.insert(service.target.clone(), service);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Ignoring labels on container {:?}: {}", container.id, e),
+        }
+    }
+
+    let count = services.len();
+    if state.discovered.replace(services) {
+        info!("Discovered {} services from container labels", count);
+        routing::reload(state).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::{Port, PortTypeEnum};
+
+    fn container(labels: &[(&str, &str)], ports: &[u16]) -> ContainerSummary {
+        ContainerSummary {
+            id: Some("4f9a1c2b3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8".to_string()),
+            names: Some(vec!["/web-1".to_string()]),
+            labels: Some(labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+            ports: Some(
+                ports
+                    .iter()
+                    .map(|port| Port {
+                        private_port: *port,
+                        typ: Some(PortTypeEnum::TCP),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            created: Some(1_700_000_000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_service_from_labels() {
+        let service = service_from_container(&container(
+            &[(DOMAIN_LABEL, "app.example.com"), (PATH_LABEL, "api/"), (STRIP_PREFIX_LABEL, "true")],
+            &[8080],
+        ))
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(service.name, "web-1");
+        assert_eq!(service.domain, "app.example.com");
+        assert_eq!(service.port, 8080);
+        assert_eq!(service.path_prefix, "/api");
+        assert!(service.strip_prefix);
+        assert!(service.discovered);
+        assert_eq!(service.id.simple().to_string(), "4f9a1c2b3d4e5f60718293a4b5c6d7e8");
+    }
+
+    #[test]
+    fn test_port_label_required_for_several_ports() {
+        assert!(service_from_container(&container(&[], &[80])).unwrap().is_none());
+        assert!(service_from_container(&container(&[(DOMAIN_LABEL, "a.test")], &[80, 443])).is_err());
+
+        let service = service_from_container(&container(&[(DOMAIN_LABEL, "a.test"), (PORT_LABEL, "443")], &[80, 443]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(service.port, 443);
+    }
+}
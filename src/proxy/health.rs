@@ -0,0 +1,93 @@
+//! Active HTTP health checks for proxied routes.
+//!
+//! Every route with a health check configured has each of its backends probed
+//! on the route's interval. A backend is ejected after `unhealthy_threshold`
+//! failed probes in a row and gets traffic again after `healthy_threshold`
+//! successful ones.
+
+use axum::body::Body;
+use axum::http::{header, HeaderValue, Request};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use super::{balancer::Backend, AppState, ProxyClient};
+
+/// How often due checks are looked for.
+const TICK: Duration = Duration::from_secs(1);
+
+/// How often routes are scanned so checks run before a route sees traffic.
+const ROUTE_SCAN: Duration = Duration::from_secs(30);
+
+/// Run health checks for every route in the background.
+pub fn spawn_checker(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(TICK);
+        let mut last_scan: Option<Instant> = None;
+
+        loop {
+            ticker.tick().await;
+            let now = Instant::now();
+
+            if last_scan.is_none_or(|last| now.duration_since(last) >= ROUTE_SCAN) {
+                last_scan = Some(now);
+                load_routes(&state).await;
+            }
+
+            for pool in state.balancers.pools() {
+                if !pool.check_due(now) {
+                    continue;
+                }
+
+                let config = pool.config().clone();
+                let Some(check) = config.health_check else {
+                    continue;
+                };
+
+                for backend in pool.backends() {
+                    let client = state.client.clone();
+                    let check = check.clone();
+                    tokio::spawn(async move {
+                        let result = probe(&client, &backend, config.port, &check.path, check.timeout_secs).await;
+                        if let Err(e) = &result {
+                            debug!("Health check for {} ({}) failed: {}", backend.id, backend.ip, e);
+                        }
+                        backend.record_check(&check, result);
+                    });
+                }
+            }
+        }
+    });
+}
+
+/// Make sure every route with a health check has a pool.
+async fn load_routes(state: &AppState) {
+    let table = state.routes.read().unwrap().clone();
+    for route in table.routes() {
+        if let Some(pool) = route.pool().filter(|pool| pool.health_check.is_some()) {
+            state.balancers.pool(&state.docker, &route.id, pool.clone()).await;
+        }
+    }
+}
+
+/// Request the health check path from one backend.
+async fn probe(
+    client: &ProxyClient,
+    backend: &Backend,
+    port: u16,
+    path: &str,
+    timeout_secs: u64,
+) -> Result<(), String> {
+    let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+    let req = Request::get(format!("http://{}:{}{}", backend.ip, port, path))
+        .header(header::USER_AGENT, HeaderValue::from_static("rustainer-health-check"))
+        .body(Body::empty())
+        .map_err(|e| e.to_string())?;
+
+    match tokio::time::timeout(Duration::from_secs(timeout_secs.max(1)), client.request(req)).await {
+        Ok(Ok(response)) if response.status().is_success() || response.status().is_redirection() => Ok(()),
+        Ok(Ok(response)) => Err(format!("Health check returned {}", response.status())),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("Health check timed out after {}s", timeout_secs.max(1))),
+    }
+}
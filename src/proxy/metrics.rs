@@ -0,0 +1,431 @@
+//! Per-route traffic metrics.
+//!
+//! Every proxied request is tracked by an [`Exchange`] from the moment it
+//! arrives until its response body has been sent. Counters are kept per
+//! application or service and exported in Prometheus text format and as JSON
+//! for the dashboard.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::ConnectInfo,
+    http::{header, HeaderMap, Request, Response, StatusCode},
+    response::IntoResponse,
+};
+use chrono::Utc;
+use hyper::body::{Body as HttpBody, Frame, SizeHint};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use super::access_log::{AccessLog, Entry};
+use super::Route;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Counters for one application or service.
+#[derive(Debug, Default)]
+pub struct RouteMetrics {
+    name: Mutex<String>,
+    requests: [AtomicU64; 5],
+    /// Requests per latency bucket, with a final bucket for slower ones.
+    latency: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_micros: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    upstream_errors: AtomicU64,
+}
+
+impl RouteMetrics {
+    fn record(&self, status: StatusCode, latency: f64, upstream_error: bool) {
+        let class = (status.as_u16() / 100).clamp(1, 5) as usize - 1;
+        self.requests[class].fetch_add(1, Ordering::Relaxed);
+
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_micros.fetch_add((latency * 1_000_000.0) as u64, Ordering::Relaxed);
+
+        if upstream_error {
+            self.upstream_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self, id: &str) -> RouteSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let requests: Vec<u64> = self.requests.iter().map(load).collect();
+        let latency: Vec<u64> = self.latency.iter().map(load).collect();
+
+        RouteSnapshot {
+            id: id.to_string(),
+            name: self.name.lock().unwrap().clone(),
+            requests: STATUS_CLASSES.iter().map(|c| c.to_string()).zip(requests.iter().copied()).collect(),
+            total_requests: requests.iter().sum(),
+            latency: LatencySnapshot::new(&latency, load(&self.latency_micros)),
+            bytes_in: load(&self.bytes_in),
+            bytes_out: load(&self.bytes_out),
+            upstream_errors: load(&self.upstream_errors),
+        }
+    }
+}
+
+/// Point-in-time view of a route's counters.
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteSnapshot {
+    pub id: String,
+    pub name: String,
+    pub requests: HashMap<String, u64>,
+    pub total_requests: u64,
+    pub latency: LatencySnapshot,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub upstream_errors: u64,
+}
+
+/// Latency histogram with estimated percentiles. Percentiles are the upper
+/// bound of the bucket they fall in, or `None` without enough data.
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencySnapshot {
+    /// Cumulative counts per bucket, keyed by upper bound in seconds.
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,
+    pub sum_secs: f64,
+    pub average_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+impl LatencySnapshot {
+    fn new(counts: &[u64], sum_micros: u64) -> Self {
+        let count: u64 = counts.iter().sum();
+        let sum_secs = sum_micros as f64 / 1_000_000.0;
+
+        let mut cumulative = 0;
+        let buckets = LATENCY_BUCKETS
+            .iter()
+            .zip(counts)
+            .map(|(bound, n)| {
+                cumulative += n;
+                (*bound, cumulative)
+            })
+            .collect::<Vec<_>>();
+
+        let percentile = |q: f64| {
+            let rank = (q * count as f64).ceil() as u64;
+            (count > 0).then(|| {
+                buckets
+                    .iter()
+                    .find(|(_, cumulative)| *cumulative >= rank)
+                    .map_or(f64::INFINITY, |(bound, _)| bound * 1000.0)
+            })
+        };
+
+        Self {
+            p50_ms: percentile(0.5),
+            p95_ms: percentile(0.95),
+            p99_ms: percentile(0.99),
+            average_ms: (count > 0).then(|| sum_secs * 1000.0 / count as f64),
+            buckets,
+            count,
+            sum_secs,
+        }
+    }
+}
+
+/// Metrics for every route, keyed by route ID.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    routes: RwLock<HashMap<String, Arc<RouteMetrics>>>,
+}
+
+impl Metrics {
+    /// The counters for a route, created on first use.
+    pub fn route(&self, id: &str, name: &str) -> Arc<RouteMetrics> {
+        let existing = self.routes.read().unwrap().get(id).cloned();
+        let metrics = match existing {
+            Some(metrics) => metrics,
+            None => self.routes.write().unwrap().entry(id.to_string()).or_default().clone(),
+        };
+
+        let mut current = metrics.name.lock().unwrap();
+        if *current != name {
+            *current = name.to_string();
+        }
+        drop(current);
+        metrics
+    }
+
+    /// Drop counters for routes that no longer exist.
+    pub fn retain(&self, ids: &HashSet<&str>) {
+        self.routes.write().unwrap().retain(|id, _| ids.contains(id.as_str()));
+    }
+
+    /// Snapshots of every route, sorted by name.
+    pub fn snapshot(&self) -> Vec<RouteSnapshot> {
+        let mut snapshots: Vec<_> = self
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, metrics)| metrics.snapshot(id))
+            .collect();
+        snapshots.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        snapshots
+    }
+
+    /// Render every route in the Prometheus text exposition format.
+    pub fn prometheus(&self) -> String {
+        let snapshots = self.snapshot();
+        let mut out = String::new();
+        let labels = |s: &RouteSnapshot| format!("route_id=\"{}\",route=\"{}\"", label(&s.id), label(&s.name));
+
+        out.push_str("# HELP rustainer_requests_total Proxied requests by status class.\n");
+        out.push_str("# TYPE rustainer_requests_total counter\n");
+        for s in &snapshots {
+            for class in STATUS_CLASSES {
+                let _ = writeln!(out, "rustainer_requests_total{{{},class=\"{}\"}} {}", labels(s), class, s.requests[class]);
+            }
+        }
+
+        out.push_str("# HELP rustainer_request_duration_seconds Time until the response headers were sent.\n");
+        out.push_str("# TYPE rustainer_request_duration_seconds histogram\n");
+        for s in &snapshots {
+            for (bound, cumulative) in &s.latency.buckets {
+                let _ = writeln!(out, "rustainer_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels(s), bound, cumulative);
+            }
+            let _ = writeln!(out, "rustainer_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels(s), s.latency.count);
+            let _ = writeln!(out, "rustainer_request_duration_seconds_sum{{{}}} {}", labels(s), s.latency.sum_secs);
+            let _ = writeln!(out, "rustainer_request_duration_seconds_count{{{}}} {}", labels(s), s.latency.count);
+        }
+
+        let counters: [(&str, &str, CounterValue); 3] = [
+            ("rustainer_request_bytes_total", "Request body bytes received from clients.", |s| s.bytes_in),
+            ("rustainer_response_bytes_total", "Response body bytes sent to clients.", |s| s.bytes_out),
+            ("rustainer_upstream_errors_total", "Requests that failed to reach an upstream.", |s| s.upstream_errors),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            for s in &snapshots {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels(s), value(s));
+            }
+        }
+
+        out
+    }
+}
+
+/// Reads one counter from a snapshot.
+type CounterValue = fn(&RouteSnapshot) -> u64;
+
+/// Escape a Prometheus label value.
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Tracks one request through the proxy for metrics and the access log.
+pub struct Exchange {
+    started: Instant,
+    entry: Entry,
+    bytes_in: Arc<AtomicU64>,
+    route: Option<Arc<RouteMetrics>>,
+}
+
+impl Exchange {
+    /// Start tracking a request, counting its body as it is read.
+    pub fn start(req: Request<Body>) -> (Self, Request<Body>) {
+        let header = |name: header::HeaderName| {
+            req.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+        };
+
+        let entry = Entry {
+            time: Utc::now(),
+            client: client_ip(&req),
+            host: header(header::HOST).or_else(|| req.uri().authority().map(|a| a.to_string())),
+            method: req.method().to_string(),
+            path: req.uri().path_and_query().map_or("/".to_string(), |p| p.to_string()),
+            protocol: format!("{:?}", req.version()),
+            status: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            duration_ms: 0.0,
+            referer: header(header::REFERER),
+            user_agent: header(header::USER_AGENT),
+            user: None,
+            route: None,
+        };
+
+        let bytes_in = Arc::new(AtomicU64::new(0));
+        let counter = bytes_in.clone();
+        let req = req.map(|body| Body::new(Counted { inner: body, counter, _done: None }));
+
+        let exchange = Self {
+            started: Instant::now(),
+            entry,
+            bytes_in,
+            route: None,
+        };
+        (exchange, req)
+    }
+
+    /// Attribute the request to a route.
+    pub fn matched(&mut self, metrics: &Metrics, route: &Route) {
+        self.route = Some(metrics.route(&route.id, &route.name));
+        self.entry.route = Some(route.name.clone());
+    }
+
+    /// Record the user the route's middleware authenticated.
+    pub fn authenticated(&mut self, headers: &HeaderMap) {
+        self.entry.user = headers
+            .get(super::middleware::USER_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+    }
+
+    /// Record the outcome and return the response, counting its body until
+    /// the last byte has been sent.
+    pub fn finish(self, log: &AccessLog, result: Result<Response<Body>, StatusCode>) -> Response<Body> {
+        // Errors raised by the proxy itself when no upstream could answer
+        let upstream_error = matches!(
+            result,
+            Err(StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT)
+        );
+        let response = result.unwrap_or_else(|status| status.into_response());
+
+        if let Some(route) = &self.route {
+            route.record(response.status(), self.started.elapsed().as_secs_f64(), upstream_error);
+        }
+
+        let mut entry = self.entry;
+        entry.status = response.status().as_u16();
+
+        let counter = Arc::new(AtomicU64::new(0));
+        let done = Done {
+            started: self.started,
+            entry,
+            log: log.clone(),
+            route: self.route,
+            bytes_in: self.bytes_in,
+            bytes_out: counter.clone(),
+        };
+        response.map(|body| Body::new(Counted { inner: body, counter, _done: Some(done) }))
+    }
+}
+
+/// The client address, if the listener recorded one.
+pub fn client_ip<B>(req: &Request<B>) -> Option<IpAddr> {
+    req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_canonical())
+}
+
+/// Completes an exchange once its response body is finished or dropped.
+struct Done {
+    started: Instant,
+    entry: Entry,
+    log: AccessLog,
+    route: Option<Arc<RouteMetrics>>,
+    bytes_in: Arc<AtomicU64>,
+    bytes_out: Arc<AtomicU64>,
+}
+
+impl Drop for Done {
+    fn drop(&mut self) {
+        let bytes_in = self.bytes_in.load(Ordering::Relaxed);
+        let bytes_out = self.bytes_out.load(Ordering::Relaxed);
+
+        if let Some(route) = &self.route {
+            route.bytes_in.fetch_add(bytes_in, Ordering::Relaxed);
+            route.bytes_out.fetch_add(bytes_out, Ordering::Relaxed);
+        }
+
+        if self.log.is_enabled() {
+            let mut entry = self.entry.clone();
+            entry.bytes_in = bytes_in;
+            entry.bytes_out = bytes_out;
+            entry.set_duration(self.started.elapsed());
+            self.log.record(entry);
+        }
+    }
+}
+
+/// A body that counts the data bytes passing through it, keeping the inner
+/// body's size hint so framing is unchanged.
+struct Counted {
+    inner: Body,
+    counter: Arc<AtomicU64>,
+    /// Held only so the exchange completes when the body is dropped.
+    _done: Option<Done>,
+}
+
+impl HttpBody for Counted {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.counter.fetch_add(data.len() as u64, Ordering::Relaxed);
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_histogram_and_percentiles() {
+        let metrics = RouteMetrics::default();
+        for _ in 0..90 {
+            metrics.record(StatusCode::OK, 0.003, false);
+        }
+        for _ in 0..10 {
+            metrics.record(StatusCode::BAD_GATEWAY, 0.2, true);
+        }
+
+        let snapshot = metrics.snapshot("web");
+        assert_eq!(snapshot.total_requests, 100);
+        assert_eq!(snapshot.requests["2xx"], 90);
+        assert_eq!(snapshot.requests["5xx"], 10);
+        assert_eq!(snapshot.upstream_errors, 10);
+        assert_eq!(snapshot.latency.buckets[0], (0.005, 90));
+        assert_eq!(snapshot.latency.p50_ms, Some(5.0));
+        assert_eq!(snapshot.latency.p95_ms, Some(250.0));
+    }
+
+    #[test]
+    fn test_prometheus_output() {
+        let metrics = Metrics::default();
+        metrics.route("id-1", "my \"app\"").record(StatusCode::NOT_FOUND, 0.02, false);
+
+        let text = metrics.prometheus();
+        assert!(text.contains("rustainer_requests_total{route_id=\"id-1\",route=\"my \\\"app\\\"\",class=\"4xx\"} 1"));
+        assert!(text.contains("rustainer_request_duration_seconds_bucket{route_id=\"id-1\",route=\"my \\\"app\\\"\",le=\"0.025\"} 1"));
+        assert!(text.contains("rustainer_request_duration_seconds_count{route_id=\"id-1\",route=\"my \\\"app\\\"\"} 1"));
+
+        metrics.retain(&HashSet::new());
+        assert!(metrics.snapshot().is_empty());
+    }
+}
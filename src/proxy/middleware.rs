@@ -0,0 +1,744 @@
+//! Per-service middleware for proxied requests.
+//!
+//! A service's [`Middleware`] settings are compiled into a [`Chain`] when the
+//! routing table is built. Requests pass the IP filter, the rate limit and
+//! authentication before header rules are applied and the request is
+//! forwarded; response header rules run on the way back.
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ipnet::IpNet;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use super::routing::{self, RoutingTable};
+use super::AppState;
+use crate::auth;
+use crate::config::SharedJwtConfig;
+use crate::models::service::{HeaderRules, Middleware, RateLimit, RouteAuth};
+
+/// Headers set from the authenticated identity; client-supplied values are
+/// always dropped.
+pub(crate) const USER_HEADER: &str = "x-forwarded-user";
+const ROLE_HEADER: &str = "x-forwarded-role";
+
+/// Upper bound on remembered basic auth logins per route.
+const MAX_VERIFIED: usize = 1024;
+
+/// Rate limit buckets kept before idle ones are swept.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Buckets untouched for this long are dropped when sweeping.
+const BUCKET_IDLE: Duration = Duration::from_secs(600);
+
+/// Query parameter that carries a login code back to a protected host.
+const LOGIN_CODE_PARAM: &str = "rustainer_login";
+
+/// How long a login code can be redeemed for.
+const LOGIN_CODE_TTL: Duration = Duration::from_secs(60);
+
+/// A login handed from the admin UI to a protected host, once.
+struct LoginCode {
+    token: String,
+    host: String,
+    expires: Instant,
+}
+
+static LOGIN_CODES: LazyLock<Mutex<HashMap<String, LoginCode>>> = LazyLock::new(Default::default);
+
+/// Header changes with names and values already parsed.
+#[derive(Debug, Default)]
+struct CompiledHeaders {
+    set: Vec<(HeaderName, HeaderValue)>,
+    remove: Vec<HeaderName>,
+}
+
+impl CompiledHeaders {
+    fn compile(rules: &HeaderRules, extra: &HashMap<String, String>) -> Result<Self> {
+        let mut set = Vec::new();
+        for (name, value) in extra.iter().chain(&rules.set) {
+            let name = HeaderName::try_from(name.as_str())
+                .with_context(|| format!("Invalid header name {}", name))?;
+            let value = HeaderValue::try_from(value.as_str())
+                .with_context(|| format!("Invalid value for header {}", name))?;
+            set.push((name, value));
+        }
+
+        let remove = rules
+            .remove
+            .iter()
+            .map(|name| HeaderName::try_from(name.as_str()).with_context(|| format!("Invalid header name {}", name)))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { set, remove })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.remove.is_empty()
+    }
+
+    fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Auth {
+    Basic {
+        challenge: HeaderValue,
+        users: HashMap<String, String>,
+        /// Digests of credentials that already passed the (slow) hash check.
+        verified: Mutex<HashSet<Vec<u8>>>,
+    },
+    Jwt {
+        roles: Vec<String>,
+        login_url: Option<String>,
+    },
+}
+
+/// The response sent instead of forwarding a rejected request.
+pub type Rejection = Box<Response<Body>>;
+
+/// Compiled middleware for one route.
+#[derive(Debug)]
+pub struct Chain {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    rate_limit: Option<RateLimit>,
+    auth: Option<Auth>,
+    request_headers: CompiledHeaders,
+    response_headers: CompiledHeaders,
+}
+
+/// Compile a service's middleware, folding in its legacy `headers` map as
+/// request headers. Returns `None` when there is nothing to do.
+pub fn compile(middleware: &Middleware, headers: &HashMap<String, String>) -> Result<Option<Chain>> {
+    let parse_nets = |cidrs: &[String]| -> Result<Vec<IpNet>> {
+        cidrs.iter().map(|cidr| parse_net(cidr)).collect()
+    };
+
+    if let Some(limit) = &middleware.rate_limit {
+        if limit.requests_per_second.is_nan() || limit.requests_per_second <= 0.0 || limit.burst == 0 {
+            return Err(anyhow!("Rate limit needs a positive rate and burst"));
+        }
+    }
+
+    let auth = match &middleware.auth {
+        None => None,
+        Some(RouteAuth::Basic { realm, users }) => {
+            let realm = realm.as_deref().unwrap_or("Restricted");
+            let challenge = HeaderValue::try_from(format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm))
+                .context("Invalid basic auth realm")?;
+            Some(Auth::Basic {
+                challenge,
+                users: users.clone(),
+                verified: Mutex::new(HashSet::new()),
+            })
+        }
+        Some(RouteAuth::Jwt { roles, login_url }) => Some(Auth::Jwt {
+            roles: roles.clone(),
+            login_url: login_url.clone(),
+        }),
+    };
+
+    let chain = Chain {
+        allow: parse_nets(&middleware.allow)?,
+        deny: parse_nets(&middleware.deny)?,
+        rate_limit: middleware.rate_limit.clone(),
+        auth,
+        request_headers: CompiledHeaders::compile(&middleware.request_headers, headers)?,
+        response_headers: CompiledHeaders::compile(&middleware.response_headers, &HashMap::new())?,
+    };
+
+    let empty = chain.allow.is_empty()
+        && chain.deny.is_empty()
+        && chain.rate_limit.is_none()
+        && chain.auth.is_none()
+        && chain.request_headers.is_empty()
+        && chain.response_headers.is_empty();
+
+    Ok((!empty).then_some(chain))
+}
+
+/// Parse a CIDR, or a bare address as a single-host network.
+fn parse_net(cidr: &str) -> Result<IpNet> {
+    let cidr = cidr.trim();
+    cidr.parse::<IpNet>()
+        .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| anyhow!("Invalid CIDR {}", cidr))
+}
+
+/// Replace plain basic auth passwords with Argon2 hashes before storing.
+pub fn hash_passwords(middleware: &mut Middleware) -> Result<()> {
+    if let Some(RouteAuth::Basic { users, .. }) = &mut middleware.auth {
+        for password in users.values_mut() {
+            if !password.starts_with("$argon2") {
+                *password = auth::hash_password(password)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Chain {
+    /// Whether the route takes Rustainer logins.
+    fn uses_jwt(&self) -> bool {
+        matches!(self.auth, Some(Auth::Jwt { .. }))
+    }
+
+    /// Run the request side of the chain. An `Err` is the response to send
+    /// instead of forwarding the request.
+    pub fn before(
+        &self,
+        state: &AppState,
+        route_id: &str,
+        client: Option<IpAddr>,
+        req: &mut Request<Body>,
+    ) -> Result<(), Rejection> {
+        if !self.ip_allowed(client) {
+            return Err(Box::new(status(StatusCode::FORBIDDEN)));
+        }
+
+        if let (Some(limit), Some(ip)) = (&self.rate_limit, client) {
+            if let Err(retry_after) = state.rate_limits.check(route_id, ip, limit) {
+                let mut response = status(StatusCode::TOO_MANY_REQUESTS);
+                response.headers_mut().insert(
+                    header::RETRY_AFTER,
+                    HeaderValue::from(retry_after.as_secs_f64().ceil().max(1.0) as u64),
+                );
+                return Err(Box::new(response));
+            }
+        }
+
+        if let Some(auth) = &self.auth {
+            self.authenticate(state, auth, req)?;
+        }
+
+        self.request_headers.apply(req.headers_mut());
+        Ok(())
+    }
+
+    /// Run the response side of the chain.
+    pub fn after(&self, response: &mut Response<Body>) {
+        self.response_headers.apply(response.headers_mut());
+    }
+
+    fn ip_allowed(&self, client: Option<IpAddr>) -> bool {
+        let Some(ip) = client else {
+            // Without a peer address only an unrestricted route can be served
+            return self.allow.is_empty() && self.deny.is_empty();
+        };
+
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+
+    fn authenticate(&self, state: &AppState, auth: &Auth, req: &mut Request<Body>) -> Result<(), Rejection> {
+        req.headers_mut().remove(USER_HEADER);
+        req.headers_mut().remove(ROLE_HEADER);
+
+        match auth {
+            Auth::Basic { challenge, users, verified } => {
+                let user = basic_credentials(req.headers())
+                    .filter(|(user, password)| check_basic(users, verified, user, password))
+                    .map(|(user, _)| user);
+
+                match user.and_then(|user| HeaderValue::try_from(user).ok()) {
+                    Some(user) => {
+                        // The credentials are for the proxy, not the service
+                        req.headers_mut().remove(header::AUTHORIZATION);
+                        req.headers_mut().insert(USER_HEADER, user);
+                        Ok(())
+                    }
+                    None => {
+                        let mut response = status(StatusCode::UNAUTHORIZED);
+                        response.headers_mut().insert(header::WWW_AUTHENTICATE, challenge.clone());
+                        Err(Box::new(response))
+                    }
+                }
+            }
+            Auth::Jwt { roles, login_url } => check_jwt(&state.jwt, roles, login_url.as_deref(), req),
+        }
+    }
+}
+
+/// Check a Rustainer login against the roles a route allows, and pass the
+/// user on in headers instead of the token.
+fn check_jwt(
+    jwt: &SharedJwtConfig,
+    roles: &[String],
+    login_url: Option<&str>,
+    req: &mut Request<Body>,
+) -> Result<(), Rejection> {
+    if let Some(code) = query_param(req.uri(), LOGIN_CODE_PARAM) {
+        return Err(Box::new(redeem_login(jwt, &code, req)));
+    }
+    let claims = auth::token_from_headers(req.headers()).and_then(|token| auth::validate_token(&token, jwt).ok());
+
+    let Some(claims) = claims else {
+        return Err(Box::new(login_required(login_url, req)));
+    };
+    if !roles.is_empty() && !roles.contains(&claims.role) {
+        return Err(Box::new(status(StatusCode::FORBIDDEN)));
+    }
+
+    // The token works on the Rustainer API, so the service must never see it
+    strip_token(req.headers_mut());
+    if let Ok(user) = HeaderValue::try_from(claims.username) {
+        req.headers_mut().insert(USER_HEADER, user);
+    }
+    if let Ok(role) = HeaderValue::try_from(claims.role) {
+        req.headers_mut().insert(ROLE_HEADER, role);
+    }
+    Ok(())
+}
+
+/// Where to send a browser that logged in on the admin UI with `rd` set.
+///
+/// The login cookie belongs to the admin UI's host, so `rd` gets a one-time
+/// code instead, which the protected host swaps for a cookie of its own.
+/// Returns `None` when `rd` is not an http(s) URL on a route that takes
+/// Rustainer logins, so logins cannot be sent anywhere else.
+pub fn login_redirect(table: &RoutingTable, rd: &str, token: &str) -> Option<String> {
+    let uri: axum::http::Uri = rd.parse().ok()?;
+    if !matches!(uri.scheme_str(), Some("http" | "https")) || rd.contains('#') {
+        return None;
+    }
+    let host = routing::normalize_host(uri.authority()?.as_str());
+    let route = table.lookup(&host, uri.path())?.route.as_ref()?;
+    if !route.middleware.as_ref().is_some_and(Chain::uses_jwt) {
+        return None;
+    }
+
+    let code: String = rand::random::<[u8; 32]>().iter().map(|b| format!("{:02x}", b)).collect();
+    let mut codes = LOGIN_CODES.lock().unwrap();
+    let now = Instant::now();
+    codes.retain(|_, code| code.expires > now);
+    codes.insert(
+        code.clone(),
+        LoginCode {
+            token: token.to_string(),
+            host,
+            expires: now + LOGIN_CODE_TTL,
+        },
+    );
+    let separator = if uri.query().is_some() { '&' } else { '?' };
+    Some(format!("{}{}{}={}", rd, separator, LOGIN_CODE_PARAM, code))
+}
+
+/// Swap a login code for a cookie on the host it was issued for, and send the
+/// browser on to the URL without the code. Unknown or expired codes get no
+/// cookie, so the browser is asked to log in again.
+fn redeem_login(jwt: &SharedJwtConfig, code: &str, req: &Request<Body>) -> Response<Body> {
+    let host = req.headers().get(header::HOST).and_then(|h| h.to_str().ok()).map(routing::normalize_host);
+    let login = LOGIN_CODES.lock().unwrap().remove(code);
+    let token = login
+        .filter(|login| login.expires > Instant::now() && Some(&login.host) == host.as_ref())
+        .map(|login| login.token)
+        .filter(|token| auth::validate_token(token, jwt).is_ok());
+
+    let path = req.uri().path();
+    let query: Vec<&str> = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && pair.split_once('=').map_or(*pair, |(name, _)| name) != LOGIN_CODE_PARAM)
+        .collect();
+    let location = if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.join("&")) };
+
+    let mut response = status(StatusCode::FOUND);
+    if let Ok(value) = HeaderValue::try_from(location) {
+        response.headers_mut().insert(header::LOCATION, value);
+    }
+    if let Some(token) = token {
+        let secure = if req.extensions().get::<super::tls::HttpsConnection>().is_some() { "; Secure" } else { "" };
+        let cookie = format!(
+            "{}={}; HttpOnly; Path=/; SameSite=Lax; Max-Age={}{}",
+            auth::TOKEN_COOKIE,
+            token,
+            jwt.expiration,
+            secure
+        );
+        if let Ok(value) = HeaderValue::try_from(cookie) {
+            response.headers_mut().insert(header::SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+/// Value of a query parameter, as sent.
+fn query_param(uri: &axum::http::Uri, name: &str) -> Option<String> {
+    uri.query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Remove a bearer `Authorization` header and the login cookie, keeping any
+/// other cookies.
+fn strip_token(headers: &mut HeaderMap) {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("Bearer "));
+    if bearer {
+        headers.remove(header::AUTHORIZATION);
+    }
+
+    let cookies: Vec<String> = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(str::trim)
+        .filter(|cookie| !cookie.is_empty())
+        .filter(|cookie| cookie.split_once('=').map_or(*cookie, |(name, _)| name) != auth::TOKEN_COOKIE)
+        .map(str::to_string)
+        .collect();
+    headers.remove(header::COOKIE);
+    if let Ok(value) = HeaderValue::try_from(cookies.join("; ")) {
+        if !cookies.is_empty() {
+            headers.insert(header::COOKIE, value);
+        }
+    }
+}
+
+/// Decode `Authorization: Basic` credentials.
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// Check basic auth credentials, remembering ones that passed.
+fn check_basic(
+    users: &HashMap<String, String>,
+    verified: &Mutex<HashSet<Vec<u8>>>,
+    user: &str,
+    password: &str,
+) -> bool {
+    let Some(hash) = users.get(user) else {
+        return false;
+    };
+
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    for part in [user, password, hash] {
+        context.update(part.as_bytes());
+        context.update(&[0]);
+    }
+    let digest = context.finish().as_ref().to_vec();
+
+    if verified.lock().unwrap().contains(&digest) {
+        return true;
+    }
+    if !auth::verify_password(password, hash) {
+        return false;
+    }
+
+    let mut verified = verified.lock().unwrap();
+    if verified.len() >= MAX_VERIFIED {
+        verified.clear();
+    }
+    verified.insert(digest);
+    true
+}
+
+/// Answer a request without a valid login, sending browsers to the login
+/// page when one is configured.
+fn login_required(login_url: Option<&str>, req: &Request<Body>) -> Response<Body> {
+    let wants_html = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    match login_url {
+        Some(login_url) if wants_html && req.method() == Method::GET => {
+            let host = req.headers().get(header::HOST).and_then(|h| h.to_str().ok()).unwrap_or("");
+            let scheme = if req.extensions().get::<super::tls::HttpsConnection>().is_some() { "https" } else { "http" };
+            let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+            let original = format!("{}://{}{}", scheme, host, path);
+            let separator = if login_url.contains('?') { '&' } else { '?' };
+            let location = format!(
+                "{}{}rd={}",
+                login_url,
+                separator,
+                percent_encoding::utf8_percent_encode(&original, percent_encoding::NON_ALPHANUMERIC)
+            );
+
+            let mut response = status(StatusCode::FOUND);
+            if let Ok(value) = HeaderValue::try_from(location) {
+                response.headers_mut().insert(header::LOCATION, value);
+            }
+            response
+        }
+        _ => status(StatusCode::UNAUTHORIZED),
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets for every rate limited route, keyed by route and client IP.
+/// Kept outside the routing table so reloads do not reset them.
+#[derive(Debug, Default)]
+pub struct RateLimiters {
+    buckets: Mutex<HashMap<(String, IpAddr), Bucket>>,
+}
+
+impl RateLimiters {
+    /// Take a token for a request, or say how long until one is available.
+    pub fn check(&self, route: &str, ip: IpAddr, limit: &RateLimit) -> Result<(), Duration> {
+        let now = Instant::now();
+        let burst = limit.burst as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated) < BUCKET_IDLE);
+        }
+
+        let bucket = buckets
+            .entry((route.to_string(), ip))
+            .or_insert(Bucket { tokens: burst, updated: now });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.requests_per_second).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.requests_per_second))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(middleware: Middleware) -> Chain {
+        compile(&middleware, &HashMap::new()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_ip_allow_and_deny_lists() {
+        let chain = chain(Middleware {
+            allow: vec!["10.0.0.0/8".to_string(), "::1".to_string()],
+            deny: vec!["10.1.0.0/16".to_string()],
+            ..Default::default()
+        });
+
+        assert!(chain.ip_allowed(Some("10.2.3.4".parse().unwrap())));
+        assert!(chain.ip_allowed(Some("::1".parse().unwrap())));
+        assert!(!chain.ip_allowed(Some("10.1.2.3".parse().unwrap())));
+        assert!(!chain.ip_allowed(Some("192.168.1.1".parse().unwrap())));
+        assert!(!chain.ip_allowed(None));
+        assert!(compile(&Middleware { deny: vec!["nope".to_string()], ..Default::default() }, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_limits_per_ip() {
+        let limiters = RateLimiters::default();
+        let limit = RateLimit { requests_per_second: 1.0, burst: 3 };
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        for _ in 0..3 {
+            assert!(limiters.check("web", a, &limit).is_ok());
+        }
+        let retry_after = limiters.check("web", a, &limit).unwrap_err();
+        assert!(retry_after <= Duration::from_secs(1));
+        assert!(limiters.check("web", b, &limit).is_ok());
+        assert!(limiters.check("api", a, &limit).is_ok());
+    }
+
+    #[test]
+    fn test_header_rules_and_service_headers() {
+        let mut extra = HashMap::new();
+        extra.insert("X-Env".to_string(), "prod".to_string());
+        let chain = compile(
+            &Middleware {
+                request_headers: HeaderRules {
+                    set: HashMap::from([("X-Team".to_string(), "web".to_string())]),
+                    remove: vec!["Cookie".to_string()],
+                },
+                ..Default::default()
+            },
+            &extra,
+        )
+        .unwrap()
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("a=b"));
+        chain.request_headers.apply(&mut headers);
+        assert_eq!(headers["x-env"], "prod");
+        assert_eq!(headers["x-team"], "web");
+        assert!(!headers.contains_key(header::COOKIE));
+    }
+
+    #[test]
+    fn test_basic_credentials_are_checked_against_hashes() {
+        let mut middleware = Middleware {
+            auth: Some(RouteAuth::Basic {
+                realm: None,
+                users: HashMap::from([("alice".to_string(), "s3cret".to_string())]),
+            }),
+            ..Default::default()
+        };
+        hash_passwords(&mut middleware).unwrap();
+        let Some(RouteAuth::Basic { users, .. }) = &middleware.auth else { unreachable!() };
+        assert!(users["alice"].starts_with("$argon2"));
+
+        let verified = Mutex::new(HashSet::new());
+        assert!(check_basic(users, &verified, "alice", "s3cret"));
+        assert!(check_basic(users, &verified, "alice", "s3cret"));
+        assert!(!check_basic(users, &verified, "alice", "wrong"));
+        assert!(!check_basic(users, &verified, "bob", "s3cret"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Basic YWxpY2U6czNjcmV0"));
+        assert_eq!(basic_credentials(&headers), Some(("alice".to_string(), "s3cret".to_string())));
+    }
+
+    fn test_jwt() -> (SharedJwtConfig, String) {
+        let jwt: SharedJwtConfig = std::sync::Arc::new(crate::config::JwtConfig {
+            secret: "test-secret".to_string(),
+            expiration: 3600,
+        });
+        let user = crate::models::User {
+            id: "1".to_string(),
+            username: "admin".to_string(),
+            password_hash: String::new(),
+            role: crate::models::user::UserRole::Admin,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+        let token = auth::create_token(&user, &jwt).unwrap();
+        (jwt, token)
+    }
+
+    #[test]
+    fn test_login_redirect_hands_the_login_to_the_protected_host() {
+        let route = |auth: Option<RouteAuth>| {
+            Some(std::sync::Arc::new(super::super::Route {
+                id: "web".to_string(),
+                name: "web".to_string(),
+                target: super::super::Target::Static(Default::default()),
+                middleware: compile(&Middleware { auth, ..Default::default() }, &HashMap::new()).unwrap(),
+            }))
+        };
+        let rule = |domain: &str, auth: Option<RouteAuth>| routing::Rule {
+            host: routing::HostPattern::parse(domain),
+            path_prefix: "/".to_string(),
+            strip_prefix: false,
+            rewrite_prefix: None,
+            priority: 0,
+            ssl: false,
+            route: route(auth),
+        };
+        let table = RoutingTable::new(vec![
+            rule("app.test", Some(RouteAuth::Jwt { roles: Vec::new(), login_url: None })),
+            rule("open.test", None),
+        ]);
+        let (jwt, token) = test_jwt();
+
+        assert!(login_redirect(&table, "http://open.test/", &token).is_none());
+        assert!(login_redirect(&table, "http://elsewhere.test/", &token).is_none());
+        assert!(login_redirect(&table, "ftp://app.test/", &token).is_none());
+        let redirect = login_redirect(&table, "https://app.test/page?x=1", &token).unwrap();
+        assert!(redirect.starts_with("https://app.test/page?x=1&rustainer_login="));
+        assert!(!redirect.contains(&token));
+
+        let callback = |url: &str, host: &str| {
+            let mut req = Request::builder().uri(url).body(Body::empty()).unwrap();
+            req.headers_mut().insert(header::HOST, HeaderValue::try_from(host).unwrap());
+            check_jwt(&jwt, &[], None, &mut req).unwrap_err()
+        };
+
+        // A code only works on its own host, and only once
+        assert!(!callback(&redirect, "other.test").headers().contains_key(header::SET_COOKIE));
+        let redirect = login_redirect(&table, "https://app.test/page?x=1", &token).unwrap();
+        let response = callback(&redirect, "app.test");
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[header::LOCATION], "/page?x=1");
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with(&format!("{}={};", auth::TOKEN_COOKIE, token)));
+        assert!(!callback(&redirect, "app.test").headers().contains_key(header::SET_COOKIE));
+    }
+
+    #[tokio::test]
+    async fn test_jwt_login_is_not_forwarded() {
+        use axum::{routing::get, Json, Router};
+        use http_body_util::BodyExt;
+
+        // Upstream echoes the headers it receives
+        let upstream = Router::new().route(
+            "/",
+            get(|headers: HeaderMap| async move {
+                let headers: HashMap<String, String> = headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or_default().to_string()))
+                    .collect();
+                Json(headers)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+
+        let (jwt, token) = test_jwt();
+
+        for (name, value, cookie) in [
+            (header::AUTHORIZATION, format!("Bearer {}", token), None),
+            (header::COOKIE, format!("theme=dark; {}={}; lang=en", auth::TOKEN_COOKIE, token), Some("theme=dark; lang=en")),
+        ] {
+            let mut req = Request::builder().uri("/").body(Body::empty()).unwrap();
+            req.headers_mut().insert(name, HeaderValue::try_from(value).unwrap());
+            check_jwt(&jwt, &[], None, &mut req).unwrap();
+
+            let client = super::super::build_client();
+            let response = super::super::forward_request(&client, req, &format!("http://{}/", addr))
+                .await
+                .unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let seen: HashMap<String, String> = serde_json::from_slice(&body).unwrap();
+
+            assert!(seen.values().all(|value| !value.contains(&token)), "{:?}", seen);
+            assert!(!seen.contains_key("authorization"));
+            assert_eq!(seen["x-forwarded-user"], "admin");
+            assert_eq!(seen.get("cookie").map(String::as_str), cookie);
+        }
+    }
+}
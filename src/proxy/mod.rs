@@ -1,193 +1,358 @@
 use axum::{
+    body::Body,
     extract::State,
-    http::{Request, StatusCode, Uri},
+    http::{header, HeaderValue, Request, StatusCode, Uri, Version},
     response::Response,
 };
-use hyper::body::Bytes;
-use hyper_util::client::legacy::Client;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use futures::StreamExt;
 use hyper_util::rt::TokioExecutor;
-use http_body_util::Full;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use sqlx::SqlitePool;
-use tracing::{error, info};
+use tracing::error;
 
+use crate::config::SharedJwtConfig;
+use crate::models::service::{LoadBalancing, Service, ServiceType};
 use crate::models::Application;
 
-pub async fn handle_proxy_request<B>(
+pub mod access_log;
+pub mod acme;
+pub mod balancer;
+pub mod discovery;
+pub mod health;
+pub mod metrics;
+pub mod middleware;
+pub mod routing;
+pub mod static_site;
+pub mod tls;
+mod upgrade;
+
+/// How long to wait for an upstream to start answering a request.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// HTTP client used to reach upstream containers and external URLs. Bodies
+/// are streamed in both directions, so requests and responses are never
+/// buffered whole.
+pub type ProxyClient = Client<HttpsConnector<HttpConnector>, Body>;
+
+/// Build the pooled client shared by every proxied request.
+pub fn build_client() -> ProxyClient {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    let tls_config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default TLS versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let connector = HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http()
+        .enable_http1()
+        .build();
+
+    Client::builder(TokioExecutor::new()).build(connector)
+}
+
+/// Where a matched domain sends its traffic.
+#[derive(Debug)]
+pub struct Route {
+    pub id: String,
+    pub name: String,
+    pub target: Target,
+    pub middleware: Option<middleware::Chain>,
+}
+
+/// What serves a route.
+#[derive(Debug)]
+pub enum Target {
+    /// Load balance over one or more containers.
+    Containers {
+        pool: balancer::PoolConfig,
+        strategy: LoadBalancing,
+    },
+    /// Files from a directory.
+    Static(PathBuf),
+    /// An external HTTP(S) server; the matched path is appended to its path.
+    Url(Uri),
+}
+
+impl Route {
+    /// Route for an enabled application that targets a container or label.
+    pub fn for_application(application: Application) -> Option<Self> {
+        if !application.enabled {
+            return None;
+        }
+
+        // Work out which containers the application targets
+        let selector = match (application.target_label, application.container_id) {
+            (Some(label), _) => balancer::Selector::Label(label),
+            (None, Some(id)) => balancer::Selector::Container(id),
+            (None, None) => return None,
+        };
+
+        Some(Self {
+            id: application.id,
+            name: application.name,
+            target: Target::Containers {
+                pool: balancer::PoolConfig {
+                    selector,
+                    port: application.container_port as u16,
+                    health_check: application.health_check.map(|check| check.0),
+                },
+                strategy: application.lb_strategy,
+            },
+            middleware: None,
+        })
+    }
+
+    /// Route for an enabled service of any type.
+    pub async fn for_service(docker: &bollard::Docker, service: Service) -> Option<Self> {
+        if !service.enabled {
+            return None;
+        }
+
+        let target = match service.service_type {
+            ServiceType::Container => Target::Containers {
+                pool: balancer::PoolConfig {
+                    selector: balancer::Selector::from_target(&service.target),
+                    port: service.port,
+                    health_check: service.health_check,
+                },
+                strategy: service.load_balancing,
+            },
+            ServiceType::StaticSite => match static_site::resolve_root(docker, &service.target).await {
+                Ok(root) => Target::Static(root),
+                Err(e) => {
+                    error!("Cannot serve {} from {}: {}", service.domain, service.target, e);
+                    return None;
+                }
+            },
+            ServiceType::CustomURL => match service.target.parse::<Uri>() {
+                Ok(uri) if uri.scheme().is_some() && uri.authority().is_some() => Target::Url(uri),
+                _ => {
+                    error!("Invalid URL {} for {}", service.target, service.domain);
+                    return None;
+                }
+            },
+        };
+
+        // A route whose middleware cannot be built must not be served without it
+        let middleware = match middleware::compile(&service.middleware, &service.headers) {
+            Ok(middleware) => middleware,
+            Err(e) => {
+                error!("Invalid middleware for {}: {}", service.domain, e);
+                return None;
+            }
+        };
+
+        Some(Self {
+            id: service.id.to_string(),
+            name: service.name,
+            target,
+            middleware,
+        })
+    }
+
+    /// The container pool behind this route, if it has one.
+    pub fn pool(&self) -> Option<&balancer::PoolConfig> {
+        match &self.target {
+            Target::Containers { pool, .. } => Some(pool),
+            _ => None,
+        }
+    }
+}
+
+pub async fn handle_proxy_request(
     State(state): State<Arc<AppState>>,
-    req: Request<B>,
-) -> Result<Response<Full<Bytes>>, StatusCode>
-where
-    B: axum::body::HttpBody + Send + 'static,
-    B::Data: Send,
-    B::Error: std::error::Error + Send + Sync + 'static,
-{
+    req: Request<Body>,
+) -> Response<Body> {
+    let (mut exchange, req) = metrics::Exchange::start(req);
+    let result = route_request(&state, &mut exchange, req).await;
+    exchange.finish(&state.access_log, result)
+}
+
+/// Match a request against the routing table and send it to its target.
+async fn route_request(
+    state: &AppState,
+    exchange: &mut metrics::Exchange,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
     // Extract host from request
-    let host = match req.headers().get("host") {
+    let host = match req.headers().get(header::HOST) {
         Some(host) => match host.to_str() {
             Ok(host) => host.to_string(),
             Err(_) => return Err(StatusCode::BAD_REQUEST),
         },
-        None => return Err(StatusCode::BAD_REQUEST),
+        None => match req.uri().authority() {
+            Some(authority) => authority.to_string(),
+            None => return Err(StatusCode::BAD_REQUEST),
+        },
     };
 
-    // Find application by domain
-    let application = match find_application_by_domain(&state.db, &host).await {
-        Ok(Some(app)) => app,
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
+    // Match the request against the routing table
+    let table = state.routes.read().unwrap().clone();
+    let rule = table
+        .lookup(&host, req.uri().path())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let route = rule.route.clone().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let path = rule.upstream_path(req.uri().path());
+    exchange.matched(&state.metrics, &route);
 
-    // Check if application is enabled
-    if !application.enabled {
-        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    // Run the route's middleware before anything reaches the target
+    if let Some(chain) = &route.middleware {
+        let client = metrics::client_ip(&req);
+        if let Err(response) = chain.before(state, &route.id, client, &mut req) {
+            return Ok(*response);
+        }
+        exchange.authenticated(req.headers());
     }
 
-    // Check if container ID is available
-    let container_id = match application.container_id {
-        Some(id) => id,
-        None => return Err(StatusCode::SERVICE_UNAVAILABLE),
-    };
+    let mut response = match &route.target {
+        Target::Containers { pool, strategy } => {
+            proxy_to_containers(state, &route.id, pool, *strategy, &path, req).await
+        }
+        Target::Static(root) => Ok(static_site::serve(root, &path, req).await),
+        Target::Url(base) => {
+            let target_uri = match req.uri().query() {
+                Some(query) => format!("{}?{}", join_url(base, &path), query),
+                None => join_url(base, &path),
+            };
 
-    // Get container IP address
-    let container_ip = match get_container_ip(&state.docker, &container_id).await {
-        Ok(ip) => ip,
-        Err(_) => return Err(StatusCode::SERVICE_UNAVAILABLE),
-    };
+            // External servers pick their virtual host from Host
+            if let Some(original) = req.headers().get(header::HOST).cloned() {
+                req.headers_mut().insert("x-forwarded-host", original);
+            }
+            if let Some(authority) = base.authority().and_then(|a| HeaderValue::from_str(a.as_str()).ok()) {
+                req.headers_mut().insert(header::HOST, authority);
+            }
 
-    // Create target URI
-    let target_uri = format!(
-        "http://{}:{}{}", 
-        container_ip, 
-        application.container_port,
-        req.uri().path_and_query().map_or("", |p| p.as_str())
-    );
-
-    // Forward request to container
-    forward_request(req, &target_uri).await
+            forward_request(&state.client, req, &target_uri).await
+        }
+    }?;
+
+    if let Some(chain) = &route.middleware {
+        chain.after(&mut response);
+    }
+    Ok(response)
 }
 
-async fn find_application_by_domain(
-    pool: &SqlitePool,
-    domain: &str,
-) -> Result<Option<Application>, sqlx::Error> {
-    sqlx::query_as::<_, Application>(
-        r#"
-        SELECT 
-            id, 
-            name, 
-            domain, 
-            container_id, 
-            container_port, 
-            enabled,
-            created_at as "created_at: chrono::DateTime<chrono::Utc>",
-            updated_at as "updated_at: chrono::DateTime<chrono::Utc>"
-        FROM applications 
-        WHERE domain = ?
-        "#
-    )
-    .bind(domain)
-    .fetch_optional(pool)
-    .await
+/// Append a request path to the path of an external base URL.
+fn join_url(base: &Uri, path: &str) -> String {
+    let scheme = base.scheme_str().unwrap_or("http");
+    let authority = base.authority().map_or("", |a| a.as_str());
+    let base_path = base.path().trim_end_matches('/');
+    format!("{}://{}{}{}", scheme, authority, base_path, path)
 }
 
-async fn get_container_ip(
-    docker: &bollard::Docker,
-    container_id: &str,
-) -> Result<String, bollard::errors::Error> {
-    // Inspect container to get network settings
-    let container_info = docker
-        .inspect_container(container_id, None::<bollard::query_parameters::InspectContainerOptions>)
-        .await?;
-
-    // Extract IP address from container info
-    if let Some(network_settings) = container_info.network_settings {
-        if let Some(networks) = network_settings.networks {
-            // Try to get IP from bridge network first
-            if let Some(bridge_network) = networks.get("bridge") {
-                if let Some(ip) = &bridge_network.ip_address {
-                    if !ip.is_empty() {
-                        return Ok(ip.clone());
-                    }
-                }
-            }
+/// Send a request to one of the containers behind a route.
+async fn proxy_to_containers(
+    state: &AppState,
+    route_id: &str,
+    pool_config: &balancer::PoolConfig,
+    strategy: LoadBalancing,
+    path: &str,
+    req: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
+    // Pick a running container to send the request to
+    let pool = state
+        .balancers
+        .pool(&state.docker, route_id, pool_config.clone())
+        .await;
+    let (backend, set_cookie) = pool
+        .pick(strategy, req.headers())
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
 
-            // If bridge network IP is not available, use the first available IP
-            for (_, network) in networks {
-                if let Some(ip) = network.ip_address {
-                    if !ip.is_empty() {
-                        return Ok(ip);
-                    }
-                }
-            }
+    // Create target URI
+    let target_uri = match req.uri().query() {
+        Some(query) => format!("http://{}:{}{}?{}", backend.ip, pool_config.port, path, query),
+        None => format!("http://{}:{}{}", backend.ip, pool_config.port, path),
+    };
+
+    // Forward request to container, counting it until the response body is done
+    let guard = backend.track();
+    let result = forward_request(&state.client, req, &target_uri).await;
+
+    // Feed the outcome into passive outlier detection
+    let failed = match &result {
+        Ok(response) => matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(_) => true,
+    };
+    backend.record_response(!failed);
+    let mut response = result?;
+
+    if set_cookie {
+        let cookie = format!("{}={}; Path=/; HttpOnly", balancer::STICKY_COOKIE, backend.id);
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(header::SET_COOKIE, value);
         }
     }
 
-    Err(bollard::errors::Error::IOError {
-        err: std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "Container IP address not found",
-        ),
-    })
+    Ok(response.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _ = &guard;
+            chunk
+        }))
+    }))
 }
 
-async fn forward_request<B>(
-    req: Request<B>,
+async fn forward_request(
+    client: &ProxyClient,
+    mut req: Request<Body>,
     target_uri: &str,
-) -> Result<Response<Full<Bytes>>, StatusCode>
-where
-    B: axum::body::HttpBody + Send + 'static,
-    B::Data: Send,
-    B::Error: std::error::Error + Send + Sync + 'static,
-{
+) -> Result<Response<Body>, StatusCode> {
     // Parse target URI
     let target_uri = target_uri.parse::<Uri>().map_err(|_| StatusCode::BAD_REQUEST)?;
 
-    // Create HTTP client
-    let client = Client::builder(TokioExecutor::new())
-        .build::<_, Full<Bytes>>(hyper_util::client::legacy::connect::HttpConnector::new());
-
-    // Create new request with same method, headers, and body
-    let (parts, _body) = req.into_parts();
-    
-    // Simplified body handling - empty body for now
-    let body_bytes = Bytes::new();
-
-    // Build new request
-    let mut builder = Request::builder()
-        .method(parts.method)
-        .uri(target_uri);
-
-    // Copy headers
-    for (name, value) in parts.headers.iter() {
-        builder = builder.header(name, value);
-    }
+    // Take over the client connection if this is a protocol switch
+    let is_upgrade = upgrade::is_upgrade_request(&req);
+    let client_upgrade = if is_upgrade {
+        req.extensions_mut().remove::<hyper::upgrade::OnUpgrade>()
+    } else {
+        None
+    };
 
-    // Build request with body
-    let forwarded_req = builder
-        .body(Full::new(body_bytes))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Point the incoming request at the container, keeping method, headers
+    // and the streaming body as they are
+    let (mut parts, body) = req.into_parts();
+    parts.uri = target_uri;
+    parts.version = Version::HTTP_11;
+    upgrade::strip_hop_by_hop_headers(&mut parts.headers, is_upgrade);
+    let forwarded_req = Request::from_parts(parts, body);
 
     // Send request to target
-    match client.request(forwarded_req).await {
-        Ok(response) => {
-            // Convert response back to axum response
-            let (parts, _body) = response.into_parts();
-            
-            // Simplified body handling - empty body for now
-            let body_bytes = Bytes::new();
-            
-            let mut builder = Response::builder()
-                .status(parts.status);
-            
-            // Copy headers
-            for (name, value) in parts.headers.iter() {
-                builder = builder.header(name, value);
+    let response = match tokio::time::timeout(UPSTREAM_TIMEOUT, client.request(forwarded_req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            error!("Upstream did not respond within {:?}", UPSTREAM_TIMEOUT);
+            return Err(StatusCode::GATEWAY_TIMEOUT);
+        }
+    };
+
+    match response {
+        Ok(mut response) => {
+            let switching = response.status() == StatusCode::SWITCHING_PROTOCOLS;
+            if switching {
+                match client_upgrade {
+                    Some(client_upgrade) => upgrade::spawn_tunnel(client_upgrade, &mut response),
+                    None => {
+                        error!("Upstream switched protocols without an upgrade request");
+                        return Err(StatusCode::BAD_GATEWAY);
+                    }
+                }
             }
-            
-            Ok(builder
-                .body(Full::new(body_bytes))
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?)
+
+            // Hand the upstream body straight back to the client
+            let (mut parts, body) = response.into_parts();
+            upgrade::strip_hop_by_hop_headers(&mut parts.headers, switching);
+            Ok(Response::from_parts(parts, Body::new(body)))
         }
         Err(e) => {
             error!("Error forwarding request: {}", e);
@@ -199,4 +364,111 @@ where
 pub struct AppState {
     pub db: SqlitePool,
     pub docker: bollard::Docker,
-}
\ No newline at end of file
+    pub client: ProxyClient,
+    pub balancers: balancer::Balancers,
+    pub routes: routing::SharedTable,
+    pub discovered: discovery::Discovered,
+    pub certs: Arc<tls::CertStore>,
+    pub acme_challenges: acme::Challenges,
+    pub https_port: u16,
+    pub jwt: SharedJwtConfig,
+    pub rate_limits: middleware::RateLimiters,
+    pub metrics: metrics::Metrics,
+    pub access_log: access_log::AccessLog,
+    pub updates: crate::config::UpdatesConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::Router;
+    use bytes::Bytes;
+    use futures::stream;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn test_forward_request_streams_bodies() {
+        // Upstream echoes whatever it receives
+        let upstream = Router::new().route("/echo", post(|body: Body| async move { body }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+
+        // Send a chunked body larger than any single read buffer
+        let chunk = Bytes::from(vec![b'x'; 64 * 1024]);
+        let chunks = (0..32).map(move |_| Ok::<_, std::io::Error>(chunk.clone()));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/echo")
+            .body(Body::from_stream(stream::iter(chunks)))
+            .unwrap();
+
+        let client = build_client();
+        let response = forward_request(&client, req, &format!("http://{}/echo", addr))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.len(), 32 * 64 * 1024);
+        assert!(body.iter().all(|b| *b == b'x'));
+    }
+
+    #[tokio::test]
+    async fn test_forward_request_tunnels_upgrades() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Upstream switches to a raw echo protocol
+        let upstream = Router::new().fallback(|mut req: Request<Body>| async move {
+            let on_upgrade = hyper::upgrade::on(&mut req);
+            tokio::spawn(async move {
+                let mut io = hyper_util::rt::TokioIo::new(on_upgrade.await.unwrap());
+                let mut buf = [0u8; 4];
+                io.read_exact(&mut buf).await.unwrap();
+                io.write_all(&buf).await.unwrap();
+            });
+            Response::builder()
+                .status(StatusCode::SWITCHING_PROTOCOLS)
+                .header("connection", "upgrade")
+                .header("upgrade", "echo")
+                .body(Body::empty())
+                .unwrap()
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+
+        // Proxy everything to the upstream
+        let client = build_client();
+        let proxy = Router::new().fallback(move |req: Request<Body>| {
+            let client = client.clone();
+            async move { forward_request(&client, req, &format!("http://{}/", upstream_addr)).await }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, proxy).await.unwrap() });
+
+        let mut stream = tokio::net::TcpStream::connect(proxy_addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: app.local\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\n")
+            .await
+            .unwrap();
+
+        // Read the handshake response up to the blank line
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await.unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap().to_ascii_lowercase();
+        assert!(head.starts_with("http/1.1 101"));
+        assert!(head.contains("upgrade: echo"));
+
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+}
@@ -0,0 +1,279 @@
+//! In-memory routing table for the domain proxy.
+//!
+//! Applications and services are compiled into a list of rules whenever they
+//! change, so requests are matched without touching SQLite. A rule matches on
+//! host (exact, `*.example.com`, or `*` for any host) and on a path prefix,
+//! and can strip or rewrite that prefix before the request is forwarded.
+//!
+//! When several rules match, the highest `priority` wins, then the most
+//! specific host, then the longest path prefix.
+
+use anyhow::Result;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use tracing::{error, info};
+
+use super::{AppState, Route};
+use crate::docker::{applications, services};
+
+/// Which hosts a rule applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPattern {
+    /// A single host name.
+    Exact(String),
+    /// Any subdomain of a domain, stored with its leading dot (`.example.com`).
+    Wildcard(String),
+    /// Every host.
+    Any,
+}
+
+impl HostPattern {
+    pub fn parse(domain: &str) -> Self {
+        let domain = normalize_host(domain);
+        if domain == "*" {
+            HostPattern::Any
+        } else if let Some(parent) = domain.strip_prefix("*.") {
+            HostPattern::Wildcard(format!(".{}", parent))
+        } else {
+            HostPattern::Exact(domain)
+        }
+    }
+
+    /// Whether a normalized host matches.
+    pub fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(name) => name == host,
+            HostPattern::Wildcard(suffix) => host.len() > suffix.len() && host.ends_with(suffix.as_str()),
+            HostPattern::Any => true,
+        }
+    }
+
+    /// Higher is more specific: exact names, then longer wildcards.
+    fn specificity(&self) -> (u8, usize) {
+        match self {
+            HostPattern::Exact(name) => (2, name.len()),
+            HostPattern::Wildcard(suffix) => (1, suffix.len()),
+            HostPattern::Any => (0, 0),
+        }
+    }
+}
+
+/// One compiled routing rule.
+#[derive(Debug)]
+pub struct Rule {
+    pub host: HostPattern,
+    pub path_prefix: String,
+    pub strip_prefix: bool,
+    pub rewrite_prefix: Option<String>,
+    pub priority: i32,
+    /// Whether plain HTTP requests should be redirected to HTTPS.
+    pub ssl: bool,
+    /// Where to send matched requests, or `None` if the target is disabled or
+    /// cannot be served.
+    pub route: Option<Arc<Route>>,
+}
+
+impl Rule {
+    fn matches(&self, host: &str, path: &str) -> bool {
+        self.host.matches(host) && path_has_prefix(path, &self.path_prefix)
+    }
+
+    /// The path to send upstream for a matched request path.
+    pub fn upstream_path(&self, path: &str) -> String {
+        let rest = &path[self.path_prefix.len().min(path.len())..];
+        match (&self.rewrite_prefix, self.strip_prefix) {
+            (Some(rewrite), _) => join_path(rewrite, rest),
+            (None, true) => join_path("/", rest),
+            (None, false) => path.to_string(),
+        }
+    }
+}
+
+/// Rules ordered from most to least preferred.
+#[derive(Debug, Default)]
+pub struct RoutingTable {
+    rules: Vec<Rule>,
+}
+
+impl RoutingTable {
+    pub fn new(mut rules: Vec<Rule>) -> Self {
+        rules.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| b.host.specificity().cmp(&a.host.specificity()))
+                .then_with(|| b.path_prefix.len().cmp(&a.path_prefix.len()))
+        });
+        Self { rules }
+    }
+
+    /// Find the rule for a `Host` header value and request path.
+    pub fn lookup(&self, host: &str, path: &str) -> Option<&Rule> {
+        let host = normalize_host(host);
+        self.rules.iter().find(|rule| rule.matches(&host, path))
+    }
+
+    /// Every servable route in the table.
+    pub fn routes(&self) -> impl Iterator<Item = &Arc<Route>> {
+        self.rules.iter().filter_map(|rule| rule.route.as_ref())
+    }
+}
+
+/// The live routing table, swapped as a whole on reload.
+pub type SharedTable = RwLock<Arc<RoutingTable>>;
+
+/// Build the routing table from the database.
+pub async fn compile(state: &AppState) -> Result<RoutingTable> {
+    let mut rules = Vec::new();
+
+    for application in applications::list_applications(&state.db).await? {
+        rules.push(Rule {
+            host: HostPattern::parse(&application.domain),
+            path_prefix: normalize_prefix(&application.path_prefix),
+            strip_prefix: application.strip_prefix,
+            rewrite_prefix: application.rewrite_prefix.clone(),
+            priority: application.priority,
+            ssl: application.enabled && application.ssl_enabled,
+            route: Route::for_application(application).map(Arc::new),
+        });
+    }
+
+    // Discovered services come last so stored ones win ties
+    let services = services::list_services(&state.db).await?;
+    for service in services.into_iter().chain(state.discovered.list()) {
+        if !service.enabled {
+            continue;
+        }
+        rules.push(Rule {
+            host: HostPattern::parse(&service.domain),
+            path_prefix: normalize_prefix(&service.path_prefix),
+            strip_prefix: service.strip_prefix,
+            rewrite_prefix: service.rewrite_prefix.clone(),
+            priority: service.priority,
+            ssl: service.ssl.enabled,
+            route: Route::for_service(&state.docker, service).await.map(Arc::new),
+        });
+    }
+
+    Ok(RoutingTable::new(rules))
+}
+
+/// Recompile the routing table after applications or services changed.
+pub async fn reload(state: &AppState) {
+    match compile(state).await {
+        Ok(table) => {
+            info!("Loaded {} routing rules", table.rules.len());
+            let ids: HashSet<&str> = table.routes().map(|route| route.id.as_str()).collect();
+            state.metrics.retain(&ids);
+            *state.routes.write().unwrap() = Arc::new(table);
+        }
+        Err(e) => error!("Failed to reload routing table: {}", e),
+    }
+}
+
+/// Lowercase a host, dropping any port and trailing dot.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = if let Some(rest) = host.strip_prefix('[') {
+        // IPv6 literal, optionally followed by a port
+        rest.split(']').next().unwrap_or(rest)
+    } else {
+        host.rsplit_once(':').map_or(host, |(name, _)| name)
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Make a path prefix start with `/` and drop any trailing `/`.
+pub fn normalize_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim().trim_matches('/');
+    format!("/{}", trimmed)
+}
+
+/// Whether `path` is `prefix` or lies below it, on a segment boundary.
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    if prefix == "/" {
+        return true;
+    }
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Join a prefix and the rest of a path with exactly one `/` between them.
+fn join_path(prefix: &str, rest: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+    let rest = rest.trim_start_matches('/');
+    if rest.is_empty() {
+        if prefix.is_empty() { "/".to_string() } else { prefix.to_string() }
+    } else {
+        format!("{}/{}", prefix, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(domain: &str, prefix: &str, priority: i32) -> Rule {
+        Rule {
+            host: HostPattern::parse(domain),
+            path_prefix: normalize_prefix(prefix),
+            strip_prefix: false,
+            rewrite_prefix: None,
+            priority,
+            ssl: false,
+            route: None,
+        }
+    }
+
+    fn matched<'a>(table: &'a RoutingTable, host: &str, path: &str) -> Option<(&'a HostPattern, &'a str)> {
+        table
+            .lookup(host, path)
+            .map(|rule| (&rule.host, rule.path_prefix.as_str()))
+    }
+
+    #[test]
+    fn test_hosts_match_with_ports_and_wildcards() {
+        let table = RoutingTable::new(vec![
+            rule("*.example.com", "/", 0),
+            rule("app.example.com", "/", 0),
+        ]);
+
+        let exact = HostPattern::Exact("app.example.com".to_string());
+        let wildcard = HostPattern::Wildcard(".example.com".to_string());
+        assert_eq!(matched(&table, "APP.example.com:8080", "/").unwrap().0, &exact);
+        assert_eq!(matched(&table, "shop.example.com", "/").unwrap().0, &wildcard);
+        assert!(matched(&table, "example.com", "/").is_none());
+        assert_eq!(normalize_host("[::1]:8080"), "::1");
+    }
+
+    #[test]
+    fn test_longest_prefix_and_priority() {
+        let table = RoutingTable::new(vec![
+            rule("example.com", "/", 0),
+            rule("example.com", "/api/", 0),
+            rule("example.com", "/api/v2", 0),
+            rule("*", "/.well-known", 10),
+        ]);
+
+        assert_eq!(matched(&table, "example.com", "/api/users").unwrap().1, "/api");
+        assert_eq!(matched(&table, "example.com", "/api/v2/users").unwrap().1, "/api/v2");
+        assert_eq!(matched(&table, "example.com", "/apix").unwrap().1, "/");
+        assert_eq!(matched(&table, "example.com", "/.well-known/x").unwrap().0, &HostPattern::Any);
+    }
+
+    #[test]
+    fn test_strip_and_rewrite_prefix() {
+        let mut strip = rule("example.com", "/api", 0);
+        strip.strip_prefix = true;
+        assert_eq!(strip.upstream_path("/api/users"), "/users");
+        assert_eq!(strip.upstream_path("/api"), "/");
+
+        let mut rewrite = rule("example.com", "/api", 0);
+        rewrite.rewrite_prefix = Some("/v1/".to_string());
+        assert_eq!(rewrite.upstream_path("/api/users"), "/v1/users");
+
+        let keep = rule("example.com", "/api", 0);
+        assert_eq!(keep.upstream_path("/api/users"), "/api/users");
+    }
+}
@@ -0,0 +1,399 @@
+//! Serving `StaticSite` services from a directory.
+//!
+//! Directory requests are answered with the first index file that exists.
+//! Responses carry an ETag and Last-Modified date for conditional requests,
+//! honour single `Range` requests, and prefer a precompressed `.br` or `.gz`
+//! sibling when the client accepts it.
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode},
+};
+use bollard::Docker;
+use percent_encoding::percent_decode_str;
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// Files tried, in order, when a directory is requested.
+const INDEX_FILES: [&str; 2] = ["index.html", "index.htm"];
+
+/// Precompressed variants, in order of preference.
+const ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Resolve a service target to the directory it serves.
+///
+/// `volume:name/sub/dir` serves a directory inside a Docker volume; anything
+/// else is a path on the host. A subdirectory that leaves the volume is
+/// rejected.
+pub async fn resolve_root(docker: &Docker, target: &str) -> Result<PathBuf> {
+    let Some(volume) = target.strip_prefix("volume:") else {
+        return Ok(PathBuf::from(target));
+    };
+
+    let (name, subdir) = volume.split_once('/').unwrap_or((volume, ""));
+    let subdir = sanitize(subdir).ok_or_else(|| anyhow!("Directory {} is outside volume {}", subdir, name))?;
+    let info = docker
+        .inspect_volume(name)
+        .await
+        .with_context(|| format!("Failed to inspect volume {}", name))?;
+    if info.mountpoint.is_empty() {
+        return Err(anyhow!("Volume {} has no mountpoint", name));
+    }
+
+    Ok(Path::new(&info.mountpoint).join(subdir))
+}
+
+/// Serve a request path from `root`.
+pub async fn serve(root: &Path, path: &str, req: Request<Body>) -> Response<Body> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        let mut response = status(StatusCode::METHOD_NOT_ALLOWED);
+        response
+            .headers_mut()
+            .insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+        return response;
+    }
+
+    let Some(relative) = sanitize(path) else {
+        return status(StatusCode::NOT_FOUND);
+    };
+    let mut file_path = root.join(&relative);
+
+    if tokio::fs::metadata(&file_path).await.is_ok_and(|m| m.is_dir()) {
+        // Relative links in the index only work with a trailing slash
+        if !path.ends_with('/') {
+            let location = match req.uri().query() {
+                Some(query) => format!("{}/?{}", req.uri().path(), query),
+                None => format!("{}/", req.uri().path()),
+            };
+            return redirect(&location);
+        }
+
+        let mut index = None;
+        for name in INDEX_FILES {
+            let candidate = file_path.join(name);
+            if tokio::fs::metadata(&candidate).await.is_ok_and(|m| m.is_file()) {
+                index = Some(candidate);
+                break;
+            }
+        }
+        match index {
+            Some(index) => file_path = index,
+            None => return status(StatusCode::NOT_FOUND),
+        }
+    }
+
+    let head = req.method() == Method::HEAD;
+    match serve_file(&file_path, req.headers(), head).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::debug!("Failed to serve {}: {}", file_path.display(), e);
+            status(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+/// Decode a request path into a relative path that cannot leave the root.
+fn sanitize(path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    if decoded.contains('\0') || decoded.contains('\\') {
+        return None;
+    }
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(decoded.as_ref()).components() {
+        match component {
+            Component::Normal(segment) => relative.push(segment),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(relative)
+}
+
+async fn serve_file(path: &Path, headers: &HeaderMap, head: bool) -> Result<Response<Body>> {
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+    let (file_path, encoding) = precompressed(path, headers).await;
+
+    let mut file = File::open(&file_path).await?;
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Err(anyhow!("Not a file"));
+    }
+
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = etag(len, modified, encoding);
+
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, content_type.as_ref())
+        .header(header::ETAG, &etag)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::VARY, "Accept-Encoding");
+    if let Some(modified) = modified {
+        builder = builder.header(header::LAST_MODIFIED, httpdate::fmt_http_date(modified));
+    }
+    if let Some(encoding) = encoding {
+        builder = builder.header(header::CONTENT_ENCODING, encoding);
+    }
+
+    if not_modified(headers, &etag, modified) {
+        return Ok(builder.status(StatusCode::NOT_MODIFIED).body(Body::empty())?);
+    }
+
+    // Only honour Range when If-Range (if any) still matches this version
+    let range_header = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| {
+            headers
+                .get(header::IF_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .is_none_or(|if_range| if_range == etag)
+        });
+
+    let (status, start, end) = match range_header.map(|range| parse_range(range, len)) {
+        None | Some(Range::Ignore) => (StatusCode::OK, 0, len),
+        Some(Range::Satisfiable(start, end)) => {
+            builder = builder.header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end - 1, len),
+            );
+            (StatusCode::PARTIAL_CONTENT, start, end)
+        }
+        Some(Range::Unsatisfiable) => {
+            return Ok(builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                .body(Body::empty())?);
+        }
+    };
+
+    builder = builder
+        .status(status)
+        .header(header::CONTENT_LENGTH, end - start);
+
+    if head {
+        return Ok(builder.body(Body::empty())?);
+    }
+
+    file.seek(SeekFrom::Start(start)).await?;
+    let body = Body::from_stream(ReaderStream::new(file.take(end - start)));
+    Ok(builder.body(body)?)
+}
+
+/// Pick a precompressed sibling of `path` the client accepts, if one exists.
+async fn precompressed(path: &Path, headers: &HeaderMap) -> (PathBuf, Option<&'static str>) {
+    let accepted: Vec<String> = headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|coding| {
+            let mut params = coding.split(';');
+            let name = params.next()?.trim().to_ascii_lowercase();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (quality > 0.0).then_some(name)
+        })
+        .collect();
+
+    for (encoding, extension) in ENCODINGS {
+        if !accepted.iter().any(|coding| coding == encoding) {
+            continue;
+        }
+
+        let mut candidate = path.as_os_str().to_owned();
+        candidate.push(".");
+        candidate.push(extension);
+        let candidate = PathBuf::from(candidate);
+        if tokio::fs::metadata(&candidate).await.is_ok_and(|m| m.is_file()) {
+            return (candidate, Some(encoding));
+        }
+    }
+
+    (path.to_path_buf(), None)
+}
+
+/// Strong validator from the file size, modification time and encoding.
+fn etag(len: u64, modified: Option<SystemTime>, encoding: Option<&str>) -> String {
+    let mtime = modified
+        .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    match encoding {
+        Some(encoding) => format!("\"{:x}-{:x}-{}\"", len, mtime, encoding),
+        None => format!("\"{:x}-{:x}\"", len, mtime),
+    }
+}
+
+/// Whether the client already has this version of the file.
+fn not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok());
+    match (since, modified) {
+        // HTTP dates have whole-second precision
+        (Some(since), Some(modified)) => httpdate::HttpDate::from(modified) <= httpdate::HttpDate::from(since),
+        _ => false,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Range {
+    /// Serve the whole file (missing, malformed or multi-range header).
+    Ignore,
+    /// Half-open byte range `[start, end)`.
+    Satisfiable(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parse a single `bytes=` range against a file length.
+fn parse_range(value: &str, len: u64) -> Range {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Range::Ignore;
+    };
+    if spec.contains(',') {
+        return Range::Ignore;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Range::Ignore;
+    };
+
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return Range::Ignore,
+        // Suffix range: the last N bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return Range::Unsatisfiable,
+            Ok(n) => (len.saturating_sub(n), len),
+            Err(_) => return Range::Ignore,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return Range::Ignore;
+            };
+            let end = match end {
+                "" => len,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => (end + 1).min(len),
+                    _ => return Range::Ignore,
+                },
+            };
+            (start, end)
+        }
+    };
+
+    if range.0 >= len {
+        Range::Unsatisfiable
+    } else {
+        Range::Satisfiable(range.0, range.1)
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+fn redirect(location: &str) -> Response<Body> {
+    let mut response = status(StatusCode::MOVED_PERMANENTLY);
+    if let Ok(value) = HeaderValue::from_str(location) {
+        response.headers_mut().insert(header::LOCATION, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Range::Satisfiable(0, 100));
+        assert_eq!(parse_range("bytes=900-", 1000), Range::Satisfiable(900, 1000));
+        assert_eq!(parse_range("bytes=-100", 1000), Range::Satisfiable(900, 1000));
+        assert_eq!(parse_range("bytes=0-5000", 1000), Range::Satisfiable(0, 1000));
+        assert_eq!(parse_range("bytes=1000-", 1000), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Range::Ignore);
+        assert_eq!(parse_range("items=0-1", 1000), Range::Ignore);
+    }
+
+    #[test]
+    fn test_sanitize_rejects_traversal() {
+        assert_eq!(sanitize("/css/site.css"), Some(PathBuf::from("css/site.css")));
+        assert_eq!(sanitize("/a%20b.txt"), Some(PathBuf::from("a b.txt")));
+        assert_eq!(sanitize("/../etc/passwd"), None);
+        assert_eq!(sanitize("/%2e%2e/etc/passwd"), None);
+    }
+
+    #[tokio::test]
+    async fn test_volume_subdir_stays_inside_the_volume() {
+        // Nothing listens here, so a resolved target fails on the inspect
+        let docker = Docker::connect_with_http("http://127.0.0.1:9", 4, bollard::API_DEFAULT_VERSION).unwrap();
+        for target in ["volume:data/../../etc", "volume:data/www/../../..", "volume:data/%2e%2e/etc"] {
+            let e = resolve_root(&docker, target).await.unwrap_err();
+            assert!(e.to_string().contains("outside volume data"), "{}: {}", target, e);
+        }
+        let e = resolve_root(&docker, "volume:data/www").await.unwrap_err();
+        assert!(e.to_string().starts_with("Failed to inspect volume data"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn test_serves_index_ranges_and_precompressed() {
+        let root = std::env::temp_dir().join(format!("rustainer-static-{}", rand::random::<u32>()));
+        tokio::fs::create_dir_all(root.join("docs")).await.unwrap();
+        tokio::fs::write(root.join("docs/index.html"), "<h1>docs</h1>").await.unwrap();
+        tokio::fs::write(root.join("app.js"), "console.log(1)").await.unwrap();
+        tokio::fs::write(root.join("app.js.gz"), "gzipped").await.unwrap();
+
+        let get = |uri: &str, headers: &[(header::HeaderName, &str)]| {
+            let mut req = Request::get(uri);
+            for (name, value) in headers {
+                req = req.header(name, *value);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        let response = serve(&root, "/docs", get("/docs", &[])).await;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+        let response = serve(&root, "/docs/", get("/docs/", &[])).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+        let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+
+        let response = serve(&root, "/docs/", get("/docs/", &[(header::IF_NONE_MATCH, &etag)])).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = serve(&root, "/app.js", get("/app.js", &[(header::RANGE, "bytes=0-6")])).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 0-6/14");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"console");
+
+        let response = serve(&root, "/app.js", get("/app.js", &[(header::ACCEPT_ENCODING, "br, gzip")])).await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/javascript");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"gzipped");
+
+        let response = serve(&root, "/missing", get("/missing", &[])).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
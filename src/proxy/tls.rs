@@ -0,0 +1,398 @@
+//! TLS termination for the domain proxy.
+//!
+//! Certificates live under `data/certs/{domain}/` (or wherever a service's
+//! `cert_path`/`key_path` point) and are picked per connection from the SNI
+//! server name. Plain HTTP requests for domains with SSL enabled are
+//! redirected to HTTPS once a certificate is available.
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    Extension, Router,
+};
+use chrono::{DateTime, Utc};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+
+use super::{acme, routing, AppState};
+
+/// Directory where issued and uploaded certificates are stored.
+pub const CERTS_DIR: &str = "./data/certs";
+
+/// A certificate loaded into the store.
+struct LoadedCert {
+    key: Arc<CertifiedKey>,
+    not_after: DateTime<Utc>,
+}
+
+/// In-memory certificate store that resolves certificates by SNI.
+pub struct CertStore {
+    dir: PathBuf,
+    certs: RwLock<HashMap<String, LoadedCert>>,
+}
+
+impl std::fmt::Debug for CertStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertStore").field("dir", &self.dir).finish()
+    }
+}
+
+impl CertStore {
+    /// Create an empty store backed by the given directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            certs: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Load every `{domain}/cert.pem` + `{domain}/key.pem` pair in the store
+    /// directory.
+    pub fn load_dir(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir).context("Failed to create certificate directory")?;
+
+        for entry in fs::read_dir(&self.dir).context("Failed to read certificate directory")? {
+            let path = entry.context("Failed to read directory entry")?.path();
+            if !path.is_dir() {
+                continue;
+            }
+
+            let domain = match path.file_name().and_then(|n| n.to_str()) {
+                Some(domain) => domain.to_string(),
+                None => continue,
+            };
+
+            if let Err(e) = self.load_files(&domain, &path.join("cert.pem"), &path.join("key.pem")) {
+                warn!("Skipping certificate for {}: {}", domain, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load a certificate chain and private key from PEM files.
+    pub fn load_files(&self, domain: &str, cert_path: &Path, key_path: &Path) -> Result<()> {
+        let cert_pem = fs::read(cert_path)
+            .with_context(|| format!("Failed to read {}", cert_path.display()))?;
+        let key_pem = fs::read(key_path)
+            .with_context(|| format!("Failed to read {}", key_path.display()))?;
+        self.insert_pem(domain, &cert_pem, &key_pem)
+    }
+
+    /// Write a certificate into the store directory and start serving it.
+    pub fn save(&self, domain: &str, cert_pem: &[u8], key_pem: &[u8]) -> Result<()> {
+        // Make sure the pair is usable before replacing anything on disk
+        if !is_valid_domain(domain) {
+            return Err(anyhow!("Invalid certificate domain {:?}", domain));
+        }
+        self.insert_pem(domain, cert_pem, key_pem)?;
+
+        let dir = self.dir.join(domain);
+        fs::create_dir_all(&dir).context("Failed to create certificate directory")?;
+        fs::write(dir.join("cert.pem"), cert_pem).context("Failed to write certificate")?;
+        write_private(&dir.join("key.pem"), key_pem).context("Failed to write private key")?;
+
+        Ok(())
+    }
+
+    /// Parse a PEM certificate chain and key and serve it for `domain`.
+    pub fn insert_pem(&self, domain: &str, cert_pem: &[u8], key_pem: &[u8]) -> Result<()> {
+        let chain = rustls_pemfile::certs(&mut &cert_pem[..])
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to parse certificate chain")?;
+        let leaf = chain.first().ok_or_else(|| anyhow!("Certificate file is empty"))?;
+
+        let (_, parsed) = x509_parser::parse_x509_certificate(leaf)
+            .map_err(|e| anyhow!("Failed to parse certificate: {}", e))?;
+        let not_after = DateTime::from_timestamp(parsed.validity().not_after.timestamp(), 0)
+            .ok_or_else(|| anyhow!("Certificate has an invalid expiry date"))?;
+
+        let key = rustls_pemfile::private_key(&mut &key_pem[..])
+            .context("Failed to parse private key")?
+            .ok_or_else(|| anyhow!("Key file contains no private key"))?;
+        let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+            .map_err(|e| anyhow!("Unsupported private key: {}", e))?;
+
+        let loaded = LoadedCert {
+            key: Arc::new(CertifiedKey::new(chain, signing_key)),
+            not_after,
+        };
+
+        self.certs
+            .write()
+            .unwrap()
+            .insert(domain.to_ascii_lowercase(), loaded);
+        info!("Loaded certificate for {} (expires {})", domain, not_after);
+
+        Ok(())
+    }
+
+    /// Whether a certificate is available for the domain.
+    pub fn has(&self, domain: &str) -> bool {
+        self.lookup(domain).is_some()
+    }
+
+    /// When the certificate for the domain expires, if there is one.
+    pub fn expires_at(&self, domain: &str) -> Option<DateTime<Utc>> {
+        self.certs
+            .read()
+            .unwrap()
+            .get(&domain.to_ascii_lowercase())
+            .map(|cert| cert.not_after)
+    }
+
+    /// Find the certificate for a server name, falling back to a wildcard
+    /// certificate for the parent domain.
+    fn lookup(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        let name = server_name.to_ascii_lowercase();
+        let certs = self.certs.read().unwrap();
+
+        if let Some(cert) = certs.get(&name) {
+            return Some(cert.key.clone());
+        }
+
+        let (_, parent) = name.split_once('.')?;
+        certs.get(&format!("*.{}", parent)).map(|cert| cert.key.clone())
+    }
+}
+
+/// Write a file that only its owner can read, such as a private key.
+pub fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // The mode only applies to new files, and older keys were written with
+    // the umask's
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+/// Whether a domain is a hostname, optionally a wildcard, that is safe to use
+/// as a directory name in the store.
+fn is_valid_domain(domain: &str) -> bool {
+    let name = domain.strip_prefix("*.").unwrap_or(domain);
+    !name.is_empty()
+        && domain.len() <= 253
+        && !name.starts_with('.')
+        && !name.contains("..")
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let server_name = client_hello.server_name()?;
+        let cert = self.lookup(server_name);
+        if cert.is_none() {
+            debug!("No certificate for SNI name {}", server_name);
+        }
+        cert
+    }
+}
+
+/// Load certificates for services that point at their own PEM files.
+pub async fn load_configured_certs(db: &SqlitePool, store: &CertStore) -> Result<()> {
+    let rows: Vec<(String, String, String)> = sqlx::query_as(
+        r#"
+        SELECT domain, ssl_cert_path, ssl_key_path
+        FROM services
+        WHERE ssl_enabled = 1 AND ssl_cert_path IS NOT NULL AND ssl_key_path IS NOT NULL
+        "#
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to fetch service certificates")?;
+
+    for (domain, cert_path, key_path) in rows {
+        if let Err(e) = store.load_files(&domain, Path::new(&cert_path), Path::new(&key_path)) {
+            warn!("Failed to load certificate for {}: {}", domain, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Middleware for the plain HTTP listener.
+///
+/// Answers ACME HTTP-01 challenges and redirects domains with SSL enabled to
+/// HTTPS, passing everything else through to the proxy.
+pub async fn redirect_to_https(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    if let Some(token) = req.uri().path().strip_prefix(acme::CHALLENGE_PATH) {
+        return match state.acme_challenges.read().unwrap().get(token) {
+            Some(key_authorization) => key_authorization.clone().into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        };
+    }
+
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .map(routing::normalize_host);
+
+    if let Some(host) = host {
+        let ssl_required = state
+            .routes
+            .read()
+            .unwrap()
+            .lookup(&host, req.uri().path())
+            .is_some_and(|rule| rule.ssl);
+
+        if ssl_required && state.certs.has(&host) {
+            let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+            let location = match state.https_port {
+                443 => format!("https://{}{}", host, path),
+                port => format!("https://{}:{}{}", host, port, path),
+            };
+            return Redirect::permanent(&location).into_response();
+        }
+    }
+
+    next.run(req).await
+}
+
+/// Build the rustls server configuration around the certificate store.
+pub fn server_config(store: Arc<CertStore>) -> Result<ServerConfig> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS protocol versions")?
+        .with_no_client_auth()
+        .with_cert_resolver(store);
+
+    // Upgrade tunnelling relies on HTTP/1.1 semantics
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(config)
+}
+
+/// Request extension marking requests that arrived over TLS.
+#[derive(Debug, Clone, Copy)]
+pub struct HttpsConnection;
+
+/// Accept TLS connections and serve them with the proxy router.
+pub async fn serve_https(listener: TcpListener, config: ServerConfig, app: Router) {
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to accept HTTPS connection: {}", e);
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(
+            app.clone()
+                .layer(Extension(ConnectInfo(peer)))
+                .layer(Extension(HttpsConnection)),
+        );
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("HTTPS connection with {} closed: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cert_store_resolves_exact_and_wildcard_names() {
+        let store = CertStore::new(std::env::temp_dir().join("rustainer-cert-store-test"));
+
+        let exact = rcgen::generate_simple_self_signed(vec!["app.test".to_string()]).unwrap();
+        store
+            .insert_pem("app.test", exact.cert.pem().as_bytes(), exact.key_pair.serialize_pem().as_bytes())
+            .unwrap();
+
+        let wildcard = rcgen::generate_simple_self_signed(vec!["*.example.com".to_string()]).unwrap();
+        store
+            .insert_pem("*.example.com", wildcard.cert.pem().as_bytes(), wildcard.key_pair.serialize_pem().as_bytes())
+            .unwrap();
+
+        assert!(store.has("APP.test"));
+        assert!(store.has("shop.example.com"));
+        assert!(!store.has("example.com"));
+        assert!(!store.has("other.test"));
+        assert!(store.expires_at("app.test").unwrap() > Utc::now());
+    }
+
+    #[test]
+    fn test_save_rejects_domains_that_are_not_hostnames() {
+        let dir = std::env::temp_dir().join("rustainer-cert-save-test");
+        let store = CertStore::new(&dir);
+        let cert = rcgen::generate_simple_self_signed(vec!["app.test".to_string()]).unwrap();
+        let (cert_pem, key_pem) = (cert.cert.pem(), cert.key_pair.serialize_pem());
+
+        for domain in ["../escape", "a/b", ".hidden", "app..test", "", "*."] {
+            assert!(store.save(domain, cert_pem.as_bytes(), key_pem.as_bytes()).is_err(), "{}", domain);
+            assert!(!store.has(domain));
+        }
+        assert!(is_valid_domain("*.example.com"));
+        store.save("app.test", cert_pem.as_bytes(), key_pem.as_bytes()).unwrap();
+        assert!(dir.join("app.test").join("cert.pem").is_file());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_private_keys_are_only_readable_by_their_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("rustainer-cert-mode-test");
+        let store = CertStore::new(&dir);
+        let cert = rcgen::generate_simple_self_signed(vec!["app.test".to_string()]).unwrap();
+        let key = dir.join("app.test").join("key.pem");
+        // A key left readable by an older version is tightened on renewal
+        fs::create_dir_all(key.parent().unwrap()).unwrap();
+        fs::write(&key, "old").unwrap();
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o644)).unwrap();
+
+        store
+            .save("app.test", cert.cert.pem().as_bytes(), cert.key_pair.serialize_pem().as_bytes())
+            .unwrap();
+        assert_eq!(fs::metadata(&key).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(&key).unwrap(), cert.key_pair.serialize_pem());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,99 @@
+//! Hop-by-hop header handling and `Connection: Upgrade` tunnelling.
+//!
+//! WebSocket (and any other `Upgrade`) handshakes are forwarded to the
+//! container like a normal request. Once the container answers with
+//! `101 Switching Protocols`, both connections are taken over from hyper and
+//! bytes are copied between them until either side closes.
+
+use axum::http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode};
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use tracing::{debug, error};
+
+/// Headers that only apply to a single connection and must never be
+/// forwarded by a proxy (RFC 9110, section 7.6.1).
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Check whether a request asks to switch protocols.
+pub fn is_upgrade_request<B>(req: &Request<B>) -> bool {
+    let connection_upgrade = req
+        .headers()
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+
+    connection_upgrade && req.headers().contains_key(header::UPGRADE)
+}
+
+/// Remove hop-by-hop headers, including any extra ones named in `Connection`.
+///
+/// When `upgrade` is set, the `Upgrade` header is kept and `Connection` is
+/// rewritten to `upgrade` so the handshake reaches the other side.
+pub fn strip_hop_by_hop_headers(headers: &mut HeaderMap, upgrade: bool) {
+    // Headers listed in Connection are hop-by-hop as well
+    let listed: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect();
+
+    let upgrade_value = headers.get(header::UPGRADE).cloned();
+
+    for name in listed.iter().map(String::as_str).chain(HOP_BY_HOP_HEADERS) {
+        headers.remove(name);
+    }
+
+    if upgrade {
+        if let Some(value) = upgrade_value {
+            headers.insert(header::UPGRADE, value);
+            headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        }
+    }
+}
+
+/// Join the client and container connections once both sides have switched
+/// protocols.
+///
+/// The tunnel runs on its own task and lives for as long as either peer keeps
+/// its end open, so long-lived WebSocket sessions are not cut short.
+pub fn spawn_tunnel<B>(client_upgrade: OnUpgrade, upstream_response: &mut Response<B>) {
+    if upstream_response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return;
+    }
+
+    let upstream_upgrade = hyper::upgrade::on(upstream_response);
+
+    tokio::spawn(async move {
+        let (client, upstream) = match tokio::try_join!(client_upgrade, upstream_upgrade) {
+            Ok(pair) => pair,
+            Err(e) => {
+                error!("Failed to upgrade proxied connection: {}", e);
+                return;
+            }
+        };
+
+        let mut client = TokioIo::new(client);
+        let mut upstream = TokioIo::new(upstream);
+
+        match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+            Ok((sent, received)) => {
+                debug!("Upgraded connection closed ({} bytes sent, {} bytes received)", sent, received);
+            }
+            Err(e) => debug!("Upgraded connection closed with error: {}", e),
+        }
+    });
+}
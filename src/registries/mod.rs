@@ -0,0 +1,471 @@
+//! Registry credential store.
+//!
+//! Registries are kept in the `registries` table, with their password sealed
+//! by the [`crate::secrets`] key. Pulls look up credentials by the registry
+//! host of the image reference: the caller's own registries come first,
+//! then those of their role, then shared ones. Stack deployments run on
+//! behalf of no one in particular and only use shared registries. The
+//! [`v2`] client browses repositories and tags with the same credentials.
+
+pub mod v2;
+
+use anyhow::{Context, Result};
+use bollard::auth::DockerCredentials;
+use chrono::Utc;
+use sqlx::{sqlite::SqliteRow, Pool, Row, Sqlite};
+use std::collections::hash_map::{Entry, HashMap};
+use uuid::Uuid;
+
+use crate::auth::Claims;
+use crate::models::registry::{CreateRegistryRequest, Registry, RegistryScope, UpdateRegistryRequest};
+use crate::models::user::UserRole;
+use crate::secrets::{self, REDACTED};
+
+/// Host that references without a registry, such as `nginx`, pull from.
+pub const DOCKER_HUB: &str = "docker.io";
+
+/// Other names of Docker Hub that registries may be stored under.
+const DOCKER_HUB_ALIASES: [&str; 3] = ["index.docker.io", "registry-1.docker.io", "registry.hub.docker.com"];
+
+/// Returned when a registry's URL, name, scope or owner is not valid.
+#[derive(Debug)]
+pub struct InvalidRegistry(pub String);
+
+impl std::fmt::Display for InvalidRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidRegistry {}
+
+/// Credentials for a registry, ready for Docker or the v2 API.
+#[derive(Clone)]
+pub struct Login {
+    /// Base URL of the registry's API.
+    pub url: String,
+    pub host: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl std::fmt::Debug for Login {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Login")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
+impl Login {
+    /// Credentials for the Docker daemon to pull with.
+    pub fn docker_credentials(&self) -> DockerCredentials {
+        DockerCredentials {
+            username: self.username.clone(),
+            password: self.password.clone(),
+            serveraddress: Some(match self.host.as_str() {
+                DOCKER_HUB => "https://index.docker.io/v1/".to_string(),
+                host => host.to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Registry host of an image reference, as the Docker CLI reads it: the
+/// first path component if it looks like a host name, else Docker Hub.
+pub fn image_host(image: &str) -> String {
+    match image.split_once('/') {
+        Some((first, _)) if first.contains('.') || first.contains(':') || first == "localhost" => {
+            normalize_host(first)
+        }
+        _ => DOCKER_HUB.to_string(),
+    }
+}
+
+/// Host of a registry URL, which may leave out the scheme.
+fn url_host(url: &str) -> Result<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split('/').next().unwrap_or_default();
+    if host.is_empty() || host.contains(char::is_whitespace) {
+        return Err(InvalidRegistry(format!("registry URL {:?} has no host", url)).into());
+    }
+    Ok(normalize_host(host))
+}
+
+fn normalize_host(host: &str) -> String {
+    let host = host.to_ascii_lowercase();
+    if DOCKER_HUB_ALIASES.contains(&host.as_str()) {
+        DOCKER_HUB.to_string()
+    } else {
+        host
+    }
+}
+
+/// Base URL of a registry's v2 API. HTTPS is assumed without a scheme, and
+/// Docker Hub is served from its own host.
+fn api_url(url: &str, host: &str) -> String {
+    if host == DOCKER_HUB {
+        return "https://registry-1.docker.io".to_string();
+    }
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix("/v2").unwrap_or(url);
+    if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("https://{}", url)
+    }
+}
+
+/// Whether a caller may see and change a registry. Admins manage all of
+/// them.
+fn visible(registry: &Registry, caller: Option<&Claims>) -> bool {
+    is_admin(caller) || applies(registry, caller)
+}
+
+/// Whether a registry's credentials are used for a caller's pulls.
+fn applies(registry: &Registry, caller: Option<&Claims>) -> bool {
+    let owner = registry.owner.as_deref();
+    match (registry.scope, caller) {
+        (RegistryScope::Shared, _) => true,
+        (RegistryScope::User, Some(claims)) => owner == Some(claims.username.as_str()),
+        (RegistryScope::Role, Some(claims)) => owner == Some(claims.role.as_str()),
+        (_, None) => false,
+    }
+}
+
+/// Returned when a caller may not give a registry the scope or owner asked
+/// for.
+#[derive(Debug)]
+pub struct NotPermitted(pub String);
+
+impl std::fmt::Display for NotPermitted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotPermitted {}
+
+fn is_admin(caller: Option<&Claims>) -> bool {
+    caller.is_some_and(|claims| UserRole::from(claims.role.clone()) == UserRole::Admin)
+}
+
+/// Check a registry's scope and owner, defaulting the owner of a `user`
+/// registry to the caller. Only admins may store registries for other users
+/// or for a role, since their credentials are used for those users' pulls.
+fn check_owner(scope: RegistryScope, owner: Option<String>, caller: Option<&Claims>) -> Result<Option<String>> {
+    let owner = owner.filter(|owner| !owner.is_empty());
+    if scope == RegistryScope::Shared {
+        return Ok(None);
+    }
+    let Some(claims) = caller else {
+        return Err(NotPermitted("user and role registries need a login".to_string()).into());
+    };
+    match scope {
+        RegistryScope::Shared => Ok(None),
+        RegistryScope::User => {
+            let owner = owner.unwrap_or_else(|| claims.username.clone());
+            if owner != claims.username && !is_admin(caller) {
+                return Err(NotPermitted(format!("only admins can store registries for {}", owner)).into());
+            }
+            Ok(Some(owner))
+        }
+        RegistryScope::Role => {
+            if !is_admin(caller) {
+                return Err(NotPermitted("only admins can store role registries".to_string()).into());
+            }
+            match owner.as_deref() {
+                Some(role @ ("admin" | "operator" | "viewer")) => Ok(Some(role.to_string())),
+                _ => Err(InvalidRegistry("a role registry needs an owner of admin, operator or viewer".to_string()).into()),
+            }
+        }
+    }
+}
+
+/// List the registries a caller can see.
+pub async fn list(db: &Pool<Sqlite>, caller: Option<&Claims>) -> Result<Vec<Registry>> {
+    let rows = sqlx::query("SELECT * FROM registries ORDER BY name")
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch registries from database")?;
+    let registries = rows.iter().map(registry_from_row).collect::<Result<Vec<_>>>()?;
+    Ok(registries.into_iter().filter(|registry| visible(registry, caller)).collect())
+}
+
+/// Get a registry by ID, if the caller can see it.
+pub async fn get(db: &Pool<Sqlite>, id: &str, caller: Option<&Claims>) -> Result<Option<Registry>> {
+    let row = sqlx::query("SELECT * FROM registries WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .context("Failed to fetch registry from database")?;
+    let registry = row.as_ref().map(registry_from_row).transpose()?;
+    Ok(registry.filter(|registry| visible(registry, caller)))
+}
+
+/// Store a new registry.
+pub async fn create(db: &Pool<Sqlite>, request: CreateRegistryRequest, caller: Option<&Claims>) -> Result<Registry> {
+    if request.name.trim().is_empty() {
+        return Err(InvalidRegistry("registry name is empty".to_string()).into());
+    }
+    let now = Utc::now();
+    let registry = Registry {
+        id: Uuid::new_v4().to_string(),
+        name: request.name.trim().to_string(),
+        host: url_host(&request.url)?,
+        url: request.url.trim().to_string(),
+        username: request.username.filter(|username| !username.is_empty()),
+        has_password: request.password.as_deref().is_some_and(|password| !password.is_empty()),
+        owner: check_owner(request.scope, request.owner, caller)?,
+        scope: request.scope,
+        created_at: now,
+        updated_at: now,
+    };
+    let password = request.password.filter(|password| !password.is_empty());
+    sqlx::query(
+        "INSERT INTO registries (id, name, url, host, username, password, scope, owner, created_at, updated_at) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&registry.id)
+    .bind(&registry.name)
+    .bind(&registry.url)
+    .bind(&registry.host)
+    .bind(&registry.username)
+    .bind(password.map(|password| secrets::seal(&context(&registry.id), &password)).transpose()?)
+    .bind(registry.scope.as_str())
+    .bind(&registry.owner)
+    .bind(registry.created_at)
+    .bind(registry.updated_at)
+    .execute(db)
+    .await
+    .context("Failed to insert registry into database")?;
+    Ok(registry)
+}
+
+/// Change a registry the caller can see.
+pub async fn update(
+    db: &Pool<Sqlite>,
+    id: &str,
+    request: UpdateRegistryRequest,
+    caller: Option<&Claims>,
+) -> Result<Option<Registry>> {
+    let Some(mut registry) = get(db, id, caller).await? else {
+        return Ok(None);
+    };
+    if let Some(name) = request.name.filter(|name| !name.trim().is_empty()) {
+        registry.name = name.trim().to_string();
+    }
+    if let Some(url) = request.url {
+        registry.host = url_host(&url)?;
+        registry.url = url.trim().to_string();
+    }
+    if let Some(username) = request.username {
+        registry.username = Some(username).filter(|username| !username.is_empty());
+    }
+    // Checked on every change, so that a role's members cannot change the
+    // credentials used for each other's pulls either
+    let scope = request.scope.unwrap_or(registry.scope);
+    registry.owner = check_owner(scope, request.owner.or(registry.owner), caller)?;
+    registry.scope = scope;
+    if let Some(password) = &request.password {
+        registry.has_password = !password.is_empty();
+    }
+    registry.updated_at = Utc::now();
+
+    // An empty password clears the stored one, and a missing one keeps it
+    let password = match request.password {
+        Some(password) if password.is_empty() => None,
+        Some(password) => Some(secrets::seal(&context(id), &password)?),
+        None => stored_password(db, id).await?,
+    };
+    sqlx::query(
+        "UPDATE registries SET name = ?, url = ?, host = ?, username = ?, password = ?, scope = ?, owner = ?, \
+         updated_at = ? WHERE id = ?",
+    )
+    .bind(&registry.name)
+    .bind(&registry.url)
+    .bind(&registry.host)
+    .bind(&registry.username)
+    .bind(password)
+    .bind(registry.scope.as_str())
+    .bind(&registry.owner)
+    .bind(registry.updated_at)
+    .bind(id)
+    .execute(db)
+    .await
+    .context("Failed to update registry in database")?;
+    Ok(Some(registry))
+}
+
+/// Delete a registry the caller can see. Returns whether it existed.
+pub async fn delete(db: &Pool<Sqlite>, id: &str, caller: Option<&Claims>) -> Result<bool> {
+    if get(db, id, caller).await?.is_none() {
+        return Ok(false);
+    }
+    let result = sqlx::query("DELETE FROM registries WHERE id = ?")
+        .bind(id)
+        .execute(db)
+        .await
+        .context("Failed to delete registry from database")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Decrypted credentials of a registry the caller can see.
+pub async fn login(db: &Pool<Sqlite>, id: &str, caller: Option<&Claims>) -> Result<Option<Login>> {
+    let Some(registry) = get(db, id, caller).await? else {
+        return Ok(None);
+    };
+    let password = stored_password(db, id).await?;
+    Ok(Some(Login {
+        url: api_url(&registry.url, &registry.host),
+        host: registry.host,
+        username: registry.username,
+        password: password.map(|stored| secrets::unseal(&context(id), &stored)).transpose()?,
+    }))
+}
+
+/// Credentials for pulling `image` on behalf of `caller`, preferring the
+/// caller's own registries over their role's and shared ones.
+pub async fn credentials_for(db: &Pool<Sqlite>, image: &str, caller: Option<&Claims>) -> Result<Option<Login>> {
+    let host = image_host(image);
+    let rows = sqlx::query("SELECT * FROM registries WHERE host = ?")
+        .bind(&host)
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch registries from database")?;
+    let mut candidates = Vec::new();
+    for row in &rows {
+        let registry = registry_from_row(row)?;
+        if applies(&registry, caller) {
+            candidates.push(registry);
+        }
+    }
+    candidates.sort_by_key(|registry| (rank(registry.scope), registry.created_at));
+    match candidates.first() {
+        Some(registry) => login(db, &registry.id, caller).await,
+        None => Ok(None),
+    }
+}
+
+/// Credentials of every registry used for `caller`'s pulls, by host, with
+/// the caller's own registries before their role's and shared ones. Without
+/// a caller, as for stack deployments, only shared registries are used.
+pub async fn logins_for(db: &Pool<Sqlite>, caller: Option<&Claims>) -> Result<HashMap<String, Login>> {
+    let mut registries: Vec<Registry> = list(db, caller)
+        .await?
+        .into_iter()
+        .filter(|registry| applies(registry, caller))
+        .collect();
+    registries.sort_by_key(|registry| (rank(registry.scope), registry.created_at));
+
+    let mut logins = HashMap::new();
+    for registry in registries {
+        if let Entry::Vacant(entry) = logins.entry(registry.host) {
+            if let Some(login) = login(db, &registry.id, caller).await? {
+                entry.insert(login);
+            }
+        }
+    }
+    Ok(logins)
+}
+
+/// Order in which registries of the same host are tried.
+fn rank(scope: RegistryScope) -> u8 {
+    match scope {
+        RegistryScope::User => 0,
+        RegistryScope::Role => 1,
+        RegistryScope::Shared => 2,
+    }
+}
+
+fn context(id: &str) -> String {
+    format!("registries.password:{}", id)
+}
+
+async fn stored_password(db: &Pool<Sqlite>, id: &str) -> Result<Option<String>> {
+    let stored: Option<Option<String>> = sqlx::query_scalar("SELECT password FROM registries WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .context("Failed to fetch registry from database")?;
+    Ok(stored.flatten())
+}
+
+fn registry_from_row(row: &SqliteRow) -> Result<Registry> {
+    let scope: String = row.try_get("scope")?;
+    let password: Option<String> = row.try_get("password")?;
+    Ok(Registry {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        url: row.try_get("url")?,
+        host: row.try_get("host")?,
+        username: row.try_get("username")?,
+        has_password: password.is_some(),
+        scope: match scope.as_str() {
+            "user" => RegistryScope::User,
+            "role" => RegistryScope::Role,
+            _ => RegistryScope::Shared,
+        },
+        owner: row.try_get("owner")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hosts() {
+        assert_eq!(image_host("nginx"), DOCKER_HUB);
+        assert_eq!(image_host("library/nginx:1.27"), DOCKER_HUB);
+        assert_eq!(image_host("ghcr.io/org/app:1.0"), "ghcr.io");
+        assert_eq!(image_host("localhost:5000/app"), "localhost:5000");
+        assert_eq!(image_host("localhost/app"), "localhost");
+
+        assert_eq!(url_host("https://index.docker.io/v1/").unwrap(), DOCKER_HUB);
+        assert_eq!(url_host("Registry.Example.com:5000").unwrap(), "registry.example.com:5000");
+        assert!(url_host("https://").is_err());
+
+        assert_eq!(api_url("registry.example.com/v2/", "registry.example.com"), "https://registry.example.com");
+        assert_eq!(api_url("http://localhost:5000", "localhost:5000"), "http://localhost:5000");
+        assert_eq!(api_url("docker.io", DOCKER_HUB), "https://registry-1.docker.io");
+    }
+
+    fn claims(username: &str, role: &str) -> Claims {
+        Claims {
+            sub: username.to_string(),
+            username: username.to_string(),
+            role: role.to_string(),
+            exp: 0,
+            iat: 0,
+        }
+    }
+
+    #[test]
+    fn test_check_owner_limits_user_and_role_registries() {
+        let (alice, admin) = (claims("alice", "operator"), claims("root", "admin"));
+        let denied = |result: Result<Option<String>>| result.unwrap_err().downcast_ref::<NotPermitted>().is_some();
+
+        // Users store registries for themselves only
+        assert_eq!(check_owner(RegistryScope::User, None, Some(&alice)).unwrap().as_deref(), Some("alice"));
+        assert_eq!(check_owner(RegistryScope::User, Some("alice".to_string()), Some(&alice)).unwrap().as_deref(), Some("alice"));
+        assert!(denied(check_owner(RegistryScope::User, Some("bob".to_string()), Some(&alice))));
+        assert_eq!(check_owner(RegistryScope::User, Some("bob".to_string()), Some(&admin)).unwrap().as_deref(), Some("bob"));
+
+        // Role registries are for admins to set up
+        assert!(denied(check_owner(RegistryScope::Role, Some("admin".to_string()), Some(&alice))));
+        assert!(denied(check_owner(RegistryScope::Role, Some("operator".to_string()), Some(&alice))));
+        assert_eq!(check_owner(RegistryScope::Role, Some("operator".to_string()), Some(&admin)).unwrap().as_deref(), Some("operator"));
+        assert!(check_owner(RegistryScope::Role, Some("nobody".to_string()), Some(&admin)).is_err());
+
+        // Without a login, only shared registries
+        assert!(denied(check_owner(RegistryScope::User, Some("alice".to_string()), None)));
+        assert!(denied(check_owner(RegistryScope::Role, Some("admin".to_string()), None)));
+        assert_eq!(check_owner(RegistryScope::Shared, Some("alice".to_string()), None).unwrap(), None);
+    }
+}
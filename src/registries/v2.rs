@@ -0,0 +1,374 @@
+//! Client for the Docker Registry HTTP API v2, for browsing repositories
+//! and tags.
+//!
+//! Requests are first sent without credentials. A registry that wants them
+//! answers 401 with a `WWW-Authenticate` challenge: `Basic` ones are
+//! answered with the stored username and password, and `Bearer` ones with a
+//! token fetched from the realm the challenge names, as Docker Hub, GHCR and
+//! `registry:2` with token auth expect.
+
+use anyhow::{anyhow, Context, Result};
+use axum::body::Body;
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use super::Login;
+use crate::models::registry::{RepositoryList, TagList};
+use crate::proxy::{build_client, ProxyClient};
+
+/// Repositories per catalog page unless asked otherwise.
+const DEFAULT_PAGE_SIZE: u32 = 100;
+
+/// Returned when the registry refuses a request or reports an error.
+#[derive(Debug)]
+pub struct RegistryError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "registry answered {}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// An authentication challenge from a `WWW-Authenticate` header.
+#[derive(Debug, PartialEq, Eq)]
+enum Challenge {
+    Basic,
+    Bearer { realm: String, params: Vec<(String, String)> },
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Catalog {
+    #[serde(default)]
+    repositories: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct Tags {
+    name: String,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+pub struct Client {
+    http: ProxyClient,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self { http: build_client() }
+    }
+
+    /// A page of the registry's repositories, after `last`.
+    pub async fn catalog(&self, login: &Login, n: Option<u32>, last: Option<&str>) -> Result<RepositoryList> {
+        let mut path = format!("/v2/_catalog?n={}", n.unwrap_or(DEFAULT_PAGE_SIZE).max(1));
+        if let Some(last) = last.filter(|last| !last.is_empty()) {
+            path.push_str("&last=");
+            path.extend(utf8_percent_encode(last, NON_ALPHANUMERIC));
+        }
+        let (headers, body) = self.get(login, &path).await?;
+        let catalog: Catalog = serde_json::from_slice(&body).context("Invalid catalog from registry")?;
+        Ok(RepositoryList {
+            repositories: catalog.repositories.unwrap_or_default(),
+            next: next_page(&headers),
+        })
+    }
+
+    /// Tags of a repository.
+    pub async fn tags(&self, login: &Login, repository: &str) -> Result<TagList> {
+        check_repository(repository)?;
+        let (_, body) = self.get(login, &format!("/v2/{}/tags/list", repository)).await?;
+        let tags: Tags = serde_json::from_slice(&body).context("Invalid tag list from registry")?;
+        let mut list = tags.tags.unwrap_or_default();
+        list.sort();
+        Ok(TagList { name: tags.name, tags: list })
+    }
+
+    /// GET a path of the API, answering the registry's auth challenge.
+    async fn get(&self, login: &Login, path: &str) -> Result<(HeaderMap, Bytes)> {
+        let url = format!("{}{}", login.url, path);
+        let (status, headers, body) = self.send(&url, None).await?;
+        if status != StatusCode::UNAUTHORIZED {
+            return checked(status, headers, body);
+        }
+
+        let authorization = match challenge(&headers) {
+            Some(Challenge::Basic) => basic(login).ok_or_else(|| error(status, &body))?,
+            Some(Challenge::Bearer { realm, params }) => format!("Bearer {}", self.token(login, &realm, &params).await?),
+            None => return Err(error(status, &body)),
+        };
+        let (status, headers, body) = self.send(&url, Some(&authorization)).await?;
+        checked(status, headers, body)
+    }
+
+    /// Fetch a bearer token from a challenge's realm.
+    async fn token(&self, login: &Login, realm: &str, params: &[(String, String)]) -> Result<String> {
+        let query: Vec<String> = params
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, utf8_percent_encode(value, NON_ALPHANUMERIC)))
+            .collect();
+        let separator = if realm.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}{}", realm, separator, query.join("&"));
+        let (status, headers, body) = self.send(&url, basic(login).as_deref()).await?;
+        let (_, body) = checked(status, headers, body)?;
+        let token: TokenResponse = serde_json::from_slice(&body).context("Invalid token response from registry")?;
+        token
+            .token
+            .or(token.access_token)
+            .ok_or_else(|| anyhow!("Registry token response has no token"))
+    }
+
+    async fn send(&self, url: &str, authorization: Option<&str>) -> Result<(StatusCode, HeaderMap, Bytes)> {
+        let mut builder = Request::builder().uri(url).header(header::ACCEPT, "application/json");
+        if let Some(authorization) = authorization {
+            builder = builder.header(header::AUTHORIZATION, authorization);
+        }
+        let request = builder.body(Body::empty()).context("Failed to build registry request")?;
+        let response = self
+            .http
+            .request(request)
+            .await
+            .with_context(|| format!("Failed to reach registry at {}", url))?;
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.context("Failed to read registry response")?.to_bytes();
+        Ok((parts.status, parts.headers, body))
+    }
+}
+
+fn basic(login: &Login) -> Option<String> {
+    let username = login.username.as_deref()?;
+    let password = login.password.as_deref().unwrap_or_default();
+    Some(format!("Basic {}", STANDARD.encode(format!("{}:{}", username, password))))
+}
+
+fn checked(status: StatusCode, headers: HeaderMap, body: Bytes) -> Result<(HeaderMap, Bytes)> {
+    if status.is_success() {
+        Ok((headers, body))
+    } else {
+        Err(error(status, &body))
+    }
+}
+
+/// The registry's error, with the message of its first error if it sent
+/// one in the v2 format.
+fn error(status: StatusCode, body: &[u8]) -> anyhow::Error {
+    #[derive(Deserialize)]
+    struct Errors {
+        errors: Vec<HashMap<String, serde_json::Value>>,
+    }
+    let message = serde_json::from_slice::<Errors>(body)
+        .ok()
+        .and_then(|errors| errors.errors.into_iter().next())
+        .and_then(|error| error.get("message").and_then(|m| m.as_str()).map(str::to_string))
+        .unwrap_or_else(|| String::from_utf8_lossy(body).trim().chars().take(200).collect());
+    RegistryError { status, message }.into()
+}
+
+/// Repository names as the distribution spec allows them, which also keeps
+/// them from leaving the `/v2/` path.
+fn check_repository(repository: &str) -> Result<()> {
+    let valid = !repository.is_empty()
+        && repository.split('/').all(|part| {
+            !part.is_empty()
+                && part.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+                && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(RegistryError {
+            status: StatusCode::BAD_REQUEST,
+            message: format!("invalid repository name {:?}", repository),
+        }
+        .into())
+    }
+}
+
+/// Parse the first challenge of a `WWW-Authenticate` header.
+fn challenge(headers: &HeaderMap) -> Option<Challenge> {
+    let value = headers.get(header::WWW_AUTHENTICATE)?.to_str().ok()?.trim();
+    let (scheme, rest) = value.split_once(' ').unwrap_or((value, ""));
+    if scheme.eq_ignore_ascii_case("basic") {
+        return Some(Challenge::Basic);
+    }
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+
+    let mut params = Vec::new();
+    let mut rest = rest.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_ascii_lowercase();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"')?,
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        params.push((key, value.to_string()));
+        rest = after.trim_start_matches(',').trim();
+    }
+    let realm = params.iter().position(|(key, _)| key == "realm").map(|i| params.remove(i).1)?;
+    Some(Challenge::Bearer { realm, params })
+}
+
+/// `last` of the next catalog page, from a `Link: <...?last=x&n=100>;
+/// rel="next"` header.
+fn next_page(headers: &HeaderMap) -> Option<String> {
+    let link = headers.get(header::LINK)?.to_str().ok()?;
+    let target = link.split(';').next()?.trim().trim_start_matches('<').trim_end_matches('>');
+    let query = target.split_once('?')?.1;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "last")
+        .map(|(_, value)| percent_decode_str(value).decode_utf8_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Query;
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::get;
+    use axum::{Json, Router};
+
+    const REPOSITORIES: [&str; 3] = ["app", "team/api", "web"];
+
+    /// A registry that wants tokens, like `registry:2` behind a token server.
+    async fn stand_in() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let realm = format!("{}/token", base);
+
+        let token = |headers: HeaderMap, Query(query): Query<HashMap<String, String>>| async move {
+            let expected = format!("Basic {}", STANDARD.encode("deploy:s3cret"));
+            let authorized = headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) == Some(&expected);
+            if !authorized || query.get("service").map(String::as_str) != Some("stand-in") {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            Json(serde_json::json!({ "token": "good-token" })).into_response()
+        };
+        let api = move |request: Request<Body>| {
+            let realm = realm.clone();
+            async move {
+                let path = request.uri().path().to_string();
+                let scope = if path == "/v2/_catalog" {
+                    "registry:catalog:*".to_string()
+                } else {
+                    let name = path.trim_start_matches("/v2/").trim_end_matches("/tags/list");
+                    format!("repository:{}:pull", name)
+                };
+                let bearer = request.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+                if bearer != Some("Bearer good-token") {
+                    let challenge = format!(r#"Bearer realm="{}",service="stand-in",scope="{}""#, realm, scope);
+                    let body = r#"{"errors":[{"code":"UNAUTHORIZED","message":"authentication required"}]}"#;
+                    return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, challenge)], body).into_response();
+                }
+                if path == "/v2/_catalog" {
+                    return catalog(request.uri().query().unwrap_or_default());
+                }
+                match path.trim_start_matches("/v2/").strip_suffix("/tags/list") {
+                    Some(name) if REPOSITORIES.contains(&name) => {
+                        Json(serde_json::json!({ "name": name, "tags": ["latest", "1.0"] })).into_response()
+                    }
+                    _ => {
+                        let body = r#"{"errors":[{"code":"NAME_UNKNOWN","message":"repository name not known to registry"}]}"#;
+                        (StatusCode::NOT_FOUND, body).into_response()
+                    }
+                }
+            }
+        };
+        let router = Router::new().route("/token", get(token)).fallback(api);
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        base
+    }
+
+    fn catalog(query: &str) -> Response {
+        let params: HashMap<String, String> = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (key.to_string(), percent_decode_str(value).decode_utf8_lossy().into_owned()))
+            .collect();
+        let n: usize = params.get("n").and_then(|n| n.parse().ok()).unwrap_or(100);
+        let after = REPOSITORIES
+            .iter()
+            .filter(|name| params.get("last").is_none_or(|last| **name > last.as_str()))
+            .collect::<Vec<_>>();
+        let page: Vec<_> = after.iter().take(n).collect();
+        let mut response = Json(serde_json::json!({ "repositories": page })).into_response();
+        if after.len() > n {
+            let last = utf8_percent_encode(page.last().unwrap(), NON_ALPHANUMERIC);
+            let link = format!(r#"</v2/_catalog?last={}&n={}>; rel="next""#, last, n);
+            response.headers_mut().insert(header::LINK, link.parse().unwrap());
+        }
+        response
+    }
+
+    fn login(url: &str, password: &str) -> Login {
+        Login {
+            url: url.to_string(),
+            host: url.trim_start_matches("http://").to_string(),
+            username: Some("deploy".to_string()),
+            password: Some(password.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_browse_with_token_auth() {
+        let url = stand_in().await;
+        let client = Client::new();
+        let login = login(&url, "s3cret");
+
+        let first = client.catalog(&login, Some(2), None).await.unwrap();
+        assert_eq!(first.repositories, ["app", "team/api"]);
+        assert_eq!(first.next.as_deref(), Some("team/api"));
+        let second = client.catalog(&login, Some(2), first.next.as_deref()).await.unwrap();
+        assert_eq!(second.repositories, ["web"]);
+        assert_eq!(second.next, None);
+
+        let tags = client.tags(&login, "team/api").await.unwrap();
+        assert_eq!((tags.name.as_str(), tags.tags), ("team/api", vec!["1.0".to_string(), "latest".to_string()]));
+
+        let missing = client.tags(&login, "nothing").await.unwrap_err();
+        let missing = missing.downcast_ref::<RegistryError>().unwrap();
+        assert_eq!(missing.status, StatusCode::NOT_FOUND);
+        assert_eq!(missing.message, "repository name not known to registry");
+        client.tags(&login, "../secrets").await.unwrap_err();
+
+        let refused = client.catalog(&self::login(&url, "wrong"), None, None).await.unwrap_err();
+        assert_eq!(refused.downcast_ref::<RegistryError>().unwrap().status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_challenge() {
+        let mut headers = HeaderMap::new();
+        let value = r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull""#;
+        headers.insert(header::WWW_AUTHENTICATE, value.parse().unwrap());
+        assert_eq!(
+            challenge(&headers),
+            Some(Challenge::Bearer {
+                realm: "https://auth.docker.io/token".to_string(),
+                params: vec![
+                    ("service".to_string(), "registry.docker.io".to_string()),
+                    ("scope".to_string(), "repository:library/nginx:pull".to_string()),
+                ],
+            })
+        );
+        headers.insert(header::WWW_AUTHENTICATE, r#"Basic realm="Registry Realm""#.parse().unwrap());
+        assert_eq!(challenge(&headers), Some(Challenge::Basic));
+    }
+}
@@ -0,0 +1,418 @@
+//! Encrypted secrets store.
+//!
+//! Secrets are kept in the `secrets` table sealed with AES-256-GCM under a
+//! master key, taken from `SECRETS_KEY` or a key file that is generated on
+//! first start. Values are only decrypted when a stack is deployed, into a
+//! container's environment or a file in the runtime directory, which should
+//! be on a tmpfs. API responses only carry a secret's metadata, and known
+//! values are masked in the logs Rustainer serves.
+//!
+//! Other credentials stored in the database, such as git passwords, are
+//! sealed with the same key through [`seal`] and [`unseal`].
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::{sqlite::SqliteRow, Pool, Row, Sqlite};
+use std::borrow::Cow;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::info;
+
+use crate::config::SecretsConfig;
+use crate::models::secret::{CreateSecretRequest, SecretInfo, UpdateSecretRequest};
+
+/// Prefix of sealed values, so values stored before encryption can still be
+/// read.
+const SEALED_PREFIX: &str = "enc:v1:";
+
+/// Length of the master key in bytes.
+const KEY_LEN: usize = 32;
+
+/// Longest secret name.
+const MAX_NAME_LEN: usize = 64;
+
+/// Shorter values are not masked in logs, where they would hide too much.
+const MIN_REDACTED_LEN: usize = 4;
+
+/// What secret values are replaced with.
+pub const REDACTED: &str = "********";
+
+/// Returned when a stack uses a secret that is not in the store.
+#[derive(Debug)]
+pub struct MissingSecret(pub String);
+
+impl std::fmt::Display for MissingSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "secret {} is not in the secrets store", self.0)
+    }
+}
+
+impl std::error::Error for MissingSecret {}
+
+/// Returned when a value that is stored in clear text, such as a stack's
+/// environment, holds the value of a stored secret.
+#[derive(Debug)]
+pub struct PlaintextSecret(pub String);
+
+impl std::fmt::Display for PlaintextSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} holds the value of a stored secret; refer to the secret by name instead", self.0)
+    }
+}
+
+impl std::error::Error for PlaintextSecret {}
+
+/// A decrypted secret, with a keyed digest that tells when it changed
+/// without giving the value away.
+#[derive(Clone)]
+pub struct Revealed {
+    pub value: String,
+    pub digest: String,
+}
+
+impl std::fmt::Debug for Revealed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Revealed")
+            .field("value", &REDACTED)
+            .field("digest", &self.digest)
+            .finish()
+    }
+}
+
+struct Cipher {
+    key: LessSafeKey,
+    digest: hmac::Key,
+    runtime_dir: PathBuf,
+}
+
+static CIPHER: OnceLock<Cipher> = OnceLock::new();
+
+impl Cipher {
+    fn new(key: &[u8], runtime_dir: PathBuf) -> Result<Self> {
+        let unbound = UnboundKey::new(&AES_256_GCM, key).map_err(|_| anyhow!("Invalid secrets key"))?;
+        // Digests use a key of their own, derived from the master key
+        let derived = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), b"rustainer secret digest");
+        Ok(Self {
+            key: LessSafeKey::new(unbound),
+            digest: hmac::Key::new(hmac::HMAC_SHA256, derived.as_ref()),
+            runtime_dir,
+        })
+    }
+
+    fn seal(&self, context: &str, value: &str) -> Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("Failed to generate a nonce"))?;
+        let mut sealed = value.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(context), &mut sealed)
+            .map_err(|_| anyhow!("Failed to encrypt {}", context))?;
+        let mut stored = nonce.to_vec();
+        stored.extend(sealed);
+        Ok(format!("{}{}", SEALED_PREFIX, STANDARD.encode(stored)))
+    }
+
+    fn unseal(&self, context: &str, stored: &str) -> Result<String> {
+        let Some(encoded) = stored.strip_prefix(SEALED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+        let mut data = STANDARD
+            .decode(encoded)
+            .with_context(|| format!("Failed to decode {}", context))?;
+        if data.len() < NONCE_LEN {
+            bail!("Failed to decrypt {}: value is truncated", context);
+        }
+        let sealed = data.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&data).map_err(|_| anyhow!("Invalid nonce in {}", context))?;
+        let mut sealed = sealed;
+        let opened = self
+            .key
+            .open_in_place(nonce, Aad::from(context), &mut sealed)
+            .map_err(|_| anyhow!("Failed to decrypt {}; was the secrets key changed?", context))?;
+        String::from_utf8(opened.to_vec()).with_context(|| format!("{} is not UTF-8", context))
+    }
+
+    fn digest(&self, value: &str) -> String {
+        hmac::sign(&self.digest, value.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Load the master key. Without `key`, the key file is read, or created with
+/// a new random key when it does not exist yet.
+pub fn init(config: &SecretsConfig) -> Result<()> {
+    let key = match &config.key {
+        Some(key) => decode_key(key).context("Invalid SECRETS_KEY")?,
+        None => load_key_file(Path::new(&config.key_file))?,
+    };
+    let _ = CIPHER.set(Cipher::new(&key, PathBuf::from(&config.runtime_dir))?);
+    Ok(())
+}
+
+fn cipher() -> Result<&'static Cipher> {
+    CIPHER.get().ok_or_else(|| anyhow!("Secrets store is not initialised"))
+}
+
+fn decode_key(encoded: &str) -> Result<Vec<u8>> {
+    let key = STANDARD.decode(encoded.trim()).context("Key is not base64")?;
+    if key.len() != KEY_LEN {
+        bail!("Key must be {} bytes, not {}", KEY_LEN, key.len());
+    }
+    Ok(key)
+}
+
+fn load_key_file(path: &Path) -> Result<Vec<u8>> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    match fs::read_to_string(path) {
+        Ok(content) => return decode_key(&content).with_context(|| format!("Invalid key file {}", path.display())),
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+        Err(_) => {}
+    }
+
+    let mut key = vec![0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| anyhow!("Failed to generate a secrets key"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(STANDARD.encode(&key).as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Generated a new secrets key in {}; back it up with the database", path.display());
+    Ok(key)
+}
+
+/// Encrypt a value for storage. `context` names what it is, such as
+/// `secret:db_password`, and must be given again to decrypt it.
+pub fn seal(context: &str, value: &str) -> Result<String> {
+    cipher()?.seal(context, value)
+}
+
+/// Decrypt a value sealed with [`seal`]. Values stored before encryption are
+/// returned as they are.
+pub fn unseal(context: &str, stored: &str) -> Result<String> {
+    cipher()?.unseal(context, stored)
+}
+
+/// Directory that secret files are written to for containers to mount.
+pub fn runtime_dir() -> Result<PathBuf> {
+    Ok(cipher()?.runtime_dir.clone())
+}
+
+/// Replace every occurrence of the given values in `text`.
+pub fn redact<'a>(text: &'a str, values: &[String]) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    for value in values {
+        if text.contains(value.as_str()) {
+            text = Cow::Owned(text.replace(value.as_str(), REDACTED));
+        }
+    }
+    text
+}
+
+/// Label of the first field whose text holds one of `values`, as listed by
+/// [`redactions`].
+pub fn find_plaintext<'a>(fields: impl IntoIterator<Item = (String, &'a str)>, values: &[String]) -> Option<String> {
+    fields
+        .into_iter()
+        .find(|(_, text)| values.iter().any(|value| text.contains(value.as_str())))
+        .map(|(label, _)| label)
+}
+
+/// Check that a name can be used in compose files and file names.
+pub fn check_name(name: &str) -> Result<()> {
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if name.is_empty() || name.len() > MAX_NAME_LEN || !valid || name.starts_with('.') {
+        bail!(
+            "Invalid secret name {:?}: use up to {} letters, digits, '_', '-' and '.'",
+            name,
+            MAX_NAME_LEN
+        );
+    }
+    Ok(())
+}
+
+fn context(name: &str) -> String {
+    format!("secret:{}", name)
+}
+
+/// List stored secrets by name.
+pub async fn list(db: &Pool<Sqlite>) -> Result<Vec<SecretInfo>> {
+    let rows = sqlx::query("SELECT name, description, created_at, updated_at FROM secrets ORDER BY name")
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch secrets from database")?;
+    rows.iter().map(info_from_row).collect()
+}
+
+/// Get a secret's metadata by name.
+pub async fn get(db: &Pool<Sqlite>, name: &str) -> Result<Option<SecretInfo>> {
+    let row = sqlx::query("SELECT name, description, created_at, updated_at FROM secrets WHERE name = ?")
+        .bind(name)
+        .fetch_optional(db)
+        .await
+        .context("Failed to fetch secret from database")?;
+    row.as_ref().map(info_from_row).transpose()
+}
+
+/// Store a new secret.
+pub async fn create(db: &Pool<Sqlite>, request: CreateSecretRequest) -> Result<SecretInfo> {
+    check_name(&request.name)?;
+    let now = Utc::now();
+    sqlx::query("INSERT INTO secrets (name, value, description, created_at, updated_at) VALUES (?, ?, ?, ?, ?)")
+        .bind(&request.name)
+        .bind(seal(&context(&request.name), &request.value)?)
+        .bind(request.description.as_deref().filter(|d| !d.is_empty()))
+        .bind(now)
+        .bind(now)
+        .execute(db)
+        .await
+        .context("Failed to insert secret into database")?;
+    Ok(SecretInfo {
+        name: request.name,
+        description: request.description.filter(|d| !d.is_empty()),
+        created_at: now,
+        updated_at: now,
+        used_by: Vec::new(),
+    })
+}
+
+/// Change a secret's value or description.
+pub async fn update(db: &Pool<Sqlite>, name: &str, request: UpdateSecretRequest) -> Result<Option<SecretInfo>> {
+    let Some(mut info) = get(db, name).await? else {
+        return Ok(None);
+    };
+    if let Some(description) = request.description {
+        info.description = Some(description).filter(|d| !d.is_empty());
+    }
+    info.updated_at = Utc::now();
+
+    let value = request.value.map(|value| seal(&context(name), &value)).transpose()?;
+    sqlx::query("UPDATE secrets SET value = COALESCE(?, value), description = ?, updated_at = ? WHERE name = ?")
+        .bind(value)
+        .bind(&info.description)
+        .bind(info.updated_at)
+        .bind(name)
+        .execute(db)
+        .await
+        .context("Failed to update secret in database")?;
+    Ok(Some(info))
+}
+
+/// Delete a secret. Returns whether it existed.
+pub async fn delete(db: &Pool<Sqlite>, name: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM secrets WHERE name = ?")
+        .bind(name)
+        .execute(db)
+        .await
+        .context("Failed to delete secret from database")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Decrypt a secret for a deployment.
+pub async fn reveal(db: &Pool<Sqlite>, name: &str) -> Result<Option<Revealed>> {
+    let stored: Option<String> = sqlx::query_scalar("SELECT value FROM secrets WHERE name = ?")
+        .bind(name)
+        .fetch_optional(db)
+        .await
+        .context("Failed to fetch secret from database")?;
+    let Some(stored) = stored else {
+        return Ok(None);
+    };
+    let cipher = cipher()?;
+    let value = cipher.unseal(&context(name), &stored)?;
+    Ok(Some(Revealed {
+        digest: cipher.digest(&value),
+        value,
+    }))
+}
+
+/// Every stored value long enough to be masked, longest first so a value
+/// containing another is masked whole.
+pub async fn redactions(db: &Pool<Sqlite>) -> Result<Vec<String>> {
+    let rows = sqlx::query("SELECT name, value FROM secrets")
+        .fetch_all(db)
+        .await
+        .context("Failed to fetch secrets from database")?;
+    let cipher = cipher()?;
+    let mut values = Vec::with_capacity(rows.len());
+    for row in &rows {
+        let name: String = row.try_get("name")?;
+        let value = cipher.unseal(&context(&name), &row.try_get::<String, _>("value")?)?;
+        if value.len() >= MIN_REDACTED_LEN {
+            values.push(value);
+        }
+    }
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+    Ok(values)
+}
+
+fn info_from_row(row: &SqliteRow) -> Result<SecretInfo> {
+    Ok(SecretInfo {
+        name: row.try_get("name")?,
+        description: row.try_get("description")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+        used_by: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cipher() -> Cipher {
+        Cipher::new(&[7u8; KEY_LEN], PathBuf::from("/tmp")).unwrap()
+    }
+
+    #[test]
+    fn test_seal_round_trip_is_bound_to_its_context() {
+        let cipher = test_cipher();
+        let sealed = cipher.seal("secret:db", "hunter22").unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert!(!sealed.contains("hunter22"));
+        assert_ne!(sealed, cipher.seal("secret:db", "hunter22").unwrap());
+        assert_eq!(cipher.unseal("secret:db", &sealed).unwrap(), "hunter22");
+        assert!(cipher.unseal("secret:other", &sealed).is_err());
+        assert!(Cipher::new(&[8u8; KEY_LEN], PathBuf::new()).unwrap().unseal("secret:db", &sealed).is_err());
+        // Values stored before encryption are read as they are
+        assert_eq!(cipher.unseal("secret:db", "plain").unwrap(), "plain");
+        assert_eq!(cipher.digest("a"), test_cipher().digest("a"));
+        assert_ne!(cipher.digest("a"), cipher.digest("b"));
+    }
+
+    #[test]
+    fn test_names_and_redaction() {
+        assert!(check_name("db_password.v2").is_ok());
+        for name in ["", ".hidden", "a/b", "with space", &"x".repeat(65)] {
+            assert!(check_name(name).is_err(), "{:?}", name);
+        }
+        let values = vec!["s3cr3t-long".to_string(), "s3cr3t".to_string()];
+        assert_eq!(redact("token=s3cr3t-long and s3cr3t", &values), "token=******** and ********");
+        assert!(matches!(redact("nothing here", &values), Cow::Borrowed(_)));
+        let fields = [("TAG".to_string(), "1.25"), ("TOKEN".to_string(), "Bearer s3cr3t")];
+        assert_eq!(find_plaintext(fields.clone(), &values).as_deref(), Some("TOKEN"));
+        assert_eq!(find_plaintext(fields, &[]), None);
+    }
+}